@@ -13,8 +13,8 @@ fn main() -> Result<(), Whatever> {
         ..load_config().whatever_context("failed to load config")?
     };
 
-    let repo = LocalFsWalker::new(&config).build_repository();
-    println!("{repo:?}");
+    let scan = LocalFsWalker::new(&config).build_repository();
+    println!("{:?}", scan.repo);
 
     Ok(())
 }
@@ -20,8 +20,11 @@ async fn main() -> Result<(), Box<dyn Error>> {
     }
     .into();
 
-    let repo = RemoteFs::new(config).create_repo().await?;
+    let (repo, issues) = RemoteFs::new(config).create_repo().await?;
 
     println!("{repo:?}");
+    for issue in issues {
+        println!("{issue}");
+    }
     Ok(())
 }
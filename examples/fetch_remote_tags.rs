@@ -8,7 +8,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let tags = connection.request(ListTags).await?;
     println!("List of all tags:\n{tags:?}");
     let tag_name: Tag = "Alligator".parse()?;
-    let tag_id = *tags.get_by_right(&tag_name).unwrap();
+    let tag_id = *tags.assignable.get_by_right(&tag_name).unwrap();
     let files = connection.request(ListFilesWithTag::new(tag_id)).await?;
     println!("Files tagged with {tag_name} are: {files:?}");
     Ok(())
@@ -0,0 +1,102 @@
+report-encrypted-folders = Übersprungene Ende-zu-Ende-verschlüsselte Ordner:
+report-encrypted-prefix = Präfix { $prefix }: { $count ->
+    [one] { $count } Ordner
+   *[other] { $count } Ordner
+}
+report-read-only-shares = { $count ->
+    [one] { $count } Datei
+   *[other] { $count } Dateien
+} in schreibgeschützten eingehenden Freigaben übersprungen:
+report-forbidden-tag = Keine Berechtigung für Tag { $tag }
+report-rejected-tag = Nextcloud hat den Tag-Namen { $tag } abgelehnt
+report-rejected-tags-hint = Benenne diese Tags lokal um. Lösche die Datei rejected-tags.json neben der Tag-Datenbank, um sie erneut anzulegen.
+report-quarantined = Quarantäne für Dateien ohne passendes Präfix:
+report-stale-files = Die Tags { $count ->
+    [one] einer Datei wurden
+   *[other] von { $count } Dateien wurden
+} lange nicht geändert:
+report-failures-local = { $failed } von { $total } lokalen Befehlen fehlgeschlagen:
+report-failures-remote = { $failed } von { $total } Befehlen für Nextcloud fehlgeschlagen:
+report-statistics = Tag-Statistik:
+report-orphaned-files = { $count ->
+    [one] { $count } lokale Datei wurde
+   *[other] { $count } lokale Dateien wurden
+} auf Nextcloud gelöscht:
+report-constraint-violations = { $count ->
+    [one] { $count } Datei hat
+   *[other] { $count } Dateien haben
+} sich ausschließende Tags und { $count ->
+    [one] wurde
+   *[other] wurden
+} nicht synchronisiert:
+report-constraint-violation = { $path }: { $tags }
+report-looping-files = Die Tags { $count ->
+    [one] einer Datei wechseln
+   *[other] von { $count } Dateien wechseln
+} ständig, vermutlich schreibt ein anderes Programm sie um. Sie werden nicht synchronisiert, bis sie mit db release-loops freigegeben werden:
+report-scan-issues = { $count ->
+    [one] { $count } Eintrag
+   *[other] { $count } Einträge
+} beim Durchsuchen übersprungen:
+
+scan-issue-prefix-failed = { $path } konnte nicht durchsucht werden, seine Tags werden nicht synchronisiert: { $error }
+scan-issue-missing-file-id = Nextcloud-Datei-ID für { $path } fehlt
+scan-issue-unreadable-file = Tags von { $path } konnten nicht gelesen werden: { $error }
+
+stats-total = Gesamt: { $stats }
+stats-counts = { $files } Dateien ({ $tagged } mit Tags), { $tags } Tags, { $distinct } verschiedene
+
+status-pending-remote = Ausstehende Änderungen für Nextcloud:
+status-pending-local = Ausstehende lokale Änderungen:
+status-none-remote = Keine ausstehenden Änderungen für Nextcloud.
+status-none-local = Keine ausstehenden lokalen Änderungen.
+dry-run-header = Probelauf, nichts wurde geändert.
+sync-confirm-initial = Die erste Synchronisierung würde { $files } Dateien ändern. Bisher wurde nichts geändert. Prüfe die obigen Änderungen und führe sync --confirm-initial aus, um sie anzuwenden.
+sync-deadline-exceeded = Nach der maximalen Dauer angehalten. { $files } Dateien verbleiben und werden beim nächsten Lauf synchronisiert.
+
+doctor-resolved = { $host } wird zu { $count ->
+    [one] { $count } Adresse
+   *[other] { $count } Adressen
+} aufgelöst:
+doctor-pinned = { $host } ist festgelegt auf:
+doctor-unresolved = { $host } konnte nicht aufgelöst werden: { $error }
+doctor-reachable = erreichbar in { $millis } ms
+doctor-failed = fehlgeschlagen: { $error }
+doctor-timed-out = Zeitüberschreitung
+doctor-prefixes = Entfernte Ordner der Präfixe:
+doctor-quota = Speicherplatz: { $used } von { $total } MiB belegt
+doctor-quota-unlimited = Speicherplatz: { $used } MiB belegt, unbegrenzt
+doctor-quota-failed = Speicherplatz konnte nicht abgefragt werden: { $error }
+doctor-quota-unreachable = Nextcloud ist nicht erreichbar
+quota-exhausted = Der Speicherplatz auf Nextcloud ist fast aufgebraucht. Manche Server lehnen dann Tag-Änderungen mit 507 Insufficient Storage ab.
+
+compaction-untagged = { $count } Einträge ohne Tags entfernt.
+compaction-deleted = { $count } Einträge von auf beiden Seiten gelöschten Dateien entfernt.
+compaction-remaining = { $count } Einträge verbleiben.
+
+conflict-comment = nextcloud-tag-sync hat Tags entfernt, um einen Konflikt aufzulösen.
+conflict-comment-local = Lokal entfernt: { $tags }
+conflict-comment-remote = Auf Nextcloud entfernt: { $tags }
+
+backup-restored = Tags von { $count } Dateien wiederhergestellt.
+backup-failed = Tags von { $count } Dateien konnten nicht wiederhergestellt werden.
+
+rebind-matched = { $count } Dateien mit allen Tags neu zugeordnet.
+rebind-partial = { $count } Dateien neu zugeordnet, denen auf Nextcloud Tags fehlen. Sie werden bei der nächsten Synchronisierung hochgeladen.
+rebind-missing = { $count } Dateien existieren nicht auf Nextcloud.
+rebind-unknown-tag = Tag { $tag } existiert nicht auf Nextcloud und wird bei der nächsten Synchronisierung angelegt.
+
+push-tags-created = Tag { $tag } angelegt.
+push-tags-failed = Tag { $tag } konnte nicht angelegt werden.
+push-tags-existing = { $count } Tags existierten bereits auf Nextcloud.
+
+inspect-tags = Dateien pro Tag:
+inspect-files = Dateien:
+
+db-diff-added = In die Datenbank aufgenommene Dateien:
+db-diff-removed = Aus der Datenbank entfernte Dateien:
+db-diff-changed = Dateien mit geänderten Tags:
+db-diff-identical = Die Datenbanken sind identisch.
+
+integrity-clean = Keine Auffälligkeiten gefunden.
+integrity-anomalies = { $count } Auffälligkeiten gefunden:
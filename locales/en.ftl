@@ -0,0 +1,102 @@
+report-encrypted-folders = Skipped end-to-end encrypted folders:
+report-encrypted-prefix = Prefix { $prefix }: { $count ->
+    [one] { $count } folder
+   *[other] { $count } folders
+}
+report-read-only-shares = Skipped { $count ->
+    [one] { $count } file
+   *[other] { $count } files
+} in read-only incoming shares:
+report-forbidden-tag = Insufficient permission for tag { $tag }
+report-rejected-tag = Nextcloud rejected the tag name { $tag }
+report-rejected-tags-hint = Rename these tags locally. Delete the rejected-tags.json file next to the tag database to try creating them again.
+report-quarantined = Quarantined files without matching prefix:
+report-stale-files = { $count ->
+    [one] { $count } file has
+   *[other] { $count } files have
+} not been retagged for a long time:
+report-failures-local = Failed to apply { $failed } of { $total } local commands:
+report-failures-remote = Failed to apply { $failed } of { $total } remote commands:
+report-statistics = Tag statistics:
+report-orphaned-files = { $count ->
+    [one] { $count } local file was
+   *[other] { $count } local files were
+} deleted on Nextcloud:
+report-constraint-violations = { $count ->
+    [one] { $count } file carries
+   *[other] { $count } files carry
+} mutually exclusive tags and { $count ->
+    [one] was
+   *[other] were
+} not synchronized:
+report-constraint-violation = { $path }: { $tags }
+report-looping-files = The tags of { $count ->
+    [one] { $count } file keep
+   *[other] { $count } files keep
+} flipping, maybe another tool rewrites them. They are not synchronized until released with db release-loops:
+report-scan-issues = Skipped { $count ->
+    [one] { $count } item
+   *[other] { $count } items
+} while scanning:
+
+scan-issue-prefix-failed = Failed to scan { $path }, its tags are not synchronized: { $error }
+scan-issue-missing-file-id = Missing Nextcloud file id for { $path }
+scan-issue-unreadable-file = Failed to read the tags of { $path }: { $error }
+
+stats-total = Total: { $stats }
+stats-counts = { $files } files ({ $tagged } tagged), { $tags } tags, { $distinct } distinct
+
+status-pending-remote = Pending Nextcloud changes:
+status-pending-local = Pending local changes:
+status-none-remote = No pending Nextcloud changes.
+status-none-local = No pending local changes.
+dry-run-header = Dry run, nothing was changed.
+sync-confirm-initial = The initial synchronization would change { $files } files. Nothing was changed yet. Review the changes above and run sync --confirm-initial to apply them.
+sync-deadline-exceeded = Stopped at the maximum duration. { $files } files are left and will be synchronized by the next run.
+
+doctor-resolved = { $host } resolves to { $count ->
+    [one] { $count } address
+   *[other] { $count } addresses
+}:
+doctor-pinned = { $host } is pinned to:
+doctor-unresolved = Failed to resolve { $host }: { $error }
+doctor-reachable = reachable in { $millis } ms
+doctor-failed = failed: { $error }
+doctor-timed-out = timed out
+doctor-prefixes = Remote folders of the prefixes:
+doctor-quota = Quota: { $used } of { $total } MiB used
+doctor-quota-unlimited = Quota: { $used } MiB used, unlimited
+doctor-quota-failed = Failed to fetch quota: { $error }
+doctor-quota-unreachable = Nextcloud is not reachable
+quota-exhausted = The quota on Nextcloud is almost used up. Some servers then reject tag changes with 507 Insufficient Storage.
+
+compaction-untagged = Dropped { $count } entries without tags.
+compaction-deleted = Dropped { $count } entries of files deleted on both sides.
+compaction-remaining = { $count } entries remaining.
+
+conflict-comment = nextcloud-tag-sync dropped tags to resolve a conflict.
+conflict-comment-local = Removed locally: { $tags }
+conflict-comment-remote = Removed on Nextcloud: { $tags }
+
+backup-restored = Restored the tags of { $count } files.
+backup-failed = Failed to restore the tags of { $count } files.
+
+rebind-matched = Rebound { $count } files with all their tags.
+rebind-partial = Rebound { $count } files with some tags missing on Nextcloud. They are uploaded in the next synchronization.
+rebind-missing = { $count } files do not exist on Nextcloud.
+rebind-unknown-tag = Tag { $tag } does not exist on Nextcloud and is created in the next synchronization.
+
+push-tags-created = Created tag { $tag }.
+push-tags-failed = Failed to create tag { $tag }.
+push-tags-existing = { $count } tags already existed on Nextcloud.
+
+inspect-tags = Files per tag:
+inspect-files = Files:
+
+db-diff-added = Files added to the database:
+db-diff-removed = Files removed from the database:
+db-diff-changed = Files with changed tags:
+db-diff-identical = The databases are identical.
+
+integrity-clean = No anomalies found.
+integrity-anomalies = Found { $count } anomalies:
@@ -0,0 +1,192 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use snafu::{ResultExt, Snafu};
+
+use crate::{
+    i18n::tr,
+    tag_repository::{LoadError, PersistingError},
+    Command, CommandOutcome, Config, FileSystem, LocalFs, Modification, Repository, TagAction,
+};
+
+/// Writes the tags of all files which lose a tag to a new timestamped file in
+/// [`Config::tag_backup_directory`]. `target` is the repository after all commands
+/// succeeded. Returns the path of the backup or `None` if no command removes a tag.
+///
+/// # Errors
+///
+/// This function will return an error if the backup cannot be written.
+pub fn backup_removed_tags(
+    config: &Config,
+    target: &Repository,
+    local: &[Command],
+    remote: &[Command],
+) -> Result<Option<PathBuf>, BackupError> {
+    let mut backup = Repository::new(target.prefixes().to_vec());
+    for cmd in local.iter().chain(remote).filter(|cmd| removes_tags(cmd)) {
+        // Each side is reverted on its own, files changed on both sides keep the union.
+        let mut before = Repository::new(target.prefixes().to_vec());
+        before.copy_entry(&cmd.path, target);
+        before.revert(cmd.clone());
+        for (path, tags) in before.into_files() {
+            backup.apply(Command {
+                path,
+                actions: tags.into_iter().map(add).collect(),
+            });
+        }
+    }
+    if backup.is_empty() {
+        return Ok(None);
+    }
+
+    let directory = &config.tag_backup_directory;
+    std::fs::create_dir_all(directory).context(CreateSnafu { path: directory })?;
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let mut path = directory.join(format!("tags-{millis}.json"));
+    for suffix in 1.. {
+        if !path.exists() {
+            break;
+        }
+        path = directory.join(format!("tags-{millis}-{suffix}.json"));
+    }
+    backup.persist_on_disk(&path).context(PersistSnafu)?;
+    tracing::info!(
+        "Backed up the tags of {} files to {}",
+        backup.len(),
+        path.display()
+    );
+    Ok(Some(path))
+}
+
+fn removes_tags(cmd: &Command) -> bool {
+    cmd.actions
+        .iter()
+        .any(|action| action.modification == Modification::Remove)
+}
+
+const fn add(tag: crate::Tag) -> TagAction {
+    TagAction {
+        tag,
+        modification: Modification::Add,
+    }
+}
+
+/// Summary of restoring a tag backup.
+#[derive(Debug, Default)]
+pub struct RestoreReport {
+    pub restored: usize,
+    pub failed: usize,
+}
+
+impl std::fmt::Display for RestoreReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "{}", tr!("backup-restored", count = self.restored))?;
+        if self.failed > 0 {
+            writeln!(f, "{}", tr!("backup-failed", count = self.failed))?;
+        }
+        Ok(())
+    }
+}
+
+/// Adds the tags of a backup to the local files again. The next synchronization uploads
+/// them to Nextcloud like any other local change. Tags added since the backup are kept.
+///
+/// # Errors
+///
+/// This function will return an error if the backup cannot be read.
+pub async fn restore_backup(
+    config: Arc<Config>,
+    path: &Path,
+) -> Result<RestoreReport, BackupError> {
+    let backup = Repository::read_from_disk(path)
+        .context(LoadSnafu)?
+        .migrate_prefixes(&config.prefixes);
+    let commands: Vec<_> = backup
+        .into_files()
+        .filter_map(|(path, tags)| {
+            Command {
+                path,
+                actions: tags.into_iter().map(add).collect(),
+            }
+            .none_if_empty()
+        })
+        .collect();
+
+    let outcomes = LocalFs::new(config).update_tags(commands).await;
+    let failed = outcomes
+        .iter()
+        .filter(|outcome| !matches!(outcome, CommandOutcome::Success(_)))
+        .count();
+    Ok(RestoreReport {
+        restored: outcomes.len() - failed,
+        failed,
+    })
+}
+
+#[derive(Debug, Snafu)]
+pub enum BackupError {
+    #[snafu(display("failed to create backup directory {}", path.display()))]
+    Create {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("failed to write tag backup"))]
+    Persist { source: PersistingError },
+    #[snafu(display("failed to read tag backup"))]
+    Load { source: LoadError },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PrefixMapping, SyncedPath, Tags};
+
+    #[test]
+    fn backup_tags_before_removal() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            tag_backup_directory: dir.path().join("backups"),
+            ..Config::default()
+        };
+        let prefixes =
+            vec![PrefixMapping::new("/local".into(), "/remote.php/dav/files/user".into()).unwrap()];
+        let tags = |tags: &[&str]| -> Tags { tags.iter().copied().collect() };
+        let action = |tag: &str, modification| TagAction {
+            tag: tag.parse().unwrap(),
+            modification,
+        };
+        let mut target = Repository::new(prefixes);
+        target.insert(SyncedPath::new(0, "a.txt"), tags(&["blue"]));
+        target.insert(SyncedPath::new(0, "b.txt"), tags(&["red", "blue"]));
+        let local = [Command {
+            path: SyncedPath::new(0, "a.txt"),
+            actions: vec![
+                action("red", Modification::Remove),
+                action("blue", Modification::Add),
+            ],
+        }];
+        let remote = [Command {
+            path: SyncedPath::new(0, "b.txt"),
+            actions: vec![action("blue", Modification::Add)],
+        }];
+
+        assert!(backup_removed_tags(&config, &target, &[], &remote)
+            .unwrap()
+            .is_none());
+        let path = backup_removed_tags(&config, &target, &local, &remote)
+            .unwrap()
+            .unwrap();
+
+        let backup = Repository::read_from_disk(&path).unwrap();
+        assert_eq!(
+            backup.into_files().collect::<Vec<_>>(),
+            [(SyncedPath::new(0, "a.txt"), tags(&["red"]))]
+        );
+    }
+}
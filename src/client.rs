@@ -0,0 +1,31 @@
+//! Requests to manage the system tags of a Nextcloud instance, usable without the
+//! synchronization. Enable the `client` feature to use them from other crates.
+//!
+//! Each request implements [`Request`] and [`Parse`] and is sent with a [`Connection`]:
+//!
+//! ```no_run
+//! use nextcloud_tag_sync::client::{Connection, GetFileId, ListTags, RemotePath, TagFile};
+//!
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let url = "https://cloud.example.com".parse()?;
+//! let connection = Connection::new(url, "alice", "app-password");
+//! let tags = connection.request(ListTags).await?;
+//! let Some((&tag, _)) = tags.assignable.iter().next() else {
+//!     return Ok(());
+//! };
+//! let path = RemotePath::from(std::path::Path::new("/remote.php/dav/files/alice/a.txt"));
+//! let request = GetFileId::new(&path).ok_or("path is not UTF-8")?;
+//! let file = connection.request(request).await?;
+//! connection.request(TagFile::new(tag, file.id)).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub use crate::remote_fs::{
+    Body, Connection, CreateTag, DeleteTag, DeserializeError, DirectoryEntry, Exchange, FileId,
+    FileProperties, GetFileId, GetFileTags, IsEncrypted, ListDirectory, ListFilesWithTag, ListTags,
+    LockFile, LockToken, Middleware, MissingLockTokenError, Parse, Permissions, RemoteFile,
+    RemotePath, Request, RequestError, ServerException, TagFile, TagId, TagKind, TagListing,
+    TaggedFile, UnlockFile, UntagFile, UpdateTag, UpdateTagError,
+};
+pub use crate::tag_repository::{Tag, TagParseError};
@@ -1,27 +1,62 @@
+#[cfg(test)]
+mod replay;
+
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use snafu::{ensure, ResultExt, Snafu};
+
 use crate::{
-    tag_repository::{DiffResult, Side},
-    SyncedPath, SyncedPathPrinter, Tag, Tags,
+    tag_repository::{DiffResult, Side, TagParseError},
+    PrefixMapping, SortOrder, SyncedPath, SyncedPathPrinter, Tag, Tags,
 };
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
 pub enum Modification {
     Add,
     Remove,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct TagAction {
     pub tag: Tag,
     pub modification: Modification,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+impl TagAction {
+    #[must_use]
+    pub const fn add(tag: Tag) -> Self {
+        Self {
+            tag,
+            modification: Modification::Add,
+        }
+    }
+
+    #[must_use]
+    pub const fn remove(tag: Tag) -> Self {
+        Self {
+            tag,
+            modification: Modification::Remove,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct Command {
     pub path: SyncedPath,
     pub actions: Vec<TagAction>,
 }
 
 impl Command {
+    /// Starts building a command for `path`, e.g.
+    /// `Command::for_path(path).add("red").remove("blue").build()`.
+    pub const fn for_path(path: SyncedPath) -> CommandBuilder {
+        CommandBuilder {
+            path,
+            actions: Vec::new(),
+        }
+    }
+
     const fn new(path: SyncedPath) -> Self {
         Self {
             path,
@@ -30,18 +65,12 @@ impl Command {
     }
 
     fn add(mut self, tags: Tags) -> Self {
-        self.actions.extend(tags.into_iter().map(|tag| TagAction {
-            modification: Modification::Add,
-            tag,
-        }));
+        self.actions.extend(tags.into_iter().map(TagAction::add));
         self
     }
 
     fn remove(mut self, tags: Tags) -> Self {
-        self.actions.extend(tags.into_iter().map(|tag| TagAction {
-            modification: Modification::Remove,
-            tag,
-        }));
+        self.actions.extend(tags.into_iter().map(TagAction::remove));
         self
     }
 
@@ -51,40 +80,207 @@ impl Command {
     }
 }
 
-pub fn resolve_diffs<I>(iter: I, source_of_truth: Side) -> (Vec<Command>, Vec<Command>)
+/// Builder for a [`Command`]. Tags are validated when the command is built.
+#[derive(Debug, Clone)]
+#[must_use]
+pub struct CommandBuilder {
+    path: SyncedPath,
+    actions: Vec<(String, Modification)>,
+}
+
+impl CommandBuilder {
+    #[allow(
+        clippy::should_implement_trait,
+        reason = "Named like the modification it adds"
+    )]
+    pub fn add(self, tag: impl Into<String>) -> Self {
+        self.modify(tag, Modification::Add)
+    }
+
+    pub fn remove(self, tag: impl Into<String>) -> Self {
+        self.modify(tag, Modification::Remove)
+    }
+
+    pub fn modify(mut self, tag: impl Into<String>, modification: Modification) -> Self {
+        self.actions.push((tag.into(), modification));
+        self
+    }
+
+    /// Builds the command. Repeated actions are only kept once.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a tag is invalid, a tag is both added and
+    /// removed or the command has no actions.
+    pub fn build(self) -> Result<Command, CommandError> {
+        let path = self.path;
+        let mut actions: Vec<TagAction> = Vec::with_capacity(self.actions.len());
+        for (tag, modification) in self.actions {
+            let parsed = tag.parse::<Tag>();
+            let tag = parsed.with_context(|_| InvalidTagSnafu {
+                path: path.clone(),
+                tag,
+            })?;
+            if let Some(existing) = actions.iter().find(|action| action.tag == tag) {
+                ensure!(
+                    existing.modification == modification,
+                    ContradictingActionsSnafu { path, tag }
+                );
+                continue;
+            }
+            actions.push(TagAction { tag, modification });
+        }
+        ensure!(!actions.is_empty(), NoActionsSnafu { path });
+        Ok(Command { path, actions })
+    }
+}
+
+#[derive(Debug, Snafu)]
+pub enum CommandError {
+    #[snafu(display("invalid tag '{tag}' for {path}"))]
+    InvalidTag {
+        path: SyncedPath,
+        tag: String,
+        source: TagParseError,
+    },
+    #[snafu(display("tag {tag} is both added to and removed from {path}"))]
+    ContradictingActions { path: SyncedPath, tag: Tag },
+    #[snafu(display("command for {path} has no actions"))]
+    NoActions { path: SyncedPath },
+}
+
+/// Result of applying a [`Command`] to a file system.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandOutcome {
+    /// All actions were applied.
+    Success(Command),
+    /// Only some actions were applied.
+    Partial {
+        applied: Command,
+        failed: Command,
+        error: String,
+    },
+    /// No action was applied.
+    Failed { command: Command, error: String },
+    /// No action was applied on purpose, e.g. because the file is in a read-only share.
+    /// Like failed commands, it is undone in the tag database, but not reported as error.
+    Skipped { command: Command, reason: String },
+}
+
+impl CommandOutcome {
+    /// Classifies the command by which of its actions could be applied.
+    #[must_use]
+    pub fn from_actions(
+        path: SyncedPath,
+        applied: Vec<TagAction>,
+        failed: Vec<TagAction>,
+        errors: &[String],
+    ) -> Self {
+        let error = errors.join("; ");
+        let failed = Command {
+            path: path.clone(),
+            actions: failed,
+        };
+        if failed.actions.is_empty() {
+            Self::Success(Command {
+                path,
+                actions: applied,
+            })
+        } else if applied.is_empty() {
+            Self::Failed {
+                command: failed,
+                error,
+            }
+        } else {
+            Self::Partial {
+                applied: Command {
+                    path,
+                    actions: applied,
+                },
+                failed,
+                error,
+            }
+        }
+    }
+
+    #[must_use]
+    pub const fn path(&self) -> &SyncedPath {
+        match self {
+            Self::Success(command)
+            | Self::Partial {
+                applied: command, ..
+            }
+            | Self::Failed { command, .. }
+            | Self::Skipped { command, .. } => &command.path,
+        }
+    }
+
+    /// The actions which were applied.
+    #[must_use]
+    pub const fn applied(&self) -> Option<&Command> {
+        match self {
+            Self::Success(applied) | Self::Partial { applied, .. } => Some(applied),
+            Self::Failed { .. } | Self::Skipped { .. } => None,
+        }
+    }
+
+    #[must_use]
+    pub const fn is_success(&self) -> bool {
+        matches!(self, Self::Success(_))
+    }
+
+    /// Whether applying the command failed at least partially. Skipped commands did not fail.
+    #[must_use]
+    pub const fn is_failure(&self) -> bool {
+        matches!(self, Self::Partial { .. } | Self::Failed { .. })
+    }
+
+    /// The actions which could not be applied.
+    #[must_use]
+    pub const fn failed(&self) -> Option<&Command> {
+        match self {
+            Self::Success(_) => None,
+            Self::Partial { failed, .. }
+            | Self::Failed {
+                command: failed, ..
+            }
+            | Self::Skipped {
+                command: failed, ..
+            } => Some(failed),
+        }
+    }
+
+    #[must_use]
+    pub fn error(&self) -> Option<&str> {
+        match self {
+            Self::Success(_) => None,
+            Self::Partial { error, .. }
+            | Self::Failed { error, .. }
+            | Self::Skipped { reason: error, .. } => Some(error),
+        }
+    }
+}
+
+/// Commands for the left and the right side which make both sides match the merged
+/// repository of the diff. Each file is resolved by the side [`DiffResult::keep`] chose
+/// for it.
+pub fn resolve_diffs<I>(iter: I) -> (Vec<Command>, Vec<Command>)
 where
     I: IntoIterator<Item = DiffResult>,
 {
-    match source_of_truth {
-        Side::Left => {
-            let left = iter
-                .into_iter()
-                .filter_map(|res| {
-                    Command::new(res.path)
-                        .add(res.left_only)
-                        .remove(res.right_only)
-                        .none_if_empty()
-                })
-                .collect();
-            (left, Vec::new())
-        }
-        Side::Right => {
-            let right = iter
-                .into_iter()
-                .filter_map(|res| {
-                    Command::new(res.path)
-                        .remove(res.left_only)
-                        .add(res.right_only)
-                        .none_if_empty()
-                })
-                .collect();
-            (Vec::new(), right)
-        }
-        Side::Both => {
-            let mut right = Vec::new();
-            let mut left = Vec::new();
+    let mut right = Vec::new();
+    let mut left = Vec::new();
 
-            for res in iter {
+    for res in iter {
+        match res.keep {
+            Side::Left => push_some(
+                &mut right,
+                Command::new(res.path)
+                    .add(res.left_only)
+                    .remove(res.right_only)
+                    .none_if_empty(),
+            ),
+            Side::Both => {
                 push_some(
                     &mut right,
                     Command::new(res.path.clone())
@@ -96,10 +292,51 @@ where
                     Command::new(res.path).add(res.right_only).none_if_empty(),
                 );
             }
-
-            (left, right)
+            // The diff resolves these to left or right for every file.
+            Side::Right | Side::Newest | Side::Origin => push_some(
+                &mut left,
+                Command::new(res.path)
+                    .remove(res.left_only)
+                    .add(res.right_only)
+                    .none_if_empty(),
+            ),
         }
     }
+
+    (left, right)
+}
+
+/// Splits off removals of `pinned` tags, which are never removed from local files.
+/// Returns the remaining commands and the split off removals.
+#[must_use]
+pub fn split_pinned(commands: Vec<Command>, pinned: &[Tag]) -> (Vec<Command>, Vec<Command>) {
+    if pinned.is_empty() {
+        return (commands, Vec::new());
+    }
+    let mut kept = Vec::new();
+    let mut removals = Vec::new();
+    for Command { path, actions } in commands {
+        let (pinned, others): (Vec<_>, Vec<_>) = actions.into_iter().partition(|action| {
+            action.modification == Modification::Remove && pinned.contains(&action.tag)
+        });
+        push_some(
+            &mut removals,
+            Command {
+                path: path.clone(),
+                actions: pinned,
+            }
+            .none_if_empty(),
+        );
+        push_some(
+            &mut kept,
+            Command {
+                path,
+                actions: others,
+            }
+            .none_if_empty(),
+        );
+    }
+    (kept, removals)
 }
 
 fn push_some<T>(vec: &mut Vec<T>, item: Option<T>) {
@@ -108,41 +345,262 @@ fn push_some<T>(vec: &mut Vec<T>, item: Option<T>) {
     }
 }
 
-pub struct CommandsFormatter<'a>(pub &'a [Command]);
+/// Restricts commands to files below one of `paths` and actions on one of `tags`.
+/// Empty lists do not restrict anything.
+#[derive(Debug, Default, Clone)]
+pub struct CommandFilter {
+    /// Local or remote paths.
+    pub paths: Vec<PathBuf>,
+    pub tags: Vec<Tag>,
+}
+
+impl CommandFilter {
+    #[must_use]
+    pub fn apply(&self, commands: Vec<Command>, prefixes: &[PrefixMapping]) -> Vec<Command> {
+        commands
+            .into_iter()
+            .filter(|cmd| self.matches_path(&cmd.path, prefixes))
+            .filter_map(|mut cmd| {
+                if !self.tags.is_empty() {
+                    cmd.actions.retain(|action| self.tags.contains(&action.tag));
+                }
+                cmd.none_if_empty()
+            })
+            .collect()
+    }
+
+    fn matches_path(&self, path: &SyncedPath, prefixes: &[PrefixMapping]) -> bool {
+        if self.paths.is_empty() {
+            return true;
+        }
+        let local = path.local_file(prefixes);
+        let remote = path.remote_file(prefixes);
+        self.paths
+            .iter()
+            .any(|filter| local.starts_with(filter) || remote.starts_with(filter))
+    }
+}
+
+/// Options for printing commands.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FormatOptions {
+    /// Highlight added tags green and removed tags red.
+    pub color: bool,
+    /// One line per file with its full path instead of a tree.
+    pub compact: bool,
+    /// Maximum number of characters for the tag actions of a file.
+    pub max_width: Option<usize>,
+    pub sort_order: SortOrder,
+    /// Print counts instead of file trees and omit statistics.
+    pub quiet: bool,
+}
 
-impl<'a> std::fmt::Display for CommandsFormatter<'a> {
+impl FormatOptions {
+    /// Colors are enabled automatically when printing to a terminal unless configured.
+    #[cfg(feature = "sync")]
+    #[must_use]
+    pub fn from_config(config: &crate::Config) -> Self {
+        Self {
+            color: config
+                .color
+                .unwrap_or_else(|| atty::is(atty::Stream::Stdout)),
+            compact: config.compact_output,
+            max_width: config.output_width,
+            sort_order: config.sort_order,
+            quiet: config.quiet,
+        }
+    }
+}
+
+pub struct CommandsFormatter<'a>(pub &'a [Command], pub FormatOptions);
+
+impl std::fmt::Display for CommandsFormatter<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         if self.0.is_empty() {
             return Ok(());
         }
 
-        let printer: SyncedPathPrinter<_> = self
-            .0
-            .iter()
-            .map(|cmd| (&cmd.path, ActionsFormatter(&cmd.actions)))
-            .collect();
+        let options = self.1;
+        if options.compact {
+            let mut commands: Vec<_> = self.0.iter().collect();
+            commands.sort_unstable_by(|l, r| l.path.cmp(&r.path));
+            for cmd in commands {
+                writeln!(f, "{}{}", cmd.path, ActionsFormatter(&cmd.actions, options))?;
+            }
+            return Ok(());
+        }
+
+        let printer = SyncedPathPrinter::new(
+            self.0
+                .iter()
+                .map(|cmd| (&cmd.path, ActionsFormatter(&cmd.actions, options))),
+            options.sort_order,
+        );
         write!(f, "{printer}")?;
         Ok(())
     }
 }
 
 #[derive(Default)]
-struct ActionsFormatter<'a>(&'a [TagAction]);
+pub struct ActionsFormatter<'a>(pub &'a [TagAction], pub FormatOptions);
 
-impl<'a> std::fmt::Display for ActionsFormatter<'a> {
+impl std::fmt::Display for ActionsFormatter<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        const GREEN: &str = "\x1b[32m";
+        const RED: &str = "\x1b[31m";
+        const RESET: &str = "\x1b[0m";
+
         if self.0.is_empty() {
             return Ok(());
         }
 
+        let options = self.1;
         f.write_str(" ->")?;
-        for action in self.0 {
-            let sign = match action.modification {
-                Modification::Add => "+",
-                Modification::Remove => "-",
+        let mut width = 3;
+        for (i, action) in self.0.iter().enumerate() {
+            let (sign, color) = match action.modification {
+                Modification::Add => ("+", GREEN),
+                Modification::Remove => ("-", RED),
             };
-            write!(f, " {sign}{}", action.tag)?;
+            let action_width = 2 + action.tag.chars().count();
+            if let Some(max_width) = options.max_width {
+                let remaining = self.0.len() - i - 1;
+                let reserved = if remaining > 0 {
+                    format!(" …(+{remaining})").chars().count()
+                } else {
+                    0
+                };
+                if width + action_width + reserved > max_width {
+                    return write!(f, " …(+{})", remaining + 1);
+                }
+            }
+            width += action_width;
+
+            if options.color {
+                write!(f, " {color}{sign}{}{RESET}", action.tag)?;
+            } else {
+                write!(f, " {sign}{}", action.tag)?;
+            }
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_actions() {
+        let action = |tag: &str, modification| TagAction {
+            tag: tag.parse().unwrap(),
+            modification,
+        };
+        let actions = [
+            action("red", Modification::Add),
+            action("blue", Modification::Remove),
+            action("green", Modification::Add),
+        ];
+        let mut options = FormatOptions::default();
+        assert_eq!(
+            ActionsFormatter(&actions, options).to_string(),
+            " -> +red -blue +green"
+        );
+
+        options.max_width = Some(16);
+        assert_eq!(
+            ActionsFormatter(&actions, options).to_string(),
+            " -> +red …(+2)"
+        );
+
+        options.max_width = None;
+        options.color = true;
+        assert_eq!(
+            ActionsFormatter(&actions[..1], options).to_string(),
+            " -> \x1b[32m+red\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn build_commands() {
+        let path = SyncedPath::new(0, "photos/a.jpg");
+        let command = Command::for_path(path.clone())
+            .add("red")
+            .remove("blue")
+            .add("red")
+            .build()
+            .unwrap();
+        assert_eq!(
+            command.actions,
+            [
+                TagAction::add("red".parse().unwrap()),
+                TagAction::remove("blue".parse().unwrap())
+            ]
+        );
+
+        let build = |builder: CommandBuilder| builder.build().unwrap_err();
+        assert!(matches!(
+            build(Command::for_path(path.clone()).add("a/b")),
+            CommandError::InvalidTag { .. }
+        ));
+        assert!(matches!(
+            build(Command::for_path(path.clone()).add("red").remove("red")),
+            CommandError::ContradictingActions { .. }
+        ));
+        assert!(matches!(
+            build(Command::for_path(path)),
+            CommandError::NoActions { .. }
+        ));
+    }
+
+    #[test]
+    fn filter_commands() {
+        let prefixes =
+            vec![PrefixMapping::new("/local".into(), "/remote.php/dav/files/user".into()).unwrap()];
+        let command = |path: &str, tags: &[&str]| Command {
+            path: SyncedPath::new(0, path),
+            actions: tags
+                .iter()
+                .map(|tag| TagAction {
+                    tag: tag.parse().unwrap(),
+                    modification: Modification::Add,
+                })
+                .collect(),
+        };
+        let commands = vec![
+            command("photos/a.jpg", &["red", "blue"]),
+            command("photos/b.jpg", &["blue"]),
+            command("docs/c.txt", &["red"]),
+        ];
+        let filter = CommandFilter {
+            paths: vec!["/local/photos".into()],
+            tags: vec!["red".parse().unwrap()],
+        };
+
+        assert_eq!(
+            filter.apply(commands, &prefixes),
+            [command("photos/a.jpg", &["red"])]
+        );
+    }
+
+    #[test]
+    fn keep_pinned_tags() {
+        let path = SyncedPath::new(0, "a.jpg");
+        let command = Command::new(path.clone())
+            .add("new".parse().unwrap())
+            .remove("backup,old".parse().unwrap());
+        let pinned = ["backup".parse().unwrap()];
+
+        let (kept, removals) = split_pinned(vec![command], &pinned);
+        assert_eq!(
+            kept,
+            [Command::new(path.clone())
+                .add("new".parse().unwrap())
+                .remove("old".parse().unwrap())]
+        );
+        assert_eq!(
+            removals,
+            [Command::new(path).remove("backup".parse().unwrap())]
+        );
+    }
+}
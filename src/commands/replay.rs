@@ -0,0 +1,124 @@
+//! Replays the merge corpus in `test_data/merge_corpus`. Each case is a JSON file with
+//! the repositories of one synchronization, e.g. a minimized input which once made the
+//! merge engine diverge. The format is versioned so recorded cases stay readable:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "keep_side_on_conflict": "Both",
+//!   "prefixes": [{ "local": "/data", "remote": "/remote.php/dav/files/erik/data" }],
+//!   "newer_local": ["0:a.txt"],
+//!   "cached": null,
+//!   "local": { "0:a.txt": ["red"] },
+//!   "remote": { "0:a.txt": ["blue"] }
+//! }
+//! ```
+//!
+//! Without `cached`, the case is an initial synchronization, otherwise a follow-up one.
+//! `newer_local` lists the files whose local tags are newer for `Newest`.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
+
+use serde::Deserialize;
+
+use super::{resolve_diffs, Command};
+use crate::{PrefixMapping, Repository, Side, SyncedPath, Tags};
+
+const VERSION: u32 = 1;
+
+type Files = BTreeMap<SyncedPath, Tags>;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Case {
+    version: u32,
+    keep_side_on_conflict: Side,
+    prefixes: Vec<PrefixMapping>,
+    #[serde(default)]
+    newer_local: BTreeSet<SyncedPath>,
+    cached: Option<Files>,
+    local: Files,
+    remote: Files,
+}
+
+impl Case {
+    fn repository(&self, files: &Files) -> Repository {
+        let mut repo = Repository::new(self.prefixes.clone());
+        for (path, tags) in files {
+            repo.insert(path.clone(), tags.clone());
+        }
+        repo
+    }
+}
+
+fn apply(repo: &mut Repository, commands: Vec<Command>) {
+    for command in commands {
+        repo.apply(command);
+    }
+}
+
+/// Files with at least one tag, because applying commands may leave empty entries.
+fn tagged(repo: &Repository) -> Files {
+    repo.files()
+        .filter(|(_, tags)| !tags.is_empty())
+        .map(|(path, tags)| (path.clone(), tags.clone()))
+        .collect()
+}
+
+/// Synchronizes the case like the updater and returns the final local, remote and merged
+/// repositories.
+fn replay(case: &Case) -> [Repository; 3] {
+    let mut local = case.repository(&case.local);
+    let mut remote = case.repository(&case.remote);
+
+    let Some(cached) = &case.cached else {
+        let side = case.keep_side_on_conflict;
+        let mut diff_events = local
+            .clone()
+            .diff(remote.clone(), side)
+            .with_newer_left(case.newer_local.clone());
+        let (local_actions, remote_actions) = resolve_diffs(&mut diff_events);
+        apply(&mut local, local_actions);
+        apply(&mut remote, remote_actions);
+        return [local, remote, diff_events.finish()];
+    };
+
+    let mut diff_events = case.repository(cached).diff(local.clone(), Side::Right);
+    let (remote_actions, _) = resolve_diffs(&mut diff_events);
+    apply(&mut remote, remote_actions);
+
+    let mut diff_events = diff_events.finish().diff(remote.clone(), Side::Right);
+    let (local_actions, _) = resolve_diffs(&mut diff_events);
+    apply(&mut local, local_actions);
+    [local, remote, diff_events.finish()]
+}
+
+#[test]
+fn replay_merge_corpus() {
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/merge_corpus");
+    let mut replayed = 0;
+    for entry in std::fs::read_dir(corpus).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|extension| extension != "json") {
+            continue;
+        }
+        let name = path.display();
+        let data = std::fs::read_to_string(&path).unwrap();
+        let case: Case =
+            serde_json::from_str(&data).unwrap_or_else(|e| panic!("invalid case {name}: {e}"));
+        assert_eq!(case.version, VERSION, "unsupported version of {name}");
+
+        let [local, remote, merged] = replay(&case);
+        assert_eq!(tagged(&local), tagged(&merged), "local diverged in {name}");
+        assert_eq!(
+            tagged(&remote),
+            tagged(&merged),
+            "remote diverged in {name}"
+        );
+        replayed += 1;
+    }
+    assert!(replayed > 0, "merge corpus is empty");
+}
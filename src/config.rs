@@ -1,37 +1,239 @@
-use std::path::PathBuf;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    net::IpAddr,
+    path::{Component, Path, PathBuf},
+};
 
 use figment::{
     providers::{Env, Format, Serialized, Toml},
     Figment,
 };
 use serde::{Deserialize, Serialize};
+use snafu::Snafu;
 use url::Url;
 
-use crate::{tag_repository::Side, take_last_n_chars, PrefixMapping};
+use crate::{
+    helper::{closest, expand_path},
+    tag_repository::{validate_prefixes, Side},
+    take_last_n_chars, CounterpartPolicy, OrphanPolicy, PrefixMapping, ScanWindow, SortOrder,
+    SystemTagPolicy, Tag,
+};
 
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
+#[expect(
+    clippy::struct_excessive_bools,
+    reason = "Independent configuration flags"
+)]
 pub struct Config {
-    pub max_concurrent_requests: usize,
+    /// Maximum number of concurrent requests while reading from Nextcloud.
+    pub scan_concurrency: usize,
+    /// Maximum number of concurrent requests which modify tags. Kept lower than
+    /// `scan_concurrency` because tag writes contend for database locks on the server.
+    pub write_concurrency: usize,
+    /// Number of threads per prefix reading the tags of local files. Reading them one
+    /// file after another dominates the scan of large trees on fast disks.
+    pub local_scan_threads: usize,
+    /// Remember the local files of a scan by their inode, so later scans only read the
+    /// attributes of untagged files which changed since and recognize renamed files.
+    /// Stored next to the tag database.
+    pub cache_untagged_files: bool,
+    /// Profile for slow devices, e.g. a NAS or Raspberry Pi. Lowers the defaults of the
+    /// concurrency settings, disables `cache_untagged_files` and does not keep the ids of
+    /// remote files between synchronizations. Values set explicitly take precedence over
+    /// the profile. Memory usage still grows with the number of tagged files.
+    pub reduced_concurrency: bool,
+    /// Seconds the `watch` command waits for further local changes before it uploads
+    /// them, because writing tags usually changes several files in a row.
+    pub watch_debounce_secs: u64,
+    /// Local times of day in which the `watch` command synchronizes everything, e.g.
+    /// `["01:00-06:00"]`, so scans do not compete with daytime usage. Local changes are
+    /// uploaded at any time. Empty allows full synchronizations at any time.
+    pub full_scan_windows: Vec<ScanWindow>,
+    /// Commands are applied in chunks of this size with the tag database persisted after
+    /// each chunk. 0 disables chunking.
+    pub command_chunk_size: usize,
+    /// Stop applying commands after this many seconds, e.g. so cron jobs do not overlap.
+    /// The remaining commands are journaled and applied by the next run.
+    pub max_duration_secs: Option<u64>,
+    /// Abort scanning or updating a side after this many seconds without progress, e.g.
+    /// when the server stops responding in the middle of a request. Responses of the
+    /// server and finished phases count as progress.
+    pub stall_timeout_secs: Option<u64>,
     pub keep_side_on_conflict: Side,
     pub prefixes: Vec<PrefixMapping>,
     pub nextcloud_instance: Url,
+    /// Absolute path of the DAV endpoint, e.g. `/index.php/dav/` behind path-rewriting
+    /// proxies. Remote prefixes must be in its `files` collection. Both are placed below
+    /// the subdirectory of `nextcloud_instance` if Nextcloud is served from one.
+    pub dav_root: String,
+    /// Connect to this address instead of resolving the host of `nextcloud_instance`,
+    /// e.g. to bypass split-horizon DNS.
+    pub resolve: Option<IpAddr>,
     pub user: String,
     pub token: String,
     pub local_tag_property_name: String,
+    /// Extended attribute which records when the tags of a local file were last changed,
+    /// e.g. `user.ncts.tagtime`. Required for `keep_side_on_conflict = "Newest"`, also if
+    /// only a prefix sets it.
+    pub tag_time_property_name: Option<String>,
+    /// Report local files whose tags were not changed for this many days. Requires
+    /// `tag_time_property_name`.
+    pub stale_after_days: Option<u64>,
     pub tag_database: std::path::PathBuf,
+    /// Directory for backups of the previous tags of files before a synchronization
+    /// removes some of them. Restore one with `restore-backup`.
+    pub tag_backup_directory: std::path::PathBuf,
+    /// Value of the `User-Agent` header sent with every request.
+    pub user_agent: String,
+    /// Additional headers sent with every request, e.g. access tokens for a front proxy.
+    pub extra_headers: BTreeMap<String, String>,
+    /// Match remote paths case-insensitively, e.g. for case-insensitive external storages.
+    pub case_insensitive_remote: bool,
+    /// Percentage of commands which are artificially failed instead of executed. Used to
+    /// validate the recovery from failed commands. 0 disables the simulation.
+    pub simulate_failures: f64,
+    /// An initial synchronization changing more files than this is only previewed until it
+    /// is confirmed, because a wrong `keep_side_on_conflict` cannot be undone.
+    pub initial_sync_confirmation_threshold: Option<usize>,
+    /// Run an initial synchronization exceeding `initial_sync_confirmation_threshold`.
+    pub confirm_initial_sync: bool,
+    /// Only print the changes `sync` would apply instead of applying them.
+    pub dry_run: bool,
+    /// Additionally write the changes of a dry run as JSON to this file.
+    pub dry_run_report: Option<PathBuf>,
+    /// Fail the run if any command fails instead of synchronizing on a best-effort basis.
+    /// The tag database is then not updated at the end of the run.
+    pub strict: bool,
+    /// Additionally store the tag database as binary snapshot which loads faster than JSON.
+    pub binary_snapshot: bool,
+    /// Order of file names in printed trees and reports.
+    pub sort_order: SortOrder,
+    /// Colorize tag changes. Enabled when printing to a terminal if unset.
+    pub color: Option<bool>,
+    /// Print one line per file instead of a tree.
+    pub compact_output: bool,
+    /// Truncate the tag changes of a file to this many characters.
+    pub output_width: Option<usize>,
+    /// Only print problems: no file trees, no statistics and nothing after a clean run.
+    pub quiet: bool,
+    /// Language of messages, e.g. `de`. Derived from `LANG` if unset.
+    pub language: Option<String>,
+    /// Warn if the clock of the server deviates by more than this many seconds. 0 disables
+    /// the check.
+    pub max_clock_skew_secs: u64,
+    /// Take a DAV lock on each remote file while changing its tags. Files locked by other
+    /// clients are skipped and retried in the next synchronization.
+    pub lock_remote_files: bool,
+    /// Remember tags the server refused to assign (e.g. restricted to a group) and skip
+    /// them in later synchronizations instead of sending requests bound to fail.
+    pub skip_forbidden_tags: bool,
+    /// How tags are handled which the user cannot assign, e.g. restricted tags or tags
+    /// used internally for collections.
+    pub system_tags: SystemTagPolicy,
+    /// Tags which are never removed from local files, e.g. markers of backup tools.
+    /// Removing them on Nextcloud does not remove them locally.
+    pub pinned_local_tags: Vec<Tag>,
+    /// Groups of tags of which a file may carry at most one, e.g. the states of a
+    /// workflow. Files violating a group are reported and not synchronized.
+    pub exclusive_tags: Vec<Vec<Tag>>,
+    /// Files and folders carrying this tag on Nextcloud, e.g. `ncts-skip`, are not
+    /// synchronized in either direction until it is removed again.
+    pub skip_tag: Option<Tag>,
+    /// Comment on Nextcloud files which tags the initial synchronization dropped to
+    /// resolve a conflict, so they remain discoverable in the web UI.
+    pub comment_conflicts: bool,
+    /// Stop synchronizing a file once one of its tags was added and removed again this
+    /// many times within a day, e.g. because another tool rewrites it. Release such files
+    /// with `db release-loops`. `None` disables the detection.
+    pub loop_flips_per_day: Option<usize>,
+    /// Take the file ids from the database of the Nextcloud desktop client (`.sync_*.db`)
+    /// if it synchronizes a prefix instead of looking them up on the server.
+    pub desktop_client_file_ids: bool,
+    /// What happens to the tags of local files which were deleted on Nextcloud.
+    pub orphaned_files: OrphanPolicy,
+    /// Rhai script whose `on_command` function inspects every command before it is
+    /// applied and keeps, rewrites or vetoes it, e.g. to never remove certain tags.
+    pub hook_script: Option<PathBuf>,
+    /// Other Nextcloud users synchronized by this process, e.g. by an admin for the
+    /// members of a family. Each keeps its tag database and state files in a directory
+    /// named after the user next to `tag_database`, and its backups in a subdirectory of
+    /// `tag_backup_directory`.
+    pub users: Vec<UserSync>,
+}
+
+/// Nextcloud user synchronized on its behalf with its own prefixes.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UserSync {
+    pub user: String,
+    /// App password of the user, e.g. created by an admin with `occ user:add-app-password`.
+    /// Nextcloud does not let admins access the files of other users over DAV.
+    pub token: String,
+    pub prefixes: Vec<PrefixMapping>,
 }
 
 impl std::fmt::Debug for Config {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         f.debug_struct("Config")
-            .field("max_concurrent_requests", &self.max_concurrent_requests)
+            .field("scan_concurrency", &self.scan_concurrency)
+            .field("write_concurrency", &self.write_concurrency)
+            .field("local_scan_threads", &self.local_scan_threads)
+            .field("cache_untagged_files", &self.cache_untagged_files)
+            .field("reduced_concurrency", &self.reduced_concurrency)
+            .field("watch_debounce_secs", &self.watch_debounce_secs)
+            .field("full_scan_windows", &self.full_scan_windows)
+            .field("command_chunk_size", &self.command_chunk_size)
+            .field("max_duration_secs", &self.max_duration_secs)
+            .field("stall_timeout_secs", &self.stall_timeout_secs)
             .field("keep_side_on_conflict", &self.keep_side_on_conflict)
             .field("prefixes", &self.prefixes)
             .field("nextcloud_instance", &self.nextcloud_instance)
+            .field("dav_root", &self.dav_root)
+            .field("resolve", &self.resolve)
             .field("user", &self.user)
             .field("token", &"EXPUNGED")
             .field("local_tag_property_name", &self.local_tag_property_name)
+            .field("tag_time_property_name", &self.tag_time_property_name)
+            .field("stale_after_days", &self.stale_after_days)
             .field("tag_database", &self.tag_database)
+            .field("tag_backup_directory", &self.tag_backup_directory)
+            .field("user_agent", &self.user_agent)
+            .field(
+                "extra_headers",
+                &self.extra_headers.keys().collect::<Vec<_>>(),
+            )
+            .field("case_insensitive_remote", &self.case_insensitive_remote)
+            .field("simulate_failures", &self.simulate_failures)
+            .field(
+                "initial_sync_confirmation_threshold",
+                &self.initial_sync_confirmation_threshold,
+            )
+            .field("confirm_initial_sync", &self.confirm_initial_sync)
+            .field("dry_run", &self.dry_run)
+            .field("dry_run_report", &self.dry_run_report)
+            .field("strict", &self.strict)
+            .field("binary_snapshot", &self.binary_snapshot)
+            .field("sort_order", &self.sort_order)
+            .field("color", &self.color)
+            .field("compact_output", &self.compact_output)
+            .field("output_width", &self.output_width)
+            .field("quiet", &self.quiet)
+            .field("language", &self.language)
+            .field("max_clock_skew_secs", &self.max_clock_skew_secs)
+            .field("lock_remote_files", &self.lock_remote_files)
+            .field("skip_forbidden_tags", &self.skip_forbidden_tags)
+            .field("system_tags", &self.system_tags)
+            .field("pinned_local_tags", &self.pinned_local_tags)
+            .field("exclusive_tags", &self.exclusive_tags)
+            .field("skip_tag", &self.skip_tag)
+            .field("comment_conflicts", &self.comment_conflicts)
+            .field("loop_flips_per_day", &self.loop_flips_per_day)
+            .field("desktop_client_file_ids", &self.desktop_client_file_ids)
+            .field("orphaned_files", &self.orphaned_files)
+            .field("hook_script", &self.hook_script)
+            .field(
+                "users",
+                &self.users.iter().map(|u| &u.user).collect::<Vec<_>>(),
+            )
             .finish()
     }
 }
@@ -41,47 +243,323 @@ impl std::fmt::Display for Config {
         writeln!(f, "Configuration:")?;
         writeln!(
             f,
-            "Maximum concurrent requests: {}",
-            self.max_concurrent_requests
+            "Maximum concurrent requests: {} for scanning, {} for writing",
+            self.scan_concurrency, self.write_concurrency
         )?;
+        writeln!(f, "Threads reading local tags: {}", self.local_scan_threads)?;
+        if self.reduced_concurrency {
+            writeln!(f, "Reduced concurrency profile")?;
+        }
+        if !self.full_scan_windows.is_empty() {
+            let windows: Vec<_> = self
+                .full_scan_windows
+                .iter()
+                .map(ToString::to_string)
+                .collect();
+            writeln!(f, "Full scan windows: {}", windows.join(", "))?;
+        }
         writeln!(
             f,
             "Keep these tags if tags mismatch: {:?}",
             self.keep_side_on_conflict
         )?;
         writeln!(f, "Tag database: {}", self.tag_database.display())?;
+        writeln!(f, "Tag backups: {}", self.tag_backup_directory.display())?;
+        if self.binary_snapshot {
+            writeln!(f, "Binary snapshot: enabled")?;
+        }
+        if self.strict {
+            writeln!(f, "Strict mode: enabled")?;
+        }
         writeln!(f, "Nextcloud instance: {}", self.nextcloud_instance)?;
+        if self.dav_root != PrefixMapping::DEFAULT_DAV_ROOT {
+            writeln!(f, "DAV root: {}", self.dav_root)?;
+        }
+        if let Some(address) = self.resolve {
+            writeln!(f, "Resolve Nextcloud instance to: {address}")?;
+        }
         writeln!(f, "Nextcloud user: {}", self.user)?;
         writeln!(
             f,
             "Nextcloud token: ...{}",
             take_last_n_chars(&self.token, 3)
         )?;
+        writeln!(f, "User agent: {}", self.user_agent)?;
+        if !self.extra_headers.is_empty() {
+            let names: Vec<_> = self.extra_headers.keys().map(String::as_str).collect();
+            writeln!(f, "Extra headers: {}", names.join(", "))?;
+        }
+        if self.case_insensitive_remote {
+            writeln!(f, "Remote paths are case-insensitive")?;
+        }
+        if self.simulate_failures > 0.0 {
+            writeln!(
+                f,
+                "Simulating failure of {}% of commands",
+                self.simulate_failures
+            )?;
+        }
+        if let Some(secs) = self.max_duration_secs {
+            writeln!(f, "Maximum duration: {secs}s")?;
+        }
+        if let Some(secs) = self.stall_timeout_secs {
+            writeln!(f, "Abort phases without progress for {secs}s")?;
+        }
+        if !self.pinned_local_tags.is_empty() {
+            let tags: Vec<_> = self
+                .pinned_local_tags
+                .iter()
+                .map(ToString::to_string)
+                .collect();
+            writeln!(f, "Never removed locally: {}", tags.join(", "))?;
+        }
+        for group in &self.exclusive_tags {
+            let tags: Vec<_> = group.iter().map(ToString::to_string).collect();
+            writeln!(f, "Mutually exclusive: {}", tags.join(", "))?;
+        }
+        if let Some(tag) = &self.skip_tag {
+            writeln!(f, "Skipped when tagged on Nextcloud: {tag}")?;
+        }
+        if let Some(script) = &self.hook_script {
+            writeln!(f, "Hook script: {}", script.display())?;
+        }
         writeln!(f, "Mapped prefixes:")?;
         for prefix in &self.prefixes {
-            writeln!(f, "Local:  {}", prefix.local().display())?;
-            writeln!(f, "Remote: {}", prefix.remote().display())?;
-            writeln!(f)?;
+            write_prefix(f, prefix)?;
+        }
+        if !self.users.is_empty() {
+            let users: Vec<_> = self.users.iter().map(|u| u.user.as_str()).collect();
+            writeln!(f, "Synchronized users: {}", users.join(", "))?;
         }
         Ok(())
     }
 }
 
+fn write_prefix(f: &mut std::fmt::Formatter, prefix: &PrefixMapping) -> std::fmt::Result {
+    writeln!(f, "Local:  {}", prefix.local().display())?;
+    writeln!(f, "Remote: {}", prefix.remote().display())?;
+    if let Some(user) = prefix.user() {
+        writeln!(f, "Owner:  {user}")?;
+    }
+    if prefix.missing_counterpart() != CounterpartPolicy::Wait {
+        writeln!(f, "Missing counterpart: {:?}", prefix.missing_counterpart())?;
+    }
+    if let Some(side) = prefix.keep_side_on_conflict() {
+        writeln!(f, "Keep on conflict: {side:?}")?;
+    }
+    writeln!(f)
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
-            max_concurrent_requests: 10,
+            scan_concurrency: 10,
+            write_concurrency: 4,
+            local_scan_threads: 4,
+            cache_untagged_files: true,
+            reduced_concurrency: false,
+            watch_debounce_secs: 2,
+            full_scan_windows: Vec::new(),
+            command_chunk_size: 1000,
+            max_duration_secs: None,
+            stall_timeout_secs: None,
             prefixes: Vec::default(),
             keep_side_on_conflict: Side::Both,
             nextcloud_instance: "https://missing_nextcloud_instance"
                 .try_into()
                 .expect("failed to create default url"),
+            dav_root: PrefixMapping::DEFAULT_DAV_ROOT.to_owned(),
+            resolve: None,
             user: "missing_username".to_owned(),
             token: "missing_token".to_owned(),
             local_tag_property_name: "user.xdg.tags".to_owned(),
+            tag_time_property_name: None,
+            stale_after_days: None,
             tag_database: PathBuf::from("nextcloud-tag-sync.db.json"),
+            tag_backup_directory: PathBuf::from("nextcloud-tag-sync-backups"),
+            user_agent: concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")).to_owned(),
+            extra_headers: BTreeMap::new(),
+            case_insensitive_remote: false,
+            simulate_failures: 0.0,
+            initial_sync_confirmation_threshold: Some(1000),
+            confirm_initial_sync: false,
+            dry_run: false,
+            dry_run_report: None,
+            strict: false,
+            binary_snapshot: false,
+            sort_order: SortOrder::Bytes,
+            color: None,
+            compact_output: false,
+            output_width: None,
+            quiet: false,
+            language: None,
+            max_clock_skew_secs: 60,
+            lock_remote_files: false,
+            skip_forbidden_tags: false,
+            system_tags: SystemTagPolicy::default(),
+            pinned_local_tags: Vec::new(),
+            exclusive_tags: Vec::new(),
+            skip_tag: None,
+            comment_conflicts: false,
+            loop_flips_per_day: Some(6),
+            desktop_client_file_ids: false,
+            orphaned_files: OrphanPolicy::default(),
+            hook_script: None,
+            users: Vec::new(),
+        }
+    }
+}
+
+/// Configuration that cannot be loaded.
+#[derive(Debug, Snafu)]
+pub enum ConfigError {
+    #[snafu(display(
+        "invalid value for `{key}`{}: {message}{}",
+        origin.as_ref().map(|o| format!(" in {o}")).unwrap_or_default(),
+        did_you_mean(suggestion.as_deref())
+    ))]
+    InvalidValue {
+        key: String,
+        message: String,
+        /// Where the value was set, e.g. the TOML file.
+        origin: Option<String>,
+        suggestion: Option<String>,
+    },
+    #[snafu(display("unknown configuration key `{key}`{}", did_you_mean(Some(suggestion))))]
+    UnknownKey { key: String, suggestion: String },
+    #[snafu(display("failed to load configuration: {message}"))]
+    Load { message: String },
+}
+
+fn did_you_mean(suggestion: Option<&str>) -> String {
+    suggestion
+        .map(|s| format!(", did you mean `{s}`?"))
+        .unwrap_or_default()
+}
+
+impl From<figment::Error> for ConfigError {
+    fn from(error: figment::Error) -> Self {
+        let error = error.into_iter().next().expect("at least one error");
+        let origin = error.metadata.as_ref().map(|metadata| {
+            metadata
+                .source
+                .as_ref()
+                .map_or_else(|| metadata.name.to_string(), ToString::to_string)
+        });
+        let (message, suggestion) = match &error.kind {
+            figment::error::Kind::UnknownVariant(found, allowed) => (
+                format!(
+                    "found `{found}`, expected one of `{}`",
+                    allowed.join("`, `")
+                ),
+                closest(found, allowed.iter().copied()).map(str::to_owned),
+            ),
+            kind => (kind.to_string(), None),
+        };
+        if error.path.is_empty() {
+            return Self::Load { message };
+        }
+        Self::InvalidValue {
+            key: error.path.join("."),
+            message,
+            origin,
+            suggestion,
+        }
+    }
+}
+
+fn invalid(key: &str, message: impl Into<String>) -> ConfigError {
+    ConfigError::InvalidValue {
+        key: key.to_owned(),
+        message: message.into(),
+        origin: None,
+        suggestion: None,
+    }
+}
+
+/// Fails for unknown top-level keys which are a near miss of a known key, e.g. typos.
+/// Other unknown keys are only logged because the environment may contain unrelated
+/// variables with the same prefix.
+fn check_unknown_keys(figment: &Figment) -> Result<(), ConfigError> {
+    let known = serde_json::to_value(Config::default()).expect("config is serializable");
+    let known: Vec<&str> = known
+        .as_object()
+        .expect("config is a struct")
+        .keys()
+        .map(String::as_str)
+        .collect();
+    let present: figment::value::Dict = figment.extract()?;
+    for key in present.keys().filter(|key| !known.contains(&key.as_str())) {
+        let Some(suggestion) = closest(key, known.iter().copied()) else {
+            tracing::warn!("Ignoring unknown configuration key {key}");
+            continue;
+        };
+        return UnknownKeySnafu {
+            key: key.clone(),
+            suggestion,
         }
+        .fail();
     }
+    Ok(())
+}
+
+impl Config {
+    /// Configuration of `user` with its own tag database and backups. The database is
+    /// placed in a directory named after the user, so the state files next to it do not
+    /// collide with those of other users.
+    #[must_use]
+    pub fn for_user(&self, user: &UserSync) -> Self {
+        let database_dir = self.tag_database.parent().unwrap_or_else(|| Path::new(""));
+        let database_name = self.tag_database.file_name().unwrap_or_default();
+        Self {
+            user: user.user.clone(),
+            token: user.token.clone(),
+            prefixes: user.prefixes.clone(),
+            tag_database: database_dir.join(&user.user).join(database_name),
+            tag_backup_directory: self.tag_backup_directory.join(&user.user),
+            users: Vec::new(),
+            ..self.clone()
+        }
+    }
+
+    /// Configurations of all synchronized users. The configured user is left out if it
+    /// only synchronizes other users.
+    #[must_use]
+    pub fn user_configs(&self) -> Vec<Self> {
+        let own = (self.users.is_empty() || !self.prefixes.is_empty()).then(|| Self {
+            users: Vec::new(),
+            ..self.clone()
+        });
+        own.into_iter()
+            .chain(self.users.iter().map(|user| self.for_user(user)))
+            .collect()
+    }
+
+    /// Defaults of the [`Config::reduced_concurrency`] profile.
+    #[must_use]
+    pub fn reduced_concurrency() -> Self {
+        Self {
+            scan_concurrency: 2,
+            write_concurrency: 1,
+            local_scan_threads: 1,
+            cache_untagged_files: false,
+            reduced_concurrency: true,
+            ..Self::default()
+        }
+    }
+}
+
+/// Extracts the configuration from `providers` merged over the defaults, which are those
+/// of the [`Config::reduced_concurrency`] profile if it is enabled.
+fn extract(providers: impl Fn(Figment) -> Figment) -> Result<Config, ConfigError> {
+    let figment = providers(Figment::from(Serialized::defaults(Config::default())));
+    check_unknown_keys(&figment)?;
+    let config: Config = figment.extract()?;
+    if !config.reduced_concurrency {
+        return Ok(config);
+    }
+    let defaults = Figment::from(Serialized::defaults(Config::reduced_concurrency()));
+    Ok(providers(defaults).extract()?)
 }
 
 /// Load the configuration from environment variables, config.toml or compile time defaults.
@@ -90,9 +568,290 @@ impl Default for Config {
 ///
 /// This function will return an error if configuration loading encounters invalid values or
 /// fails to load the configuration files.
-pub fn load_config() -> Result<Config, figment::Error> {
-    Figment::from(Serialized::defaults(Config::default()))
-        .merge(Toml::file("config.toml"))
-        .merge(Env::prefixed("NCTS_"))
+pub fn load_config() -> Result<Config, ConfigError> {
+    let mut config = extract(|figment| {
+        figment
+            .merge(Toml::file("config.toml"))
+            .merge(Env::prefixed("NCTS_"))
+    })?;
+    expand_paths(&mut config)?;
+    apply_instance_path(&mut config);
+    validate_prefixes(&config.prefixes).map_err(|e| invalid("prefixes", e.to_string()))?;
+    validate_dav_root(&config).map_err(|e| invalid("dav_root", e))?;
+    validate_users(&config)?;
+    if !(0.0..=100.0).contains(&config.simulate_failures) {
+        return Err(invalid(
+            "simulate_failures",
+            "must be a percentage between 0 and 100",
+        ));
+    }
+    if config.local_scan_threads == 0 {
+        return Err(invalid("local_scan_threads", "must be at least 1"));
+    }
+    if config.stall_timeout_secs == Some(0) {
+        return Err(invalid("stall_timeout_secs", "must be at least 1"));
+    }
+    let newest = std::iter::once(config.keep_side_on_conflict)
+        .chain(
+            config
+                .prefixes
+                .iter()
+                .chain(config.users.iter().flat_map(|user| &user.prefixes))
+                .filter_map(PrefixMapping::keep_side_on_conflict),
+        )
+        .any(|side| side == Side::Newest);
+    if newest && config.tag_time_property_name.is_none() {
+        return Err(invalid(
+            "keep_side_on_conflict",
+            "\"Newest\" requires tag_time_property_name",
+        ));
+    }
+    Ok(config)
+}
+
+/// Expands `~` and environment variables in local paths, so configs can be shared
+/// across machines.
+fn expand_paths(config: &mut Config) -> Result<(), ConfigError> {
+    let unset = |name: String| format!("environment variable {name} is not set");
+    config.tag_database = expand_path(&config.tag_database, |name| std::env::var(name).ok())
+        .map_err(|name| invalid("tag_database", unset(name)))?;
+    config.tag_backup_directory = expand_path(&config.tag_backup_directory, |name| {
+        std::env::var(name).ok()
+    })
+    .map_err(|name| invalid("tag_backup_directory", unset(name)))?;
+    if let Some(report) = &mut config.dry_run_report {
+        *report = expand_path(report, |name| std::env::var(name).ok())
+            .map_err(|name| invalid("dry_run_report", unset(name)))?;
+    }
+    if let Some(script) = &mut config.hook_script {
+        *script = expand_path(script, |name| std::env::var(name).ok())
+            .map_err(|name| invalid("hook_script", unset(name)))?;
+    }
+    for prefix in all_prefixes(config) {
+        prefix
+            .expand_local()
+            .map_err(|name| invalid("prefixes", unset(name)))?;
+    }
+    Ok(())
+}
+
+/// Moves the DAV root and the remote prefixes below the subdirectory Nextcloud is served
+/// from, e.g. `/nextcloud` of `https://example.com/nextcloud/`, so they match the hrefs
+/// in responses of the server. Paths which already include it are kept.
+fn apply_instance_path(config: &mut Config) {
+    let subdirectory = config.nextcloud_instance.path().trim_end_matches('/');
+    if subdirectory.is_empty() {
+        return;
+    }
+    let subdirectory = PathBuf::from(subdirectory);
+    if config.dav_root.starts_with('/') && !Path::new(&config.dav_root).starts_with(&subdirectory) {
+        config.dav_root = format!("{}{}", subdirectory.display(), config.dav_root);
+    }
+    for prefix in all_prefixes(config) {
+        prefix.place_below(&subdirectory);
+    }
+}
+
+/// Prefixes of the configured user and of all synchronized users.
+fn all_prefixes(config: &mut Config) -> impl Iterator<Item = &mut PrefixMapping> {
+    config.prefixes.iter_mut().chain(
+        config
+            .users
+            .iter_mut()
+            .flat_map(|user| user.prefixes.iter_mut()),
+    )
+}
+
+/// Ensures that every user is synchronized once and with valid prefixes.
+fn validate_users(config: &Config) -> Result<(), ConfigError> {
+    let mut names = BTreeSet::from([config.user.as_str()]);
+    for user in &config.users {
+        // The name becomes a directory of the state files and backups of the user.
+        let mut components = Path::new(&user.user).components();
+        if !matches!(
+            (components.next(), components.next()),
+            (Some(Component::Normal(_)), None)
+        ) || user.user.contains(['/', '\\'])
+        {
+            return Err(invalid(
+                "users",
+                format!("user name {:?} is not a valid directory name", user.user),
+            ));
+        }
+        if !names.insert(&user.user) {
+            return Err(invalid(
+                "users",
+                format!("user {} is synchronized more than once", user.user),
+            ));
+        }
+        let user_config = config.for_user(user);
+        validate_prefixes(&user_config.prefixes)
+            .map_err(|e| e.to_string())
+            .and_then(|()| validate_dav_root(&user_config))
+            .map_err(|e| invalid("users", format!("{}: {e}", user.user)))?;
+    }
+    Ok(())
+}
+
+/// Ensures that every remote prefix is served by the configured DAV endpoint.
+fn validate_dav_root(config: &Config) -> Result<(), String> {
+    if !config.dav_root.starts_with('/') {
+        return Err(format!(
+            "dav_root must be an absolute path, got {}",
+            config.dav_root
+        ));
+    }
+    if let Some(prefix) = config
+        .prefixes
+        .iter()
+        .find(|prefix| !prefix.is_below(&config.dav_root))
+    {
+        return Err(format!(
+            "remote prefix {} is not in the files collection of the DAV root {}",
+            prefix.remote().display(),
+            config.dav_root
+        ));
+    }
+    if let Some(prefix) = config
+        .prefixes
+        .iter()
+        .find(|prefix| prefix.user_folder(&config.dav_root).is_none())
+    {
+        return Err(format!(
+            "remote prefix {} is not in the files of user {}",
+            prefix.remote().display(),
+            prefix.user().unwrap_or_default()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn figment(toml: &str) -> Figment {
+        Figment::from(Serialized::defaults(Config::default())).merge(Toml::string(toml))
+    }
+
+    #[test]
+    fn suggest_fixes() {
+        let error = ConfigError::from(
+            figment("keep_side_on_conflict = \"Bth\"")
+                .extract::<Config>()
+                .unwrap_err(),
+        );
+        let message = error.to_string();
+        assert!(message.starts_with("invalid value for `keep_side_on_conflict`"));
+        assert!(message.contains("`Left`, `Right`, `Both`, `Newest`, `Origin`"));
+        assert!(message.ends_with("did you mean `Both`?"), "{message}");
+
+        let error = check_unknown_keys(&figment("keep_side_on_conflit = \"Both\"")).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "unknown configuration key `keep_side_on_conflit`, did you mean `keep_side_on_conflict`?"
+        );
+        assert!(check_unknown_keys(&figment("unrelated = 1")).is_ok());
+    }
+
+    #[test]
+    fn apply_reduced_concurrency_profile() {
+        let config =
+            extract(|figment| figment.merge(Toml::string("write_concurrency = 3"))).unwrap();
+        assert_eq!(config.scan_concurrency, Config::default().scan_concurrency);
+
+        let config = extract(|figment| {
+            figment.merge(Toml::string(
+                "reduced_concurrency = true\nwrite_concurrency = 3",
+            ))
+        })
+        .unwrap();
+        assert_eq!(config.scan_concurrency, 2);
+        assert_eq!(config.write_concurrency, 3);
+        assert!(!config.cache_untagged_files);
+    }
+
+    #[test]
+    fn place_paths_below_instance_subdirectory() {
+        let mut config: Config = figment(
+            r#"
+            nextcloud_instance = "https://example.com/nextcloud/"
+            prefixes = [
+                { local = "/a", remote = "/remote.php/dav/files/user/a" },
+                { local = "/b", remote = "/nextcloud/remote.php/dav/files/user/b" },
+            ]
+            "#,
+        )
         .extract()
+        .unwrap();
+        apply_instance_path(&mut config);
+        assert_eq!(config.dav_root, "/nextcloud/remote.php/dav/");
+        let remotes: Vec<_> = config.prefixes.iter().map(PrefixMapping::remote).collect();
+        assert_eq!(
+            remotes,
+            [
+                Path::new("/nextcloud/remote.php/dav/files/user/a"),
+                Path::new("/nextcloud/remote.php/dav/files/user/b")
+            ]
+        );
+        assert_eq!(validate_dav_root(&config), Ok(()));
+        apply_instance_path(&mut config);
+        assert_eq!(config.dav_root, "/nextcloud/remote.php/dav/");
+    }
+
+    #[test]
+    fn policy_per_prefix() {
+        let config: Config = figment(
+            r#"
+            prefixes = [
+                { local = "/a", remote = "/remote.php/dav/files/user/a" },
+                { local = "/b", remote = "/remote.php/dav/files/user/b", missing_counterpart = "mirror-when-appears" },
+            ]
+            "#,
+        )
+        .extract()
+        .unwrap();
+        let policies: Vec<_> = config
+            .prefixes
+            .iter()
+            .map(PrefixMapping::missing_counterpart)
+            .collect();
+        assert_eq!(
+            policies,
+            [
+                CounterpartPolicy::Wait,
+                CounterpartPolicy::MirrorWhenAppears
+            ]
+        );
+    }
+
+    #[test]
+    fn separate_state_per_user() {
+        let config: Config = figment(
+            r#"
+            user = "admin"
+            tag_database = "/state/db.json"
+            users = [
+                { user = "alice", token = "secret", prefixes = [{ local = "/alice", remote = "/remote.php/dav/files/alice" }] },
+            ]
+            "#,
+        )
+        .extract()
+        .unwrap();
+        validate_users(&config).unwrap();
+        let configs = config.user_configs();
+        assert_eq!(configs.len(), 1);
+        assert_eq!(configs[0].user, "alice");
+        assert_eq!(configs[0].tag_database, Path::new("/state/alice/db.json"));
+
+        let mut twice = config.clone();
+        twice.users.push(config.users[0].clone());
+        assert!(validate_users(&twice).is_err());
+
+        for name in ["", ".", "..", "a/b", "../alice", "/alice", "a\\b"] {
+            let mut invalid = config.clone();
+            invalid.users[0].user = name.to_owned();
+            assert!(validate_users(&invalid).is_err(), "{name}");
+        }
+    }
 }
@@ -0,0 +1,49 @@
+use std::{
+    backtrace::Backtrace,
+    io::Write,
+    panic::PanicHookInfo,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::Config;
+
+/// The crash report is stored next to the tag database.
+#[must_use]
+pub fn crash_report_path(config: &Config) -> PathBuf {
+    config.tag_database.with_extension("crash.txt")
+}
+
+/// Installs a panic hook which writes a crash report before the default hook runs.
+/// Pending commands are journaled while unwinding so the synchronization can be resumed.
+pub fn install_panic_hook(config: &Config) {
+    let path = crash_report_path(config);
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        match write_crash_report(&path, info) {
+            Ok(()) => eprintln!("Crash report written to {}", path.display()),
+            Err(e) => eprintln!("Failed to write crash report to {}: {e}", path.display()),
+        }
+        default_hook(info);
+    }));
+}
+
+fn write_crash_report(path: &Path, info: &PanicHookInfo) -> std::io::Result<()> {
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let thread = std::thread::current();
+    let mut file = std::fs::File::create(path)?;
+    writeln!(
+        file,
+        "{} {} crashed at {timestamp} (seconds since epoch)",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION")
+    )?;
+    writeln!(
+        file,
+        "Thread '{}' {info}",
+        thread.name().unwrap_or("<unnamed>")
+    )?;
+    writeln!(file, "{}", Backtrace::force_capture())
+}
@@ -0,0 +1,112 @@
+use std::{
+    fmt::Write as _,
+    fs::File,
+    hash::{BuildHasher, Hasher, RandomState},
+    path::{Path, PathBuf},
+};
+
+use snafu::{ResultExt, Snafu};
+
+use crate::{crash_report_path, Config, Repository};
+
+/// Writes an uncompressed tar archive with everything needed to debug an issue report.
+///
+/// It contains the configuration without the token, the last crash report, a summary of
+/// the tag database and the tag database itself with all file names and tags hashed.
+///
+/// Problems with the tag database are recorded in the summary instead of failing, as the
+/// bundle is most useful when something is broken.
+///
+/// # Errors
+///
+/// This function will return an error if the archive cannot be written.
+pub fn create_debug_bundle(config: &Config, output: &Path) -> Result<(), DebugBundleError> {
+    let file = File::create(output).context(WriteSnafu { path: output })?;
+    let mut archive = tar::Builder::new(file);
+    let mut append = |name: &str, data: &[u8]| {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        archive
+            .append_data(&mut header, name, data)
+            .context(WriteSnafu { path: output })
+    };
+
+    append("config.txt", format!("{config:#?}\n").as_bytes())?;
+    if let Ok(crash_report) = std::fs::read(crash_report_path(config)) {
+        append("crash.txt", &crash_report)?;
+    }
+
+    let mut summary = format!("{} {}\n", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    match Repository::check_integrity(&config.tag_database) {
+        Ok(report) => {
+            let repo = report.repaired.anonymized(salt());
+            let _ = writeln!(summary, "Anomalies: {}", report.anomalies.len());
+            let _ = write!(summary, "{}", repo.stats());
+            let json = serde_json::to_vec_pretty(&repo).context(SerializeSnafu)?;
+            append("repository.json", &json)?;
+        }
+        Err(e) => {
+            let _ = writeln!(summary, "Failed to read tag database: {e}");
+        }
+    }
+    append("summary.txt", summary.as_bytes())?;
+
+    archive.into_inner().context(WriteSnafu { path: output })?;
+    Ok(())
+}
+
+/// A new salt per bundle prevents guessing common names from their hashes.
+fn salt() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+#[derive(Debug, Snafu)]
+pub enum DebugBundleError {
+    #[snafu(display("failed to write debug bundle {}", path.display()))]
+    Write {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("failed to serialize tag database"))]
+    Serialize { source: serde_json::Error },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PrefixMapping;
+
+    #[test]
+    fn bundle_without_secrets() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            tag_database: dir.path().join("db.json"),
+            token: "secret-token".to_owned(),
+            ..Config::default()
+        };
+        let prefixes =
+            vec![PrefixMapping::new("/local".into(), "/remote.php/dav/files/user".into()).unwrap()];
+        let mut repo = Repository::new(prefixes);
+        repo.insert_local(Path::new("/local/diary.txt"), "private".parse().unwrap())
+            .unwrap();
+        repo.persist_on_disk(&config.tag_database).unwrap();
+
+        let output = dir.path().join("bundle.tar");
+        create_debug_bundle(&config, &output).unwrap();
+
+        let mut archive = tar::Archive::new(File::open(&output).unwrap());
+        let mut names = Vec::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let mut content = String::new();
+            std::io::Read::read_to_string(&mut entry, &mut content).unwrap();
+            for secret in ["secret-token", "diary", "private"] {
+                assert!(!content.contains(secret), "{secret} leaked");
+            }
+            names.push(entry.path().unwrap().display().to_string());
+        }
+        assert_eq!(names, ["config.txt", "repository.json", "summary.txt"]);
+    }
+}
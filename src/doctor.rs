@@ -0,0 +1,271 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    http_client, i18n::tr, instance_url, Config, Connection, ListDirectory, OcsUserInfo, Quota,
+    RemotePath,
+};
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Outcome of a single connectivity probe.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Probe {
+    Reachable(Duration),
+    Failed(String),
+    TimedOut,
+}
+
+impl Probe {
+    async fn run<F, T, E>(probe: F) -> Self
+    where
+        F: std::future::Future<Output = Result<T, E>>,
+        E: std::fmt::Display,
+    {
+        let start = Instant::now();
+        match tokio::time::timeout(PROBE_TIMEOUT, probe).await {
+            Ok(Ok(_)) => Self::Reachable(start.elapsed()),
+            Ok(Err(e)) => Self::Failed(e.to_string()),
+            Err(_) => Self::TimedOut,
+        }
+    }
+}
+
+impl std::fmt::Display for Probe {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Reachable(duration) => {
+                let millis = duration.as_millis().to_string();
+                write!(f, "{}", tr!("doctor-reachable", millis = millis))
+            }
+            Self::Failed(error) => write!(f, "{}", tr!("doctor-failed", error = error.as_str())),
+            Self::TimedOut => write!(f, "{}", tr!("doctor-timed-out")),
+        }
+    }
+}
+
+/// Connectivity of the Nextcloud instance, to debug synchronizations which hang.
+#[derive(Debug)]
+pub struct DoctorReport {
+    pub host: String,
+    /// Addresses of the host. Pinned by [`Config::resolve`] instead of looked up if set.
+    pub addresses: Result<Vec<SocketAddr>, String>,
+    pub pinned: bool,
+    /// TCP connection to each IPv4 and IPv6 address.
+    pub tcp: Vec<(SocketAddr, Probe)>,
+    /// Connection to a local proxy listening on a unix socket.
+    pub unix_socket: Option<(PathBuf, Probe)>,
+    /// HTTP request to `status.php` with the configured client.
+    pub http: Probe,
+    /// Listing of the remote folder of each prefix.
+    pub prefixes: Vec<(PathBuf, Probe)>,
+    /// Storage quota of the user. Tag writes may fail once it is used up.
+    pub quota: Result<Quota, String>,
+}
+
+impl DoctorReport {
+    /// Whether every probe succeeded.
+    #[must_use]
+    pub fn is_healthy(&self) -> bool {
+        let reachable = |probe: &Probe| matches!(probe, Probe::Reachable(_));
+        self.addresses.is_ok()
+            && self.tcp.iter().all(|(_, probe)| reachable(probe))
+            && self.unix_socket.iter().all(|(_, probe)| reachable(probe))
+            && reachable(&self.http)
+            && self.prefixes.iter().all(|(_, probe)| reachable(probe))
+            && !self.quota.as_ref().is_ok_and(Quota::is_exhausted)
+    }
+}
+
+impl std::fmt::Display for DoctorReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let host = self.host.as_str();
+        match &self.addresses {
+            Ok(addresses) if self.pinned => {
+                let count = addresses.len();
+                writeln!(f, "{}", tr!("doctor-pinned", host = host, count = count))?;
+            }
+            Ok(addresses) => {
+                let count = addresses.len();
+                writeln!(f, "{}", tr!("doctor-resolved", host = host, count = count))?;
+            }
+            Err(error) => {
+                let error = error.as_str();
+                writeln!(
+                    f,
+                    "{}",
+                    tr!("doctor-unresolved", host = host, error = error)
+                )?;
+            }
+        }
+        for (address, probe) in &self.tcp {
+            let family = if address.is_ipv4() { "IPv4" } else { "IPv6" };
+            writeln!(f, "  {family} {address}: {probe}")?;
+        }
+        if let Some((path, probe)) = &self.unix_socket {
+            writeln!(f, "  unix:{}: {probe}", path.display())?;
+        }
+        writeln!(f, "  HTTP: {}", self.http)?;
+        if !self.prefixes.is_empty() {
+            writeln!(f, "{}", tr!("doctor-prefixes"))?;
+        }
+        for (path, probe) in &self.prefixes {
+            writeln!(f, "  {}: {probe}", path.display())?;
+        }
+        match &self.quota {
+            Ok(quota) => {
+                let used = mebibytes(quota.used);
+                match quota.total {
+                    Some(total) => {
+                        let total = mebibytes(total);
+                        writeln!(f, "{}", tr!("doctor-quota", used = used, total = total))?;
+                    }
+                    None => writeln!(f, "{}", tr!("doctor-quota-unlimited", used = used))?,
+                }
+                if quota.is_exhausted() {
+                    writeln!(f, "{}", tr!("quota-exhausted"))?;
+                }
+            }
+            Err(error) => {
+                let error = error.as_str();
+                writeln!(f, "{}", tr!("doctor-quota-failed", error = error))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn mebibytes(bytes: u64) -> String {
+    const MIB: u64 = 1024 * 1024;
+    format!("{}.{}", bytes / MIB, bytes % MIB * 10 / MIB)
+}
+
+/// The server address pinned by the configuration, if any.
+#[must_use]
+pub fn pinned_address(config: &Config) -> Option<SocketAddr> {
+    let port = config.nextcloud_instance.port_or_known_default()?;
+    config.resolve.map(|ip: IpAddr| SocketAddr::new(ip, port))
+}
+
+/// Probes the connectivity of the configured Nextcloud instance over IPv4, IPv6 and
+/// optionally through a local proxy listening on `unix_socket`.
+///
+/// # Panics
+///
+/// Panics if the URL of `status.php` cannot be derived from the instance URL.
+pub async fn diagnose(config: &Config, unix_socket: Option<&Path>) -> DoctorReport {
+    let instance = &config.nextcloud_instance;
+    let host = instance.host_str().unwrap_or_default().to_owned();
+    let port = instance.port_or_known_default().unwrap_or(443);
+
+    let pinned = pinned_address(config);
+    let addresses = match pinned {
+        Some(address) => Ok(vec![address]),
+        None => tokio::net::lookup_host((host.as_str(), port))
+            .await
+            .map(Iterator::collect)
+            .map_err(|e| e.to_string()),
+    };
+
+    let mut tcp = Vec::new();
+    for &address in addresses.iter().flatten() {
+        let probe = Probe::run(tokio::net::TcpStream::connect(address)).await;
+        tcp.push((address, probe));
+    }
+
+    let unix_socket = match unix_socket {
+        Some(path) => Some((path.to_owned(), probe_unix_socket(path).await)),
+        None => None,
+    };
+
+    let status = instance_url(instance, "status.php").expect("failed to create URL");
+    let http = Probe::run(async {
+        http_client(config)
+            .get(status)
+            .send()
+            .await?
+            .error_for_status()
+    })
+    .await;
+
+    let connection = Connection::from_config(config);
+    let mut prefixes = Vec::new();
+    for prefix in &config.prefixes {
+        let probe = Probe::run(probe_remote_folder(&connection, prefix.remote())).await;
+        prefixes.push((prefix.remote().to_owned(), probe));
+    }
+    let quota = if matches!(http, Probe::Reachable(_)) {
+        tokio::time::timeout(PROBE_TIMEOUT, connection.request(OcsUserInfo))
+            .await
+            .map_or_else(
+                |_| Err(tr!("doctor-timed-out")),
+                |quota| quota.map_err(|e| e.to_string()),
+            )
+    } else {
+        Err(tr!("doctor-quota-unreachable"))
+    };
+
+    DoctorReport {
+        host,
+        addresses,
+        pinned: pinned.is_some(),
+        tcp,
+        unix_socket,
+        http,
+        prefixes,
+        quota,
+    }
+}
+
+/// Lists the remote folder of a prefix to ensure it exists and is a folder.
+async fn probe_remote_folder(connection: &Connection, path: &Path) -> Result<(), String> {
+    let request = ListDirectory::new(&RemotePath::from(path)).ok_or("path is not UTF-8")?;
+    let entries = connection
+        .request(request)
+        .await
+        .map_err(|e| e.to_string())?;
+    match entries.first() {
+        Some(entry) if entry.is_directory => Ok(()),
+        _ => Err("not a folder".to_owned()),
+    }
+}
+
+#[cfg(unix)]
+async fn probe_unix_socket(path: &Path) -> Probe {
+    Probe::run(tokio::net::UnixStream::connect(path)).await
+}
+
+#[cfg(not(unix))]
+async fn probe_unix_socket(_path: &Path) -> Probe {
+    Probe::Failed("unix sockets are not supported on this platform".to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn probe_pinned_address() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                drop(stream);
+            }
+        });
+        let config = Config {
+            nextcloud_instance: format!("http://cloud.invalid:{port}").parse().unwrap(),
+            resolve: Some("127.0.0.1".parse().unwrap()),
+            ..Config::default()
+        };
+
+        let report = diagnose(&config, None).await;
+        assert!(report.pinned);
+        assert_eq!(report.tcp.len(), 1);
+        assert!(matches!(report.tcp[0].1, Probe::Reachable(_)));
+        assert!(!report.is_healthy(), "listener does not speak HTTP");
+    }
+}
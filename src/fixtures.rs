@@ -0,0 +1,107 @@
+//! Request and response samples for tests. Available to other crates with the `fixtures`
+//! feature, e.g. for a mock server.
+
+/// Response to [`ListTags`](crate::ListTags) with 237 regular tags.
+pub const ALL_TAGS: &str = include_str!("../test_data/all_tags.xml");
+/// Response to [`ListFilesWithTag`](crate::ListFilesWithTag) with 105 files.
+pub const AIRPLANES: &str = include_str!("../test_data/airplanes.xml");
+/// Response to [`GetFileId`](crate::GetFileId) for a file the user owns.
+pub const FILE_ID: &str = include_str!("../test_data/file_id.xml");
+/// Response to [`GetFileId`](crate::GetFileId) for a file in a read-only incoming share.
+pub const FILE_ID_SHARED: &str = include_str!("../test_data/file_id_shared.xml");
+/// Response to [`IsEncrypted`](crate::IsEncrypted) for an end-to-end encrypted folder.
+pub const IS_ENCRYPTED: &str = include_str!("../test_data/is_encrypted.xml");
+/// Error body of a server in maintenance mode.
+pub const MAINTENANCE: &str = include_str!("../test_data/maintenance.xml");
+
+/// Builds DAV multistatus responses as returned by `PROPFIND` and `REPORT` requests.
+#[derive(Debug, Default, Clone)]
+pub struct MultiStatusBuilder {
+    responses: Vec<String>,
+}
+
+impl MultiStatusBuilder {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a response for `href` with properties such as `("oc:fileid", "52")`. Empty
+    /// values are written as empty elements.
+    #[must_use]
+    pub fn response<'a, I>(mut self, href: &str, props: I) -> Self
+    where
+        I: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        let props: String = props
+            .into_iter()
+            .map(|(name, value)| {
+                if value.is_empty() {
+                    format!("<{name}/>")
+                } else {
+                    format!("<{name}>{}</{name}>", escape(value))
+                }
+            })
+            .collect();
+        self.responses.push(format!(
+            "<d:response><d:href>{}</d:href><d:propstat><d:prop>{props}</d:prop>\
+             <d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>",
+            escape(href)
+        ));
+        self
+    }
+
+    /// Adds a system tag as listed by [`ListTags`](crate::ListTags).
+    #[must_use]
+    pub fn tag(self, id: u64, name: &str, visible: bool, assignable: bool) -> Self {
+        let id = id.to_string();
+        self.response(
+            &format!("/remote.php/dav/systemtags/{id}"),
+            [
+                ("oc:id", id.as_str()),
+                ("oc:display-name", name),
+                ("oc:user-visible", if visible { "true" } else { "false" }),
+                (
+                    "oc:user-assignable",
+                    if assignable { "true" } else { "false" },
+                ),
+            ],
+        )
+    }
+
+    /// Adds a file as listed by [`ListFilesWithTag`](crate::ListFilesWithTag).
+    #[must_use]
+    pub fn file(self, href: &str, id: u64) -> Self {
+        let id = id.to_string();
+        self.response(href, [("oc:fileid", id.as_str()), ("d:resourcetype", "")])
+    }
+
+    /// Adds a folder as listed by [`ListPathsWithTag`](crate::ListPathsWithTag).
+    #[must_use]
+    pub fn folder(mut self, href: &str, id: u64) -> Self {
+        self.responses.push(format!(
+            "<d:response><d:href>{}</d:href><d:propstat><d:prop><oc:fileid>{id}</oc:fileid>\
+             <d:resourcetype><d:collection/></d:resourcetype></d:prop>\
+             <d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>",
+            escape(href)
+        ));
+        self
+    }
+
+    #[must_use]
+    pub fn build(&self) -> String {
+        format!(
+            "<?xml version=\"1.0\"?>\n<d:multistatus xmlns:d=\"DAV:\" \
+             xmlns:oc=\"http://owncloud.org/ns\" xmlns:nc=\"http://nextcloud.org/ns\">{}\
+             </d:multistatus>",
+            self.responses.concat()
+        )
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
@@ -3,6 +3,7 @@ use std::{
     cmp::Ordering,
     ffi::OsStr,
     fmt::{Display, Formatter},
+    path::{Path, PathBuf},
 };
 use termtree::Tree;
 
@@ -27,6 +28,75 @@ fn into_either<T>(res: Result<T, T>) -> (bool, T) {
     }
 }
 
+/// The candidate most similar to `word` if it differs by only a few edits, e.g. to
+/// suggest the correct spelling of a misspelled name.
+pub fn closest<'a, I>(word: &str, candidates: I) -> Option<&'a str>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let max_distance = (word.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(word, candidate), candidate))
+        .filter(|(distance, _)| (1..=max_distance).contains(distance))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Levenshtein distance of two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Expands a leading `~` as well as `$VAR` and `${VAR}` in a path, looking up variables
+/// with `var`. A `$` that does not start a variable name is kept as-is.
+///
+/// # Errors
+///
+/// Returns the name of the first variable that is not set.
+pub fn expand_path(path: &Path, var: impl Fn(&str) -> Option<String>) -> Result<PathBuf, String> {
+    let Some(input) = path.to_str() else {
+        return Ok(path.to_owned());
+    };
+    let mut expanded = String::new();
+    let mut rest = input;
+    if rest == "~" || rest.starts_with("~/") {
+        expanded.push_str(&var("HOME").ok_or("HOME")?);
+        rest = &rest[1..];
+    }
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let (name, remainder) = after.strip_prefix('{').map_or_else(
+            || {
+                let end = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                after.split_at(end)
+            },
+            |braced| braced.split_once('}').unwrap_or(("", after)),
+        );
+        if name.is_empty() {
+            expanded.push('$');
+        } else {
+            expanded.push_str(&var(name).ok_or(name)?);
+        }
+        rest = remainder;
+    }
+    expanded.push_str(rest);
+    Ok(PathBuf::from(expanded))
+}
+
 pub fn take_last_n_chars(string: &str, n: usize) -> &str {
     let len = string
         .char_indices()
@@ -110,7 +180,7 @@ where
     }
 }
 
-impl<'a, T> Item<'a, T>
+impl<T> Item<'_, T>
 where
     T: Display,
 {
@@ -121,7 +191,7 @@ where
     }
 }
 
-impl<'a, T> PartialEq for Item<'a, T>
+impl<T> PartialEq for Item<'_, T>
 where
     T: Display,
 {
@@ -136,61 +206,131 @@ where
     }
 }
 
-impl<'a, T> PartialOrd for Item<'a, T>
+impl<T> Item<'_, T>
 where
     T: Display,
 {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    fn compare(&self, other: &Self, order: SortOrder) -> Option<Ordering> {
         match (self, other) {
             (Item::Number(l), Item::Number(r)) => l.partial_cmp(r),
-            (Item::String { name: l, .. }, Item::String { name: r, .. }) => l.partial_cmp(r),
+            (Item::String { name: l, .. }, Item::String { name: r, .. }) => {
+                Some(order.compare(l, r))
+            }
             (Item::Number(_), Item::String { .. }) | (Item::String { .. }, Item::Number(_)) => None,
         }
     }
 }
 
-impl<'a, T> Display for Item<'a, T>
-where
-    T: Display,
-{
-    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+/// Order of file names in printed trees.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum SortOrder {
+    /// Raw byte order.
+    #[default]
+    Bytes,
+    /// Numbers are compared by value and letters ignore case and common diacritics,
+    /// e.g. `file2` before `file10` and `Äpfel` before `Birnen`.
+    Natural,
+}
+
+impl SortOrder {
+    fn compare(self, l: &OsStr, r: &OsStr) -> Ordering {
         match self {
-            Item::Number(num) => write!(f, "{num}"),
-            Item::String { name, extras } => write!(f, "{}{}", name.to_string_lossy(), extras),
+            Self::Bytes => l.cmp(r),
+            Self::Natural => {
+                natural_cmp(&l.to_string_lossy(), &r.to_string_lossy()).then_with(|| l.cmp(r))
+            }
         }
     }
 }
 
-pub struct SyncedPathPrinter<'a, T: Display> {
-    tree: Tree<Item<'a, T>>,
+fn natural_cmp(l: &str, r: &str) -> Ordering {
+    let (l, r) = (chunks(l), chunks(r));
+    for (l, r) in l.iter().zip(&r) {
+        let ordering = match (l, r) {
+            (Chunk::Number(l), Chunk::Number(r)) => {
+                let (l, r) = (l.trim_start_matches('0'), r.trim_start_matches('0'));
+                l.len().cmp(&r.len()).then_with(|| l.cmp(r))
+            }
+            (Chunk::Text(l), Chunk::Text(r)) => fold(l).cmp(&fold(r)),
+            (Chunk::Number(_), Chunk::Text(_)) => Ordering::Less,
+            (Chunk::Text(_), Chunk::Number(_)) => Ordering::Greater,
+        };
+        if ordering.is_ne() {
+            return ordering;
+        }
+    }
+    l.len().cmp(&r.len())
+}
+
+enum Chunk<'a> {
+    Number(&'a str),
+    Text(&'a str),
+}
+
+fn chunks(s: &str) -> Vec<Chunk<'_>> {
+    let mut chunks = Vec::new();
+    let mut rest = s;
+    while let Some(first) = rest.chars().next() {
+        let is_digit = first.is_ascii_digit();
+        let end = rest
+            .find(|c: char| c.is_ascii_digit() != is_digit)
+            .unwrap_or(rest.len());
+        let (chunk, tail) = rest.split_at(end);
+        chunks.push(if is_digit {
+            Chunk::Number(chunk)
+        } else {
+            Chunk::Text(chunk)
+        });
+        rest = tail;
+    }
+    chunks
 }
 
-impl<'a, T> Display for SyncedPathPrinter<'a, T>
+/// Lowercases and strips common diacritics so umlauts sort next to their base letter.
+fn fold(s: &str) -> String {
+    let mut folded = String::with_capacity(s.len());
+    for c in s.chars().flat_map(char::to_lowercase) {
+        let base = match c {
+            'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' => "a",
+            'ç' => "c",
+            'è' | 'é' | 'ê' | 'ë' => "e",
+            'ì' | 'í' | 'î' | 'ï' => "i",
+            'ñ' => "n",
+            'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' => "o",
+            'ù' | 'ú' | 'û' | 'ü' => "u",
+            'ý' | 'ÿ' => "y",
+            'ß' => "ss",
+            _ => {
+                folded.push(c);
+                continue;
+            }
+        };
+        folded.push_str(base);
+    }
+    folded
+}
+
+impl<T> Display for Item<'_, T>
 where
     T: Display,
 {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-        write!(f, "{}", self.tree)
+        match self {
+            Item::Number(num) => write!(f, "{num}"),
+            Item::String { name, extras } => write!(f, "{}{}", name.to_string_lossy(), extras),
+        }
     }
 }
 
-impl<'a> FromIterator<&'a SyncedPath> for SyncedPathPrinter<'a, DisplayUnit> {
-    fn from_iter<I>(collection: I) -> Self
-    where
-        I: IntoIterator<Item = &'a SyncedPath>,
-    {
-        collection
-            .into_iter()
-            .map(|x| (x, DisplayUnit))
-            .collect::<SyncedPathPrinter<_>>()
-    }
+pub struct SyncedPathPrinter<'a, T: Display> {
+    tree: Tree<Item<'a, T>>,
 }
 
-impl<'a, T> FromIterator<(&'a SyncedPath, T)> for SyncedPathPrinter<'a, T>
+impl<'a, T> SyncedPathPrinter<'a, T>
 where
     T: Display + Default,
 {
-    fn from_iter<I>(collection: I) -> Self
+    pub fn new<I>(collection: I, order: SortOrder) -> Self
     where
         I: IntoIterator<Item = (&'a SyncedPath, T)>,
     {
@@ -212,7 +352,7 @@ where
                 let element = Item::os_str(component.as_os_str());
                 let (already_exists, element_at) = into_either(tree.leaves.binary_search_by(|x| {
                     x.root
-                        .partial_cmp(&element)
+                        .compare(&element, order)
                         .expect("should only compare strings here")
                 }));
                 if !already_exists {
@@ -227,8 +367,41 @@ where
     }
 }
 
+impl<T> Display for SyncedPathPrinter<'_, T>
+where
+    T: Display,
+{
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.tree)
+    }
+}
+
+impl<'a> FromIterator<&'a SyncedPath> for SyncedPathPrinter<'a, DisplayUnit> {
+    fn from_iter<I>(collection: I) -> Self
+    where
+        I: IntoIterator<Item = &'a SyncedPath>,
+    {
+        collection
+            .into_iter()
+            .map(|x| (x, DisplayUnit))
+            .collect::<SyncedPathPrinter<_>>()
+    }
+}
+
+impl<'a, T> FromIterator<(&'a SyncedPath, T)> for SyncedPathPrinter<'a, T>
+where
+    T: Display + Default,
+{
+    fn from_iter<I>(collection: I) -> Self
+    where
+        I: IntoIterator<Item = (&'a SyncedPath, T)>,
+    {
+        Self::new(collection, SortOrder::default())
+    }
+}
+
 #[derive(Default)]
-struct DisplayUnit;
+pub struct DisplayUnit;
 
 impl Display for DisplayUnit {
     fn fmt(&self, _: &mut Formatter) -> std::fmt::Result {
@@ -240,6 +413,35 @@ impl Display for DisplayUnit {
 mod tests {
     use super::*;
 
+    #[test]
+    fn suggest_near_miss() {
+        let keys = ["keep_side_on_conflict", "prefixes", "user"];
+        assert_eq!(
+            closest("keep_side_on_conflit", keys),
+            Some("keep_side_on_conflict")
+        );
+        assert_eq!(closest("prefix", keys), Some("prefixes"));
+        assert_eq!(closest("prefixes", keys), None);
+        assert_eq!(closest("token", keys), None);
+    }
+
+    #[test]
+    fn expand_home_and_variables() {
+        let var = |name: &str| match name {
+            "HOME" => Some("/home/user".to_owned()),
+            "SYNC" => Some("sync".to_owned()),
+            _ => None,
+        };
+        let expand = |path: &str| expand_path(Path::new(path), var);
+        assert_eq!(expand("~/photos"), Ok("/home/user/photos".into()));
+        assert_eq!(
+            expand("$HOME/${SYNC}_db.json"),
+            Ok("/home/user/sync_db.json".into())
+        );
+        assert_eq!(expand("/data/~user/100$"), Ok("/data/~user/100$".into()));
+        assert_eq!(expand("/data/$MISSING"), Err("MISSING".into()));
+    }
+
     fn files() -> [SyncedPath; 15] {
         [
             SyncedPath::new(1, "test/house.txt"),
@@ -296,6 +498,30 @@ mod tests {
         );
     }
 
+    #[test]
+    fn natural_sort_order() {
+        let files = [
+            SyncedPath::new(0, "file10.txt"),
+            SyncedPath::new(0, "Zebra.txt"),
+            SyncedPath::new(0, "file2.txt"),
+            SyncedPath::new(0, "Äpfel.txt"),
+            SyncedPath::new(0, "Birnen.txt"),
+        ];
+        let printer =
+            SyncedPathPrinter::new(files.iter().map(|x| (x, DisplayUnit)), SortOrder::Natural);
+
+        assert_eq!(
+            printer.to_string(),
+            "ROOT
+└── 0
+    ├── Äpfel.txt
+    ├── Birnen.txt
+    ├── file2.txt
+    ├── file10.txt
+    └── Zebra.txt\n"
+        );
+    }
+
     #[test]
     fn print_tree_with_extras() {
         let files = files();
@@ -0,0 +1,124 @@
+//! Translations of user-facing messages. Messages are looked up in the configured language
+//! and fall back to English.
+
+use std::sync::OnceLock;
+
+use fluent_bundle::{concurrent::FluentBundle, FluentArgs, FluentResource};
+use unic_langid::LanguageIdentifier;
+
+const FALLBACK: &str = "en";
+const RESOURCES: &[(&str, &str)] = &[
+    ("en", include_str!("../locales/en.ftl")),
+    ("de", include_str!("../locales/de.ftl")),
+];
+
+static LOCALIZER: OnceLock<Localizer> = OnceLock::new();
+
+struct Localizer {
+    bundles: Vec<FluentBundle<FluentResource>>,
+}
+
+impl Localizer {
+    fn new(language: &str) -> Self {
+        let bundles = [language, FALLBACK]
+            .into_iter()
+            .filter_map(|language| {
+                let (name, source) = RESOURCES.iter().find(|(name, _)| *name == language)?;
+                let id: LanguageIdentifier = name.parse().expect("valid language identifier");
+                let resource =
+                    FluentResource::try_new((*source).to_owned()).expect("valid fluent resource");
+                let mut bundle = FluentBundle::new_concurrent(vec![id]);
+                bundle.set_use_isolating(false);
+                bundle
+                    .add_resource(resource)
+                    .expect("no duplicate messages");
+                Some(bundle)
+            })
+            .collect();
+        Self { bundles }
+    }
+
+    fn format(&self, id: &str, args: Option<&FluentArgs>) -> String {
+        for bundle in &self.bundles {
+            let Some(pattern) = bundle.get_message(id).and_then(|msg| msg.value()) else {
+                continue;
+            };
+            let mut errors = Vec::new();
+            let message = bundle.format_pattern(pattern, args, &mut errors);
+            if !errors.is_empty() {
+                tracing::warn!("Failed to format message {id}: {errors:?}");
+            }
+            return message.into_owned();
+        }
+        tracing::warn!("Missing translation for message {id}");
+        id.to_owned()
+    }
+}
+
+/// Derives the language from `LC_ALL`, `LC_MESSAGES` or `LANG`, e.g. `de` from `de_DE.UTF-8`.
+fn language_from_env() -> Option<String> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .into_iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty())
+        .and_then(|value| {
+            let language = value.split(['_', '.', '@', '-']).next()?.to_lowercase();
+            (language != "c" && language != "posix").then_some(language)
+        })
+}
+
+/// Selects the language of all messages. Without a language, it is derived from the
+/// environment. Only the first call has an effect.
+pub fn init(language: Option<&str>) {
+    let language = language.map_or_else(
+        || language_from_env().unwrap_or_else(|| FALLBACK.to_owned()),
+        str::to_owned,
+    );
+    if LOCALIZER.set(Localizer::new(&language)).is_err() {
+        tracing::debug!("Language was already selected");
+    }
+}
+
+/// Formats a message in the selected language. Defaults to English if [`init`] was not
+/// called.
+pub fn translate(id: &str, args: Option<&FluentArgs>) -> String {
+    LOCALIZER
+        .get_or_init(|| Localizer::new(FALLBACK))
+        .format(id, args)
+}
+
+/// Translates a message, optionally with named arguments: `tr!("id", count = 3)`.
+macro_rules! tr {
+    ($id:literal) => {
+        $crate::i18n::translate($id, None)
+    };
+    ($id:literal, $($name:ident = $value:expr),+ $(,)?) => {{
+        let mut args = fluent_bundle::FluentArgs::new();
+        $(args.set(stringify!($name), $value);)+
+        $crate::i18n::translate($id, Some(&args))
+    }};
+}
+
+pub(crate) use tr;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translate_with_fallback() {
+        let german = Localizer::new("de");
+        let mut args = FluentArgs::new();
+        args.set("count", 1);
+        assert_eq!(
+            german.format("report-read-only-shares", Some(&args)),
+            "1 Datei in schreibgeschützten eingehenden Freigaben übersprungen:"
+        );
+
+        let unknown = Localizer::new("xx");
+        assert_eq!(
+            unknown.format("integrity-clean", None),
+            "No anomalies found."
+        );
+    }
+}
@@ -4,39 +4,129 @@
     clippy::future_not_send,
     reason = "bimap's Iter type is (probably) incorrectly not marked Send + Sync which in turn affects the futures"
 )]
+#![cfg_attr(
+    not(feature = "sync"),
+    allow(
+        dead_code,
+        reason = "Some helpers are only used by the synchronization"
+    )
+)]
 
+#[cfg(feature = "sync")]
+mod backup;
+#[cfg(feature = "client")]
+pub mod client;
 mod commands;
+#[cfg(feature = "sync")]
 mod config;
+#[cfg(feature = "sync")]
+mod crash;
+#[cfg(feature = "sync")]
+mod debug_bundle;
+#[cfg(feature = "sync")]
+mod doctor;
+#[cfg(any(test, feature = "fixtures"))]
+pub mod fixtures;
 mod helper;
+mod i18n;
+#[cfg(feature = "sync")]
 mod local_fs;
+#[cfg(feature = "sync")]
+mod maintenance;
+mod preview;
+#[cfg(feature = "sync")]
+mod query;
 mod remote_fs;
+#[cfg(feature = "sync")]
+mod report;
+#[cfg(feature = "sync")]
+mod scan_issues;
 mod tag_repository;
+#[cfg(feature = "sync")]
 mod updater;
+#[cfg(feature = "sync")]
+mod watcher;
+
+use helper::{newtype, SyncedPathPrinter};
+#[cfg(feature = "sync")]
+use helper::{take_last_n_chars, IntoOk};
 
-use helper::{newtype, take_last_n_chars, IntoOk, SyncedPathPrinter};
-use tag_repository::SyncedPath;
+pub use helper::SortOrder;
 
+#[cfg(feature = "sync")]
+pub use backup::{restore_backup, BackupError, RestoreReport};
 pub use commands::*;
-pub use config::{load_config, Config};
+#[cfg(feature = "sync")]
+pub use config::{load_config, Config, ConfigError, UserSync};
+#[cfg(feature = "sync")]
+pub use crash::{crash_report_path, install_panic_hook};
+#[cfg(feature = "sync")]
+pub use debug_bundle::{create_debug_bundle, DebugBundleError};
+#[cfg(feature = "sync")]
+pub use doctor::{diagnose, DoctorReport, Probe};
+pub use i18n::{init as init_language, translate};
+#[cfg(feature = "sync")]
 pub use local_fs::{
-    get_tags_of_file, FileError, FileSystemLoopError, LocalError, LocalFs, LocalFsWalker,
+    get_tag_time, get_tags_of_file, ChangeTime, FileError, FileSystemLoopError, LocalError,
+    LocalFileId, LocalFs, LocalFsWalker, LocalScan, ScannedFile,
+};
+#[cfg(feature = "sync")]
+pub use maintenance::{
+    check_database, compact_database, delete_tag, push_tags, rebind_database, rename_tag,
+    CompactionReport, MaintenanceError, PushTagsReport, RebindReport,
+};
+pub use preview::{pending_changes, preview, PendingChanges, PreviewError};
+#[cfg(feature = "sync")]
+pub use query::{
+    diff_databases, find_tagged, inspect_database, remote_tags_of, tag_origins_of, tags_of,
+    DatabaseDiff, Inspection, QueryError,
 };
+pub use remote_fs::RemotePath;
+#[cfg(all(feature = "sync", any(test, feature = "fixtures")))]
+pub use remote_fs::Simulator;
+#[cfg(feature = "sync")]
 pub use remote_fs::{
-    parse, Body, Connection, CreateTag, DeserializeError, FileId, FileMap, ListFilesWithTag,
-    ListTags, ListTagsError, ListTagsMultiStatus, Parse, RemoteFs, Request, TagFile, TagId, TagMap,
-    UntagFile,
+    http_client, instance_url, parse, AddComment, Body, Connection, CreateTag, DeleteTag,
+    DeserializeError, DirectoryEntry, Exchange, FileId, FileMap, FileProperties, GetFileId,
+    GetFileTags, IsEncrypted, ListDirectory, ListFilesWithTag, ListPathsWithTag, ListTags,
+    ListTagsError, ListTagsMultiStatus, LockFile, LockToken, ManageTagError, Middleware,
+    MissingLockTokenError, OcsError, OcsUserInfo, Parse, Permissions, Property, Quota,
+    RemoteBackend, RemoteFile, RemoteFs, Request, RequestError, ServerException, SystemTagPolicy,
+    TagFile, TagId, TagKind, TagListing, TagMap, TaggedFile, UnlockFile, UntagFile, UpdateTag,
+    UpdateTagError,
 };
-pub use tag_repository::{FileLocation, PrefixMapping, Repository, Side, Tag, Tags};
+#[cfg(feature = "sync")]
+pub use report::{DryRunReport, SyncReport};
+#[cfg(feature = "sync")]
+pub use scan_issues::{ScanIssue, ScanIssues};
+pub use tag_repository::{
+    AmbiguousPrefixError, Anomaly, CounterpartPolicy, FileLocation, IntegrityReport,
+    MissingPrefixError, PrefixMapping, PrefixMappingId, Repository, RepositoryBuilder,
+    RepositoryStats, Side, SyncedPath, SyncedPathParseError, Tag, TagParseError, TagStats, Tags,
+};
+#[cfg(feature = "sync")]
+pub use tag_repository::{SnapshotError, SnapshotReader};
 
-pub use updater::{InitError, Initialized, Uninitialized};
+#[cfg(feature = "sync")]
+pub use updater::{
+    release_looping_files, HookError, InitError, Initialized, OrphanPolicy, Phase, TagOrigin,
+    Uninitialized, ORPHAN_TAG,
+};
+#[cfg(feature = "sync")]
+pub use watcher::{
+    time_until_scan_window, LocalChanges, ScanWindow, ScanWindowParseError, WatchError, Watcher,
+};
 
+#[cfg(feature = "sync")]
 #[allow(
     async_fn_in_trait,
     reason = "Implementations don't return Send+Sync futures anyway due to limitation in bimap"
 )]
 pub trait FileSystem {
-    async fn create_repo(&mut self) -> Result<Repository, InitError>;
-    async fn update_tags<I>(&mut self, commands: I)
+    /// Builds the repository of all tagged files. Skipped items are returned alongside it.
+    async fn create_repo(&mut self) -> Result<(Repository, ScanIssues), InitError>;
+    /// Applies the commands and returns the outcome of each one.
+    async fn update_tags<I>(&mut self, commands: I) -> Vec<CommandOutcome>
     where
         I: IntoIterator<Item = Command> + Send;
 }
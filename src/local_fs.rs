@@ -1,5 +1,7 @@
 mod fs;
 mod fs_walker;
+mod scan_cache;
 
-pub use fs::{get_tags_of_file, FileError, LocalError, LocalFs};
-pub use fs_walker::{FileSystemLoopError, LocalFsWalker};
+pub use fs::{get_tag_time, get_tags_of_file, FileError, LocalError, LocalFs};
+pub use fs_walker::{FileSystemLoopError, LocalFsWalker, LocalScan};
+pub use scan_cache::{ChangeTime, LocalFileId, ScannedFile};
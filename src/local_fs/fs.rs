@@ -1,91 +1,216 @@
 use std::{
+    collections::{btree_map::Entry, BTreeMap, BTreeSet},
     path::{Path, PathBuf},
     sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use futures::FutureExt as _;
 use snafu::prelude::*;
 use tokio::task::JoinError;
-use tracing::{debug, error};
+use tracing::{debug, error, warn};
 
 use crate::{
-    updater::LocalSnafu, Command, Config, FileSystem, IntoOk, Modification, PrefixMapping,
-    TagAction, Tags,
+    updater::LocalSnafu, Command, CommandOutcome, Config, FileSystem, IntoOk, Modification,
+    SyncedPath, TagAction, Tags,
 };
 
-use super::LocalFsWalker;
+use super::{scan_cache::ScanCache, LocalFsWalker, LocalScan};
 
+/// Local files whose tags are stored in an extended attribute.
+///
+/// ```no_run
+/// use std::sync::Arc;
+///
+/// use nextcloud_tag_sync::{load_config, Command, FileSystem as _, LocalFs};
+///
+/// # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut local = LocalFs::new(Arc::new(load_config()?));
+/// let (repo, issues) = local.create_repo().await?;
+/// for issue in issues {
+///     println!("{issue}");
+/// }
+/// let Some((path, _)) = repo.files().next() else {
+///     return Ok(());
+/// };
+/// let command = Command::for_path(path.clone()).add("reviewed").build()?;
+/// for outcome in local.update_tags([command]).await {
+///     assert!(outcome.is_success(), "{:?}", outcome.error());
+/// }
+/// # Ok(())
+/// # }
+/// ```
 #[derive(Debug)]
 pub struct LocalFs {
+    /// Files which were skipped because they do not belong to any prefix.
+    pub quarantined: BTreeSet<PathBuf>,
+    /// Time of the last tag modification of each file, if tag times are recorded.
+    pub tag_times: BTreeMap<SyncedPath, SystemTime>,
+    /// Tagged files which the previous scan found at another path, by their new path.
+    pub renamed: BTreeMap<PathBuf, PathBuf>,
     config: Arc<Config>,
 }
 
 impl LocalFs {
     #[must_use]
     pub const fn new(config: Arc<Config>) -> Self {
-        Self { config }
+        Self {
+            quarantined: BTreeSet::new(),
+            tag_times: BTreeMap::new(),
+            renamed: BTreeMap::new(),
+            config,
+        }
+    }
+
+    /// Reads the tags of the files at or below `paths` without walking the prefixes.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the scan panics.
+    pub async fn scan_paths(
+        &mut self,
+        paths: BTreeSet<PathBuf>,
+    ) -> Result<(crate::Repository, crate::ScanIssues), crate::InitError> {
+        let config = self.config.clone();
+        let scan =
+            tokio::task::spawn_blocking(move || LocalFsWalker::new(&config).scan_paths(&paths))
+                .await
+                .context(JoinSnafu)
+                .context(LocalSnafu)?;
+        self.quarantined.extend(scan.quarantined);
+        self.tag_times.extend(scan.tag_times);
+        Ok((scan.repo, scan.issues))
     }
 }
 
 impl FileSystem for LocalFs {
-    async fn create_repo(&mut self) -> Result<crate::Repository, crate::InitError> {
-        let config = self.config.clone();
-        tokio::task::spawn_blocking(move || LocalFsWalker::new(&config).build_repository())
+    async fn create_repo(
+        &mut self,
+    ) -> Result<(crate::Repository, crate::ScanIssues), crate::InitError> {
+        let started = SystemTime::now();
+        let cache = Arc::new(if self.config.cache_untagged_files {
+            ScanCache::load(&self.config)
+        } else {
+            ScanCache::default()
+        });
+        // Prefixes are usually on different folders or drives, so walk them in parallel.
+        let walks = (0..self.config.prefixes.len()).map(|index| {
+            let config = self.config.clone();
+            let cache = cache.clone();
+            tokio::task::spawn_blocking(move || {
+                LocalFsWalker::new(&config)
+                    .with_cache(&cache)
+                    .scan_prefix(index)
+            })
             .map(|res| match res {
                 Ok(o) => Ok(o),
                 Err(e) => Err(e).context(JoinSnafu),
             })
-            .await
-            .context(LocalSnafu)
+        });
+        let mut scan = LocalScan::empty(&self.config.prefixes);
+        for walk in futures::future::join_all(walks).await {
+            scan.merge(walk.context(LocalSnafu)?);
+        }
+        self.quarantined.extend(scan.quarantined);
+        self.tag_times.extend(scan.tag_times);
+        self.renamed.extend(scan.renamed);
+        if self.config.cache_untagged_files {
+            ScanCache::new(&self.config, scan.files, started).persist(&self.config);
+        }
+        Ok((scan.repo, scan.issues))
     }
 
-    async fn update_tags<I>(&mut self, commands: I)
+    async fn update_tags<I>(&mut self, commands: I) -> Vec<CommandOutcome>
     where
         I: IntoIterator<Item = Command> + Send,
     {
-        for cmd in commands {
-            let path = cmd.path.clone();
-            match run_command(
-                cmd,
-                &self.config.local_tag_property_name,
-                &self.config.prefixes,
-            ) {
+        coalesce(commands)
+            .into_iter()
+            .map(|cmd| match run_command(&cmd, &self.config) {
                 Ok(()) => {
-                    debug!("Successfully updated tags for file {path}");
+                    debug!("Successfully updated tags for file {}", cmd.path);
+                    CommandOutcome::Success(cmd)
                 }
                 Err(e) => {
-                    error!("Failed to update tags for file {path}: {e}");
+                    error!("Failed to update tags for file {}: {e}", cmd.path);
+                    CommandOutcome::Failed {
+                        command: cmd,
+                        error: e.to_string(),
+                    }
                 }
+            })
+            .collect()
+    }
+}
+
+/// Merges all commands for the same file so its attribute is read and written only once.
+fn coalesce<I: IntoIterator<Item = Command>>(commands: I) -> Vec<Command> {
+    let mut merged: BTreeMap<SyncedPath, Command> = BTreeMap::new();
+    for cmd in commands {
+        match merged.entry(cmd.path.clone()) {
+            Entry::Vacant(entry) => {
+                entry.insert(cmd);
             }
+            Entry::Occupied(mut entry) => entry.get_mut().actions.extend(cmd.actions),
         }
     }
+    merged.into_values().collect()
 }
 
-fn run_command(
-    cmd: Command,
-    tag_property_name: &str,
-    prefixes: &[PrefixMapping],
-) -> Result<(), FileError> {
-    let path = cmd.path.local_file(prefixes);
+fn run_command(cmd: &Command, config: &Config) -> Result<(), FileError> {
+    let tag_property_name = &config.local_tag_property_name;
+    let path = cmd.path.local_file(&config.prefixes);
 
-    let mut tags = match get_tags_of_file(&path, tag_property_name) {
-        Ok(ok) => ok,
-        Err(err) => return Err(err),
-    };
+    let original = get_tags_of_file(&path, tag_property_name)?;
+    let mut tags = original.clone();
 
-    for TagAction { tag, modification } in cmd.actions {
+    for TagAction { tag, modification } in &cmd.actions {
         match modification {
-            Modification::Add => tags.insert_one(tag),
-            Modification::Remove => tags.remove_one(&tag),
+            Modification::Add => tags.insert_one(tag.clone()),
+            Modification::Remove => tags.remove_one(tag),
         }
     }
 
+    if tags == original {
+        return Ok(());
+    }
+
     xattr::set(&path, tag_property_name, tags.to_string().as_bytes())
-        .with_context(|_| XAttrSnafu { path })?;
+        .with_context(|_| XAttrSnafu { path: &path })?;
+
+    if let Some(time_property_name) = &config.tag_time_property_name {
+        // The tags are already written, so a missing time is not worth failing the command.
+        if let Err(e) = set_tag_time(&path, time_property_name, SystemTime::now()) {
+            warn!("Failed to record tag time: {e}");
+        }
+    }
 
     Ok(())
 }
 
+/// Load the time of the last tag modification of the given local file. The time is stored
+/// as seconds since the Unix epoch.
+///
+/// # Errors
+///
+/// This function will return an error if the extended attribute cannot be read.
+pub fn get_tag_time(path: &Path, property_name: &str) -> Result<Option<SystemTime>, FileError> {
+    let time = xattr::get(path, property_name).with_context(|_| XAttrSnafu { path })?;
+    Ok(time
+        .and_then(|time| String::from_utf8(time).ok())
+        .and_then(|time| time.trim().parse().ok())
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs)))
+}
+
+fn set_tag_time(path: &Path, property_name: &str, time: SystemTime) -> Result<(), FileError> {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    xattr::set(path, property_name, secs.to_string().as_bytes())
+        .with_context(|_| XAttrSnafu { path })
+}
+
 /// Load all tags of the given local file using its extended attributes.
 ///
 /// # Errors
@@ -96,7 +221,11 @@ fn run_command(
 /// - the path is not a file
 pub fn get_tags_of_file(path: &Path, tag_property_name: &str) -> Result<Tags, FileError> {
     ensure!(path.is_file(), IsDirectorySnafu { path });
+    read_tags_attribute(path, tag_property_name)
+}
 
+/// Loads the tags of the given local file without checking that it is a file.
+pub(super) fn read_tags_attribute(path: &Path, tag_property_name: &str) -> Result<Tags, FileError> {
     debug!("reading tags of file {}", path.display());
 
     let tag = xattr::get(path, tag_property_name)
@@ -128,3 +257,40 @@ pub enum FileError {
 pub enum LocalError {
     Join { source: JoinError },
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coalesce_commands_per_file() {
+        let action = |tag: &str, modification| TagAction {
+            tag: tag.parse().unwrap(),
+            modification,
+        };
+        let command = |path, actions| Command {
+            path: SyncedPath::new(0, path),
+            actions,
+        };
+
+        let merged = coalesce([
+            command("a.txt", vec![action("red", Modification::Add)]),
+            command("b.txt", vec![action("red", Modification::Add)]),
+            command("a.txt", vec![action("blue", Modification::Remove)]),
+        ]);
+
+        assert_eq!(
+            merged,
+            [
+                command(
+                    "a.txt",
+                    vec![
+                        action("red", Modification::Add),
+                        action("blue", Modification::Remove)
+                    ]
+                ),
+                command("b.txt", vec![action("red", Modification::Add)]),
+            ]
+        );
+    }
+}
@@ -1,16 +1,81 @@
-use std::path::Path;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
 
 use snafu::prelude::*;
-use tracing::{debug, error, warn};
+use tracing::{debug, error, info, warn};
 use walkdir::WalkDir;
 
-use crate::{Config, PrefixMapping, Repository};
+use crate::{
+    Config, FileLocation, PrefixMapping, Repository, ScanIssue, ScanIssues, SyncedPath, Tags,
+};
 
-use super::{get_tags_of_file, FileError};
+use super::{
+    fs::read_tags_attribute,
+    get_tag_time,
+    scan_cache::{change_time, ChangeTime, LocalFileId, ScanCache, ScannedFile},
+    FileError,
+};
+
+/// Result of walking all local prefixes.
+#[derive(Debug)]
+pub struct LocalScan {
+    pub repo: Repository,
+    /// Files which could not be mapped to any prefix, e.g. because of symbolic links.
+    pub quarantined: BTreeSet<PathBuf>,
+    /// Time of the last tag modification of tagged files which recorded one.
+    pub tag_times: BTreeMap<SyncedPath, SystemTime>,
+    /// Files whose tags could not be read and prefixes that could not be walked.
+    pub issues: ScanIssues,
+    /// Identity and state of the files whose tags were read.
+    pub files: BTreeMap<LocalFileId, ScannedFile>,
+    /// Tagged files which the previous scan found at another path, by their new path.
+    pub renamed: BTreeMap<PathBuf, PathBuf>,
+}
+
+impl LocalScan {
+    pub(crate) fn empty(prefixes: &[PrefixMapping]) -> Self {
+        Self {
+            repo: Repository::new(prefixes.into()),
+            quarantined: BTreeSet::new(),
+            tag_times: BTreeMap::new(),
+            issues: ScanIssues::default(),
+            files: BTreeMap::new(),
+            renamed: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the results of another scan with the same prefixes, e.g. of another prefix.
+    pub fn merge(&mut self, other: Self) {
+        self.repo.append(other.repo);
+        self.quarantined.extend(other.quarantined);
+        self.tag_times.extend(other.tag_times);
+        self.issues.extend(other.issues);
+        self.files.extend(other.files);
+        self.renamed.extend(other.renamed);
+    }
+}
+
+/// Number of paths collected by the walk before their tags are read concurrently.
+const BATCH_SIZE: usize = 4096;
+
+/// Tags and tag time read from a local file.
+struct FileTags {
+    path: PathBuf,
+    tags: Result<Tags, FileError>,
+    time: Option<SystemTime>,
+    state: Option<(LocalFileId, ChangeTime)>,
+    renamed_from: Option<PathBuf>,
+}
 
 pub struct LocalFsWalker<'a> {
     tag_property_name: &'a str,
+    tag_time_property_name: Option<&'a str>,
     prefixes: &'a [PrefixMapping],
+    threads: usize,
+    cache: Option<&'a ScanCache>,
 }
 
 impl<'a> LocalFsWalker<'a> {
@@ -18,34 +83,258 @@ impl<'a> LocalFsWalker<'a> {
     pub fn new(config: &'a Config) -> Self {
         Self {
             tag_property_name: &config.local_tag_property_name,
+            tag_time_property_name: config.tag_time_property_name.as_deref(),
             prefixes: &config.prefixes,
+            threads: config.local_scan_threads,
+            cache: None,
         }
     }
 
-    pub fn build_repository(&self) -> Repository {
-        let mut repo = Repository::new(self.prefixes.into());
-        for prefix in self.prefixes {
-            let walker = WalkDir::new(prefix.local());
-            for entry in walker {
-                let Some(path) = get_path(entry) else {
-                    continue;
-                };
+    /// Skips reading the attributes of files which were untagged and did not change since
+    /// the scan of `cache` and recognizes renamed files.
+    #[must_use]
+    pub(crate) const fn with_cache(mut self, cache: &'a ScanCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
 
-                match get_tags_of_file(&path, self.tag_property_name) {
-                    Ok(tags) => {
-                        if tags.is_empty() {
-                            debug!("skipping file: {}", path.display());
-                        } else {
-                            repo.insert_local(&path, tags);
+    /// Walks all prefixes one after another.
+    #[must_use]
+    pub fn build_repository(&self) -> LocalScan {
+        let mut scan = LocalScan::empty(self.prefixes);
+        for index in 0..self.prefixes.len() {
+            scan.merge(self.scan_prefix(index));
+        }
+        scan
+    }
+
+    /// Walks the prefix with the given index. If its folder cannot be read, e.g. because
+    /// a drive is not mounted, the prefix is reported as failed instead of being empty.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no prefix with this index.
+    #[must_use]
+    pub fn scan_prefix(&self, index: usize) -> LocalScan {
+        let prefix = &self.prefixes[index];
+        let mut scan = LocalScan::empty(self.prefixes);
+        // Nested prefixes are walked on their own.
+        let is_other_prefix = |entry: &walkdir::DirEntry| {
+            entry.path() != prefix.local()
+                && self.prefixes.iter().any(|p| p.local() == entry.path())
+        };
+        let walker = WalkDir::new(prefix.local())
+            .into_iter()
+            .filter_entry(|entry| !is_other_prefix(entry));
+        let mut batch = Vec::with_capacity(BATCH_SIZE);
+        for entry in walker {
+            let entry = match entry {
+                Err(e) if e.depth() == 0 => {
+                    error!("Failed to scan {}: {e}", prefix.local().display());
+                    scan.issues.push(ScanIssue::PrefixFailed {
+                        prefix_id: index.into(),
+                        path: prefix.local().to_owned(),
+                        error: e.to_string(),
+                    });
+                    return scan;
+                }
+                entry => entry,
+            };
+            let Some(path) = get_path(entry) else {
+                continue;
+            };
+            batch.push(path);
+            if batch.len() == BATCH_SIZE {
+                for file in self.read_tags(std::mem::take(&mut batch)) {
+                    record(&mut scan, file);
+                }
+            }
+        }
+        for file in self.read_tags(batch) {
+            record(&mut scan, file);
+        }
+        info!(
+            "Scanned {}: {} tagged files",
+            prefix.local().display(),
+            scan.repo.len()
+        );
+        scan
+    }
+
+    /// Reads the tags of the files at or below `paths`, e.g. after they were reported as
+    /// changed. Paths which no longer exist are skipped.
+    #[must_use]
+    pub fn scan_paths(&self, paths: &BTreeSet<PathBuf>) -> LocalScan {
+        let mut scan = LocalScan::empty(self.prefixes);
+        let roots = paths.iter().filter(|path| {
+            !paths
+                .iter()
+                .any(|other| other != *path && path.starts_with(other))
+        });
+        let mut batch = Vec::new();
+        for root in roots {
+            for entry in WalkDir::new(root) {
+                let entry = match entry {
+                    Err(e) if e.depth() == 0 => {
+                        if e.io_error().map(std::io::Error::kind)
+                            != Some(std::io::ErrorKind::NotFound)
+                        {
+                            error!("Failed to scan {}: {e}", root.display());
+                            scan.issues.push(ScanIssue::UnreadableFile {
+                                path: root.clone(),
+                                error: e.to_string(),
+                            });
                         }
+                        break;
                     }
-                    Err(FileError::IsDirectory { .. }) => {}
-                    Err(err) => error!("skipping file: {err}"),
-                }
+                    entry => entry,
+                };
+                batch.extend(get_path(entry));
             }
         }
+        for file in self.read_tags(batch) {
+            record(&mut scan, file);
+        }
+        scan
+    }
+
+    /// Reads the tags of `paths` on up to [`Config::local_scan_threads`] threads, keeping
+    /// the order of the paths.
+    fn read_tags(&self, paths: Vec<PathBuf>) -> Vec<FileTags> {
+        let threads = self.threads.clamp(1, paths.len().max(1));
+        if threads == 1 {
+            return paths.into_iter().map(|path| self.read(path)).collect();
+        }
+        let chunk_size = paths.len().div_ceil(threads);
+        std::thread::scope(|scope| {
+            #[expect(
+                clippy::needless_collect,
+                reason = "All workers are spawned before the first one is joined"
+            )]
+            let workers: Vec<_> = paths
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        chunk
+                            .iter()
+                            .map(|path| self.read(path.clone()))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|worker| {
+                    worker
+                        .join()
+                        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+                })
+                .collect()
+        })
+    }
+
+    fn read(&self, path: PathBuf) -> FileTags {
+        let metadata = std::fs::metadata(&path)
+            .ok()
+            .filter(std::fs::Metadata::is_file);
+        let Some(metadata) = metadata else {
+            return FileTags {
+                tags: Err(FileError::IsDirectory { path: path.clone() }),
+                path,
+                time: None,
+                state: None,
+                renamed_from: None,
+            };
+        };
+        let (id, changed) = (LocalFileId::of(&metadata), change_time(&metadata));
+        if self
+            .cache
+            .is_some_and(|cache| cache.is_untagged(id, changed))
+        {
+            return FileTags {
+                path,
+                tags: Ok(Tags::default()),
+                time: None,
+                state: Some((id, changed)),
+                renamed_from: None,
+            };
+        }
+        let tags = read_tags_attribute(&path, self.tag_property_name);
+        let (time, renamed_from) = match &tags {
+            Ok(tags) if !tags.is_empty() => (
+                self.tag_time_of(&path),
+                self.cache
+                    .and_then(|cache| cache.renamed_from(id, &path))
+                    .map(Path::to_path_buf),
+            ),
+            _ => (None, None),
+        };
+        FileTags {
+            path,
+            tags,
+            time,
+            state: Some((id, changed)),
+            renamed_from,
+        }
+    }
 
-        repo
+    fn tag_time_of(&self, path: &Path) -> Option<SystemTime> {
+        let property_name = self.tag_time_property_name?;
+        get_tag_time(path, property_name).unwrap_or_else(|e| {
+            warn!("ignoring tag time: {e}");
+            None
+        })
+    }
+}
+
+/// Adds a file read by [`LocalFsWalker::read_tags`] to `scan`.
+fn record(
+    scan: &mut LocalScan,
+    FileTags {
+        path,
+        tags,
+        time,
+        state,
+        renamed_from,
+    }: FileTags,
+) {
+    if let (Some((id, changed)), Ok(tags)) = (state, &tags) {
+        let untagged = tags.is_empty();
+        let path = path.clone();
+        scan.files.insert(
+            id,
+            ScannedFile {
+                path,
+                changed,
+                untagged,
+            },
+        );
+    }
+    if let Some(previous) = renamed_from {
+        debug!("{} was renamed from {}", path.display(), previous.display());
+        scan.renamed.insert(path.clone(), previous);
+    }
+    match tags {
+        Ok(tags) => {
+            if tags.is_empty() {
+                debug!("skipping file: {}", path.display());
+            } else if let Err(e) = scan.repo.insert_local(&path, tags) {
+                warn!("skipping file: {e}");
+                scan.quarantined.insert(path);
+            } else if let Some(time) = time {
+                if let Ok(synced) = scan.repo.synced_path_of(&path, FileLocation::Local) {
+                    scan.tag_times.insert(synced, time);
+                }
+            }
+        }
+        Err(FileError::IsDirectory { .. }) => {}
+        Err(err) => {
+            error!("skipping file: {err}");
+            scan.issues.push(ScanIssue::UnreadableFile {
+                path,
+                error: err.to_string(),
+            });
+        }
     }
 }
 
@@ -61,7 +350,7 @@ fn get_path(entry: Result<walkdir::DirEntry, walkdir::Error>) -> Option<std::pat
                 );
             } else {
                 error!("Could not access path: {e}");
-                if e.path().map(Path::is_dir) == Some(true) {
+                if e.path().is_some_and(Path::is_dir) {
                     warn!("Ignoring all files in this subtree.");
                 }
             }
@@ -75,3 +364,83 @@ fn get_path(entry: Result<walkdir::DirEntry, walkdir::Error>) -> Option<std::pat
 pub struct FileSystemLoopError {
     pub source: walkdir::Error,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scan_changed_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            prefixes: vec![PrefixMapping::new(
+                dir.path().to_owned(),
+                "/remote.php/dav/files/user".into(),
+            )
+            .unwrap()],
+            ..Config::default()
+        };
+        let folder = dir.path().join("folder");
+        std::fs::create_dir(&folder).unwrap();
+        for path in [folder.join("a.txt"), dir.path().join("b.txt")] {
+            std::fs::write(&path, "").unwrap();
+            xattr::set(&path, &config.local_tag_property_name, b"red").unwrap();
+        }
+
+        let paths = BTreeSet::from([
+            folder.clone(),
+            folder.join("a.txt"),
+            dir.path().join("deleted.txt"),
+        ]);
+        let scan = LocalFsWalker::new(&config).scan_paths(&paths);
+        let files: Vec<_> = scan.repo.files().map(|(path, _)| path.clone()).collect();
+        assert_eq!(files, [SyncedPath::new(0, "folder/a.txt")]);
+        assert!(scan.issues.is_empty());
+    }
+
+    #[test]
+    fn read_tags_concurrently() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            prefixes: vec![PrefixMapping::new(
+                dir.path().to_owned(),
+                "/remote.php/dav/files/user".into(),
+            )
+            .unwrap()],
+            local_scan_threads: 3,
+            ..Config::default()
+        };
+        let paths: Vec<_> = (0..10)
+            .map(|i| dir.path().join(format!("{i}.txt")))
+            .collect();
+        for (i, path) in paths.iter().enumerate() {
+            std::fs::write(path, "").unwrap();
+            if i % 2 == 0 {
+                xattr::set(
+                    path,
+                    &config.local_tag_property_name,
+                    format!("tag{i}").as_bytes(),
+                )
+                .unwrap();
+            }
+        }
+
+        let walker = LocalFsWalker::new(&config);
+        let read = walker.read_tags(paths.clone());
+        let read: Vec<_> = read
+            .iter()
+            .map(|file| (&file.path, file.tags.as_ref().unwrap()))
+            .collect();
+        assert_eq!(read.len(), paths.len());
+        for (i, (path, tags)) in read.into_iter().enumerate() {
+            assert_eq!(path, &paths[i]);
+            let expected = if i % 2 == 0 {
+                format!("tag{i}")
+            } else {
+                String::new()
+            };
+            assert_eq!(tags, &expected.parse::<Tags>().unwrap());
+        }
+        assert_eq!(walker.scan_prefix(0).repo.len(), 5);
+    }
+}
@@ -0,0 +1,251 @@
+use std::{
+    collections::BTreeMap,
+    fs::Metadata,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use atomic_write_file::AtomicWriteFile;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::Config;
+
+/// Seconds and nanoseconds of the last status change of a file. Unlike the modification
+/// time, it also changes when an extended attribute is written.
+pub type ChangeTime = (i64, i64);
+
+/// Files which changed this many seconds before a scan are not cached, because the clock
+/// of the file system is coarse and a change right after reading the file could keep its
+/// change time.
+const RACY_SECS: i64 = 2;
+
+/// Identity of a local file which is kept when the file is renamed or moved within its
+/// file system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct LocalFileId {
+    pub device: u64,
+    pub inode: u64,
+}
+
+impl LocalFileId {
+    #[must_use]
+    pub fn of(metadata: &Metadata) -> Self {
+        use std::os::unix::fs::MetadataExt;
+        Self {
+            device: metadata.dev(),
+            inode: metadata.ino(),
+        }
+    }
+}
+
+#[must_use]
+pub fn change_time(metadata: &Metadata) -> ChangeTime {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.ctime(), metadata.ctime_nsec())
+}
+
+/// State of a file found by a scan.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScannedFile {
+    pub path: PathBuf,
+    pub changed: ChangeTime,
+    /// The file had no tags at `changed`.
+    pub untagged: bool,
+}
+
+/// Local files of the previous scan by their identity, so later scans can skip reading
+/// the attributes of untagged files while they are unchanged and recognize renamed files.
+/// Stored next to the tag database.
+#[derive(Debug, Default)]
+pub struct ScanCache {
+    /// Attribute the files had no tags in. The cache is void if it is configured differently.
+    property_name: String,
+    files: BTreeMap<LocalFileId, ScannedFile>,
+}
+
+/// JSON objects only have string keys, so the files are stored as a list.
+#[derive(Serialize, Deserialize)]
+struct StoredCache {
+    property_name: String,
+    files: Vec<(LocalFileId, ScannedFile)>,
+}
+
+impl From<StoredCache> for ScanCache {
+    fn from(stored: StoredCache) -> Self {
+        Self {
+            property_name: stored.property_name,
+            files: stored.files.into_iter().collect(),
+        }
+    }
+}
+
+impl From<ScanCache> for StoredCache {
+    fn from(cache: ScanCache) -> Self {
+        Self {
+            property_name: cache.property_name,
+            files: cache.files.into_iter().collect(),
+        }
+    }
+}
+
+fn path(config: &Config) -> PathBuf {
+    config.tag_database.with_extension("scan-cache.json")
+}
+
+impl ScanCache {
+    /// Caches the `files` of a scan which started at `started`.
+    #[must_use]
+    pub fn new(
+        config: &Config,
+        mut files: BTreeMap<LocalFileId, ScannedFile>,
+        started: SystemTime,
+    ) -> Self {
+        let started = started.duration_since(UNIX_EPOCH).map_or(0, |since| {
+            i64::try_from(since.as_secs()).unwrap_or(i64::MAX)
+        });
+        for file in files.values_mut() {
+            file.untagged &= file.changed.0 < started.saturating_sub(RACY_SECS);
+        }
+        Self {
+            property_name: config.local_tag_property_name.clone(),
+            files,
+        }
+    }
+
+    /// Loads the files of the previous scan.
+    pub fn load(config: &Config) -> Self {
+        let path = path(config);
+        let data = match std::fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Self::default(),
+            Err(e) => {
+                warn!("Failed to read scan cache {}: {e}", path.display());
+                return Self::default();
+            }
+        };
+        let cache = serde_json::from_slice::<StoredCache>(&data).map_or_else(
+            |e| {
+                warn!("Ignoring invalid scan cache {}: {e}", path.display());
+                Self::default()
+            },
+            Self::from,
+        );
+        if cache.property_name == config.local_tag_property_name {
+            cache
+        } else {
+            Self::default()
+        }
+    }
+
+    pub fn persist(self, config: &Config) {
+        let path = path(config);
+        let write = || -> std::io::Result<()> {
+            let data = serde_json::to_vec(&StoredCache::from(self))?;
+            let mut file = AtomicWriteFile::open(&path)?;
+            std::io::Write::write_all(&mut file, &data)?;
+            file.commit()
+        };
+        if let Err(e) = write() {
+            warn!("Failed to store scan cache {}: {e}", path.display());
+        }
+    }
+
+    /// Whether the file had no tags and its status did not change since, whatever its
+    /// path is now.
+    #[must_use]
+    pub fn is_untagged(&self, id: LocalFileId, changed: ChangeTime) -> bool {
+        self.files
+            .get(&id)
+            .is_some_and(|file| file.untagged && file.changed == changed)
+    }
+
+    /// Path the previous scan found the file at, if it is not `path` and was not found
+    /// there anymore, i.e. the file was renamed or moved.
+    #[must_use]
+    pub fn renamed_from(&self, id: LocalFileId, path: &Path) -> Option<&Path> {
+        let previous = &self.files.get(&id)?.path;
+        (previous != path && !previous.exists()).then_some(previous.as_path())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{LocalFsWalker, PrefixMapping};
+
+    #[test]
+    fn skip_unchanged_untagged_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            prefixes: vec![PrefixMapping::new(
+                dir.path().to_owned(),
+                "/remote.php/dav/files/user".into(),
+            )
+            .unwrap()],
+            ..Config::default()
+        };
+        let untagged = dir.path().join("untagged.txt");
+        let tagged = dir.path().join("tagged.txt");
+        std::fs::write(&untagged, "").unwrap();
+        std::fs::write(&tagged, "").unwrap();
+        xattr::set(&tagged, &config.local_tag_property_name, b"red").unwrap();
+        let id = |path| LocalFileId::of(&std::fs::metadata(path).unwrap());
+
+        let scan = LocalFsWalker::new(&config).scan_prefix(0);
+        assert_eq!(scan.repo.len(), 1);
+        assert!(scan.files[&id(&untagged)].untagged);
+        assert!(!scan.files[&id(&tagged)].untagged);
+        let now = SystemTime::now();
+        let cache = ScanCache::new(&config, scan.files.clone(), now);
+        assert!(cache.files.values().all(|file| !file.untagged));
+
+        // A cached file is not read again while its change time is the same.
+        let later = now + Duration::from_mins(1);
+        let cached = |changed: fn(ChangeTime) -> ChangeTime| {
+            let mut files = scan.files.clone();
+            let file = files.get_mut(&id(&tagged)).unwrap();
+            file.untagged = true;
+            file.changed = changed(file.changed);
+            ScanCache::new(&config, files, later)
+        };
+        let cache = cached(|changed| changed);
+        let scan = LocalFsWalker::new(&config)
+            .with_cache(&cache)
+            .scan_prefix(0);
+        assert!(scan.repo.is_empty());
+
+        let cache = cached(|(secs, nanos)| (secs - 10, nanos));
+        let scan = LocalFsWalker::new(&config)
+            .with_cache(&cache)
+            .scan_prefix(0);
+        assert_eq!(scan.repo.len(), 1);
+    }
+
+    #[test]
+    fn detect_renamed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            prefixes: vec![PrefixMapping::new(
+                dir.path().to_owned(),
+                "/remote.php/dav/files/user".into(),
+            )
+            .unwrap()],
+            ..Config::default()
+        };
+        let old = dir.path().join("old.txt");
+        let new = dir.path().join("new.txt");
+        std::fs::write(&old, "").unwrap();
+        xattr::set(&old, &config.local_tag_property_name, b"red").unwrap();
+
+        let scan = LocalFsWalker::new(&config).scan_prefix(0);
+        let cache = ScanCache::new(&config, scan.files, SystemTime::now());
+        std::fs::rename(&old, &new).unwrap();
+        let scan = LocalFsWalker::new(&config)
+            .with_cache(&cache)
+            .scan_prefix(0);
+        assert_eq!(scan.renamed, BTreeMap::from([(new, old)]));
+    }
+}
@@ -1,41 +1,583 @@
-use std::sync::Arc;
+use std::{path::PathBuf, sync::Arc, time::Duration};
 
-use nextcloud_tag_sync::{load_config, Uninitialized};
+use clap::{Parser, Subcommand};
+use nextcloud_tag_sync::{
+    check_database, compact_database, create_debug_bundle, delete_tag, diagnose, diff_databases,
+    find_tagged, init_language, inspect_database, install_panic_hook, load_config, push_tags,
+    rebind_database, release_looping_files, remote_tags_of, rename_tag, restore_backup,
+    tag_origins_of, tags_of, time_until_scan_window, translate, CommandFilter, CommandsFormatter,
+    Config, Connection, DryRunReport, FormatOptions, InitError, Initialized, Inspection,
+    LocalChanges, OcsUserInfo, PendingChanges, SortOrder, SyncReport, Tag, Uninitialized, Watcher,
+};
 use snafu::{prelude::*, Whatever};
-use tracing::info;
+use tracing::{error, info, warn};
 use tracing_subscriber::EnvFilter;
 
+/// Synchronize file tags between the local file system and a Nextcloud instance.
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<CliCommand>,
+    /// Only print warnings, errors and problems of the synchronization, e.g. for cron jobs.
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Print progress information. Repeat for debug and trace output.
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Only use the configuration of this user, e.g. one of the configured `users`.
+    #[arg(long, global = true, value_name = "NAME")]
+    user: Option<String>,
+}
+
+impl Cli {
+    /// The flags take precedence over `RUST_LOG`.
+    fn log_filter(&self) -> EnvFilter {
+        let directive = match (self.quiet, self.verbose) {
+            (true, _) => "warn",
+            (false, 0) => return EnvFilter::from_default_env(),
+            (false, 1) => "info",
+            (false, 2) => "warn,nextcloud_tag_sync=debug",
+            (false, _) => "info,nextcloud_tag_sync=trace",
+        };
+        EnvFilter::new(directive)
+    }
+}
+
+#[derive(Subcommand)]
+enum CliCommand {
+    /// Synchronize tags in both directions (default).
+    Sync {
+        /// Continue an interrupted synchronization instead of starting a new one.
+        #[arg(long)]
+        resume: bool,
+        /// Artificially fail this percentage of commands to test the recovery from failures.
+        #[arg(long, value_name = "PCT")]
+        simulate_failures: Option<f64>,
+        /// Run an initial synchronization even if it changes many files.
+        #[arg(long)]
+        confirm_initial: bool,
+        /// Stop after this many seconds and continue with the remaining files in the next run.
+        #[arg(long, value_name = "SECS")]
+        max_duration: Option<u64>,
+        /// Only print the changes instead of applying them.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Synchronize once, then upload the tags of local files as they change.
+    Watch {
+        /// Additionally synchronize everything in both directions at this interval, so
+        /// changes on Nextcloud are downloaded. Waits for the configured full scan windows.
+        #[arg(long, value_name = "SECS")]
+        full_sync_every: Option<u64>,
+    },
+    /// Show the changes a synchronization would apply without applying them.
+    Status {
+        /// Only show changes of files below this local or remote path. Can be repeated.
+        #[arg(long, value_name = "PATH")]
+        filter_path: Vec<PathBuf>,
+        /// Only show changes of this tag. Can be repeated.
+        #[arg(long, value_name = "TAG")]
+        filter_tag: Vec<Tag>,
+    },
+    /// List the local files with the given tag according to the tag database.
+    Find { tag: Tag },
+    /// Show the tags of a local file according to the tag database and which side added them.
+    Tags {
+        path: PathBuf,
+        /// Ask Nextcloud for the tags currently assigned to the file.
+        #[arg(long)]
+        remote: bool,
+    },
+    /// Check the connectivity of the Nextcloud instance over IPv4 and IPv6.
+    Doctor {
+        /// Additionally check a local proxy listening on this unix socket.
+        #[arg(long, value_name = "PATH")]
+        unix_socket: Option<PathBuf>,
+    },
+    /// Collect the configuration, crash report and an anonymized tag database into an
+    /// archive that can be attached to issues.
+    DebugBundle {
+        #[arg(long, short, default_value = "nextcloud-tag-sync-debug.tar")]
+        output: PathBuf,
+    },
+    /// Add the tags of a backup, written before a synchronization removed tags, to the
+    /// local files again. The next synchronization uploads them.
+    RestoreBackup { file: PathBuf },
+    /// Rename a tag on Nextcloud. The next synchronization renames it on the local files.
+    RenameTag { old: Tag, new: Tag },
+    /// Create all tags of the local files on Nextcloud without assigning them, so their names
+    /// can be reviewed centrally before synchronizing.
+    PushTags,
+    /// Delete a tag on Nextcloud. The next synchronization removes it from the local files.
+    DeleteTag {
+        tag: Tag,
+        /// Delete the tag even if it is still assigned to files.
+        #[arg(long)]
+        force: bool,
+    },
+    /// Maintain the persisted tag database.
+    #[command(subcommand)]
+    Db(DbCommand),
+}
+
+#[derive(Subcommand)]
+enum DbCommand {
+    /// Drop entries without tags or whose files no longer exist on either side.
+    Compact,
+    /// Match the tag database against a migrated Nextcloud instance by tag name and path.
+    Rebind,
+    /// Check the tag database for anomalies.
+    Fsck {
+        /// Persist the repaired tag database.
+        #[arg(long)]
+        repair: bool,
+    },
+    /// Synchronize files again which were held back because their tags kept flipping.
+    ReleaseLoops,
+    /// Show the contents of any tag database or snapshot. Needs no configuration.
+    Inspect {
+        path: PathBuf,
+        /// Only list the files with this tag.
+        #[arg(long)]
+        tag: Option<Tag>,
+    },
+    /// Show what changed between two tag databases or snapshots. Needs no configuration.
+    Diff { old: PathBuf, new: PathBuf },
+}
+
 #[tokio::main]
 #[snafu::report]
 async fn main() -> Result<(), Whatever> {
+    let cli = Cli::parse();
     tracing_subscriber::fmt()
         .with_ansi(atty::is(atty::Stream::Stdout))
-        .with_env_filter(EnvFilter::from_default_env())
+        .with_env_filter(cli.log_filter())
         .init();
-    let config = Arc::new(load_config().whatever_context("failed to load config")?);
-    info!("Starting with configuration: {config}");
+    if let Some(CliCommand::Db(DbCommand::Inspect { path, tag })) = cli.command {
+        init_language(None);
+        let repo = inspect_database(&path).whatever_context("failed to read tag database")?;
+        let inspection = Inspection {
+            repo,
+            tag,
+            sort_order: SortOrder::default(),
+        };
+        print!("{inspection}");
+        return Ok(());
+    }
+    if let Some(CliCommand::Db(DbCommand::Diff { old, new })) = cli.command {
+        init_language(None);
+        let diff =
+            diff_databases(&old, &new).whatever_context("failed to compare tag databases")?;
+        print!("{diff}");
+        return Ok(());
+    }
+    let mut config = load_config().whatever_context("failed to load config")?;
+    config.quiet |= cli.quiet;
+    init_language(config.language.as_deref());
+    install_panic_hook(&config);
+    let command = cli.command.unwrap_or(CliCommand::Sync {
+        resume: false,
+        simulate_failures: None,
+        confirm_initial: false,
+        max_duration: None,
+        dry_run: false,
+    });
+    if let CliCommand::Sync {
+        confirm_initial: true,
+        ..
+    } = command
+    {
+        config.confirm_initial_sync = true;
+    }
+    if let CliCommand::Sync {
+        simulate_failures: Some(percentage),
+        ..
+    } = command
+    {
+        ensure_whatever!(
+            (0.0..=100.0).contains(&percentage),
+            "--simulate-failures must be between 0 and 100"
+        );
+        config.simulate_failures = percentage;
+    }
+    if let CliCommand::Sync {
+        max_duration: Some(secs),
+        ..
+    } = command
+    {
+        config.max_duration_secs = Some(secs);
+    }
+    if let CliCommand::Sync { dry_run: true, .. } = command {
+        config.dry_run = true;
+    }
+    if config.simulate_failures > 0.0 {
+        warn!(
+            "Simulating failure of {}% of commands. Affected files keep their previous tags.",
+            config.simulate_failures
+        );
+    }
+    let mut configs = config.user_configs();
+    if let Some(user) = &cli.user {
+        configs.retain(|config| &config.user == user);
+        ensure_whatever!(!configs.is_empty(), "user {user} is not configured");
+    }
+    let mut configs: Vec<_> = configs.into_iter().map(Arc::new).collect();
+    for config in &configs {
+        info!("Starting with configuration: {config}");
+    }
+    if let CliCommand::Sync { resume, .. } = command {
+        return sync_users(configs, resume).await;
+    }
+    ensure_whatever!(
+        configs.len() == 1,
+        "select one of the configured users with --user"
+    );
+    let config = configs.remove(0);
+
+    match command {
+        CliCommand::Sync { .. } => unreachable!("handled for all users above"),
+        CliCommand::Watch { full_sync_every } => {
+            watch(config, full_sync_every.map(Duration::from_secs)).await
+        }
+        CliCommand::Status {
+            filter_path,
+            filter_tag,
+        } => {
+            let filter = CommandFilter {
+                paths: filter_path,
+                tags: filter_tag,
+            };
+            status(config, &filter).await
+        }
+        CliCommand::Find { tag } => {
+            let files = find_tagged(&config, &tag).whatever_context("failed to query files")?;
+            for file in files {
+                println!("{}", file.display());
+            }
+            Ok(())
+        }
+        CliCommand::Tags { path, remote } => {
+            let tags = if remote {
+                remote_tags_of(&config, &path).await
+            } else {
+                tags_of(&config, &path)
+            };
+            match tags.whatever_context("failed to query tags")? {
+                Some(tags) => println!("{tags}"),
+                None => println!("{} is not tracked", path.display()),
+            }
+            if !remote {
+                let origins =
+                    tag_origins_of(&config, &path).whatever_context("failed to query tags")?;
+                for (tag, origin) in origins {
+                    println!("  {tag}: {origin}");
+                }
+            }
+            Ok(())
+        }
+        CliCommand::Doctor { unix_socket } => {
+            let report = diagnose(&config, unix_socket.as_deref()).await;
+            print!("{report}");
+            ensure_whatever!(
+                report.is_healthy(),
+                "Nextcloud instance is not fully reachable"
+            );
+            Ok(())
+        }
+        CliCommand::DebugBundle { output } => {
+            create_debug_bundle(&config, &output)
+                .whatever_context("failed to create debug bundle")?;
+            println!("Debug bundle written to {}", output.display());
+            Ok(())
+        }
+        CliCommand::RestoreBackup { file } => {
+            let report = restore_backup(config, &file)
+                .await
+                .whatever_context("failed to restore tag backup")?;
+            print!("{report}");
+            Ok(())
+        }
+        CliCommand::RenameTag { old, new } => {
+            rename_tag(config, &old, new.clone())
+                .await
+                .whatever_context("failed to rename tag")?;
+            println!("Renamed {old} to {new}.");
+            Ok(())
+        }
+        CliCommand::PushTags => {
+            let report = push_tags(config)
+                .await
+                .whatever_context("failed to push tags")?;
+            print!("{report}");
+            Ok(())
+        }
+        CliCommand::DeleteTag { tag, force } => {
+            delete_tag(config, &tag, force)
+                .await
+                .whatever_context("failed to delete tag")?;
+            println!("Deleted {tag}.");
+            Ok(())
+        }
+        CliCommand::Db(DbCommand::Compact) => {
+            let report = compact_database(config)
+                .await
+                .whatever_context("failed to compact tag database")?;
+            print!("{report}");
+            Ok(())
+        }
+        CliCommand::Db(DbCommand::Rebind) => {
+            let report = rebind_database(config)
+                .await
+                .whatever_context("failed to rebind tag database")?;
+            print!("{report}");
+            Ok(())
+        }
+        CliCommand::Db(DbCommand::Fsck { repair }) => {
+            let report =
+                check_database(&config, repair).whatever_context("failed to check tag database")?;
+            print!("{report}");
+            if repair && !report.is_clean() {
+                println!("Repaired tag database written.");
+            }
+            Ok(())
+        }
+        CliCommand::Db(DbCommand::ReleaseLoops) => {
+            let released = release_looping_files(&config);
+            println!("Released {released} files.");
+            Ok(())
+        }
+        CliCommand::Db(DbCommand::Inspect { .. } | DbCommand::Diff { .. }) => {
+            unreachable!("handled before loading config")
+        }
+    }
+}
 
+fn ensure_test_instance(config: &Config) -> Result<(), Whatever> {
     ensure_whatever!(
         config.nextcloud_instance.host() == Some(url::Host::Domain("localhost")),
         "use docker nextcloud for test!"
     );
+    Ok(())
+}
 
-    let uninitialized = Uninitialized::new(config);
-    let mut initialized = uninitialized
-        .initialize()
-        .await
-        .whatever_context("failed to initialize repository")?;
-    initialized
-        .sync_local_to_remote()
+/// Synchronizes the users one after another. A failing user does not stop the others.
+async fn sync_users(configs: Vec<Arc<Config>>, resume: bool) -> Result<(), Whatever> {
+    if let [config] = configs.as_slice() {
+        return sync(config.clone(), resume).await;
+    }
+    let mut failed = Vec::new();
+    for config in configs {
+        info!("Synchronizing user {}", config.user);
+        if let Err(e) = sync(config.clone(), resume).await {
+            error!(
+                "Failed to synchronize user {}: {}",
+                config.user,
+                snafu::Report::from_error(e)
+            );
+            failed.push(config.user.clone());
+        }
+    }
+    ensure_whatever!(
+        failed.is_empty(),
+        "failed to synchronize users {}",
+        failed.join(", ")
+    );
+    Ok(())
+}
+
+async fn sync(config: Arc<Config>, resume: bool) -> Result<(), Whatever> {
+    ensure_test_instance(&config)?;
+    if config.dry_run {
+        return dry_run(&config).await;
+    }
+
+    // Time-boxed runs always continue where the previous run stopped.
+    if resume || config.max_duration_secs.is_some() {
+        let resumed = match Uninitialized::new(config.clone()).resume().await {
+            Ok(resumed) => resumed,
+            Err(InitError::DeadlineExceeded { files }) => {
+                print_deadline_exceeded(files);
+                return Ok(());
+            }
+            Err(e) => return Err(e).whatever_context("failed to resume session"),
+        };
+        if let Some(initialized) = resumed {
+            return finish(&config, &initialized, &initialized.report());
+        }
+        info!("No unfinished session found. Starting a new synchronization.");
+    }
+
+    let Some(mut initialized) = initialize(&config).await? else {
+        return Ok(());
+    };
+    let report = match initialized.sync().await {
+        Ok(report) => report,
+        Err(InitError::DeadlineExceeded { files }) => {
+            print_deadline_exceeded(files);
+            return Ok(());
+        }
+        Err(e) => return Err(e).whatever_context("failed to synchronize tags"),
+    };
+    finish(&config, &initialized, &report)
+}
+
+/// Prints the changes of a synchronization and optionally writes them as JSON.
+async fn dry_run(config: &Arc<Config>) -> Result<(), Whatever> {
+    let changes = Uninitialized::new(config.clone())
+        .status()
         .await
-        .whatever_context("failed to sync local to remote")?;
-    initialized
-        .sync_remote_to_local()
+        .whatever_context("failed to compute pending changes")?;
+    let report = DryRunReport {
+        changes,
+        format: FormatOptions::from_config(config),
+    };
+    print!("{report}");
+    if let Some(path) = &config.dry_run_report {
+        report
+            .write_json(&config.prefixes, path)
+            .with_whatever_context(|_| format!("failed to write {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Starts a new synchronization. Returns `None` if it stopped early with a message.
+async fn initialize(config: &Arc<Config>) -> Result<Option<Initialized>, Whatever> {
+    match Uninitialized::new(config.clone()).initialize().await {
+        Ok(initialized) => Ok(Some(initialized)),
+        Err(InitError::ConfirmationRequired { files, changes }) => {
+            print_changes(config, changes, &CommandFilter::default());
+            let mut args = fluent_bundle::FluentArgs::new();
+            args.set("files", files);
+            println!("{}", translate("sync-confirm-initial", Some(&args)));
+            Ok(None)
+        }
+        Err(InitError::DeadlineExceeded { files }) => {
+            print_deadline_exceeded(files);
+            Ok(None)
+        }
+        Err(e) => Err(e).whatever_context("failed to initialize repository"),
+    }
+}
+
+/// Synchronizes once and then uploads local changes reported by the file system until
+/// a synchronization fails.
+async fn watch(config: Arc<Config>, full_sync_every: Option<Duration>) -> Result<(), Whatever> {
+    ensure_test_instance(&config)?;
+    // Started first, so changes during the initial synchronization are not missed.
+    let mut watcher = Watcher::new(&config).whatever_context("failed to watch local files")?;
+    let Some(mut initialized) = initialize(&config).await? else {
+        return Ok(());
+    };
+    let report = initialized
+        .sync()
         .await
-        .whatever_context("failed to sync remote to local")?;
+        .whatever_context("failed to synchronize tags")?;
+    finish(&config, &initialized, &report)?;
+    let mut full_sync = full_sync_every
+        .map(|period| tokio::time::interval_at(tokio::time::Instant::now() + period, period));
+    // Full synchronizations wait for the next of the configured scan windows.
+    let mut full_sync_pending = false;
+    loop {
+        let full_sync_due = async {
+            match &mut full_sync {
+                Some(interval) => drop(interval.tick().await),
+                None => std::future::pending().await,
+            }
+        };
+        let window_open = async {
+            if full_sync_pending {
+                tokio::time::sleep(time_until_scan_window(&config.full_scan_windows)).await;
+            } else {
+                std::future::pending().await
+            }
+        };
+        let result = tokio::select! {
+            changes = watcher.changes() => match changes {
+                LocalChanges::Paths(paths) => {
+                    info!("Uploading the tags of {} changed local paths", paths.len());
+                    initialized.sync_local_changes(paths).await
+                }
+                LocalChanges::Rescan => {
+                    warn!("Lost track of local changes, scheduling a full synchronization");
+                    full_sync_pending = true;
+                    continue;
+                }
+            },
+            () = full_sync_due => {
+                full_sync_pending = true;
+                continue;
+            }
+            () = window_open => {
+                full_sync_pending = false;
+                initialized.sync().await.map(drop)
+            }
+        };
+        result.whatever_context("failed to synchronize tags")?;
+        initialized
+            .persist_repository()
+            .whatever_context("failed to persist repository")?;
+    }
+}
+
+/// Persists the repository and prints the report. In strict mode, any failed command
+/// fails the run before the repository is persisted.
+fn finish(config: &Config, initialized: &Initialized, report: &SyncReport) -> Result<(), Whatever> {
+    let failed = report.failed_commands();
+    if config.strict && failed > 0 {
+        print_report(report);
+        whatever!("{failed} commands failed in strict mode, the tag database was not updated");
+    }
     initialized
         .persist_repository()
         .whatever_context("failed to persist repository")?;
+    print_report(report);
+    Ok(())
+}
 
+async fn status(config: Arc<Config>, filter: &CommandFilter) -> Result<(), Whatever> {
+    let changes = Uninitialized::new(config.clone())
+        .status()
+        .await
+        .whatever_context("failed to compute pending changes")?;
+    let uploads = !changes.remote.is_empty();
+    print_changes(&config, changes, filter);
+    if uploads {
+        warn_exhausted_quota(&config).await;
+    }
     Ok(())
 }
+
+/// Tag writes may fail with 507 Insufficient Storage once the quota is used up.
+async fn warn_exhausted_quota(config: &Config) {
+    match Connection::from_config(config).request(OcsUserInfo).await {
+        Ok(quota) if quota.is_exhausted() => println!("{}", translate("quota-exhausted", None)),
+        Ok(_) => {}
+        Err(e) => warn!("Failed to fetch quota: {e}"),
+    }
+}
+
+fn print_changes(config: &Config, changes: PendingChanges, filter: &CommandFilter) {
+    let format = FormatOptions::from_config(config);
+    for (side, commands) in [("remote", changes.remote), ("local", changes.local)] {
+        let commands = filter.apply(commands, &config.prefixes);
+        if commands.is_empty() {
+            println!("{}", translate(&format!("status-none-{side}"), None));
+        } else {
+            println!("{}", translate(&format!("status-pending-{side}"), None));
+            print!("{}", CommandsFormatter(&commands, format));
+        }
+    }
+}
+
+fn print_deadline_exceeded(files: usize) {
+    let mut args = fluent_bundle::FluentArgs::new();
+    args.set("files", files);
+    println!("{}", translate("sync-deadline-exceeded", Some(&args)));
+}
+
+fn print_report(report: &SyncReport) {
+    if !report.format.quiet || !report.is_empty() {
+        print!("{report}");
+    }
+}
@@ -0,0 +1,408 @@
+use std::collections::{BTreeSet, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use snafu::{ensure, ResultExt, Snafu};
+
+use crate::{
+    i18n::tr,
+    tag_repository::{IntegrityReport, LoadError, PersistingError},
+    Config, FileSystem, InitError, LocalFs, ManageTagError, RemoteFs, Repository, Tag, Tags,
+};
+
+/// Summary of a database compaction.
+#[derive(Debug, Default)]
+pub struct CompactionReport {
+    pub untagged: usize,
+    pub deleted: usize,
+    pub remaining: usize,
+}
+
+impl std::fmt::Display for CompactionReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "{}", tr!("compaction-untagged", count = self.untagged))?;
+        writeln!(f, "{}", tr!("compaction-deleted", count = self.deleted))?;
+        writeln!(f, "{}", tr!("compaction-remaining", count = self.remaining))
+    }
+}
+
+/// Rewrite the persisted repository without entries that no longer carry information:
+/// entries without any tags and entries whose file exists neither locally nor remotely.
+///
+/// # Errors
+///
+/// This function will return an error if the repository cannot be loaded or persisted.
+pub async fn compact_database(config: Arc<Config>) -> Result<CompactionReport, MaintenanceError> {
+    let path = &config.tag_database;
+    let mut repo = Repository::read_from_disk(path)
+        .context(LoadSnafu)?
+        .migrate_prefixes(&config.prefixes);
+
+    let untagged = repo.retain(|_, tags| !tags.is_empty());
+
+    let missing_locally: Vec<_> = repo
+        .files()
+        .map(|(path, _)| path)
+        .filter(|path| !path.local_file(&config.prefixes).exists())
+        .cloned()
+        .collect();
+    tracing::info!(
+        "{} files are missing locally, checking remote",
+        missing_locally.len()
+    );
+
+    let remote_fs = RemoteFs::new(config.clone());
+    let missing: HashSet<_> = remote_fs
+        .find_missing_files(missing_locally)
+        .await
+        .into_iter()
+        .collect();
+    ensure!(!remote_fs.in_maintenance(), ServerMaintenanceSnafu);
+    let deleted = repo.retain(|path, _| !missing.contains(path));
+
+    repo.persist_on_disk(path).context(PersistSnafu)?;
+
+    Ok(CompactionReport {
+        untagged,
+        deleted,
+        remaining: repo.len(),
+    })
+}
+
+/// Summary of rebinding the tag database to a migrated Nextcloud instance.
+#[derive(Debug, Default)]
+pub struct RebindReport {
+    /// Files found on the server with all cached tags.
+    pub matched: usize,
+    /// Files found on the server without some of their cached tags.
+    pub partial: usize,
+    /// Files which do not exist on the server.
+    pub missing: usize,
+    /// Cached tags which do not exist on the server. They are created in the next synchronization.
+    pub unknown_tags: BTreeSet<Tag>,
+}
+
+impl std::fmt::Display for RebindReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "{}", tr!("rebind-matched", count = self.matched))?;
+        writeln!(f, "{}", tr!("rebind-partial", count = self.partial))?;
+        writeln!(f, "{}", tr!("rebind-missing", count = self.missing))?;
+        for tag in &self.unknown_tags {
+            let tag = tag.to_string();
+            writeln!(f, "{}", tr!("rebind-unknown-tag", tag = tag))?;
+        }
+        Ok(())
+    }
+}
+
+/// Matches the cached tags by name and the cached files by path against the tags and file
+/// ids of a migrated Nextcloud instance, whose ids all changed.
+///
+/// Cached tags which the server lost are dropped from the database, so the next
+/// synchronization uploads them from the local files instead of removing them locally.
+/// Prefixes are migrated, e.g. to the user on the new instance, as long as their local or
+/// remote path is unchanged. The database is left untouched if rebinding fails.
+///
+/// # Errors
+///
+/// This function will return an error if the repository cannot be loaded or persisted,
+/// a cached prefix matches no configured prefix or the server cannot be scanned.
+pub async fn rebind_database(config: Arc<Config>) -> Result<RebindReport, MaintenanceError> {
+    rebind(RemoteFs::new(config.clone()), &config).await
+}
+
+async fn rebind(
+    mut remote_fs: RemoteFs,
+    config: &Config,
+) -> Result<RebindReport, MaintenanceError> {
+    let path = &config.tag_database;
+    let cached = Repository::read_from_disk(path).context(LoadSnafu)?;
+    if let Some(prefix) = cached.unmatched_prefixes(&config.prefixes).first() {
+        return UnmatchedPrefixSnafu {
+            local: prefix.local(),
+            remote: prefix.remote(),
+        }
+        .fail();
+    }
+    let cached = cached.migrate_prefixes(&config.prefixes);
+
+    let (remote, _) = remote_fs.create_repo().await.context(ScanSnafu)?;
+    let known_tags: HashSet<_> = remote_fs.tags.right_values().collect();
+
+    let mut report = RebindReport::default();
+    let mut unbound = Vec::new();
+    let mut repo = Repository::new(cached.prefixes().to_vec());
+    for (file, tags) in cached.into_files() {
+        report
+            .unknown_tags
+            .extend(tags.iter().filter(|tag| !known_tags.contains(tag)).cloned());
+        let Some(remote_tags) = remote.tags(&file) else {
+            unbound.push(file.clone());
+            repo.insert(file, Tags::default());
+            continue;
+        };
+        let mut kept = Tags::default();
+        for tag in tags.intersection(remote_tags) {
+            kept.insert_one(tag.clone());
+        }
+        if kept == tags {
+            report.matched += 1;
+        } else {
+            report.partial += 1;
+        }
+        repo.insert(file, kept);
+    }
+
+    // Files without any tags on the server are not part of the scan.
+    let missing = remote_fs.find_missing_files(unbound.clone()).await;
+    ensure!(!remote_fs.in_maintenance(), ServerMaintenanceSnafu);
+    report.missing = missing.len();
+    report.partial += unbound.len() - missing.len();
+
+    repo.persist_on_disk(path).context(PersistSnafu)?;
+    Ok(report)
+}
+
+/// Check the persisted repository for anomalies and optionally persist the repaired version.
+///
+/// # Errors
+///
+/// This function will return an error if the repository cannot be read or persisted.
+pub fn check_database(config: &Config, repair: bool) -> Result<IntegrityReport, MaintenanceError> {
+    let path = &config.tag_database;
+    let report = Repository::check_integrity(path).context(LoadSnafu)?;
+
+    if repair && !report.is_clean() {
+        report
+            .repaired
+            .persist_on_disk(path)
+            .context(PersistSnafu)?;
+    }
+
+    Ok(report)
+}
+
+/// Renames a tag on the server. The tag database keeps the old name, so the next
+/// synchronization replaces it on the local files like any other remote change.
+///
+/// # Errors
+///
+/// This function will return an error if the tag cannot be renamed.
+pub async fn rename_tag(config: Arc<Config>, old: &Tag, new: Tag) -> Result<(), MaintenanceError> {
+    let mut remote_fs = RemoteFs::new(config);
+    let result = remote_fs.rename_tag(old, new).await;
+    ensure!(!remote_fs.in_maintenance(), ServerMaintenanceSnafu);
+    result.context(ManageTagSnafu)
+}
+
+/// Deletes a tag on the server. Unless `force` is set, tags still assigned to files are
+/// kept. Local files lose a forcibly deleted tag in the next synchronization.
+///
+/// # Errors
+///
+/// This function will return an error if the tag cannot be deleted.
+pub async fn delete_tag(
+    config: Arc<Config>,
+    tag: &Tag,
+    force: bool,
+) -> Result<(), MaintenanceError> {
+    let mut remote_fs = RemoteFs::new(config);
+    let result = remote_fs.delete_tag(tag, force).await;
+    ensure!(!remote_fs.in_maintenance(), ServerMaintenanceSnafu);
+    result.context(ManageTagSnafu)
+}
+
+/// Summary of creating the tags of the local files on the server.
+#[derive(Debug, Default)]
+pub struct PushTagsReport {
+    pub created: BTreeSet<Tag>,
+    /// Tags which already existed on the server.
+    pub existing: usize,
+    /// Tags which the server rejected or could not create.
+    pub failed: BTreeSet<Tag>,
+}
+
+impl std::fmt::Display for PushTagsReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for tag in &self.created {
+            let tag = tag.to_string();
+            writeln!(f, "{}", tr!("push-tags-created", tag = tag))?;
+        }
+        for tag in &self.failed {
+            let tag = tag.to_string();
+            writeln!(f, "{}", tr!("push-tags-failed", tag = tag))?;
+        }
+        writeln!(f, "{}", tr!("push-tags-existing", count = self.existing))
+    }
+}
+
+/// Creates all tags of the local files on the server in one pass without assigning them,
+/// e.g. so admins can review and adjust the names before the first synchronization.
+///
+/// # Errors
+///
+/// This function will return an error if the local files cannot be scanned or the tags
+/// on the server cannot be listed.
+pub async fn push_tags(config: Arc<Config>) -> Result<PushTagsReport, MaintenanceError> {
+    let (local, _) = LocalFs::new(config.clone())
+        .create_repo()
+        .await
+        .context(LocalScanSnafu)?;
+    let tags: BTreeSet<Tag> = local
+        .files()
+        .flat_map(|(_, tags)| tags.iter().cloned())
+        .collect();
+
+    let mut remote_fs = RemoteFs::new(config);
+    let result = remote_fs.create_tags(tags.clone()).await;
+    ensure!(!remote_fs.in_maintenance(), ServerMaintenanceSnafu);
+    let (created, failed) = result.context(ManageTagSnafu)?;
+    Ok(PushTagsReport {
+        existing: tags.len() - created.len() - failed.len(),
+        created,
+        failed,
+    })
+}
+
+#[derive(Debug, Snafu)]
+pub enum MaintenanceError {
+    #[snafu(display("failed to load tag database"))]
+    Load { source: LoadError },
+    #[snafu(display("failed to persist tag database"))]
+    Persist { source: PersistingError },
+    #[snafu(display("Nextcloud is in maintenance mode, try again later"))]
+    ServerMaintenance,
+    #[snafu(display("failed to scan Nextcloud"))]
+    Scan { source: InitError },
+    #[snafu(display(
+        "prefix {} -> {} of the tag database matches no configured prefix",
+        local.display(),
+        remote.display()
+    ))]
+    UnmatchedPrefix { local: PathBuf, remote: PathBuf },
+    #[snafu(display("failed to scan the local files"))]
+    LocalScan { source: InitError },
+    #[snafu(display("{source}"))]
+    ManageTag { source: ManageTagError },
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use reqwest::StatusCode;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{Connection, PrefixMapping, RemoteBackend, Simulator, SyncedPath};
+
+    const OLD_REMOTE: &str = "/remote.php/dav/files/olduser/data";
+    const NEW_REMOTE: &str = "/remote.php/dav/files/erik/data";
+
+    /// Rejects all requests like a server with revoked credentials.
+    #[derive(Debug)]
+    struct Unauthorized;
+
+    impl RemoteBackend for Unauthorized {
+        fn respond(&self, _: &reqwest::Request) -> http::Response<String> {
+            http::Response::builder()
+                .status(StatusCode::UNAUTHORIZED)
+                .body(String::new())
+                .unwrap()
+        }
+    }
+
+    /// Persists a database of the old instance and returns the config of the new one.
+    fn migrated(dir: &TempDir, old_local: &Path) -> Config {
+        let local = dir.path().join("data");
+        let old = PrefixMapping::new(old_local.to_owned(), OLD_REMOTE.into()).unwrap();
+        let mut repo = Repository::new(vec![old]);
+        for (file, tags) in [("a.jpg", "red,blue"), ("b.jpg", "green"), ("c.jpg", "red")] {
+            repo.insert(SyncedPath::new(0, file), tags.parse().unwrap());
+        }
+        let tag_database = dir.path().join("db.json");
+        repo.persist_on_disk(&tag_database).unwrap();
+        Config {
+            nextcloud_instance: "https://new.example.com".parse().unwrap(),
+            prefixes: vec![PrefixMapping::new(local, NEW_REMOTE.into()).unwrap()],
+            tag_database,
+            ..Config::default()
+        }
+    }
+
+    fn remote_fs(config: &Config, backend: impl RemoteBackend + 'static) -> RemoteFs {
+        let connection = Connection::from_config(config).with_backend(backend);
+        RemoteFs::with_connection(connection, Arc::new(config.clone()))
+    }
+
+    #[tokio::test]
+    async fn rebind_to_new_instance_and_user() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = migrated(&dir, &dir.path().join("data"));
+        let simulator = Simulator::new();
+        simulator.add_file(format!("{NEW_REMOTE}/a.jpg"));
+        simulator.add_file(format!("{NEW_REMOTE}/b.jpg"));
+        for tag in ["red", "blue"] {
+            simulator.add_tag(tag, true, true);
+            simulator.assign(format!("{NEW_REMOTE}/a.jpg"), tag);
+        }
+
+        let report = rebind(remote_fs(&config, simulator), &config)
+            .await
+            .unwrap();
+
+        assert_eq!((report.matched, report.partial, report.missing), (1, 1, 1));
+        assert_eq!(report.unknown_tags, ["green".parse().unwrap()].into());
+        let repo = Repository::read_from_disk(&config.tag_database).unwrap();
+        assert_eq!(repo.prefixes(), config.prefixes);
+        let files: Vec<_> = repo
+            .files()
+            .map(|(path, tags)| (path.clone(), tags.to_string()))
+            .collect();
+        assert_eq!(
+            files,
+            [
+                (SyncedPath::new(0, "a.jpg"), "blue,red".to_owned()),
+                (SyncedPath::new(0, "b.jpg"), String::new()),
+                (SyncedPath::new(0, "c.jpg"), String::new()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn reject_unmatched_prefixes() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = migrated(&dir, &dir.path().join("elsewhere"));
+        let database = std::fs::read_to_string(&config.tag_database).unwrap();
+
+        let err = rebind(remote_fs(&config, Simulator::new()), &config)
+            .await
+            .unwrap_err();
+
+        assert!(
+            matches!(err, MaintenanceError::UnmatchedPrefix { .. }),
+            "{err}"
+        );
+        assert_eq!(
+            std::fs::read_to_string(&config.tag_database).unwrap(),
+            database
+        );
+    }
+
+    #[tokio::test]
+    async fn keep_database_if_rebinding_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = migrated(&dir, &dir.path().join("data"));
+        let database = std::fs::read_to_string(&config.tag_database).unwrap();
+
+        let err = rebind(remote_fs(&config, Unauthorized), &config)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, MaintenanceError::Scan { .. }), "{err}");
+        assert_eq!(
+            std::fs::read_to_string(&config.tag_database).unwrap(),
+            database
+        );
+    }
+}
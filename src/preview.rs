@@ -0,0 +1,126 @@
+//! Changes a synchronization would apply, computed from repositories alone. This module
+//! does not touch any file system, so it is also built without the `sync` feature, e.g.
+//! for a web UI compiled to `wasm32-unknown-unknown`.
+
+use std::collections::BTreeSet;
+
+use snafu::{ensure, ResultExt, Snafu};
+
+use crate::{resolve_diffs, Command, Repository, Side, SyncedPath};
+
+/// Commands a synchronization would apply.
+#[derive(Debug, Default)]
+pub struct PendingChanges {
+    /// Commands to apply to the local file system.
+    pub local: Vec<Command>,
+    /// Commands to apply to Nextcloud.
+    pub remote: Vec<Command>,
+}
+
+/// Computes the commands which synchronize `local` and `remote`.
+///
+/// `cached` is the tag database of the previous run. Without it, conflicts are resolved
+/// with `keep_side_on_conflict` unless a prefix overrides it and `newer_local` lists the
+/// files whose local tags are preferred.
+///
+/// # Panics
+///
+/// This function panics if the prefixes of the repositories don't match.
+#[must_use]
+pub fn pending_changes(
+    local: Repository,
+    remote: Repository,
+    cached: Option<Repository>,
+    keep_side_on_conflict: Side,
+    newer_local: BTreeSet<SyncedPath>,
+) -> PendingChanges {
+    let Some(cached) = cached else {
+        let mut diff_events = local
+            .diff(remote, keep_side_on_conflict)
+            .with_prefix_overrides()
+            .with_newer_left(newer_local);
+        let (local, remote) = resolve_diffs(&mut diff_events);
+        return PendingChanges { local, remote };
+    };
+
+    // Commands which make the cache match one side are applied to the other side.
+    let (remote_actions, _) = resolve_diffs(&mut cached.clone().diff(local, Side::Right));
+    let (local_actions, _) = resolve_diffs(&mut cached.diff(remote, Side::Right));
+    PendingChanges {
+        local: local_actions,
+        remote: remote_actions,
+    }
+}
+
+#[derive(Debug, Snafu)]
+pub enum PreviewError {
+    #[snafu(display("failed to read the {side} repository"))]
+    InvalidRepository {
+        side: &'static str,
+        source: serde_json::Error,
+    },
+    #[snafu(display("the {side} repository synchronizes other prefixes than the local one"))]
+    PrefixMismatch { side: &'static str },
+}
+
+/// Previews a synchronization of exported repositories in the JSON format of the tag
+/// database.
+///
+/// # Errors
+///
+/// This function will return an error if a repository is invalid or the repositories
+/// synchronize different prefixes.
+pub fn preview(
+    local: &str,
+    remote: &str,
+    cached: Option<&str>,
+    keep_side_on_conflict: Side,
+) -> Result<PendingChanges, PreviewError> {
+    let parse = |json: &str, side| -> Result<Repository, PreviewError> {
+        let repo: Repository =
+            serde_json::from_str(json).context(InvalidRepositorySnafu { side })?;
+        Ok(repo)
+    };
+    let local = parse(local, "local")?;
+    let remote = parse(remote, "remote")?;
+    let cached = cached.map(|cached| parse(cached, "cached")).transpose()?;
+    for (side, repo) in [("remote", Some(&remote)), ("cached", cached.as_ref())] {
+        if let Some(repo) = repo {
+            ensure!(
+                repo.prefixes() == local.prefixes(),
+                PrefixMismatchSnafu { side }
+            );
+        }
+    }
+    Ok(pending_changes(
+        local,
+        remote,
+        cached,
+        keep_side_on_conflict,
+        BTreeSet::new(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCAL: &str = r#"{
+        "prefixes": [{ "local": "/data", "remote": "/remote.php/dav/files/erik/data" }],
+        "files": { "0:a.txt": ["red"] }
+    }"#;
+
+    #[test]
+    fn preview_exported_repositories() {
+        let remote = LOCAL.replace("red", "blue");
+        let changes = preview(LOCAL, &remote, None, Side::Both).unwrap();
+        assert_eq!(changes.local.len(), 1);
+        assert_eq!(changes.remote.len(), 1);
+
+        let other = LOCAL.replace("/data", "/other");
+        assert!(matches!(
+            preview(LOCAL, LOCAL, Some(&other), Side::Both),
+            Err(PreviewError::PrefixMismatch { side: "cached" })
+        ));
+    }
+}
@@ -0,0 +1,394 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+
+use crate::{
+    i18n::tr,
+    remote_fs::GetFileId,
+    tag_repository::{LoadError, SnapshotReader},
+    updater::tag_origins,
+    Config, Connection, DeserializeError, FileLocation, GetFileTags, MissingPrefixError,
+    PrefixMapping, Repository, RequestError, SnapshotError, SortOrder, SyncedPath,
+    SyncedPathPrinter, Tag, TagOrigin, Tags,
+};
+
+type Entries = Box<dyn Iterator<Item = Result<(SyncedPath, Tags), SnapshotError>>>;
+
+/// Opens the binary snapshot of the tag database if it is up to date.
+fn open_snapshot(config: &Config) -> Option<SnapshotReader> {
+    match SnapshotReader::open(&config.tag_database) {
+        Ok(Some(reader)) => return Some(reader),
+        Ok(None) => tracing::info!("No up to date snapshot, reading JSON database"),
+        Err(e) => tracing::warn!("Failed to open snapshot, reading JSON database: {e}"),
+    }
+    None
+}
+
+/// Streams the entries of the tag database. The binary snapshot is preferred because it is
+/// read entry by entry instead of loading the whole database.
+fn entries(config: &Config) -> Result<(Vec<PrefixMapping>, Entries), QueryError> {
+    if let Some(reader) = open_snapshot(config) {
+        return Ok((reader.prefixes().to_vec(), Box::new(reader)));
+    }
+    let repo = Repository::read_from_disk(&config.tag_database).context(LoadSnafu)?;
+    let prefixes = repo.prefixes().to_vec();
+    Ok((prefixes, Box::new(repo.into_files().map(Ok))))
+}
+
+/// Local paths of all files with the given tag according to the tag database.
+///
+/// # Errors
+///
+/// This function will return an error if the tag database cannot be read.
+pub fn find_tagged(config: &Config, tag: &Tag) -> Result<Vec<PathBuf>, QueryError> {
+    let (prefixes, entries) = entries(config)?;
+    let mut found = Vec::new();
+    for entry in entries {
+        let (path, tags) = entry.context(SnapshotSnafu)?;
+        if tags.contains(tag) {
+            found.push(path.local_file(&prefixes));
+        }
+    }
+    Ok(found)
+}
+
+/// Tags of a local file according to the tag database. Returns `None` if the file is not
+/// tracked. The binary snapshot is searched through its index, so only a few entries are
+/// read.
+///
+/// # Errors
+///
+/// This function will return an error if the tag database cannot be read or the file is
+/// not inside any prefix.
+pub fn tags_of(config: &Config, file: &Path) -> Result<Option<Tags>, QueryError> {
+    let file = std::path::absolute(file).unwrap_or_else(|_| file.to_owned());
+    let target = |prefixes: &[PrefixMapping]| {
+        Repository::new(prefixes.to_vec())
+            .synced_path_of(&file, FileLocation::Local)
+            .context(MissingPrefixSnafu)
+    };
+    if let Some(reader) = open_snapshot(config) {
+        let target = target(reader.prefixes())?;
+        return reader.tags_of(&target).context(SnapshotSnafu);
+    }
+    let repo = Repository::read_from_disk(&config.tag_database).context(LoadSnafu)?;
+    let target = target(repo.prefixes())?;
+    Ok(repo.tags(&target).cloned())
+}
+
+/// Tags of a local file as currently assigned on Nextcloud, regardless of the tag database.
+/// Returns `None` if the file does not exist on the server.
+///
+/// # Errors
+///
+/// This function will return an error if the file is not inside any prefix or the server
+/// cannot be queried.
+pub async fn remote_tags_of(config: &Config, file: &Path) -> Result<Option<Tags>, QueryError> {
+    let file = std::path::absolute(file).unwrap_or_else(|_| file.to_owned());
+    let path = Repository::new(config.prefixes.clone())
+        .synced_path_of(&file, FileLocation::Local)
+        .context(MissingPrefixSnafu)?;
+    let request = GetFileId::new(&path.remote_file(&config.prefixes)).context(NotUtf8Snafu)?;
+    let connection = Connection::from_config(config);
+    let file_id = match connection.request(request).await {
+        Ok(file) => file.id,
+        Err(e) if e.is_not_found() => return Ok(None),
+        Err(source) => return Err(QueryError::Remote { source }),
+    };
+    let tags = connection
+        .request(GetFileTags::new(file_id))
+        .await
+        .context(RemoteSnafu)?;
+    Ok(Some(tags))
+}
+
+/// Which side introduced the tags of a local file and when, as recorded by earlier
+/// synchronizations. Tags which were on both sides from the start are missing.
+///
+/// # Errors
+///
+/// This function will return an error if the file is not inside any prefix.
+pub fn tag_origins_of(
+    config: &Config,
+    file: &Path,
+) -> Result<BTreeMap<Tag, TagOrigin>, QueryError> {
+    let file = std::path::absolute(file).unwrap_or_else(|_| file.to_owned());
+    let path = Repository::new(config.prefixes.clone())
+        .synced_path_of(&file, FileLocation::Local)
+        .context(MissingPrefixSnafu)?;
+    Ok(tag_origins(config, &path))
+}
+
+/// Read-only view of an arbitrary tag database, e.g. one attached to a bug report.
+#[derive(Debug)]
+pub struct Inspection {
+    pub repo: Repository,
+    /// Only list the files with this tag.
+    pub tag: Option<Tag>,
+    pub sort_order: SortOrder,
+}
+
+/// Loads the JSON database or binary snapshot at `path` without touching the files it
+/// refers to.
+///
+/// # Errors
+///
+/// This function will return an error if the file is neither a valid snapshot nor a
+/// valid JSON database.
+pub fn inspect_database(path: &Path) -> Result<Repository, QueryError> {
+    match SnapshotReader::open_file(path) {
+        Ok(reader) => reader.into_repository().context(SnapshotSnafu),
+        Err(SnapshotError::Magic | SnapshotError::Truncated) => {
+            Repository::read_from_disk(path).context(LoadSnafu)
+        }
+        Err(e) => Err(e).context(SnapshotSnafu),
+    }
+}
+
+/// Appends the tags of a file to its name in a tree.
+#[derive(Default)]
+struct TagsSuffix<'a>(Option<&'a Tags>);
+
+impl std::fmt::Display for TagsSuffix<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.0.map_or(Ok(()), |tags| write!(f, " [{tags}]"))
+    }
+}
+
+impl std::fmt::Display for Inspection {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.repo.stats())?;
+
+        let mut per_tag: BTreeMap<&Tag, usize> = BTreeMap::new();
+        for (_, tags) in self.repo.files() {
+            for tag in tags.iter() {
+                *per_tag.entry(tag).or_default() += 1;
+            }
+        }
+        writeln!(f, "{}", tr!("inspect-tags"))?;
+        for (tag, count) in per_tag {
+            writeln!(f, "  {tag}: {count}")?;
+        }
+
+        let files = self
+            .repo
+            .files()
+            .filter(|(_, tags)| self.tag.as_ref().is_none_or(|tag| tags.contains(tag)))
+            .map(|(path, tags)| (path, TagsSuffix(Some(tags))));
+        writeln!(f, "{}", tr!("inspect-files"))?;
+        write!(f, "{}", SyncedPathPrinter::new(files, self.sort_order))
+    }
+}
+
+/// Changes between two tag databases, e.g. snapshots taken before and after a run.
+#[derive(Debug)]
+pub struct DatabaseDiff {
+    prefixes: Vec<PrefixMapping>,
+    /// Files only in the new database with their tags.
+    pub added: BTreeMap<SyncedPath, Tags>,
+    /// Files only in the old database with their tags.
+    pub removed: BTreeMap<SyncedPath, Tags>,
+    /// Files in both databases with the tags added to and removed from them.
+    pub changed: BTreeMap<SyncedPath, (Tags, Tags)>,
+}
+
+impl DatabaseDiff {
+    /// Compares the files of two databases.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the databases synchronize different prefixes.
+    pub fn new(old: &Repository, new: &Repository) -> Result<Self, QueryError> {
+        ensure!(old.prefixes() == new.prefixes(), PrefixMismatchSnafu);
+        let only = |tags: &Tags, other: &Tags| {
+            let mut only = Tags::default();
+            for tag in tags.difference(other) {
+                only.insert_one(tag.clone());
+            }
+            only
+        };
+        let mut diff = Self {
+            prefixes: new.prefixes().to_vec(),
+            added: BTreeMap::new(),
+            removed: BTreeMap::new(),
+            changed: BTreeMap::new(),
+        };
+        for (path, old_tags) in old.files() {
+            match new.tags(path) {
+                None => {
+                    diff.removed.insert(path.clone(), old_tags.clone());
+                }
+                Some(new_tags) if new_tags != old_tags => {
+                    let delta = (only(new_tags, old_tags), only(old_tags, new_tags));
+                    diff.changed.insert(path.clone(), delta);
+                }
+                Some(_) => {}
+            }
+        }
+        for (path, tags) in new.files() {
+            if old.tags(path).is_none() {
+                diff.added.insert(path.clone(), tags.clone());
+            }
+        }
+        Ok(diff)
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl std::fmt::Display for DatabaseDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "{}", tr!("db-diff-identical"));
+        }
+        let path = |path: &SyncedPath| path.local_file(&self.prefixes);
+        let sections = [
+            (tr!("db-diff-added"), &self.added),
+            (tr!("db-diff-removed"), &self.removed),
+        ];
+        for (title, files) in sections {
+            if files.is_empty() {
+                continue;
+            }
+            writeln!(f, "{title}")?;
+            for (file, tags) in files {
+                writeln!(f, "  {} [{tags}]", path(file).display())?;
+            }
+        }
+        if !self.changed.is_empty() {
+            writeln!(f, "{}", tr!("db-diff-changed"))?;
+            for (file, (added, removed)) in &self.changed {
+                write!(f, "  {}", path(file).display())?;
+                if !added.is_empty() {
+                    write!(f, " +{added}")?;
+                }
+                if !removed.is_empty() {
+                    write!(f, " -{removed}")?;
+                }
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compares the JSON databases or binary snapshots at `old` and `new`.
+///
+/// # Errors
+///
+/// This function will return an error if a database cannot be read or the databases
+/// synchronize different prefixes.
+pub fn diff_databases(old: &Path, new: &Path) -> Result<DatabaseDiff, QueryError> {
+    DatabaseDiff::new(&inspect_database(old)?, &inspect_database(new)?)
+}
+
+#[derive(Debug, Snafu)]
+pub enum QueryError {
+    #[snafu(display("failed to load tag database"))]
+    Load { source: LoadError },
+    #[snafu(display("failed to read snapshot"))]
+    Snapshot { source: SnapshotError },
+    #[snafu(display("file is not synchronized"))]
+    MissingPrefix { source: MissingPrefixError },
+    #[snafu(display("path is not valid UTF-8"))]
+    NotUtf8,
+    #[snafu(display("the databases synchronize different prefixes"))]
+    PrefixMismatch,
+    #[snafu(display("failed to query Nextcloud: {source}"))]
+    Remote {
+        source: RequestError<DeserializeError>,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_json_and_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            tag_database: dir.path().join("db.json"),
+            ..Config::default()
+        };
+        let prefixes =
+            vec![PrefixMapping::new("/local".into(), "/remote.php/dav/files/user".into()).unwrap()];
+        let mut repo = Repository::new(prefixes);
+        repo.insert_local(Path::new("/local/a.txt"), "red,blue".parse().unwrap())
+            .unwrap();
+        repo.insert_local(Path::new("/local/b.txt"), "blue".parse().unwrap())
+            .unwrap();
+        repo.persist_on_disk(&config.tag_database).unwrap();
+
+        let red: Tag = "red".parse().unwrap();
+        let query = || {
+            (
+                find_tagged(&config, &red).unwrap(),
+                tags_of(&config, Path::new("/local/b.txt")).unwrap(),
+            )
+        };
+        let expected = (
+            vec![PathBuf::from("/local/a.txt")],
+            Some("blue".parse().unwrap()),
+        );
+        assert_eq!(query(), expected);
+
+        repo.persist_snapshot(&config.tag_database).unwrap();
+        assert_eq!(query(), expected);
+        assert!(tags_of(&config, Path::new("/elsewhere")).is_err());
+    }
+
+    #[test]
+    fn inspect_any_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let json = dir.path().join("attached.json");
+        let prefixes =
+            vec![PrefixMapping::new("/local".into(), "/remote.php/dav/files/user".into()).unwrap()];
+        let mut repo = Repository::new(prefixes);
+        repo.insert_local(Path::new("/local/a.txt"), "red,blue".parse().unwrap())
+            .unwrap();
+        repo.persist_on_disk(&json).unwrap();
+        repo.persist_snapshot(&json).unwrap();
+
+        let entries = |repo: Repository| repo.into_files().collect::<Vec<_>>();
+        let expected = entries(repo.clone());
+        assert_eq!(entries(inspect_database(&json).unwrap()), expected);
+        let snapshot = json.with_extension("snapshot");
+        assert_eq!(entries(inspect_database(&snapshot).unwrap()), expected);
+
+        let inspection = Inspection {
+            repo,
+            tag: Some("red".parse().unwrap()),
+            sort_order: SortOrder::default(),
+        };
+        assert!(inspection.to_string().contains("a.txt [blue,red]"));
+    }
+
+    #[test]
+    fn diff_snapshots() {
+        let prefixes =
+            vec![PrefixMapping::new("/local".into(), "/remote.php/dav/files/user".into()).unwrap()];
+        let repo = |files: &[(&str, &str)]| {
+            let mut repo = Repository::new(prefixes.clone());
+            for (file, tags) in files {
+                repo.insert_local(&Path::new("/local").join(file), tags.parse().unwrap())
+                    .unwrap();
+            }
+            repo
+        };
+        let old = repo(&[("a.txt", "red,blue"), ("b.txt", "blue"), ("c.txt", "red")]);
+        let new = repo(&[("a.txt", "red,green"), ("c.txt", "red"), ("d.txt", "blue")]);
+
+        let diff = DatabaseDiff::new(&old, &new).unwrap();
+        let output = diff.to_string();
+        assert!(output.contains("/local/d.txt [blue]"));
+        assert!(output.contains("/local/b.txt [blue]"));
+        assert!(output.contains("/local/a.txt +green -blue"));
+        assert!(!output.contains("c.txt"));
+        assert!(DatabaseDiff::new(&new, &new).unwrap().is_empty());
+    }
+}
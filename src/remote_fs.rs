@@ -1,7 +1,23 @@
+#[cfg(feature = "sync")]
 mod common;
+#[cfg(feature = "sync")]
+mod desktop_client;
+#[cfg(feature = "sync")]
 mod fs;
+mod remote_path;
+#[cfg(feature = "sync")]
 mod requests;
+#[cfg(all(feature = "sync", any(test, feature = "fixtures")))]
+mod simulator;
+#[cfg(feature = "sync")]
+mod tag_lists;
 
-pub use common::{FileId, TagId};
-pub use fs::{FileMap, ListTagsError, RemoteFs, TagMap};
+#[cfg(feature = "sync")]
+pub use common::{FileId, Permissions, TagId};
+#[cfg(feature = "sync")]
+pub use fs::{FileMap, ListTagsError, ManageTagError, RemoteFs, TagMap};
+pub use remote_path::RemotePath;
+#[cfg(feature = "sync")]
 pub use requests::*;
+#[cfg(all(feature = "sync", any(test, feature = "fixtures")))]
+pub use simulator::Simulator;
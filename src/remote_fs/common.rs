@@ -1,22 +1,62 @@
 use crate::newtype;
 use futures::Future;
+use tokio_util::sync::CancellationToken;
 
 newtype!(TagId, u64);
 newtype!(FileId, u64);
 
+/// Permissions of a remote file as reported by `oc:permissions`, e.g. `SRGDNVW`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(transparent)]
+pub struct Permissions(String);
+
+impl Permissions {
+    /// The file was shared with the user by somebody else.
+    #[must_use]
+    pub fn is_incoming_share(&self) -> bool {
+        self.0.contains('S')
+    }
+
+    #[must_use]
+    pub fn is_writable(&self) -> bool {
+        self.0.contains('W')
+    }
+
+    /// Nextcloud only allows assigning tags to shared files the user may modify.
+    #[must_use]
+    pub fn can_tag(&self) -> bool {
+        !self.is_incoming_share() || self.is_writable()
+    }
+}
+
+impl From<&str> for Permissions {
+    fn from(value: &str) -> Self {
+        Self(value.to_owned())
+    }
+}
+
 pub struct LimitedConcurrency<Iter> {
     elements: Iter,
     max_concurrent_requests: usize,
+    cancel: CancellationToken,
 }
 
 impl<Iter> LimitedConcurrency<Iter> {
-    pub const fn new(elements: Iter, max_concurrent_requests: usize) -> Self {
+    pub fn new(elements: Iter, max_concurrent_requests: usize) -> Self {
         Self {
             elements,
             max_concurrent_requests,
+            cancel: CancellationToken::new(),
         }
     }
 
+    /// Stops starting new elements once `cancel` is cancelled. Elements that are already
+    /// running are still awaited and aggregated.
+    pub fn with_cancellation(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
     pub const fn transform<F, Fut>(self, element_action: F) -> TransformElements<Iter, F>
     where
         Iter: IntoIterator,
@@ -36,23 +76,6 @@ pub struct TransformElements<Iter, EAction> {
 }
 
 impl<Iter, EAction> TransformElements<Iter, EAction> {
-    pub(crate) async fn execute<Fut>(self)
-    where
-        Iter: IntoIterator,
-        EAction: Fn(Iter::Item) -> Fut,
-        Fut: Future<Output = ()>,
-    {
-        use futures::StreamExt;
-
-        let async_drop = |()| std::future::ready(());
-
-        futures::stream::iter(self.base.elements)
-            .map(self.element_action)
-            .buffer_unordered(self.base.max_concurrent_requests)
-            .for_each(async_drop)
-            .await;
-    }
-
     pub(crate) const fn aggregate<AAction>(
         self,
         aggregate_action: AAction,
@@ -77,15 +100,57 @@ impl<Iter, EAction, AAction> AggregateElements<Iter, EAction, AAction> {
         Iter: IntoIterator,
         EAction: Fn(Iter::Item) -> EFut,
         EFut: Future,
+    {
+        self.collect_into_with(Res::default()).await
+    }
+
+    /// Like [`Self::collect_into`] but starts aggregating from `initial`.
+    pub(crate) async fn collect_into_with<Res, EFut>(self, initial: Res) -> Res
+    where
+        AAction: Fn(&mut Res, EFut::Output),
+        Iter: IntoIterator,
+        EAction: Fn(Iter::Item) -> EFut,
+        EFut: Future,
     {
         use futures::StreamExt;
-        futures::stream::iter(self.base.base.elements)
+        let LimitedConcurrency {
+            elements,
+            max_concurrent_requests,
+            cancel,
+        } = self.base.base;
+        let elements = elements.into_iter().take_while(|_| !cancel.is_cancelled());
+        futures::stream::iter(elements)
             .map(self.base.element_action)
-            .buffer_unordered(self.base.base.max_concurrent_requests)
-            .fold(Res::default(), |mut res, temp| {
+            .buffer_unordered(max_concurrent_requests)
+            .fold(initial, |mut res, temp| {
                 (self.aggregate_action)(&mut res, temp);
                 futures::future::ready(res)
             })
             .await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn cancellation_stops_new_elements() {
+        let cancel = CancellationToken::new();
+        let seen: Vec<u32> = LimitedConcurrency::new(0..100, 1)
+            .with_cancellation(cancel.clone())
+            .transform(|i| {
+                let cancel = &cancel;
+                async move {
+                    if i == 3 {
+                        cancel.cancel();
+                    }
+                    i
+                }
+            })
+            .aggregate(|seen: &mut Vec<_>, i| seen.push(i))
+            .collect_into()
+            .await;
+        assert_eq!(seen, [0, 1, 2, 3]);
+    }
+}
@@ -0,0 +1,134 @@
+use std::path::{Path, PathBuf};
+
+use rusqlite::{Connection, OpenFlags, OptionalExtension};
+use tracing::{info, warn};
+
+use crate::{Config, FileId, Permissions};
+
+/// Database of the Nextcloud desktop client, stored as `.sync_*.db` in the root of each
+/// folder it synchronizes. It records the file id of every synchronized file.
+#[derive(Debug)]
+pub struct ClientDatabase {
+    connection: Connection,
+    /// Folder synchronized by the desktop client.
+    root: PathBuf,
+}
+
+impl ClientDatabase {
+    /// Finds the database of the desktop client synchronizing `folder` or an ancestor.
+    #[must_use]
+    pub fn find(folder: &Path) -> Option<PathBuf> {
+        folder.ancestors().find_map(|dir| {
+            std::fs::read_dir(dir)
+                .ok()?
+                .filter_map(Result::ok)
+                .map(|entry| entry.path())
+                .find(|path| is_client_database(path))
+        })
+    }
+
+    /// Opens the database read-only so the desktop client is not disturbed.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file is not an `SQLite` database.
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        Ok(Self {
+            connection,
+            root: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+        })
+    }
+
+    /// File id and permissions of a local file, if the desktop client synchronized it.
+    #[must_use]
+    pub fn file_id(&self, local: &Path) -> Option<(FileId, Permissions)> {
+        let relative = local.strip_prefix(&self.root).ok()?.to_str()?;
+        let (file_id, permissions) = self
+            .connection
+            .query_row(
+                "SELECT fileid, remotePerm FROM metadata WHERE path = ?1",
+                [relative],
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?)),
+            )
+            .optional()
+            .inspect_err(|e| warn!("Failed to query desktop client database: {e}"))
+            .ok()??;
+        // The client stores the numeric id followed by the instance id, e.g. `00000042oc1a2b3c`.
+        let digits = file_id
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(file_id.len());
+        let file_id = file_id[..digits].parse::<u64>().ok()?;
+        Some((
+            FileId::from(file_id),
+            Permissions::from(permissions.unwrap_or_default().as_str()),
+        ))
+    }
+}
+
+/// Older clients prefix the database with `._sync_` instead of `.sync_`.
+fn is_client_database(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| {
+            (name.starts_with(".sync_") || name.starts_with("._sync_"))
+                && path.extension().is_some_and(|extension| extension == "db")
+        })
+}
+
+/// Opens the desktop client database of each prefix if [`Config::desktop_client_file_ids`]
+/// is set. Otherwise, found databases are only mentioned so users learn about the option.
+pub fn open_databases(config: &Config) -> Vec<Option<ClientDatabase>> {
+    config
+        .prefixes
+        .iter()
+        .map(|prefix| {
+            let path = ClientDatabase::find(prefix.local())?;
+            if !config.desktop_client_file_ids {
+                info!(
+                    "{} is synchronized by the Nextcloud desktop client, set \
+                     desktop_client_file_ids to skip looking up file ids",
+                    prefix.local().display()
+                );
+                return None;
+            }
+            ClientDatabase::open(&path)
+                .inspect_err(|e| warn!("Failed to open {}: {e}", path.display()))
+                .ok()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_ids_from_client_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".sync_0123456789ab.db");
+        let connection = Connection::open(&path).unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE metadata(path VARCHAR(4096), fileid VARCHAR(128), remotePerm VARCHAR(128));
+                 INSERT INTO metadata VALUES ('Photos/a.jpg', '00000042ocabc123', 'SRGDNVW');
+                 INSERT INTO metadata VALUES ('Photos', '00000040ocabc123', NULL);",
+            )
+            .unwrap();
+        drop(connection);
+        let photos = dir.path().join("Photos");
+        std::fs::create_dir(&photos).unwrap();
+
+        assert_eq!(ClientDatabase::find(&photos), Some(path.clone()));
+        let database = ClientDatabase::open(&path).unwrap();
+        assert_eq!(
+            database.file_id(&photos.join("a.jpg")),
+            Some((FileId::from(42), Permissions::from("SRGDNVW")))
+        );
+        assert_eq!(
+            database.file_id(&photos),
+            Some((FileId::from(40), Permissions::default()))
+        );
+        assert_eq!(database.file_id(&photos.join("b.jpg")), None);
+    }
+}
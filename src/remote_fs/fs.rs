@@ -1,69 +1,414 @@
 use std::{
-    collections::{hash_map::Entry, HashMap, HashSet},
-    path::Path,
-    sync::Arc,
+    collections::{hash_map::Entry, BTreeMap, BTreeSet, HashMap, HashSet},
+    sync::{Arc, Mutex},
 };
 
-use snafu::{ResultExt, Snafu};
-use tracing::{debug, error, warn};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
 
 use crate::{
-    updater::RemoteSnafu, Command, Config, Connection, CreateTag, FileId, FileSystem, IntoOk,
-    Modification, SyncedPath, Tag, TagFile, TagId, Tags, UntagFile,
+    updater::{MaintenanceSnafu, RemoteSnafu, SkippedSnafu},
+    Command, CommandOutcome, Config, Connection, CreateTag, FileId, FileSystem, IntoOk,
+    Modification, PrefixMappingId, Repository, ScanIssue, ScanIssues, SyncedPath, Tag, TagFile,
+    TagId, Tags, UntagFile,
 };
 
-use super::{common::LimitedConcurrency, DeserializeError, GetFileId, RequestError};
+use super::{
+    common::LimitedConcurrency,
+    desktop_client::{open_databases, ClientDatabase},
+    tag_lists::TagList,
+    AddComment, DeleteTag, DeserializeError, GetFileId, IsEncrypted, ListFilesWithTag,
+    ListPathsWithTag, LockFile, LockToken, RemoteFile, RequestError, SystemTagPolicy, TagKind,
+    TaggedFile, UnlockFile, UpdateTag, UpdateTagError,
+};
 
 pub type FileMap = bimap::BiHashMap<FileId, SyncedPath>;
 pub type TagMap = bimap::BiHashMap<TagId, Tag>;
 
+/// Lists the files with a tag in a prefix, sent for the owner of the prefix.
+type ListRequest<'a> = (PrefixMappingId, &'a str, &'a Tag, ListFilesWithTag);
+
+/// Files of the Nextcloud instance whose tags are system tags.
+///
+/// Together with [`LocalFs`](crate::LocalFs), it performs an initial synchronization
+/// which keeps the tags of both sides:
+///
+/// ```no_run
+/// use std::sync::Arc;
+///
+/// use nextcloud_tag_sync::{load_config, resolve_diffs, FileSystem, LocalFs, RemoteFs, Side};
+///
+/// # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+/// let config = Arc::new(load_config()?);
+/// let mut local = LocalFs::new(config.clone());
+/// let mut remote = RemoteFs::new(config.clone());
+/// let (local_repo, _) = local.create_repo().await?;
+/// let (remote_repo, _) = remote.create_repo().await?;
+///
+/// let mut diff = local_repo.diff(remote_repo, Side::Both);
+/// let (local_commands, remote_commands) = resolve_diffs(&mut diff);
+/// let outcomes = remote.update_tags(remote_commands).await;
+/// let failed = outcomes.iter().filter(|outcome| !outcome.is_success()).count();
+/// println!("{failed} remote commands failed");
+/// local.update_tags(local_commands).await;
+/// diff.finish().persist_on_disk(&config.tag_database)?;
+/// # Ok(())
+/// # }
+/// ```
 #[derive(Debug)]
 pub struct RemoteFs {
     pub tags: TagMap,
     pub files: FileMap,
+    /// End-to-end encrypted folders. They cannot carry server-side tags and are skipped.
+    pub encrypted_folders: BTreeSet<SyncedPath>,
+    plain_folders: HashSet<SyncedPath>,
+    /// Files in incoming shares which were skipped because the user may not modify them.
+    pub read_only_shares: BTreeSet<SyncedPath>,
+    read_only: HashSet<FileId>,
+    /// Tags the server refused to assign with 403 Forbidden.
+    forbidden_tags: Mutex<BTreeSet<Tag>>,
+    /// Restricted and invisible tags which are not synchronized.
+    pub system_tags: BTreeMap<Tag, TagKind>,
+    /// Tags whose names the server rejected. They are not created again.
+    pub rejected_tags: BTreeSet<Tag>,
+    /// Id of [`Config::skip_tag`] once the tags were loaded and it exists.
+    skip_tag_id: Option<TagId>,
+    connection: Arc<Connection>,
     config: Arc<Config>,
 }
 
 impl RemoteFs {
     #[must_use]
     pub fn new(config: Arc<Config>) -> Self {
+        Self::with_connection(Connection::from_config(&config), config)
+    }
+
+    /// Uses `connection` for all requests, e.g. to add a [`Middleware`](crate::Middleware).
+    #[must_use]
+    pub fn with_connection(connection: Connection, config: Arc<Config>) -> Self {
         Self {
             tags: TagMap::default(),
             files: FileMap::default(),
+            encrypted_folders: BTreeSet::default(),
+            plain_folders: HashSet::default(),
+            read_only_shares: BTreeSet::default(),
+            read_only: HashSet::default(),
+            forbidden_tags: Mutex::new(if config.skip_forbidden_tags {
+                TagList::Forbidden.load(&config)
+            } else {
+                BTreeSet::new()
+            }),
+            system_tags: BTreeMap::new(),
+            rejected_tags: TagList::Rejected.load(&config),
+            skip_tag_id: None,
+            connection: Arc::new(connection),
             config,
         }
     }
 
-    async fn create_missing_tags<I>(&mut self, commands: I, connection: &Connection)
-    where
-        I: IntoIterator<Item = Command> + Send,
-    {
-        let tags_to_create = self.get_unknown_tags(commands);
-        let new_tags = LimitedConcurrency::new(tags_to_create, self.config.max_concurrent_requests)
+    /// Tags the user may not assign or remove, e.g. because they are restricted to a group.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lock is poisoned.
+    #[must_use]
+    pub fn forbidden_tags(&self) -> BTreeSet<Tag> {
+        self.forbidden_tags.lock().unwrap().clone()
+    }
+
+    fn is_forbidden(&self, tag: &Tag) -> bool {
+        self.config.skip_forbidden_tags && self.forbidden_tags.lock().unwrap().contains(tag)
+    }
+
+    /// Whether the server reported maintenance mode. The synchronization is incomplete then.
+    #[must_use]
+    pub fn in_maintenance(&self) -> bool {
+        self.connection.in_maintenance()
+    }
+
+    fn encrypted_parent(&self, path: &SyncedPath) -> Option<&SyncedPath> {
+        self.encrypted_folders
+            .iter()
+            .find(|folder| path.starts_with(folder))
+    }
+
+    /// Queries the `nc:is-encrypted` property of the parent folders of `paths` which were
+    /// not queried before. The property of files is not used because server-side
+    /// encryption sets it as well, while only end-to-end encryption marks folders.
+    async fn find_encrypted_folders<'a>(
+        &mut self,
+        paths: impl IntoIterator<Item = &'a SyncedPath>,
+        connection: &Connection,
+    ) {
+        let folders: BTreeSet<_> = paths
+            .into_iter()
+            .flat_map(SyncedPath::parents)
+            .filter(|folder| {
+                !self.encrypted_folders.contains(folder) && !self.plain_folders.contains(folder)
+            })
+            .collect();
+        let requests = folders.into_iter().filter_map(|folder| {
+            let request = IsEncrypted::new(&folder.remote_file(&self.config.prefixes))?;
+            Some((folder, request))
+        });
+        let results = LimitedConcurrency::new(requests, self.config.scan_concurrency)
             .transform(
-                |tag| async move { (tag.clone(), connection.request(CreateTag::new(tag)).await) },
+                |(folder, request)| async move { (folder, connection.request(request).await) },
             )
-            .aggregate(|new_tags: &mut TagMap, (tag, result)| match result {
-                Ok(tag_id) => {
-                    new_tags.insert(tag_id, tag);
+            .aggregate(|results: &mut Vec<_>, result| results.push(result))
+            .collect_into()
+            .await;
+        for (folder, result) in results {
+            match result {
+                Ok(true) => {
+                    if self.encrypted_parent(&folder).is_none() {
+                        warn!("Skipping end-to-end encrypted folder {folder} because it cannot carry server-side tags");
+                    }
+                    self.encrypted_folders.insert(folder);
+                }
+                Ok(false) => {
+                    self.plain_folders.insert(folder);
                 }
                 Err(e) => {
-                    warn!("Failed to create tag {tag}: {e}");
+                    if !e.is_not_found() {
+                        warn!("failed to check encryption of folder {folder}: {e}");
+                    }
                 }
-            })
-            .collect_into()
+            }
+        }
+    }
+
+    /// Drops the commands of files in incoming shares the user may not modify. They are
+    /// added to `skipped`, so they are undone in the tag database.
+    fn skip_read_only_shares(
+        &mut self,
+        commands: Vec<Command>,
+        skipped: &mut Vec<CommandOutcome>,
+    ) -> Vec<Command> {
+        let (skipped_shares, commands): (Vec<_>, Vec<_>) = commands.into_iter().partition(|cmd| {
+            self.files
+                .get_by_right(&cmd.path)
+                .is_some_and(|id| self.read_only.contains(id))
+        });
+
+        if !skipped_shares.is_empty() {
+            warn!(
+                "Skipping {} files in incoming shares without write permission",
+                skipped_shares.len()
+            );
+        }
+        for command in skipped_shares {
+            debug!("Skipping read-only shared file {}", command.path);
+            self.read_only_shares.insert(command.path.clone());
+            skipped.push(CommandOutcome::Skipped {
+                command,
+                reason: "file is in a read-only share".to_owned(),
+            });
+        }
+
+        commands
+    }
+
+    /// Drops the commands of files in end-to-end encrypted folders. They are added to
+    /// `skipped`, so they are undone in the tag database.
+    fn skip_encrypted(
+        &self,
+        commands: Vec<Command>,
+        skipped: &mut Vec<CommandOutcome>,
+    ) -> Vec<Command> {
+        let (encrypted, commands): (Vec<_>, Vec<_>) = commands
+            .into_iter()
+            .partition(|cmd| self.encrypted_parent(&cmd.path).is_some());
+        for command in encrypted {
+            debug!(
+                "Skipping file {} in end-to-end encrypted folder",
+                command.path
+            );
+            skipped.push(CommandOutcome::Skipped {
+                command,
+                reason: "file is in an end-to-end encrypted folder".to_owned(),
+            });
+        }
+        commands
+    }
+
+    /// Creates the tags which do not exist on the server yet, without assigning them to any
+    /// file. Returns the created tags and those which could not be created.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the existing tags cannot be listed.
+    pub async fn create_tags<I>(
+        &mut self,
+        tags: I,
+    ) -> Result<(BTreeSet<Tag>, BTreeSet<Tag>), ManageTagError>
+    where
+        I: IntoIterator<Item = Tag> + Send,
+    {
+        let connection = self.connection.clone();
+        self.load_tags(&connection).await.context(LoadTagsSnafu)?;
+        let unknown: HashSet<_> = tags
+            .into_iter()
+            .filter(|tag| !self.tags.contains_right(tag) && !self.system_tags.contains_key(tag))
+            .collect();
+        self.create_tags_on_server(unknown.clone(), &connection)
             .await;
+        Ok(unknown
+            .into_iter()
+            .partition(|tag| self.tags.contains_right(tag)))
+    }
+
+    async fn create_missing_tags<I>(&mut self, commands: I, connection: &Connection)
+    where
+        I: IntoIterator<Item = Command> + Send,
+    {
+        let tags_to_create = self.get_unknown_tags(commands);
+        self.create_tags_on_server(tags_to_create, connection).await;
+    }
+
+    /// Creates the tags concurrently. Tags the server rejected are remembered and not
+    /// created again.
+    async fn create_tags_on_server(
+        &mut self,
+        mut tags_to_create: HashSet<Tag>,
+        connection: &Connection,
+    ) {
+        tags_to_create.retain(|tag| !self.rejected_tags.contains(tag));
+        let (new_tags, rejected) =
+            LimitedConcurrency::new(tags_to_create, self.config.write_concurrency)
+                .transform(|tag| async move {
+                    let result = connection
+                        .request(CreateTag::new(tag.clone()))
+                        .await
+                        // Whether the server rejected the tag name.
+                        .map_err(|e| {
+                            warn!("Failed to create tag {tag}: {e}");
+                            e.is_rejected()
+                        });
+                    (tag, result)
+                })
+                .aggregate(
+                    |(new_tags, rejected): &mut (TagMap, BTreeSet<_>), (tag, result)| match result {
+                        Ok(tag_id) => {
+                            new_tags.insert(tag_id, tag);
+                        }
+                        Err(true) => {
+                            rejected.insert(tag);
+                        }
+                        Err(false) => {}
+                    },
+                )
+                .collect_into()
+                .await;
         self.tags.extend(new_tags);
+        if !rejected.is_empty() {
+            self.rejected_tags.extend(rejected);
+            TagList::Rejected.persist(&self.config, &self.rejected_tags);
+        }
     }
 
     async fn load_tags(&mut self, connection: &Connection) -> Result<(), ListTagsError> {
-        let tag_map = connection
+        let listing = connection
             .request(crate::ListTags)
             .await
             .context(ListTagsSnafu)?;
-        debug!("Received mapping of {} tags", tag_map.len());
-        self.tags.extend(tag_map);
+        debug!(
+            "Received mapping of {} tags and {} system tags",
+            listing.assignable.len(),
+            listing.system.len()
+        );
+        if let Some(skip_tag) = &self.config.skip_tag {
+            self.skip_tag_id = listing
+                .assignable
+                .get_by_right(skip_tag)
+                .copied()
+                .or_else(|| {
+                    listing
+                        .system
+                        .iter()
+                        .find(|(_, tag, _)| tag == skip_tag)
+                        .map(|(id, ..)| *id)
+                });
+        }
+        self.tags.extend(listing.assignable);
+        for (id, tag, kind) in listing.system {
+            // Regular tags may share their name with system tags.
+            if self.tags.contains_right(&tag) {
+                continue;
+            }
+            let mirror = self.config.system_tags == SystemTagPolicy::MirrorRestricted
+                && kind == TagKind::Restricted;
+            if mirror {
+                self.tags.insert(id, tag);
+            } else {
+                self.system_tags.insert(tag, kind);
+            }
+        }
+
+        Ok(())
+    }
 
+    /// Renames a tag on the server. Local files keep the old name until the next
+    /// synchronization replaces it.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `old` does not exist, `new` already exists or
+    /// the server rejects the update.
+    pub async fn rename_tag(&mut self, old: &Tag, new: Tag) -> Result<(), ManageTagError> {
+        let connection = self.connection.clone();
+        self.load_tags(&connection).await.context(LoadTagsSnafu)?;
+        let &id = self
+            .tags
+            .get_by_right(old)
+            .context(UnknownTagSnafu { tag: old.clone() })?;
+        ensure!(
+            !self.tags.contains_right(&new),
+            TagExistsSnafu { tag: new.clone() }
+        );
+        connection
+            .request(UpdateTag::new(id).with_name(new.clone()))
+            .await
+            .context(UpdateTagSnafu)?;
+        info!("Renamed tag {old} to {new}");
+        self.tags.insert(id, new);
+        Ok(())
+    }
+
+    /// Deletes a tag on the server. Unless `force` is set, tags which are still assigned
+    /// to files of the user are kept. Assignments of other users are not visible.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the tag does not exist, is still assigned or
+    /// cannot be deleted.
+    pub async fn delete_tag(&mut self, tag: &Tag, force: bool) -> Result<(), ManageTagError> {
+        let connection = self.connection.clone();
+        self.load_tags(&connection).await.context(LoadTagsSnafu)?;
+        let &id = self
+            .tags
+            .get_by_right(tag)
+            .context(UnknownTagSnafu { tag: tag.clone() })?;
+        if !force {
+            let files = connection
+                .request(ListFilesWithTag::new(id))
+                .await
+                .context(ListAssignedSnafu)?;
+            ensure!(
+                files.is_empty(),
+                StillAssignedSnafu {
+                    tag: tag.clone(),
+                    files: files.len()
+                }
+            );
+        }
+        connection
+            .request(DeleteTag::new(id))
+            .await
+            .context(DeleteTagSnafu)?;
+        info!("Deleted tag {tag}");
+        self.tags.remove_by_left(&id);
         Ok(())
     }
 
@@ -71,7 +416,9 @@ impl RemoteFs {
     where
         I: IntoIterator<Item = Command>,
     {
-        let is_unknown_tag = |tag: &Tag| -> bool { !self.tags.contains_right(tag) };
+        let is_unknown_tag = |tag: &Tag| -> bool {
+            !self.tags.contains_right(tag) && !self.system_tags.contains_key(tag)
+        };
         commands
             .into_iter()
             .flat_map(|cmd| cmd.actions)
@@ -87,63 +434,248 @@ impl RemoteFs {
             .collect()
     }
 
-    async fn get_missing_file_ids<I>(&mut self, commands: I, connection: &Connection)
+    async fn get_missing_file_ids<I>(&mut self, paths: I, connection: &Connection)
     where
-        I: IntoIterator<Item = Command> + Send,
+        I: IntoIterator<Item = SyncedPath> + Send,
         I::IntoIter: Send,
     {
+        // The desktop client already knows the ids of the files it synchronizes.
+        let client_databases = open_databases(&self.config);
+        let mut missing = Vec::new();
+        for path in paths {
+            if !self.files.contains_right(&path)
+                && !self.take_client_file_id(&client_databases, &path)
+            {
+                missing.push(path);
+            }
+        }
         let Config {
-            max_concurrent_requests,
+            scan_concurrency,
             prefixes,
             ..
         } = &*self.config;
-        let missing_file_id_requests = commands
-            .into_iter()
-            .map(|cmd| cmd.path)
-            .filter(|path| !self.files.contains_right(path))
-            .filter_map(|path| {
-                let request = GetFileId::new(&path.remote_file(prefixes));
+        let missing_file_id_requests = missing.into_iter().filter_map(|path| {
+            let request = GetFileId::new(&path.remote_file(prefixes));
 
-                if request.is_none() {
-                    warn!("failed to format file {path} as UTF-8");
-                }
+            if request.is_none() {
+                warn!("failed to format file {path} as UTF-8");
+            }
 
-                request.map(|req| (path, req))
-            });
+            request.map(|req| (path, req))
+        });
+
+        let (new_files, read_only) =
+            LimitedConcurrency::new(missing_file_id_requests, *scan_concurrency)
+                .transform(
+                    |(path, request)| async move { (path, connection.request(request).await) },
+                )
+                .aggregate(
+                    |(new_files, read_only): &mut (FileMap, HashSet<FileId>),
+                     (path, result): (_, Result<RemoteFile, _>)| {
+                        match result {
+                            Ok(file) => {
+                                if !file.permissions.can_tag() {
+                                    read_only.insert(file.id);
+                                }
+                                new_files.insert(file.id, path);
+                            }
+                            Err(e) => {
+                                warn!("failed to query file id for {path}: {e}");
+                            }
+                        }
+                    },
+                )
+                .collect_into()
+                .await;
+        self.files.extend(new_files);
+        self.read_only.extend(read_only);
+    }
+
+    /// Takes the file id from the desktop client database of the prefix. Returns whether
+    /// the database knew the file.
+    fn take_client_file_id(
+        &mut self,
+        databases: &[Option<ClientDatabase>],
+        path: &SyncedPath,
+    ) -> bool {
+        let Some(database) = databases
+            .get(path.root().into_inner())
+            .and_then(Option::as_ref)
+        else {
+            return false;
+        };
+        let Some((file_id, permissions)) =
+            database.file_id(&path.local_file(&self.config.prefixes))
+        else {
+            return false;
+        };
+        if !permissions.can_tag() {
+            self.read_only.insert(file_id);
+        }
+        self.files.insert(file_id, path.clone());
+        true
+    }
 
-        let new_files = LimitedConcurrency::new(missing_file_id_requests, *max_concurrent_requests)
+    /// Returns all given paths which do not exist on the remote.
+    /// Paths which could not be checked are assumed to exist.
+    pub async fn find_missing_files<I>(&self, paths: I) -> Vec<SyncedPath>
+    where
+        I: IntoIterator<Item = SyncedPath> + Send,
+        I::IntoIter: Send,
+    {
+        let connection = &self.connection.clone();
+        let prefixes = &self.config.prefixes;
+        let requests = paths.into_iter().filter_map(|path| {
+            let request = GetFileId::new(&path.remote_file(prefixes));
+            if request.is_none() {
+                warn!("failed to format file {path} as UTF-8");
+            }
+            request.map(|req| (path, req))
+        });
+
+        LimitedConcurrency::new(requests, self.config.scan_concurrency)
             .transform(|(path, request)| async move { (path, connection.request(request).await) })
-            .aggregate(|new_files: &mut FileMap, (path, result)| match result {
-                Ok(file_id) => {
-                    new_files.insert(file_id, path);
-                }
-                Err(e) => {
-                    warn!("failed to query file id for {path}: {e}");
+            .aggregate(
+                |missing: &mut Vec<_>, (path, result): (_, Result<_, RequestError<_>>)| match result
+                {
+                    Ok(_) => {}
+                    Err(e) if e.is_not_found() => missing.push(path),
+                    Err(e) => warn!("failed to check existence of {path}: {e}"),
+                },
+            )
+            .collect_into()
+            .await
+    }
+
+    /// Files and folders which carry [`Config::skip_tag`]. Files below a returned folder
+    /// are skipped as well.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the tags or the tagged paths of a prefix
+    /// cannot be listed.
+    pub async fn find_skipped(&mut self) -> Result<BTreeSet<SyncedPath>, crate::InitError> {
+        if self.config.skip_tag.is_none() {
+            return Ok(BTreeSet::new());
+        }
+        let connection = self.connection.clone();
+        self.load_tags(&connection).await.context(RemoteSnafu)?;
+        let Some(id) = self.skip_tag_id else {
+            return Ok(BTreeSet::new());
+        };
+        let scope = Repository::new(self.config.prefixes.clone());
+        let mut skipped = BTreeSet::new();
+        for prefix in &self.config.prefixes {
+            // Such prefixes are not scanned either.
+            let Some((user, folder)) = prefix.user_folder(&self.config.dav_root) else {
+                continue;
+            };
+            let Some(request) = ListPathsWithTag::in_folder(id, folder) else {
+                continue;
+            };
+            let paths = connection
+                .request_as(user, request)
+                .await
+                .context(SkippedSnafu)?;
+            skipped.extend(paths.iter().filter_map(|path| scope.remote_path(path).ok()));
+        }
+        if !skipped.is_empty() {
+            info!(
+                "Skipping {} files and folders tagged on Nextcloud",
+                skipped.len()
+            );
+        }
+        Ok(skipped)
+    }
+
+    /// Adds the message of each file as a comment. Failures are only logged because the
+    /// comments are informational.
+    pub async fn comment(&mut self, comments: BTreeMap<SyncedPath, String>) {
+        let connection = self.connection.clone();
+        self.get_missing_file_ids(comments.keys().cloned(), &connection)
+            .await;
+        let connection = &connection;
+        let requests = comments.into_iter().filter_map(|(path, message)| {
+            let Some(&id) = self.files.get_by_right(&path) else {
+                warn!("Cannot comment on {path} without a file id");
+                return None;
+            };
+            Some((path, AddComment::new(id, message)))
+        });
+        let failed = LimitedConcurrency::new(requests, self.config.write_concurrency)
+            .transform(|(path, request)| async move { (path, connection.request(request).await) })
+            .aggregate(|failed: &mut usize, (path, result)| {
+                if let Err(e) = result {
+                    debug!("Failed to comment on {path}: {e}");
+                    *failed += 1;
                 }
             })
             .collect_into()
             .await;
-        self.files.extend(new_files);
+        if failed > 0 {
+            warn!("Failed to comment on {failed} files");
+        }
     }
 
-    async fn run_command(&self, cmd: Command, connection: &Connection) {
+    async fn run_command(&self, cmd: Command, connection: &Connection) -> CommandOutcome {
         let path = &cmd.path;
 
         let Some(&file_id) = self.files.get_by_right(path) else {
             // We queried unknown file ids before. Can only land here if query failed.
             error!("Unknown file {path}. Ensure file is synced so it has an ID.");
-            return;
+            return CommandOutcome::Failed {
+                command: cmd,
+                error: "unknown file id".to_owned(),
+            };
         };
 
+        let lock = if self.config.lock_remote_files {
+            match self.lock(path, connection).await {
+                Ok(lock) => lock,
+                Err(error) => {
+                    return CommandOutcome::Failed {
+                        command: cmd,
+                        error,
+                    }
+                }
+            }
+        } else {
+            None
+        };
+
+        let mut applied = Vec::new();
+        let mut failed = Vec::new();
+        let mut errors = Vec::new();
         for action in cmd.actions {
             let tag = &action.tag;
 
             let Some(&tag_id) = self.tags.get_by_right(&action.tag) else {
+                if let Some(kind) = self.system_tags.get(tag) {
+                    debug!("Skipping {kind:?} system tag {tag} for file {path}");
+                    errors.push(format!("tag {tag} is reserved by Nextcloud"));
+                    failed.push(action);
+                    continue;
+                }
+                if self.rejected_tags.contains(tag) {
+                    debug!("Skipping rejected tag {tag} for file {path}");
+                    errors.push(format!("tag name {tag} rejected by Nextcloud"));
+                    failed.push(action);
+                    continue;
+                }
                 // We created unknown tags before. Can only land here if tag creation failed.
                 error!("Unknown tag {tag}. Failed to update tags for file {path}.");
+                errors.push(format!("unknown tag {tag}"));
+                failed.push(action);
                 continue;
             };
 
+            if self.is_forbidden(tag) {
+                debug!("Skipping forbidden tag {tag} for file {path}");
+                errors.push(format!("insufficient permission for tag {tag}"));
+                failed.push(action);
+                continue;
+            }
+
             let res = match action.modification {
                 Modification::Add => connection.request(TagFile::new(tag_id, file_id)).await,
                 Modification::Remove => connection.request(UntagFile::new(tag_id, file_id)).await,
@@ -157,77 +689,250 @@ impl RemoteFs {
                     };
 
                     debug!("Successfully {updated} tag {tag} for file {path}");
+                    applied.push(action);
+                }
+                Err(e) if e.is_forbidden() => {
+                    warn!("Insufficient permission for tag {tag} on file {path}");
+                    self.forbidden_tags.lock().unwrap().insert(tag.clone());
+                    errors.push(format!("insufficient permission for tag {tag}"));
+                    failed.push(action);
                 }
                 Err(e) => {
-                    // TODO handle this case for remote and also local fs
-                    // What happens if update fails: cached repo should not be updated
-                    // for this file tag but it will be right now. This will lead to
-                    // issues in the next reverse direction run with tags being reset to the previous
-                    // state.
-                    // This can especially happen when a directory is tagged in Nextcloud as at least
-                    // BTRFS does not support tagging directories.
                     error!("Failed to update tag {tag} for file {path}: {e}",);
+                    if e.is_insufficient_storage() {
+                        warn!("Nextcloud is out of storage, check the quota with doctor");
+                    }
+                    errors.push(format!("tag {tag}: {e}"));
+                    failed.push(action);
                 }
             }
         }
+
+        if let Some(token) = lock {
+            self.unlock(path, token, connection).await;
+        }
+
+        CommandOutcome::from_actions(cmd.path, applied, failed, &errors)
+    }
+
+    /// Requests listing the files of each tag in each prefix. Prefixes whose path cannot be
+    /// sent are already marked as failed in the returned helper.
+    fn list_files_per_prefix(&self) -> (Vec<ListRequest<'_>>, FileTagHelper) {
+        let mut helper = FileTagHelper::default();
+        let mut requests = Vec::new();
+        for (index, prefix) in self.config.prefixes.iter().enumerate() {
+            let prefix_id = PrefixMappingId::from(index);
+            let Some((user, folder)) = prefix.user_folder(&self.config.dav_root) else {
+                helper
+                    .failed
+                    .insert(prefix_id, "not in the files of its user".to_owned());
+                continue;
+            };
+            for (&id, tag) in &self.tags {
+                let Some(request) = ListFilesWithTag::in_folder(id, folder) else {
+                    helper
+                        .failed
+                        .insert(prefix_id, "path is not UTF-8".to_owned());
+                    break;
+                };
+                requests.push((prefix_id, user, tag, request));
+                *helper.pending.entry(prefix_id).or_default() += 1;
+            }
+        }
+        (requests, helper)
+    }
+
+    /// Locks the remote file. Returns `None` if the path cannot be sent in a request.
+    async fn lock(
+        &self,
+        path: &SyncedPath,
+        connection: &Connection,
+    ) -> Result<Option<LockToken>, String> {
+        const LOCK_TIMEOUT_SECS: u64 = 60;
+
+        let remote = path.remote_file(&self.config.prefixes);
+        let Some(request) = LockFile::new(&remote, LOCK_TIMEOUT_SECS) else {
+            warn!("Cannot lock {path} with non UTF-8 path");
+            return Ok(None);
+        };
+        match connection.request(request).await {
+            Ok(token) => Ok(Some(token)),
+            Err(e) if e.is_locked() => {
+                warn!("Skipping {path} because it is locked by another client");
+                Err("locked by another client".to_owned())
+            }
+            Err(e) => {
+                error!("Failed to lock file {path}: {e}");
+                Err(format!("failed to lock file: {e}"))
+            }
+        }
+    }
+
+    async fn unlock(&self, path: &SyncedPath, token: LockToken, connection: &Connection) {
+        let remote = path.remote_file(&self.config.prefixes);
+        let Some(request) = UnlockFile::new(&remote, token) else {
+            return;
+        };
+        if let Err(e) = connection.request(request).await {
+            warn!("Failed to unlock file {path}, the lock expires on its own: {e}");
+        }
     }
 }
 
 impl FileSystem for RemoteFs {
-    async fn create_repo(&mut self) -> Result<crate::Repository, crate::InitError> {
-        use crate::{ListFilesWithTag, Repository};
-        let connection = &Connection::from_config(&self.config);
-        self.load_tags(connection).await.context(RemoteSnafu)?;
-        let file_tag_helper =
-            LimitedConcurrency::new(&self.tags, self.config.max_concurrent_requests)
-                .transform(|(id, tag)| async move {
-                    (tag, connection.request(ListFilesWithTag::new(*id)).await)
-                })
-                .aggregate(
-                    |tags: &mut FileTagHelper, (tag, result): (&Tag, Result<Vec<_>, _>)| {
-                        match result {
-                            Ok(files) => {
-                                debug!("Processing tag {tag} with {} files", files.len());
-                                tags.group_tags_by_file(tag, files);
-                            }
-                            Err(err) => error!("Failed to fetch file for tag {tag}: {err}"),
-                        }
-                    },
-                )
-                .collect_into()
-                .await;
+    async fn create_repo(
+        &mut self,
+    ) -> Result<(crate::Repository, crate::ScanIssues), crate::InitError> {
+        let connection = &self.connection.clone();
+        let loaded = self.load_tags(connection).await;
+        ensure!(!connection.in_maintenance(), MaintenanceSnafu);
+        loaded.context(RemoteSnafu)?;
         let mut repo = Repository::new(self.config.prefixes.clone());
-        for (file, tags) in file_tag_helper.file_tags {
-            let synced_path = repo.insert_remote(Path::new(&file), tags);
-            let Some(&id) = file_tag_helper.file_ids.get_by_right(&file) else {
-                warn!("Missing id for file {file}");
+        let scope = &repo;
+        let (requests, initial) = self.list_files_per_prefix();
+        let cancel = CancellationToken::new();
+        // Each prefix is listed on its own so a failing folder does not affect the others.
+        // Only tagged files are listed, so there is no folder tree whose unchanged parts
+        // could be skipped by their ETag. ETags also do not reliably change when a tag is
+        // assigned, so skipping would miss remote tag changes. The fulltext search and
+        // metadata apps cannot narrow the listing either, as they record neither tag
+        // assignments nor when they happened. Nextcloud only answers the `sync-collection`
+        // report for address books and calendars, and tag changes would not advance the
+        // sync token of the files anyway.
+        let mut file_tag_helper = LimitedConcurrency::new(requests, self.config.scan_concurrency)
+            .with_cancellation(cancel.clone())
+            .transform(|(prefix_id, user, tag, request)| {
+                let cancel = &cancel;
+                async move {
+                    let result = connection.request_as(user, request).await;
+                    if result.as_ref().is_err_and(RequestError::is_fatal) {
+                        cancel.cancel();
+                    }
+                    (prefix_id, tag, result)
+                }
+            })
+            .aggregate(
+                |tags: &mut FileTagHelper,
+                 (prefix_id, tag, result): (_, &Tag, Result<Vec<_>, RequestError<_>>)| {
+                    match result {
+                        Ok(files) => {
+                            debug!("Processing tag {tag} with {} files", files.len());
+                            tags.group_tags_by_file(tag, prefix_id, files, scope);
+                        }
+                        Err(err) if err.is_fatal() => {
+                            error!(
+                            "Failed to fetch file for tag {tag}, skipping remaining tags: {err}"
+                        );
+                            tags.fatal.get_or_insert(err);
+                        }
+                        Err(err) => {
+                            error!("Failed to fetch file for tag {tag} in prefix {prefix_id}: {err}");
+                            tags.failed
+                                .entry(prefix_id)
+                                .or_insert_with(|| format!("tag {tag}: {err}"));
+                        }
+                    }
+                    tags.finish_request(prefix_id, &self.config);
+                },
+            )
+            .collect_into_with(initial)
+            .await;
+        // Missing tags would be interpreted as removed, so never return a partial repository.
+        ensure!(!connection.in_maintenance(), MaintenanceSnafu);
+        if let Some(source) = file_tag_helper.fatal {
+            return Err(crate::InitError::Aborted { source });
+        }
+        let mut issues = ScanIssues::default();
+        for (prefix_id, error) in std::mem::take(&mut file_tag_helper.failed) {
+            file_tag_helper.drop_prefix(prefix_id);
+            issues.push(ScanIssue::PrefixFailed {
+                prefix_id,
+                path: self.config.prefixes[prefix_id.into_inner()]
+                    .remote()
+                    .to_owned(),
+                error,
+            });
+        }
+        if file_tag_helper.outside > 0 {
+            info!(
+                "Ignored {} tag assignments of files outside of synced folders",
+                file_tag_helper.outside
+            );
+        }
+        self.find_encrypted_folders(file_tag_helper.file_tags.keys(), connection)
+            .await;
+        for (synced_path, tags) in file_tag_helper.file_tags {
+            if self.encrypted_parent(&synced_path).is_some() {
+                debug!("Skipping file {synced_path} in end-to-end encrypted folder");
+                continue;
+            }
+            repo.insert(synced_path.clone(), tags);
+            let Some(&id) = file_tag_helper.file_ids.get_by_right(&synced_path) else {
+                warn!("Missing id for file {synced_path}");
+                issues.push(ScanIssue::MissingFileId { path: synced_path });
                 continue;
             };
-            self.files.insert(id, synced_path);
+            // Ids are queried again when a file is updated instead.
+            if !self.config.reduced_concurrency {
+                self.files.insert(id, synced_path);
+            }
         }
+        self.read_only.extend(file_tag_helper.read_only);
 
-        Ok(repo)
+        Ok((repo, issues))
     }
 
-    async fn update_tags<I>(&mut self, commands: I)
+    async fn update_tags<I>(&mut self, commands: I) -> Vec<CommandOutcome>
     where
         I: IntoIterator<Item = Command> + Send,
     {
-        let connection = Connection::from_config(&self.config);
-        let commands: Vec<_> = commands.into_iter().collect();
+        let connection = self.connection.clone();
+        let mut skipped = Vec::new();
+        let commands = self.skip_encrypted(commands.into_iter().collect(), &mut skipped);
         if let Err(e) = self.load_tags(&connection).await {
             tracing::warn!("Failed to load existing tags from Nextcloud: {e}");
         }
-        self.create_missing_tags(commands.clone(), &connection)
+        if connection.in_maintenance() {
+            skipped.extend(commands.into_iter().map(|command| CommandOutcome::Failed {
+                command,
+                error: "Nextcloud is in maintenance mode".to_owned(),
+            }));
+            return skipped;
+        }
+
+        self.get_missing_file_ids(commands.iter().map(|cmd| cmd.path.clone()), &connection)
             .await;
 
-        self.get_missing_file_ids(commands.clone(), &connection)
+        // Files in end-to-end encrypted folders are stored under a different name
+        // on the server, so their lookup fails.
+        let unknown_files: Vec<_> = commands
+            .iter()
+            .map(|cmd| &cmd.path)
+            .filter(|path| !self.files.contains_right(*path))
+            .cloned()
+            .collect();
+        self.find_encrypted_folders(&unknown_files, &connection)
             .await;
+        let commands = self.skip_encrypted(commands, &mut skipped);
+        let commands = self.skip_read_only_shares(commands, &mut skipped);
 
-        LimitedConcurrency::new(commands, self.config.max_concurrent_requests)
+        self.create_missing_tags(commands.clone(), &connection)
+            .await;
+
+        let mut outcomes = LimitedConcurrency::new(commands, self.config.write_concurrency)
             .transform(|cmd| self.run_command(cmd, &connection))
-            .execute()
+            .aggregate(|outcomes: &mut Vec<_>, outcome| outcomes.push(outcome))
+            .collect_into()
             .await;
+        outcomes.append(&mut skipped);
+
+        if self.config.skip_forbidden_tags {
+            TagList::Forbidden.persist(&self.config, &self.forbidden_tags());
+        }
+        if self.config.reduced_concurrency {
+            self.files.clear();
+        }
+        outcomes
     }
 }
 
@@ -237,21 +942,75 @@ pub struct ListTagsError {
     pub source: RequestError<DeserializeError>,
 }
 
+#[derive(Debug, Snafu)]
+pub enum ManageTagError {
+    #[snafu(display("{source}"))]
+    LoadTags { source: ListTagsError },
+    #[snafu(display("tag {tag} does not exist"))]
+    UnknownTag { tag: Tag },
+    #[snafu(display("tag {tag} already exists"))]
+    TagExists { tag: Tag },
+    #[snafu(display("failed to update tag: {source}"))]
+    UpdateTag {
+        source: RequestError<UpdateTagError>,
+    },
+    #[snafu(display("failed to list files with the tag: {source}"))]
+    ListAssigned {
+        source: RequestError<DeserializeError>,
+    },
+    #[snafu(display("tag {tag} is still assigned to {files} files"))]
+    StillAssigned { tag: Tag, files: usize },
+    #[snafu(display("failed to delete tag: {source}"))]
+    DeleteTag {
+        source: RequestError<std::convert::Infallible>,
+    },
+}
+
 #[derive(Debug, Default)]
 struct FileTagHelper {
-    file_ids: bimap::BiHashMap<FileId, String>,
-    file_tags: HashMap<String, Tags>,
+    file_ids: bimap::BiHashMap<FileId, SyncedPath>,
+    read_only: HashSet<FileId>,
+    file_tags: HashMap<SyncedPath, Tags>,
+    /// Tag assignments of files outside of synced folders. They are only counted to keep
+    /// memory usage independent of the size of the whole Nextcloud.
+    outside: usize,
+    /// First error after which no further tags were fetched.
+    fatal: Option<RequestError<DeserializeError>>,
+    /// First error of each prefix which could not be listed completely.
+    failed: BTreeMap<PrefixMappingId, String>,
+    /// Number of outstanding requests of each prefix.
+    pending: HashMap<PrefixMappingId, usize>,
 }
 
 impl FileTagHelper {
-    fn group_tags_by_file<I: IntoIterator<Item = (FileId, String)>>(
+    /// Groups the tags of files of `prefix` in `scope`. Other files are dropped right away.
+    fn group_tags_by_file<I: IntoIterator<Item = TaggedFile>>(
         &mut self,
         tag: &str,
+        prefix: PrefixMappingId,
         files: I,
+        scope: &Repository,
     ) {
         #[allow(unstable_name_collisions)]
         let tag: Tags = tag.parse().into_ok();
-        for (id, file) in files {
+        for TaggedFile {
+            id,
+            path,
+            permissions,
+            ..
+        } in files
+        {
+            let Ok(file) = scope.remote_path(&path) else {
+                self.outside += 1;
+                continue;
+            };
+            // Files of nested prefixes are listed with their own prefix.
+            if file.root() != prefix {
+                continue;
+            }
+            if !permissions.can_tag() {
+                self.read_only.insert(id);
+            }
             self.file_ids.insert(id, file.clone());
             match self.file_tags.entry(file) {
                 Entry::Occupied(mut entry) => entry.get_mut().insert_all(&tag),
@@ -261,32 +1020,67 @@ impl FileTagHelper {
             }
         }
     }
+
+    fn finish_request(&mut self, prefix: PrefixMappingId, config: &Config) {
+        let Some(pending) = self.pending.get_mut(&prefix) else {
+            return;
+        };
+        *pending -= 1;
+        if *pending == 0 && !self.failed.contains_key(&prefix) {
+            let remote = config.prefixes[prefix.into_inner()].remote();
+            info!("Scanned {}", remote.display());
+        }
+    }
+
+    /// Forgets the partial results of a prefix that could not be listed completely.
+    fn drop_prefix(&mut self, prefix: PrefixMappingId) {
+        self.file_tags.retain(|path, _| path.root() != prefix);
+        self.file_ids.retain(|_, path| path.root() != prefix);
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::path::Path;
+
     use super::*;
+    use crate::{FileProperties, Permissions, PrefixMapping, RemotePath};
 
     #[test]
     fn group_tags() {
-        let files = (0..2000).map(|i| (FileId::from(i), format!("/basic/{i}/bla")));
-        let files1 = (2000..4000).map(|i| (FileId::from(i), format!("/basic/{i}/blob")));
+        let tagged_file = |i, folder| TaggedFile {
+            id: FileId::from(i),
+            path: RemotePath::from_href(&format!("/remote.php/dav/files/user/{folder}/{i}")),
+            permissions: Permissions::default(),
+            properties: FileProperties::default(),
+        };
+        let files = (0..2000).map(|i| tagged_file(i, "basic"));
+        let files1 = (2000..4000).map(|i| tagged_file(i, "basic"));
+        let outside = (4000..4500).map(|i| tagged_file(i, "other"));
+        let scope = Repository::new(vec![PrefixMapping::new(
+            "/local".into(),
+            "/remote.php/dav/files/user/basic".into(),
+        )
+        .unwrap()]);
         let mut ftt = FileTagHelper::default();
 
-        ftt.group_tags_by_file("tag", files.clone());
-        ftt.group_tags_by_file("tag1", files.clone());
-        ftt.group_tags_by_file("tag2", files.clone());
-        ftt.group_tags_by_file("tag3", files);
-        ftt.group_tags_by_file("tag3", files1);
+        let prefix = PrefixMappingId::from(0);
+        ftt.group_tags_by_file("tag", prefix, files.clone(), &scope);
+        ftt.group_tags_by_file("tag1", prefix, files.clone(), &scope);
+        ftt.group_tags_by_file("tag2", prefix, files.clone(), &scope);
+        ftt.group_tags_by_file("tag3", prefix, files, &scope);
+        ftt.group_tags_by_file("tag3", prefix, files1, &scope);
+        ftt.group_tags_by_file("tag3", prefix, outside, &scope);
 
         assert_eq!(ftt.file_tags.len(), 4000);
         for tags in ftt.file_tags.values() {
             assert!(tags.len() <= 4);
         }
+        assert_eq!(ftt.outside, 500);
 
         assert_eq!(ftt.file_ids.len(), 4000);
         for (id, file) in ftt.file_ids {
-            assert!(file.contains(&id.to_string()));
+            assert_eq!(file.relative(), Path::new(&id.to_string()));
         }
     }
 }
@@ -0,0 +1,114 @@
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+/// Characters that are kept as-is in a path segment, see RFC 3986 "unreserved".
+const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Decoded path of a remote file, e.g. `/remote.php/dav/files/user/My Photos/a#1.jpg`.
+///
+/// Paths are only percent-encoded when they are sent to the server and decoded
+/// as soon as they are received, so they can be compared with local paths.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RemotePath(PathBuf);
+
+impl RemotePath {
+    /// Parses an `href` as returned in a DAV multistatus response.
+    ///
+    /// Segments that are not valid UTF-8 once decoded are kept encoded.
+    #[must_use]
+    pub fn from_href(href: &str) -> Self {
+        let mut path = PathBuf::from("/");
+        for segment in href.split('/').filter(|s| !s.is_empty()) {
+            let decoded = percent_decode_str(segment)
+                .decode_utf8()
+                .unwrap_or_else(|_| segment.into());
+            path.push(&*decoded);
+        }
+        Self(path)
+    }
+
+    /// Appends a relative path, e.g. the path of a file inside a synced folder.
+    #[must_use]
+    pub fn join(&self, relative: &Path) -> Self {
+        Self(self.0.join(relative))
+    }
+
+    #[must_use]
+    pub fn as_path(&self) -> &Path {
+        &self.0
+    }
+
+    #[must_use]
+    pub fn starts_with(&self, base: &Path) -> bool {
+        self.0.starts_with(base)
+    }
+
+    /// Percent-encoded form for use in a request URL. Returns `None` if the path is not UTF-8.
+    #[must_use]
+    pub fn to_href(&self) -> Option<String> {
+        let mut href = String::new();
+        for component in self.0.components() {
+            if let Component::Normal(segment) = component {
+                href.push('/');
+                href.extend(utf8_percent_encode(segment.to_str()?, SEGMENT));
+            }
+        }
+        if href.is_empty() {
+            href.push('/');
+        }
+        Some(href)
+    }
+}
+
+impl From<&Path> for RemotePath {
+    fn from(value: &Path) -> Self {
+        Self(value.to_owned())
+    }
+}
+
+impl From<PathBuf> for RemotePath {
+    fn from(value: PathBuf) -> Self {
+        Self(value)
+    }
+}
+
+impl AsRef<Path> for RemotePath {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl fmt::Display for RemotePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.display().fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_odd_names() {
+        let href = "/remote.php/dav/files/user/My%20Photos/a%231%3F%25.jpg";
+        let path = RemotePath::from_href(href);
+        assert_eq!(
+            path.as_path(),
+            Path::new("/remote.php/dav/files/user/My Photos/a#1?%.jpg")
+        );
+        assert_eq!(path.to_href().as_deref(), Some(href));
+
+        let joined = RemotePath::from(Path::new("/remote.php/dav/files/user"))
+            .join(Path::new("Ünïcode/100% done.txt"));
+        assert_eq!(
+            joined.to_href().as_deref(),
+            Some("/remote.php/dav/files/user/%C3%9Cn%C3%AFcode/100%25%20done.txt")
+        );
+    }
+}
@@ -1,20 +1,43 @@
+mod add_comment;
+mod backend;
 mod common;
 mod create_tag;
+mod delete_tag;
 mod get_file_id;
+mod get_file_tags;
+mod is_encrypted;
+mod list_directory;
 mod list_files_with_tag;
 mod list_tags;
+mod lock_file;
+mod middleware;
+mod ocs;
+mod redirect;
 mod tag_file;
 mod untag_file;
+mod update_tag;
+mod xml;
 
 use common::{empty_as_none, str_to_method};
 
-pub use common::{Connection, RequestError};
+pub use add_comment::AddComment;
+pub use backend::RemoteBackend;
+pub use common::{http_client, instance_url, Connection, RequestError, ServerException};
 pub use create_tag::CreateTag;
-pub use get_file_id::GetFileId;
-pub use list_files_with_tag::ListFilesWithTag;
-pub use list_tags::ListTags;
+pub use delete_tag::DeleteTag;
+pub use get_file_id::{GetFileId, RemoteFile};
+pub use get_file_tags::GetFileTags;
+pub use is_encrypted::IsEncrypted;
+pub use list_directory::{DirectoryEntry, ListDirectory};
+pub use list_files_with_tag::{ListFilesWithTag, ListPathsWithTag, TaggedFile};
+pub use list_tags::{ListTags, SystemTagPolicy, TagKind, TagListing};
+pub use lock_file::{LockFile, LockToken, MissingLockTokenError, UnlockFile};
+pub use middleware::{Exchange, Middleware};
+pub use ocs::{OcsError, OcsUserInfo, Quota};
 pub use tag_file::TagFile;
 pub use untag_file::UntagFile;
+pub use update_tag::{UpdateTag, UpdateTagError};
+pub use xml::{FileProperties, MultiStatus, Property};
 pub type ListTagsMultiStatus = list_tags::MultiStatus;
 
 pub use common::{parse, Body, DeserializeError, Parse, Request};
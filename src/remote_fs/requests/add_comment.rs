@@ -0,0 +1,53 @@
+use std::{borrow::Cow, convert::Infallible};
+
+use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+
+use super::{Body, Parse, Request};
+use crate::remote_fs::FileId;
+
+/// Adds a comment to a file, shown in its details in the web UI.
+pub struct AddComment {
+    file: FileId,
+    message: String,
+}
+
+impl AddComment {
+    #[must_use]
+    pub const fn new(file: FileId, message: String) -> Self {
+        Self { file, message }
+    }
+}
+
+impl Request for AddComment {
+    fn method(&self) -> reqwest::Method {
+        reqwest::Method::POST
+    }
+
+    fn endpoint(&self) -> Cow<'_, str> {
+        format!("comments/files/{}", self.file).into()
+    }
+
+    fn body(&self) -> Body {
+        let body = serde_json::json!({
+            "actorType": "users",
+            "verb": "comment",
+            "message": self.message,
+        });
+        Body::Raw(body.to_string().into_bytes())
+    }
+
+    fn headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        headers
+    }
+}
+
+impl Parse for AddComment {
+    type Output = ();
+    type Error = Infallible;
+
+    fn parse(_: &HeaderMap, _: &str) -> Result<Self::Output, Self::Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,16 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+
+/// Answers requests in place of a Nextcloud server, e.g. the
+/// [`Simulator`](crate::Simulator) in tests without network access.
+pub trait RemoteBackend: Debug + Send + Sync {
+    /// Response to `request`, which already passed all middlewares.
+    fn respond(&self, request: &reqwest::Request) -> http::Response<String>;
+}
+
+/// Allows keeping a handle to a backend, e.g. to inspect its state.
+impl<B: RemoteBackend + ?Sized> RemoteBackend for Arc<B> {
+    fn respond(&self, request: &reqwest::Request) -> http::Response<String> {
+        (**self).respond(request)
+    }
+}
@@ -1,52 +1,329 @@
-use std::borrow::Cow;
+use std::{
+    borrow::Cow,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, SystemTime},
+};
 
-use askama::Template;
-use reqwest::header::{HeaderMap, CONTENT_TYPE};
+use reqwest::header::{
+    HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE, DATE, LOCATION, RETRY_AFTER, USER_AGENT,
+};
+use reqwest::StatusCode;
 use snafu::{prelude::*, ResultExt};
-use tracing::{debug, error, info, trace};
+use tracing::{debug, error, info, trace, warn};
 use url::Url;
 
 use crate::Config;
 
+use super::{redirect, Exchange, Middleware, RemoteBackend};
+
+/// Redirects followed in a row before a request fails, to break redirect loops.
+const MAX_REDIRECTS: usize = 5;
+/// Rate limited requests are retried this many times before they fail.
+const MAX_RATE_LIMIT_RETRIES: usize = 3;
+/// Wait for a rate limit without `Retry-After` header.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(5);
+/// Longest wait for a rate limit, so a huge `Retry-After` fails the request instead.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(30);
+
+/// Headers attached to every request. Invalid entries are skipped with a warning.
+fn default_headers(config: &Config) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    match HeaderValue::from_str(&config.user_agent) {
+        Ok(value) => {
+            headers.insert(USER_AGENT, value);
+        }
+        Err(e) => warn!("Ignoring invalid user agent {:?}: {e}", config.user_agent),
+    }
+    for (name, value) in &config.extra_headers {
+        match (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) {
+            (Ok(name), Ok(mut value)) => {
+                value.set_sensitive(true);
+                headers.insert(name, value);
+            }
+            (Err(e), _) => warn!("Ignoring extra header with invalid name {name:?}: {e}"),
+            (_, Err(e)) => warn!("Ignoring extra header {name} with invalid value: {e}"),
+        }
+    }
+    headers
+}
+
+/// Resolves `path` below the Nextcloud instance.
+///
+/// The instance may be served from a subdirectory, e.g. `https://example.com/nextcloud/`.
+/// Absolute paths are relative to the instance
+/// unless they already start with its subdirectory, like the hrefs in DAV responses.
+///
+/// # Errors
+///
+/// This function will return an error if `path` does not form a valid URL.
+pub fn instance_url(instance: &Url, path: &str) -> Result<Url, url::ParseError> {
+    let mut base = instance.clone();
+    if !base.path().ends_with('/') {
+        base.set_path(&format!("{}/", base.path()));
+    }
+    if path.starts_with(base.path()) {
+        base.join(path)
+    } else {
+        base.join(path.trim_start_matches('/'))
+    }
+}
+
+/// Resolves the configured DAV root against the Nextcloud instance.
+///
+/// # Panics
+///
+/// Panics if the DAV root is not a valid URL path.
+fn dav_root_url(instance: &Url, dav_root: &str) -> Url {
+    let mut dav_root = dav_root.to_owned();
+    if !dav_root.ends_with('/') {
+        dav_root.push('/');
+    }
+    instance_url(instance, &dav_root).expect("failed to create URL")
+}
+
+/// HTTP client sending the configured headers. Connects to the pinned server address if
+/// [`Config::resolve`] is set.
+///
+/// Redirects are not followed because they turn DAV requests into `GET` requests.
+/// [`Connection`] follows safe ones itself.
+///
+/// # Panics
+///
+/// Panics if the HTTP client cannot be initialized, e.g. because no TLS backend is available.
+#[must_use]
+pub fn http_client(config: &Config) -> reqwest::Client {
+    let mut builder = reqwest::Client::builder()
+        .default_headers(default_headers(config))
+        .redirect(reqwest::redirect::Policy::none());
+    if let (Some(domain), Some(address)) = (
+        config.nextcloud_instance.domain(),
+        crate::doctor::pinned_address(config),
+    ) {
+        builder = builder.resolve(domain, address);
+    }
+    builder.build().expect("failed to create HTTP client")
+}
+
+/// Authenticated connection to a Nextcloud instance which sends [`Request`]s.
 #[derive(Debug)]
 pub struct Connection {
-    host: Url,
+    endpoints: RwLock<Endpoints>,
+    configured_instance: Url,
+    /// Where the canonical instance URL is stored once a redirect revealed it.
+    instance_file: Option<PathBuf>,
     user: String,
     token: String,
     client: reqwest::Client,
+    /// Set once the server reported maintenance mode. All further requests fail immediately.
+    maintenance: AtomicBool,
+    /// Set once the clock of the server was compared to ours.
+    clock_checked: AtomicBool,
+    max_clock_skew: Duration,
+    middleware: Vec<Arc<dyn Middleware>>,
+    /// Answers requests instead of the server if set.
+    backend: Option<Arc<dyn RemoteBackend>>,
+}
+
+/// Base URLs of requests, which change when the server redirects to another address.
+#[derive(Debug, Clone)]
+struct Endpoints {
+    host: Url,
+    dav_root: Url,
 }
 
 impl Connection {
+    /// Creates a connection that sends the configured user agent and extra headers
+    /// with every request.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the HTTP client cannot be initialized, e.g. because no TLS backend is available.
     #[must_use]
     pub fn from_config(config: &Config) -> Self {
+        let host =
+            redirect::load_canonical(config).unwrap_or_else(|| config.nextcloud_instance.clone());
         Self {
-            client: reqwest::Client::default(),
+            client: http_client(config),
             user: config.user.clone(),
             token: config.token.clone(),
-            host: config.nextcloud_instance.clone(),
+            endpoints: RwLock::new(Endpoints {
+                dav_root: dav_root_url(&host, &config.dav_root),
+                host,
+            }),
+            configured_instance: config.nextcloud_instance.clone(),
+            instance_file: Some(redirect::instance_path(config)),
+            maintenance: AtomicBool::new(false),
+            clock_checked: AtomicBool::new(config.max_clock_skew_secs == 0),
+            max_clock_skew: Duration::from_secs(config.max_clock_skew_secs),
+            middleware: Vec::new(),
+            backend: None,
+        }
+    }
+
+    /// Creates a connection with default settings, authenticating with an app password.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the HTTP client cannot be initialized, e.g. because no TLS backend is available.
+    #[must_use]
+    pub fn new(instance: Url, user: impl Into<String>, app_password: impl Into<String>) -> Self {
+        let config = Config {
+            nextcloud_instance: instance,
+            user: user.into(),
+            token: app_password.into(),
+            ..Config::default()
+        };
+        Self {
+            endpoints: RwLock::new(Endpoints {
+                dav_root: dav_root_url(&config.nextcloud_instance, &config.dav_root),
+                host: config.nextcloud_instance.clone(),
+            }),
+            instance_file: None,
+            ..Self::from_config(&config)
+        }
+    }
+
+    /// Adds a middleware that is called for every request after the ones added before.
+    #[must_use]
+    pub fn with_middleware(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Sends all requests to `backend` instead of the server.
+    #[must_use]
+    pub fn with_backend(mut self, backend: impl RemoteBackend + 'static) -> Self {
+        self.backend = Some(Arc::new(backend));
+        self
+    }
+
+    /// Warns once if the `Date` header of the server deviates too much from the local clock.
+    fn check_clock_skew(&self, headers: &HeaderMap) {
+        if self.clock_checked.load(Ordering::Relaxed) {
+            return;
+        }
+        let Some(skew) = clock_skew(headers, SystemTime::now()) else {
+            return;
+        };
+        if self.clock_checked.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        match skew {
+            Ok(behind) if behind > self.max_clock_skew => {
+                warn!("Local clock is {}s behind the server", behind.as_secs());
+            }
+            Err(ahead) if ahead > self.max_clock_skew => {
+                warn!("Local clock is {}s ahead of the server", ahead.as_secs());
+            }
+            _ => debug!("Clock skew to server is {skew:?}"),
         }
     }
 
+    fn endpoints(&self) -> Endpoints {
+        self.endpoints
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Moves the connection to the address a redirect from `url` points to, if it is safe
+    /// to follow. The address is stored for later runs.
+    fn follow_redirect<E>(&self, url: &Url, headers: &HeaderMap) -> Result<(), RequestError<E>>
+    where
+        E: std::fmt::Display + std::error::Error + 'static,
+    {
+        let location = headers
+            .get(LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .and_then(|location| url.join(location).ok());
+        let Endpoints { host, dav_root } = self.endpoints();
+        let Some(moved) = location
+            .as_ref()
+            .and_then(|location| redirect::moved_instance(&host, url, location))
+        else {
+            return RedirectSnafu {
+                location: location.map(String::from).unwrap_or_default(),
+            }
+            .fail();
+        };
+        info!("Nextcloud instance {host} moved to {moved}");
+        if let Some(path) = &self.instance_file {
+            redirect::persist_canonical(path, &self.configured_instance, &moved);
+        }
+        *self
+            .endpoints
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner) = Endpoints {
+            dav_root: dav_root_url(&moved, dav_root.path()),
+            host: moved,
+        };
+        Ok(())
+    }
+
+    /// Whether the server responded that it is in maintenance mode.
+    #[must_use]
+    pub fn in_maintenance(&self) -> bool {
+        self.maintenance.load(Ordering::Relaxed)
+    }
+
+    /// Sends the request to the Nextcloud instance and parses its response.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the request fails or the response cannot be parsed.
     pub async fn request<T>(&self, request: T) -> Result<T::Output, RequestError<T::Error>>
     where
         T: Request + Parse + Send,
     {
+        self.request_as(&self.user, request).await
+    }
+
+    /// Like [`Self::request`] but resolves the URL against the DAV root of `user`, e.g. the
+    /// owner of a shared folder. Authentication still uses the configured credentials.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the request fails or the response cannot be parsed.
+    pub async fn request_as<T>(
+        &self,
+        user: &str,
+        request: T,
+    ) -> Result<T::Output, RequestError<T::Error>>
+    where
+        T: Request + Parse + Send,
+    {
+        let mut redirects = 0;
+        let mut rate_limited = 0;
         loop {
-            let url = request.url(&self.host, &self.user);
+            ensure!(!self.in_maintenance(), MaintenanceSnafu);
+            let Endpoints { host, dav_root } = self.endpoints();
+            let url = request
+                .url(&host, &dav_root, user)
+                .with_context(|_| InvalidUrlSnafu {
+                    endpoint: request.endpoint(),
+                })?;
             let method = request.method();
 
             debug!("Starting request {method} {url}");
-            let (payload, headers, error) = if true {
+            let (payload, headers, status) = if true {
                 let mut request_builder = self
                     .client
-                    .request(method, url)
-                    .basic_auth(&self.user, Some(&self.token));
+                    .request(method.clone(), url)
+                    .basic_auth(&self.user, Some(&self.token))
+                    .headers(request.headers());
 
                 match request.body() {
-                    Body::Askama { content, mime_type } => {
-                        let body = content.context(AskamaSnafu)?;
-                        request_builder =
-                            request_builder.header(CONTENT_TYPE, mime_type).body(body);
+                    Body::Xml(data) => {
+                        request_builder = request_builder
+                            .header(CONTENT_TYPE, "application/xml; charset=utf-8")
+                            .body(data);
                     }
                     Body::Empty => {}
                     Body::Raw(data) => {
@@ -54,42 +331,127 @@ impl Connection {
                     }
                 }
 
-                let response = request_builder.send().await.context(ReqwestSnafu)?;
-                let error = response.error_for_status_ref().err();
+                let mut http_request = request_builder.build().context(ReqwestSnafu)?;
+                for middleware in &self.middleware {
+                    middleware.on_request(&mut http_request);
+                }
+                let (method, url) = (http_request.method().clone(), http_request.url().clone());
+
+                let response = match &self.backend {
+                    Some(backend) => backend.respond(&http_request).into(),
+                    None => self
+                        .client
+                        .execute(http_request)
+                        .await
+                        .context(ReqwestSnafu)?,
+                };
+                let status = response.status();
 
                 let headers = response.headers().clone();
+                self.check_clock_skew(&headers);
                 let body = response.text().await.context(ReqwestSnafu)?;
 
-                (body, headers, error)
+                let exchange = Exchange {
+                    method: &method,
+                    url: &url,
+                    status,
+                    headers: &headers,
+                    payload: &body,
+                };
+                for middleware in &self.middleware {
+                    middleware.on_response(&exchange);
+                }
+                if status.is_redirection() {
+                    redirects += 1;
+                    ensure!(
+                        redirects <= MAX_REDIRECTS,
+                        RedirectSnafu {
+                            location: url.as_str()
+                        }
+                    );
+                    self.follow_redirect(&url, &headers)?;
+                    continue;
+                }
+
+                (body, headers, status)
             } else {
                 //read_sample_data(&method, &url, &body)
                 todo!()
             };
 
-            if let Some(error) = error {
+            if status.is_client_error() || status.is_server_error() {
+                if is_maintenance_mode(status, &headers, &payload) {
+                    if !self.maintenance.swap(true, Ordering::Relaxed) {
+                        warn!("Nextcloud is in maintenance mode. Skipping all further requests.");
+                    }
+                    return MaintenanceSnafu.fail();
+                }
                 error!("Received payload {payload:#} and headers {headers:#?}");
-                if is_database_lock_error(&error, &payload) {
+                if is_database_lock_error(status, &payload) {
                     info!("Retrying because of transient error reason locked DB");
                     continue;
                 }
-                return Err(error).context(ReqwestSnafu);
+                let error = RequestError::from_response(&method, status, &headers, &payload);
+                if let Some(wait) = rate_limit_delay(&error, rate_limited) {
+                    rate_limited += 1;
+                    info!("Rate limited by Nextcloud, retrying in {}s", wait.as_secs());
+                    tokio::time::sleep(wait).await;
+                    continue;
+                }
+                return Err(error);
             }
 
             trace!("Received payload {payload} and headers {headers:?}");
 
             // update_sample_data(&method1, &url1, &body1, &payload).await;
 
+            ensure!(!is_html(&headers), UnexpectedHtmlSnafu);
             return T::parse(&headers, &payload).context(DeserializeSnafu);
         }
     }
 }
 
-fn is_database_lock_error(error: &reqwest::Error, payload: &str) -> bool {
-    let Some(status) = error.status() else {
-        return false;
+/// Difference between the `Date` header of the server and `now`. `Ok` if the local clock is
+/// behind the server, `Err` if it is ahead. The header has a resolution of one second.
+fn clock_skew(headers: &HeaderMap, now: SystemTime) -> Option<Result<Duration, Duration>> {
+    let date = headers.get(DATE)?.to_str().ok()?;
+    let server = httpdate::parse_http_date(date).ok()?;
+    Some(server.duration_since(now).map_err(|e| e.duration()))
+}
+
+/// Nextcloud answers all requests with 503 Service Unavailable while in maintenance mode.
+fn is_maintenance_mode(status: StatusCode, headers: &HeaderMap, payload: &str) -> bool {
+    status == StatusCode::SERVICE_UNAVAILABLE
+        && (headers.contains_key("X-Nextcloud-Maintenance-Mode")
+            || payload.contains("maintenance mode"))
+}
+
+/// Nextcloud answers DAV and OCS requests with XML or JSON. HTML comes from login pages of
+/// proxies or from a URL which does not point to Nextcloud.
+fn is_html(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("text/html"))
+}
+
+/// Wait before retrying a rate limited request after `retries` retries, `None` if it
+/// fails instead.
+fn rate_limit_delay<E>(error: &RequestError<E>, retries: usize) -> Option<Duration>
+where
+    E: std::fmt::Display + std::error::Error + 'static,
+{
+    let RequestError::RateLimited { retry_after, .. } = error else {
+        return None;
     };
+    let wait = retry_after.unwrap_or(DEFAULT_RETRY_AFTER);
+    (retries < MAX_RATE_LIMIT_RETRIES && wait <= MAX_RETRY_AFTER).then_some(wait)
+}
+
+fn is_database_lock_error(status: StatusCode, payload: &str) -> bool {
     if status.is_server_error() && payload.contains("LockWaitTimeoutException") {
-        error!("Request failed: {}", error);
+        error!("Request failed: {status}");
         return true;
     }
     false
@@ -137,37 +499,35 @@ fn read_sample_data(method: &reqwest::Method, url: &url::Url, body: &str) -> Str
 
 pub trait Request {
     fn method(&self) -> reqwest::Method;
-    fn endpoint(&self) -> Cow<str>;
-    fn url(&self, host: &Url, _user: &str) -> Url {
-        let url = host.join("remote.php/dav/").expect("failed to create URL");
-        url.join(&self.endpoint()).expect("failed to create URL")
+    fn endpoint(&self) -> Cow<'_, str>;
+    /// URL of the request. `dav_root` is the base of all DAV collections, e.g.
+    /// `https://cloud.example.com/remote.php/dav/`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the endpoint does not form a valid URL.
+    fn url(&self, _host: &Url, dav_root: &Url, _user: &str) -> Result<Url, url::ParseError> {
+        dav_root.join(&self.endpoint())
     }
 
     fn body(&self) -> Body {
         Body::default()
     }
+
+    fn headers(&self) -> HeaderMap {
+        HeaderMap::new()
+    }
 }
 
 #[derive(Debug, Default)]
 pub enum Body {
-    Askama {
-        content: askama::Result<String>,
-        mime_type: &'static str,
-    },
+    /// Document built with [`document`](super::xml::document).
+    Xml(Vec<u8>),
     Raw(Vec<u8>),
     #[default]
     Empty,
 }
 
-impl<T: Template> From<&T> for Body {
-    fn from(value: &T) -> Self {
-        Self::Askama {
-            content: value.render(),
-            mime_type: T::MIME_TYPE,
-        }
-    }
-}
-
 pub trait Parse {
     type Output;
     type Error: snafu::Error + 'static;
@@ -207,12 +567,457 @@ pub fn parse<'de, T: serde::Deserialize<'de>>(input: &'de str) -> Result<T, Dese
     serde_path_to_error::deserialize(deserializer)
 }
 
+/// Exception which Nextcloud describes in the XML body of an error response, e.g.
+/// `Sabre\DAV\Exception\NotFound` with a message naming the missing file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Deserialize)]
+pub struct ServerException {
+    pub exception: Option<String>,
+    pub message: Option<String>,
+}
+
+impl ServerException {
+    /// Parses the body of an error response. Bodies without an exception, e.g. from
+    /// proxies, give an empty exception.
+    #[must_use]
+    pub fn parse(payload: &str) -> Self {
+        parse(payload).unwrap_or_default()
+    }
+}
+
+impl std::fmt::Display for ServerException {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.message, &self.exception) {
+            (Some(message), _) => write!(f, ": {message}"),
+            (None, Some(exception)) => write!(f, ": {exception}"),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
 #[derive(Debug, Snafu)]
 pub enum RequestError<DeserializeError: std::fmt::Display + std::error::Error + 'static> {
-    #[snafu(display("Failed to render request template: {source}"))]
-    Askama { source: askama::Error },
+    /// The request could not be sent or its response could not be received.
     #[snafu(display("Request failed: {source}"))]
     Reqwest { source: reqwest::Error },
+    #[snafu(display("Nextcloud rejected the credentials{exception}"))]
+    Unauthorized { exception: ServerException },
+    #[snafu(display("Access denied{exception}"))]
+    Forbidden { exception: ServerException },
+    #[snafu(display("Not found{exception}"))]
+    NotFound { exception: ServerException },
+    /// Usually a proxy in front of Nextcloud which only lets through plain HTTP methods.
+    #[snafu(display(
+        "The server rejected the {method} request with 405 Method Not Allowed, proxies in \
+         front of Nextcloud must allow the DAV methods PROPFIND, REPORT, PROPPATCH, LOCK \
+         and UNLOCK"
+    ))]
+    MethodNotAllowed { method: reqwest::Method },
+    #[snafu(display("Locked by another client{exception}"))]
+    Locked { exception: ServerException },
+    #[snafu(display("Rate limited by Nextcloud{exception}"))]
+    RateLimited {
+        /// Wait the server asked for before the next request.
+        retry_after: Option<Duration>,
+        exception: ServerException,
+    },
+    #[snafu(display("Server error {status}{exception}"))]
+    ServerError {
+        status: StatusCode,
+        exception: ServerException,
+    },
+    /// Any other status which is not a success.
+    #[snafu(display("Request failed with {status}{exception}"))]
+    Status {
+        status: StatusCode,
+        exception: ServerException,
+    },
     #[snafu(display("Failed to deserialize response: {source}"))]
     Deserialize { source: DeserializeError },
+    #[snafu(display("Nextcloud is in maintenance mode"))]
+    Maintenance,
+    #[snafu(display(
+        "Nextcloud redirected to {location:?}, update nextcloud_instance and dav_root"
+    ))]
+    Redirect { location: String },
+    #[snafu(display(
+        "Received an HTML page instead of a Nextcloud response, check nextcloud_instance, \
+         the credentials and proxies in between"
+    ))]
+    UnexpectedHtml,
+    #[snafu(display("Failed to create the URL for {endpoint:?}: {source}"))]
+    InvalidUrl {
+        endpoint: String,
+        source: url::ParseError,
+    },
+}
+
+impl<E: std::fmt::Display + std::error::Error + 'static> RequestError<E> {
+    /// Classifies an error response to a `method` request by its status and the
+    /// exception in its body.
+    #[must_use]
+    pub fn from_response(
+        method: &reqwest::Method,
+        status: StatusCode,
+        headers: &HeaderMap,
+        payload: &str,
+    ) -> Self {
+        let exception = ServerException::parse(payload);
+        match status {
+            StatusCode::UNAUTHORIZED => Self::Unauthorized { exception },
+            StatusCode::FORBIDDEN => Self::Forbidden { exception },
+            StatusCode::NOT_FOUND => Self::NotFound { exception },
+            StatusCode::METHOD_NOT_ALLOWED => Self::MethodNotAllowed {
+                method: method.clone(),
+            },
+            StatusCode::LOCKED => Self::Locked { exception },
+            StatusCode::TOO_MANY_REQUESTS => Self::RateLimited {
+                retry_after: headers
+                    .get(RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|secs| secs.trim().parse().ok())
+                    .map(Duration::from_secs),
+                exception,
+            },
+            status if status.is_server_error() => Self::ServerError { status, exception },
+            status => Self::Status { status, exception },
+        }
+    }
+
+    /// The HTTP status code of the failed request, if the server responded at all.
+    #[must_use]
+    pub fn status(&self) -> Option<reqwest::StatusCode> {
+        match self {
+            Self::Reqwest { source } => source.status(),
+            Self::Unauthorized { .. } => Some(StatusCode::UNAUTHORIZED),
+            Self::Forbidden { .. } => Some(StatusCode::FORBIDDEN),
+            Self::NotFound { .. } => Some(StatusCode::NOT_FOUND),
+            Self::MethodNotAllowed { .. } => Some(StatusCode::METHOD_NOT_ALLOWED),
+            Self::Locked { .. } => Some(StatusCode::LOCKED),
+            Self::RateLimited { .. } => Some(StatusCode::TOO_MANY_REQUESTS),
+            Self::ServerError { status, .. } | Self::Status { status, .. } => Some(*status),
+            Self::Maintenance => Some(StatusCode::SERVICE_UNAVAILABLE),
+            Self::Deserialize { .. }
+            | Self::Redirect { .. }
+            | Self::UnexpectedHtml
+            | Self::InvalidUrl { .. } => None,
+        }
+    }
+
+    /// Whether the server responded that the requested resource does not exist.
+    #[must_use]
+    pub const fn is_not_found(&self) -> bool {
+        matches!(self, Self::NotFound { .. })
+    }
+
+    /// Whether the server (or a proxy in front of it) rejected the HTTP method.
+    #[must_use]
+    pub const fn is_method_not_allowed(&self) -> bool {
+        matches!(self, Self::MethodNotAllowed { .. })
+    }
+
+    /// Whether the user lacks the permission for the request.
+    #[must_use]
+    pub const fn is_forbidden(&self) -> bool {
+        matches!(self, Self::Forbidden { .. })
+    }
+
+    /// Whether the server ran out of storage, e.g. because the quota of the user is used up.
+    #[must_use]
+    pub fn is_insufficient_storage(&self) -> bool {
+        self.status() == Some(reqwest::StatusCode::INSUFFICIENT_STORAGE)
+    }
+
+    /// Whether the server rejected the content of the request, e.g. an invalid tag name.
+    #[must_use]
+    pub fn is_rejected(&self) -> bool {
+        matches!(
+            self.status(),
+            Some(StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY)
+        )
+    }
+
+    /// Whether the resource is locked by someone else.
+    #[must_use]
+    pub const fn is_locked(&self) -> bool {
+        matches!(self, Self::Locked { .. })
+    }
+
+    /// Whether the same request may succeed later, e.g. after a rate limit or an overload
+    /// of the server.
+    #[must_use]
+    pub const fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Self::Reqwest { .. }
+                | Self::Locked { .. }
+                | Self::RateLimited { .. }
+                | Self::ServerError { .. }
+        )
+    }
+
+    /// Whether all further requests are doomed, e.g. because the credentials are wrong.
+    #[must_use]
+    pub const fn is_fatal(&self) -> bool {
+        self.is_maintenance() || matches!(self, Self::UnexpectedHtml | Self::Unauthorized { .. })
+    }
+
+    /// Whether the server is in maintenance mode.
+    #[must_use]
+    pub const fn is_maintenance(&self) -> bool {
+        matches!(self, Self::Maintenance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Method;
+
+    use super::*;
+
+    #[test]
+    fn keep_subdirectory_of_instance() {
+        let url = |instance: &str, path| {
+            instance_url(&instance.parse().unwrap(), path)
+                .unwrap()
+                .to_string()
+        };
+        for instance in [
+            "https://example.com/nextcloud",
+            "https://example.com/nextcloud/",
+        ] {
+            assert_eq!(
+                url(instance, "/remote.php/dav/files/user/a.txt"),
+                "https://example.com/nextcloud/remote.php/dav/files/user/a.txt"
+            );
+            assert_eq!(
+                url(instance, "/nextcloud/remote.php/dav/files/user/a.txt"),
+                "https://example.com/nextcloud/remote.php/dav/files/user/a.txt"
+            );
+            assert_eq!(
+                url(instance, "ocs/v2.php/cloud/users/"),
+                "https://example.com/nextcloud/ocs/v2.php/cloud/users/"
+            );
+        }
+        assert_eq!(
+            url("https://example.com", "/remote.php/dav/"),
+            "https://example.com/remote.php/dav/"
+        );
+        assert_eq!(
+            dav_root_url(
+                &"https://example.com/nextcloud".parse().unwrap(),
+                "/remote.php/dav"
+            )
+            .to_string(),
+            "https://example.com/nextcloud/remote.php/dav/"
+        );
+    }
+
+    #[test]
+    fn detect_maintenance_mode() {
+        let payload = crate::fixtures::MAINTENANCE;
+        let unavailable = StatusCode::SERVICE_UNAVAILABLE;
+        assert!(is_maintenance_mode(unavailable, &HeaderMap::new(), payload));
+        assert!(!is_maintenance_mode(unavailable, &HeaderMap::new(), ""));
+        assert!(!is_maintenance_mode(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &HeaderMap::new(),
+            payload
+        ));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "X-Nextcloud-Maintenance-Mode",
+            HeaderValue::from_static("1"),
+        );
+        assert!(is_maintenance_mode(unavailable, &headers, ""));
+    }
+
+    #[test]
+    fn classify_error_responses() {
+        type Error = RequestError<DeserializeError>;
+        let not_found = r#"<?xml version="1.0" encoding="utf-8"?>
+            <d:error xmlns:d="DAV:" xmlns:s="http://sabredav.org/ns">
+              <s:exception>Sabre\DAV\Exception\NotFound</s:exception>
+              <s:message>File with name a.txt could not be located</s:message>
+            </d:error>"#;
+        let error = Error::from_response(
+            &Method::GET,
+            StatusCode::NOT_FOUND,
+            &HeaderMap::new(),
+            not_found,
+        );
+        assert!(error.is_not_found());
+        assert_eq!(
+            error.to_string(),
+            "Not found: File with name a.txt could not be located"
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("2"));
+        let error = Error::from_response(&Method::GET, StatusCode::TOO_MANY_REQUESTS, &headers, "");
+        assert!(matches!(
+            error,
+            RequestError::RateLimited { retry_after: Some(wait), .. } if wait == Duration::from_secs(2)
+        ));
+        assert_eq!(rate_limit_delay(&error, 0), Some(Duration::from_secs(2)));
+        assert_eq!(rate_limit_delay(&error, MAX_RATE_LIMIT_RETRIES), None);
+
+        let error = Error::from_response(
+            &Method::GET,
+            StatusCode::BAD_GATEWAY,
+            &HeaderMap::new(),
+            "<html>",
+        );
+        assert!(error.is_transient() && !error.is_fatal());
+        assert!(Error::from_response(
+            &Method::GET,
+            StatusCode::UNAUTHORIZED,
+            &HeaderMap::new(),
+            ""
+        )
+        .is_fatal());
+
+        let report = str_to_method("REPORT");
+        let error = Error::from_response(
+            &report,
+            StatusCode::METHOD_NOT_ALLOWED,
+            &HeaderMap::new(),
+            "",
+        );
+        assert!(error.is_method_not_allowed() && !error.is_transient());
+        assert!(error
+            .to_string()
+            .starts_with("The server rejected the REPORT request"));
+    }
+
+    #[test]
+    fn measure_clock_skew() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(clock_skew(&headers, SystemTime::now()), None);
+
+        headers.insert(
+            DATE,
+            HeaderValue::from_static("Sun, 06 Nov 1994 08:49:37 GMT"),
+        );
+        let server = httpdate::parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT").unwrap();
+        let minute = Duration::from_mins(1);
+        assert_eq!(clock_skew(&headers, server - minute), Some(Ok(minute)));
+        assert_eq!(clock_skew(&headers, server + minute), Some(Err(minute)));
+    }
+
+    #[derive(Debug, Default)]
+    struct Recorder(std::sync::Mutex<Vec<String>>);
+
+    impl Middleware for Recorder {
+        fn on_request(&self, request: &mut reqwest::Request) {
+            let line = format!("{} {}", request.method(), request.url().path());
+            self.0.lock().unwrap().push(line);
+        }
+
+        fn on_response(&self, exchange: &Exchange<'_>) {
+            self.0.lock().unwrap().push(exchange.status.to_string());
+        }
+    }
+
+    /// Answers the first request to the returned port with `response`.
+    async fn serve_once(response: String) -> u16 {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buffer = [0; 4096];
+            let _ = stream.read(&mut buffer).await.unwrap();
+            stream.write_all(response.as_bytes()).await.unwrap();
+        });
+        port
+    }
+
+    const NOT_FOUND: &str = "HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\n\r\n";
+
+    #[tokio::test]
+    async fn middleware_sees_every_request() {
+        let port = serve_once(NOT_FOUND.to_owned()).await;
+        let config = Config {
+            nextcloud_instance: format!("http://127.0.0.1:{port}").parse().unwrap(),
+            ..Config::default()
+        };
+        let recorder = Arc::new(Recorder::default());
+        let connection = Connection::from_config(&config).with_middleware(recorder.clone());
+
+        let path = crate::RemotePath::from_href("/remote.php/dav/files/user/a");
+        let request = super::super::IsEncrypted::new(&path).unwrap();
+        assert!(connection.request(request).await.is_err());
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            ["PROPFIND /remote.php/dav/files/user/a", "404 Not Found"]
+        );
+    }
+
+    #[tokio::test]
+    async fn reject_html_login_page() {
+        let port = serve_once(
+            "HTTP/1.1 200 OK\r\ncontent-type: text/html; charset=utf-8\r\n\
+             content-length: 26\r\n\r\n<html><form></form></html>"
+                .to_owned(),
+        )
+        .await;
+        let connection = Connection::new(
+            format!("http://127.0.0.1:{port}").parse().unwrap(),
+            "user",
+            "",
+        );
+
+        let path = crate::RemotePath::from_href("/remote.php/dav/files/user/a");
+        let request = super::super::IsEncrypted::new(&path).unwrap();
+        let error = connection.request(request).await.unwrap_err();
+        assert!(matches!(error, RequestError::UnexpectedHtml), "{error}");
+        assert!(error.is_fatal());
+    }
+
+    #[tokio::test]
+    async fn report_invalid_url() {
+        let url = "http://127.0.0.1:9".parse().unwrap();
+        let connection = Connection::new(url, "http://[user", "");
+
+        let error = connection
+            .request(super::super::OcsUserInfo)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, RequestError::InvalidUrl { .. }), "{error}");
+        assert!(!error.is_fatal());
+    }
+
+    #[tokio::test]
+    async fn follow_redirect_to_new_port() {
+        let dir = tempfile::tempdir().unwrap();
+        let moved = serve_once(NOT_FOUND.to_owned()).await;
+        let port = serve_once(format!(
+            "HTTP/1.1 301 Moved Permanently\r\n\
+             location: http://127.0.0.1:{moved}/remote.php/dav/files/user/a\r\n\
+             content-length: 0\r\n\r\n"
+        ))
+        .await;
+        let config = Config {
+            nextcloud_instance: format!("http://127.0.0.1:{port}").parse().unwrap(),
+            tag_database: dir.path().join("tags.json"),
+            ..Config::default()
+        };
+        let recorder = Arc::new(Recorder::default());
+        let connection = Connection::from_config(&config).with_middleware(recorder.clone());
+
+        let path = crate::RemotePath::from_href("/remote.php/dav/files/user/a");
+        let request = super::super::IsEncrypted::new(&path).unwrap();
+        assert!(connection
+            .request(request)
+            .await
+            .unwrap_err()
+            .is_not_found());
+        assert_eq!(recorder.0.lock().unwrap().len(), 4);
+        assert_eq!(
+            redirect::load_canonical(&config),
+            Some(format!("http://127.0.0.1:{moved}").parse().unwrap())
+        );
+    }
 }
@@ -1,15 +1,13 @@
 use std::{borrow::Cow, num::ParseIntError};
 
-use askama::Template;
-use reqwest::header::{HeaderMap, ToStrError, CONTENT_LOCATION};
+use reqwest::header::{HeaderMap, HeaderValue, ToStrError, CONTENT_LOCATION, CONTENT_TYPE};
 use snafu::{OptionExt, ResultExt, Snafu};
 
 use crate::{Tag, TagId};
 
 use super::{Body, Parse, Request};
 
-#[derive(Template)]
-#[template(path = "create_tag.json", escape = "none")]
+/// Creates a visible and assignable tag. Returns the id of the new tag.
 pub struct CreateTag {
     tag: Tag,
 }
@@ -26,12 +24,24 @@ impl Request for CreateTag {
         reqwest::Method::POST
     }
 
-    fn endpoint(&self) -> Cow<str> {
+    fn endpoint(&self) -> Cow<'_, str> {
         "systemtags".into()
     }
 
     fn body(&self) -> Body {
-        self.into()
+        let body = serde_json::json!({
+            "userVisible": true,
+            "userAssignable": true,
+            "canAssign": true,
+            "name": &*self.tag,
+        });
+        Body::Raw(body.to_string().into_bytes())
+    }
+
+    fn headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        headers
     }
 }
 
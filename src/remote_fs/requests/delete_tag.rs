@@ -0,0 +1,37 @@
+use std::{borrow::Cow, convert::Infallible};
+
+use reqwest::header::HeaderMap;
+
+use super::{Parse, Request};
+use crate::remote_fs::TagId;
+
+/// Deletes a tag and removes it from all files.
+pub struct DeleteTag {
+    tag: TagId,
+}
+
+impl DeleteTag {
+    #[must_use]
+    pub const fn new(tag: TagId) -> Self {
+        Self { tag }
+    }
+}
+
+impl Request for DeleteTag {
+    fn method(&self) -> reqwest::Method {
+        reqwest::Method::DELETE
+    }
+
+    fn endpoint(&self) -> Cow<'_, str> {
+        format!("systemtags/{}", self.tag).into()
+    }
+}
+
+impl Parse for DeleteTag {
+    type Output = ();
+    type Error = Infallible;
+
+    fn parse(_: &HeaderMap, _: &str) -> Result<Self::Output, Self::Error> {
+        Ok(())
+    }
+}
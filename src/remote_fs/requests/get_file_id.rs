@@ -1,25 +1,46 @@
-use std::{borrow::Cow, path::Path};
-
-use askama::Template;
+use std::borrow::Cow;
 
 use reqwest::header::HeaderMap;
 
-use crate::FileId;
+use crate::{FileId, Permissions};
+
+use crate::remote_fs::RemotePath;
 
-use super::{parse, str_to_method, Body, DeserializeError, Parse, Request};
+use super::{
+    instance_url, parse, str_to_method,
+    xml::{missing, propfind, FileProp, FileProperties, MultiStatus, Property},
+    Body, DeserializeError, Parse, Request,
+};
 
-#[derive(Template)]
-#[template(path = "get_file_id.xml")]
+/// Looks up the id and permissions of a remote file.
 pub struct GetFileId {
     path: String,
+    properties: Vec<Property>,
 }
 
 impl GetFileId {
-    pub fn new(remote_path: &Path) -> Option<Self> {
+    #[must_use]
+    pub fn new(remote_path: &RemotePath) -> Option<Self> {
         Some(Self {
-            path: remote_path.to_str()?.to_owned(),
+            path: remote_path.to_href()?,
+            properties: vec![Property::FileId, Property::Tags, Property::Permissions],
         })
     }
+
+    /// Additionally requests `properties`, returned in [`RemoteFile::properties`].
+    #[must_use]
+    pub fn with_properties(mut self, properties: &[Property]) -> Self {
+        self.properties.extend_from_slice(properties);
+        self
+    }
+}
+
+/// Id, permissions and requested properties of a remote file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteFile {
+    pub id: FileId,
+    pub permissions: Permissions,
+    pub properties: FileProperties,
 }
 
 impl Request for GetFileId {
@@ -27,35 +48,42 @@ impl Request for GetFileId {
         str_to_method("PROPFIND")
     }
 
-    fn endpoint(&self) -> Cow<str> {
+    fn endpoint(&self) -> Cow<'_, str> {
         (&self.path).into()
     }
 
-    fn url(&self, host: &reqwest::Url, _user: &str) -> reqwest::Url {
-        host.join(&self.endpoint()).expect("failed to create URL")
+    fn url(
+        &self,
+        host: &reqwest::Url,
+        _dav_root: &reqwest::Url,
+        _user: &str,
+    ) -> Result<reqwest::Url, url::ParseError> {
+        instance_url(host, &self.endpoint())
     }
 
     fn body(&self) -> Body {
-        self.into()
+        propfind(&self.properties)
     }
 }
 
 impl Parse for GetFileId {
-    type Output = FileId;
+    type Output = RemoteFile;
     type Error = DeserializeError;
 
     fn parse(_: &HeaderMap, input: &str) -> Result<Self::Output, Self::Error> {
-        let element: MultiStatus = parse(input)?;
-        Ok(element.file_id)
+        let prop = parse::<MultiStatus<FileProp>>(input)?.first();
+        let id = prop.as_ref().and_then(|prop| prop.fileid);
+        let (Some(prop), Some(id)) = (prop, id) else {
+            return Err(missing(Property::FileId));
+        };
+        Ok(RemoteFile {
+            id,
+            properties: prop.properties(),
+            permissions: prop.permissions,
+        })
     }
 }
 
-#[derive(Debug, serde_query::Deserialize)]
-struct MultiStatus {
-    #[query(".response.propstat.prop.fileid")]
-    file_id: FileId,
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -63,8 +91,78 @@ mod tests {
 
     #[test]
     fn deserialize_all_tags() {
-        let input = include_str!("../../../test_data/file_id.xml");
-        let file_id = GetFileId::parse(&HeaderMap::new(), input).unwrap();
-        assert_eq!(file_id, FileId::from(52));
+        let input = crate::fixtures::FILE_ID;
+        let file = GetFileId::parse(&HeaderMap::new(), input).unwrap();
+        assert_eq!(file.id, FileId::from(52));
+        assert!(file.permissions.can_tag());
+        assert_eq!(file.properties, FileProperties::default());
+    }
+
+    #[test]
+    fn deserialize_read_only_share() {
+        let input = crate::fixtures::FILE_ID_SHARED;
+        let file = GetFileId::parse(&HeaderMap::new(), input).unwrap();
+        assert_eq!(file.id, FileId::from(731));
+        assert!(file.permissions.is_incoming_share());
+        assert!(!file.permissions.can_tag());
+    }
+
+    #[test]
+    fn deserialize_requested_properties() {
+        let input = r#"<?xml version="1.0"?>
+<d:multistatus xmlns:d="DAV:" xmlns:oc="http://owncloud.org/ns">
+  <d:response>
+    <d:href>/remote.php/dav/files/erik/a.txt</d:href>
+    <d:propstat>
+      <d:prop>
+        <oc:fileid>52</oc:fileid>
+        <oc:permissions>RGDNVW</oc:permissions>
+        <d:getetag>&quot;8f2c1e0d&quot;</d:getetag>
+        <d:getlastmodified>Tue, 15 Oct 2024 10:01:02 GMT</d:getlastmodified>
+        <oc:size>1234</oc:size>
+      </d:prop>
+      <d:status>HTTP/1.1 200 OK</d:status>
+    </d:propstat>
+  </d:response>
+</d:multistatus>"#;
+        let file = GetFileId::parse(&HeaderMap::new(), input).unwrap();
+        assert_eq!(file.id, FileId::from(52));
+        assert_eq!(file.properties.etag.as_deref(), Some("8f2c1e0d"));
+        assert_eq!(
+            file.properties.last_modified,
+            Some(httpdate::parse_http_date("Tue, 15 Oct 2024 10:01:02 GMT").unwrap())
+        );
+        assert_eq!(file.properties.size, Some(1234));
+    }
+
+    #[test]
+    fn skip_properties_not_found() {
+        let input = r#"<?xml version="1.0"?>
+<d:multistatus xmlns:d="DAV:" xmlns:oc="http://owncloud.org/ns">
+  <d:response>
+    <d:href>/remote.php/dav/files/erik/a.txt</d:href>
+    <d:propstat>
+      <d:prop>
+        <d:getetag/>
+        <oc:size/>
+      </d:prop>
+      <d:status>HTTP/1.1 404 Not Found</d:status>
+    </d:propstat>
+    <d:propstat>
+      <d:prop>
+        <oc:fileid>52</oc:fileid>
+        <oc:permissions>RGDNVW</oc:permissions>
+      </d:prop>
+      <d:status>HTTP/1.1 200 OK</d:status>
+    </d:propstat>
+  </d:response>
+</d:multistatus>"#;
+        let file = GetFileId::parse(&HeaderMap::new(), input).unwrap();
+        assert_eq!(file.id, FileId::from(52));
+        assert!(file.permissions.is_writable());
+        assert_eq!(file.properties, FileProperties::default());
+
+        let not_found = input.replace("200 OK", "403 Forbidden");
+        assert!(GetFileId::parse(&HeaderMap::new(), &not_found).is_err());
     }
 }
@@ -0,0 +1,67 @@
+use std::borrow::Cow;
+
+use reqwest::header::HeaderMap;
+
+use crate::{FileId, Tags};
+
+use super::{
+    list_tags::TAG_PROPERTIES, parse, str_to_method, xml::propfind, Body, DeserializeError,
+    ListTagsMultiStatus, Parse, Request, TagKind,
+};
+
+/// Lists the tags of a single file which the user can assign.
+pub struct GetFileTags {
+    file: FileId,
+}
+
+impl GetFileTags {
+    #[must_use]
+    pub const fn new(file: FileId) -> Self {
+        Self { file }
+    }
+}
+
+impl Request for GetFileTags {
+    fn method(&self) -> reqwest::Method {
+        str_to_method("PROPFIND")
+    }
+
+    fn endpoint(&self) -> Cow<'_, str> {
+        format!("systemtags-relations/files/{}", self.file).into()
+    }
+
+    fn body(&self) -> Body {
+        propfind(TAG_PROPERTIES)
+    }
+}
+
+impl Parse for GetFileTags {
+    type Output = Tags;
+    type Error = DeserializeError;
+
+    fn parse(_: &HeaderMap, input: &str) -> Result<Self::Output, Self::Error> {
+        let element: ListTagsMultiStatus = parse(input)?;
+
+        Ok(element
+            .found()
+            .filter(|(_, prop)| prop.kind() == TagKind::Assignable)
+            .filter_map(|(_, prop)| prop.display_name)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::MultiStatusBuilder;
+
+    #[test]
+    fn only_assignable_file_tags() {
+        let input = MultiStatusBuilder::new()
+            .tag(1, "Holiday", true, true)
+            .tag(2, "Legal hold", true, false)
+            .build();
+        let tags = GetFileTags::parse(&HeaderMap::new(), &input).unwrap();
+        assert_eq!(tags, std::iter::once("Holiday").collect());
+    }
+}
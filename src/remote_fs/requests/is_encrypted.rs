@@ -0,0 +1,85 @@
+use std::borrow::Cow;
+
+use reqwest::header::{HeaderMap, HeaderValue};
+
+use crate::remote_fs::RemotePath;
+
+use super::{
+    instance_url, parse, str_to_method,
+    xml::{propfind, MultiStatus, Property},
+    Body, DeserializeError, Parse, Request,
+};
+
+/// Check whether the given remote folder is end-to-end encrypted.
+pub struct IsEncrypted {
+    path: String,
+}
+
+impl IsEncrypted {
+    #[must_use]
+    pub fn new(remote_path: &RemotePath) -> Option<Self> {
+        Some(Self {
+            path: remote_path.to_href()?,
+        })
+    }
+}
+
+impl Request for IsEncrypted {
+    fn method(&self) -> reqwest::Method {
+        str_to_method("PROPFIND")
+    }
+
+    fn endpoint(&self) -> Cow<'_, str> {
+        (&self.path).into()
+    }
+
+    fn url(
+        &self,
+        host: &reqwest::Url,
+        _dav_root: &reqwest::Url,
+        _user: &str,
+    ) -> Result<reqwest::Url, url::ParseError> {
+        instance_url(host, &self.endpoint())
+    }
+
+    fn body(&self) -> Body {
+        propfind(&[Property::IsEncrypted])
+    }
+
+    fn headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("Depth", HeaderValue::from_static("0"));
+        headers
+    }
+}
+
+impl Parse for IsEncrypted {
+    type Output = bool;
+    type Error = DeserializeError;
+
+    fn parse(_: &HeaderMap, input: &str) -> Result<Self::Output, Self::Error> {
+        let element: MultiStatus<Prop> = parse(input)?;
+        // Servers without end-to-end encryption do not know the property.
+        Ok(element.first().is_some_and(|prop| prop.is_encrypted == "1"))
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct Prop {
+    #[serde(default)]
+    is_encrypted: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_log::test;
+
+    #[test]
+    fn deserialize_encrypted_folder() {
+        let input = crate::fixtures::IS_ENCRYPTED;
+        let encrypted = IsEncrypted::parse(&HeaderMap::new(), input).unwrap();
+        assert!(encrypted);
+    }
+}
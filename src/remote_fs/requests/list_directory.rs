@@ -0,0 +1,134 @@
+use std::borrow::Cow;
+
+use reqwest::header::{HeaderMap, HeaderValue};
+
+use crate::remote_fs::RemotePath;
+use crate::FileId;
+
+use super::{
+    instance_url, parse, str_to_method,
+    xml::{propfind, FileProp, MultiStatus, Property},
+    Body, DeserializeError, Parse, Request,
+};
+
+/// Lists a remote folder and its direct children.
+pub struct ListDirectory {
+    path: String,
+}
+
+impl ListDirectory {
+    #[must_use]
+    pub fn new(remote_path: &RemotePath) -> Option<Self> {
+        Some(Self {
+            path: remote_path.to_href()?,
+        })
+    }
+}
+
+impl Request for ListDirectory {
+    fn method(&self) -> reqwest::Method {
+        str_to_method("PROPFIND")
+    }
+
+    fn endpoint(&self) -> Cow<'_, str> {
+        (&self.path).into()
+    }
+
+    fn url(
+        &self,
+        host: &reqwest::Url,
+        _dav_root: &reqwest::Url,
+        _user: &str,
+    ) -> Result<reqwest::Url, url::ParseError> {
+        instance_url(host, &self.endpoint())
+    }
+
+    fn body(&self) -> Body {
+        propfind(&[Property::FileId, Property::ETag, Property::ResourceType])
+    }
+
+    fn headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("Depth", HeaderValue::from_static("1"));
+        headers
+    }
+}
+
+/// File or folder in a [`ListDirectory`] response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirectoryEntry {
+    pub id: FileId,
+    pub path: RemotePath,
+    /// Changes whenever the file or anything inside the folder changes.
+    pub etag: String,
+    pub is_directory: bool,
+}
+
+impl Parse for ListDirectory {
+    /// The listed folder comes first, followed by its children.
+    type Output = Vec<DirectoryEntry>;
+    type Error = DeserializeError;
+
+    fn parse(_: &HeaderMap, input: &str) -> Result<Self::Output, Self::Error> {
+        let element: MultiStatus<FileProp> = parse(input)?;
+
+        Ok(element
+            .found()
+            .filter_map(|(href, prop)| {
+                Some(DirectoryEntry {
+                    id: prop.fileid?,
+                    path: RemotePath::from_href(&href),
+                    etag: prop.properties().etag.unwrap_or_default(),
+                    is_directory: prop.resourcetype.is_collection(),
+                })
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn deserialize_directory() {
+        let input = r#"<?xml version="1.0"?>
+<d:multistatus xmlns:d="DAV:" xmlns:oc="http://owncloud.org/ns">
+  <d:response>
+    <d:href>/remote.php/dav/files/erik/Photos/</d:href>
+    <d:propstat>
+      <d:prop>
+        <oc:fileid>40</oc:fileid>
+        <d:getetag>"66f1d0c1a2b3c"</d:getetag>
+        <d:resourcetype><d:collection/></d:resourcetype>
+      </d:prop>
+      <d:status>HTTP/1.1 200 OK</d:status>
+    </d:propstat>
+  </d:response>
+  <d:response>
+    <d:href>/remote.php/dav/files/erik/Photos/a%20b.jpg</d:href>
+    <d:propstat>
+      <d:prop>
+        <oc:fileid>42</oc:fileid>
+        <d:getetag>"0c5a4f2e"</d:getetag>
+        <d:resourcetype/>
+      </d:prop>
+      <d:status>HTTP/1.1 200 OK</d:status>
+    </d:propstat>
+  </d:response>
+</d:multistatus>"#;
+        let entries = ListDirectory::parse(&HeaderMap::new(), input).unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert!(entries[0].is_directory);
+        assert_eq!(entries[1].id, FileId::from(42));
+        assert_eq!(entries[1].etag, "0c5a4f2e");
+        assert!(!entries[1].is_directory);
+        assert_eq!(
+            entries[1].path.as_path(),
+            Path::new("/remote.php/dav/files/erik/Photos/a b.jpg")
+        );
+    }
+}
@@ -1,24 +1,54 @@
 use std::borrow::Cow;
+use std::path::Path;
 
-use askama::Template;
 use reqwest::header::HeaderMap;
 use url::Url;
 
-use crate::{FileId, TagId};
+use crate::remote_fs::RemotePath;
+use crate::{FileId, Permissions, TagId};
 
-use super::{common::str_to_method, parse, Body, DeserializeError, Parse, Request};
+use super::{
+    common::str_to_method,
+    parse,
+    xml::{document, prop, FileProp, FileProperties, MultiStatus, Node, Property},
+    Body, DeserializeError, Parse, Request,
+};
 
 /// List all files with the given tag. Directories are ignored.
-#[derive(Template)]
-#[template(path = "list_files_with_tag.xml")]
 pub struct ListFilesWithTag {
     tag: TagId,
+    /// Encoded path of the folder to search relative to the files of the user.
+    folder: String,
+    /// Requested in addition to the properties every listing needs.
+    properties: Vec<Property>,
 }
 
 impl ListFilesWithTag {
     #[must_use]
     pub const fn new(tag: TagId) -> Self {
-        Self { tag }
+        Self {
+            tag,
+            folder: String::new(),
+            properties: Vec::new(),
+        }
+    }
+
+    /// Only lists files below `folder`, which is relative to the files of the user the
+    /// request is sent for. Returns `None` if the path is not UTF-8.
+    #[must_use]
+    pub fn in_folder(tag: TagId, folder: &Path) -> Option<Self> {
+        Some(Self {
+            tag,
+            folder: RemotePath::from(folder).to_href()?,
+            properties: Vec::new(),
+        })
+    }
+
+    /// Additionally requests `properties`, returned in [`TaggedFile::properties`].
+    #[must_use]
+    pub fn with_properties(mut self, properties: &[Property]) -> Self {
+        self.properties.extend_from_slice(properties);
+        self
     }
 }
 
@@ -27,72 +57,145 @@ impl Request for ListFilesWithTag {
         str_to_method("REPORT")
     }
 
-    fn endpoint(&self) -> Cow<str> {
+    fn endpoint(&self) -> Cow<'_, str> {
         "files".into()
     }
 
-    fn url(&self, host: &Url, user: &str) -> Url {
-        let suffix = format!("remote.php/dav/{}/{user}", self.endpoint());
-        host.join(&suffix).expect("failed to create URL")
+    fn url(&self, _host: &Url, dav_root: &Url, user: &str) -> Result<Url, url::ParseError> {
+        let suffix = format!("{}/{user}{}", self.endpoint(), self.folder);
+        dav_root.join(&suffix)
     }
 
     fn body(&self) -> Body {
-        self.into()
+        let rules = Node::Parent(
+            "oc:filter-rules",
+            vec![Node::text("oc:systemtag", self.tag.to_string())],
+        );
+        let mut properties = vec![
+            Property::FileId,
+            Property::ResourceType,
+            Property::Permissions,
+        ];
+        properties.extend_from_slice(&self.properties);
+        document("oc:filter-files", &[prop(&properties), rules])
     }
 }
 
-impl Parse for ListFilesWithTag {
-    type Output = Vec<(FileId, String)>;
+/// List all files and folders with the given tag.
+pub struct ListPathsWithTag(ListFilesWithTag);
+
+impl ListPathsWithTag {
+    /// Only lists paths below `folder`, see [`ListFilesWithTag::in_folder`].
+    #[must_use]
+    pub fn in_folder(tag: TagId, folder: &Path) -> Option<Self> {
+        ListFilesWithTag::in_folder(tag, folder).map(Self)
+    }
+}
+
+impl Request for ListPathsWithTag {
+    fn method(&self) -> reqwest::Method {
+        self.0.method()
+    }
+
+    fn endpoint(&self) -> Cow<'_, str> {
+        self.0.endpoint()
+    }
+
+    fn url(&self, host: &Url, dav_root: &Url, user: &str) -> Result<Url, url::ParseError> {
+        self.0.url(host, dav_root, user)
+    }
+
+    fn body(&self) -> Body {
+        self.0.body()
+    }
+}
+
+impl Parse for ListPathsWithTag {
+    type Output = Vec<RemotePath>;
     type Error = DeserializeError;
 
     fn parse(_: &HeaderMap, input: &str) -> Result<Self::Output, Self::Error> {
-        let element: MultiStatus = parse(input)?;
-
+        let element: MultiStatus<FileProp> = parse(input)?;
         Ok(element
-            .response
-            .into_iter()
-            .filter(|r| r.resource_type.collection.is_none())
-            .map(|r| (r.file_id, r.href))
+            .found()
+            .map(|(href, _)| RemotePath::from_href(&href))
             .collect())
     }
 }
 
-#[derive(Debug, serde::Deserialize)]
-struct MultiStatus {
-    #[serde(default)]
-    response: Vec<Response>,
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaggedFile {
+    pub id: FileId,
+    pub path: RemotePath,
+    pub permissions: Permissions,
+    pub properties: FileProperties,
 }
 
-#[derive(Debug, serde_query::Deserialize)]
-struct Response {
-    #[query(".href")]
-    href: String,
-    #[query(".propstat.prop.fileid")]
-    file_id: FileId,
-    #[query(".propstat.prop.resourcetype")]
-    resource_type: ResourceType,
-}
+impl Parse for ListFilesWithTag {
+    type Output = Vec<TaggedFile>;
+    type Error = DeserializeError;
 
-#[derive(Debug, serde::Deserialize)]
-struct ResourceType {
-    collection: Option<String>,
+    fn parse(_: &HeaderMap, input: &str) -> Result<Self::Output, Self::Error> {
+        let element: MultiStatus<FileProp> = parse(input)?;
+
+        Ok(element
+            .found()
+            .filter(|(_, prop)| !prop.resourcetype.is_collection())
+            .filter_map(|(href, prop)| {
+                Some(TaggedFile {
+                    id: prop.fileid?,
+                    path: RemotePath::from_href(&href),
+                    properties: prop.properties(),
+                    permissions: prop.permissions,
+                })
+            })
+            .collect())
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::path::Path;
+
     use super::*;
 
     #[test]
     fn deserialize_tagged_files() {
-        let input = include_str!("../../../test_data/airplanes.xml");
+        let input = crate::fixtures::AIRPLANES;
         let tags = ListFilesWithTag::parse(&HeaderMap::new(), input).unwrap();
 
         assert_eq!(tags.len(), 105);
-        assert!(tags.iter().any(|(id, name)| *id == FileId::from(58_988)
-            && name == "/remote.php/dav/files/erik/Pictures/2021/2021-09-22T12-50-42.jpg"));
-        assert!(tags.iter().any(|(id, name)| *id == FileId::from(1_220_518)
-            && name == "/remote.php/dav/files/erik/Pictures/2022/2022-07-16T17-15-28.jpg"));
-        assert!(tags.iter().any(|(id, name)| *id == FileId::from(34_934)
-            && name == "/remote.php/dav/files/erik/Pictures/2010/2010-07-10T14-02-59.jpg"));
+        assert!(tags.iter().any(|f| f.id == FileId::from(58_988)
+            && f.path.as_path()
+                == Path::new("/remote.php/dav/files/erik/Pictures/2021/2021-09-22T12-50-42.jpg")));
+        assert!(tags.iter().any(|f| f.id == FileId::from(1_220_518)
+            && f.path.as_path()
+                == Path::new("/remote.php/dav/files/erik/Pictures/2022/2022-07-16T17-15-28.jpg")));
+        assert!(tags.iter().any(|f| f.id == FileId::from(34_934)
+            && f.path.as_path()
+                == Path::new("/remote.php/dav/files/erik/Pictures/2010/2010-07-10T14-02-59.jpg")));
+    }
+
+    #[test]
+    fn list_folders_with_paths() {
+        let input = crate::fixtures::MultiStatusBuilder::new()
+            .folder("/remote.php/dav/files/erik/Pictures/private/", 7)
+            .file("/remote.php/dav/files/erik/Pictures/a.jpg", 8)
+            .build();
+
+        assert_eq!(
+            ListFilesWithTag::parse(&HeaderMap::new(), &input)
+                .unwrap()
+                .len(),
+            1
+        );
+        let paths = ListPathsWithTag::parse(&HeaderMap::new(), &input).unwrap();
+        assert_eq!(
+            paths,
+            [
+                RemotePath::from_href("/remote.php/dav/files/erik/Pictures/private/"),
+                RemotePath::from_href("/remote.php/dav/files/erik/Pictures/a.jpg"),
+            ]
+        );
     }
 }
@@ -1,87 +1,234 @@
 use std::borrow::Cow;
 
-use askama::Template;
-
 use bimap::BiMap;
 use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
 
 use crate::{Tag, TagId};
 
-use super::{empty_as_none, parse, str_to_method, Body, DeserializeError, Parse, Request};
+use super::{
+    empty_as_none, parse, str_to_method,
+    xml::{propfind, Property},
+    Body, DeserializeError, Parse, Request,
+};
 
-#[derive(Template)]
-#[template(path = "load_tags.xml")]
+/// Lists all tags of the server with their visibility.
 pub struct ListTags;
 
+/// Properties of a tag needed to classify it.
+pub const TAG_PROPERTIES: &[Property] = &[
+    Property::DisplayName,
+    Property::UserVisible,
+    Property::UserAssignable,
+    Property::CanAssign,
+    Property::TagId,
+];
+
+/// How the user may use a tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagKind {
+    /// Regular tag which the user can see and assign.
+    Assignable,
+    /// Visible tag which only admins or certain groups may assign.
+    Restricted,
+    /// Tag used internally by Nextcloud, e.g. for collections and related resources.
+    Invisible,
+}
+
+impl TagKind {
+    /// Classifies a tag by its properties. `can_assign` is only reported by the DAV API and
+    /// is `true` if the user belongs to a group which may assign a restricted tag.
+    #[must_use]
+    pub const fn classify(visible: bool, assignable: bool, can_assign: Option<bool>) -> Self {
+        match (visible, assignable, can_assign) {
+            (false, _, _) => Self::Invisible,
+            (true, true, _) | (true, false, Some(true)) => Self::Assignable,
+            (true, false, _) => Self::Restricted,
+        }
+    }
+}
+
+/// What to do with tags that are not assignable by the user.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SystemTagPolicy {
+    /// Ignore restricted and invisible tags. Local tags with the same name are not synced.
+    #[default]
+    Skip,
+    /// Also mirror restricted tags to local files. Changing them fails unless the user
+    /// may assign them after all. Invisible tags are still ignored.
+    MirrorRestricted,
+}
+
+/// All tags of the server.
+#[derive(Debug, Default)]
+pub struct TagListing {
+    /// Tags the user can assign.
+    pub assignable: BiMap<TagId, Tag>,
+    /// Restricted and invisible tags.
+    pub system: Vec<(TagId, Tag, TagKind)>,
+}
+
+impl FromIterator<(TagId, Tag, TagKind)> for TagListing {
+    fn from_iter<T: IntoIterator<Item = (TagId, Tag, TagKind)>>(iter: T) -> Self {
+        let mut listing = Self::default();
+        for (id, tag, kind) in iter {
+            match kind {
+                TagKind::Assignable => {
+                    listing.assignable.insert(id, tag);
+                }
+                TagKind::Restricted | TagKind::Invisible => listing.system.push((id, tag, kind)),
+            }
+        }
+        listing
+    }
+}
+
 impl Request for ListTags {
     fn method(&self) -> reqwest::Method {
         str_to_method("PROPFIND")
     }
 
-    fn endpoint(&self) -> Cow<str> {
+    fn endpoint(&self) -> Cow<'_, str> {
         "systemtags".into()
     }
 
     fn body(&self) -> Body {
-        self.into()
+        propfind(TAG_PROPERTIES)
     }
 }
 
 impl Parse for ListTags {
-    type Output = BiMap<TagId, Tag>;
+    type Output = TagListing;
     type Error = DeserializeError;
 
     fn parse(_: &HeaderMap, input: &str) -> Result<Self::Output, Self::Error> {
         let element: MultiStatus = parse(input)?;
 
         Ok(element
-            .props
-            .into_iter()
-            .filter_map(|prop| {
-                let visible = prop.user_visible.unwrap_or_default();
-                let assignable = prop.user_assignable.unwrap_or_default();
-                if !visible || !assignable {
-                    return None;
-                }
-
-                let tag_name = prop.display_name.and_then(|n| Tag::new_or_log_error(&n));
-                prop.id.zip(tag_name)
+            .found()
+            .filter_map(|(_, prop)| {
+                let kind = prop.kind();
+                let tag_name = prop.display_name.and_then(|n| Tag::new_or_log_error(&n))?;
+                Some((prop.id?, tag_name, kind))
             })
             .collect())
     }
 }
 
-#[derive(Debug, serde_query::Deserialize)]
-pub struct MultiStatus {
-    #[query(".response.[].propstat.prop")]
-    pub props: Vec<Prop>,
-}
+pub type MultiStatus = super::MultiStatus<Prop>;
 
+/// Found properties of a tag. Properties the server did not find are `None`.
 #[derive(serde::Deserialize, Debug, Clone)]
 #[serde(rename_all = "kebab-case")]
 pub struct Prop {
-    #[serde(deserialize_with = "empty_as_none")]
+    #[serde(default, deserialize_with = "empty_as_none")]
     pub id: Option<TagId>,
     pub display_name: Option<String>,
-    #[serde(deserialize_with = "empty_as_none")]
+    #[serde(default, deserialize_with = "empty_as_none")]
     pub user_visible: Option<bool>,
-    #[serde(deserialize_with = "empty_as_none")]
+    #[serde(default, deserialize_with = "empty_as_none")]
     pub user_assignable: Option<bool>,
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub can_assign: Option<bool>,
+}
+
+impl Prop {
+    /// Classifies the tag. A tag whose visibility or assignability is unknown is treated
+    /// as restricted, so it is neither changed nor mistaken for an invisible tag.
+    #[must_use]
+    pub fn kind(&self) -> TagKind {
+        let (Some(visible), Some(assignable)) = (self.user_visible, self.user_assignable) else {
+            warn!(
+                "Server did not report whether tag {} is visible and assignable",
+                self.display_name.as_deref().unwrap_or_default()
+            );
+            return TagKind::Restricted;
+        };
+        TagKind::classify(visible, assignable, self.can_assign)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::fixtures::MultiStatusBuilder;
     use test_log::test;
 
     #[test]
     fn deserialize_all_tags() {
-        let input = include_str!("../../../test_data/all_tags.xml");
+        let input = crate::fixtures::ALL_TAGS;
         let tags = ListTags::parse(&HeaderMap::new(), input).unwrap();
         let arch: Tag = "Architecture".parse().unwrap();
-        assert_eq!(tags.len(), 237);
+        assert_eq!(tags.assignable.len(), 237);
+        assert!(tags.system.is_empty());
         assert!(tags
+            .assignable
             .iter()
             .any(|(&id, name)| id == TagId::from(73) && name == &arch));
     }
+
+    #[test]
+    fn deserialize_system_tags() {
+        let input = MultiStatusBuilder::new()
+            .tag(1, "Holiday", true, true)
+            .tag(2, "Legal hold", true, false)
+            .tag(3, "Collection", false, false)
+            .build();
+        let tags = ListTags::parse(&HeaderMap::new(), &input).unwrap();
+        assert_eq!(tags.assignable.len(), 1);
+        let kinds: Vec<_> = tags.system.iter().map(|(_, _, kind)| *kind).collect();
+        assert_eq!(kinds, [TagKind::Restricted, TagKind::Invisible]);
+    }
+
+    #[test]
+    fn ignore_properties_not_found() {
+        let response = |id: u32, found: &str, not_found: &str| {
+            format!(
+                "<d:response><d:href>/remote.php/dav/systemtags/{id}</d:href>\
+                 <d:propstat><d:prop><oc:id>{id}</oc:id><oc:display-name>Tag {id}</oc:display-name>\
+                 {found}</d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat>\
+                 <d:propstat><d:prop>{not_found}</d:prop>\
+                 <d:status>HTTP/1.1 404 Not Found</d:status></d:propstat></d:response>"
+            )
+        };
+        let input = format!(
+            r#"<d:multistatus xmlns:d="DAV:" xmlns:oc="http://owncloud.org/ns">{}{}</d:multistatus>"#,
+            response(
+                1,
+                "<oc:user-visible>true</oc:user-visible>\
+                 <oc:user-assignable>true</oc:user-assignable>",
+                "<oc:can-assign/>"
+            ),
+            response(
+                2,
+                "<oc:user-visible>true</oc:user-visible>",
+                "<oc:user-assignable/><oc:can-assign/>"
+            ),
+        );
+        let tags = ListTags::parse(&HeaderMap::new(), &input).unwrap();
+        assert_eq!(
+            tags.assignable.get_by_left(&TagId::from(1)),
+            Some(&"Tag 1".parse().unwrap())
+        );
+        let kinds: Vec<_> = tags.system.iter().map(|(_, _, kind)| *kind).collect();
+        assert_eq!(kinds, [TagKind::Restricted]);
+    }
+
+    #[test]
+    fn classify_tags() {
+        assert_eq!(TagKind::classify(true, true, None), TagKind::Assignable);
+        assert_eq!(
+            TagKind::classify(true, false, Some(true)),
+            TagKind::Assignable
+        );
+        assert_eq!(
+            TagKind::classify(true, false, Some(false)),
+            TagKind::Restricted
+        );
+        assert_eq!(
+            TagKind::classify(false, true, Some(true)),
+            TagKind::Invisible
+        );
+    }
 }
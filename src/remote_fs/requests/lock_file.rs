@@ -0,0 +1,166 @@
+use std::{borrow::Cow, convert::Infallible};
+
+use reqwest::header::{HeaderMap, HeaderValue};
+use snafu::{OptionExt, Snafu};
+
+use crate::remote_fs::RemotePath;
+
+use super::{
+    instance_url, str_to_method,
+    xml::{document, Node},
+    Body, Parse, Request,
+};
+
+/// Takes an exclusive write lock on a remote file so other well-behaved clients do not
+/// modify it concurrently.
+pub struct LockFile {
+    path: String,
+    owner: &'static str,
+    timeout_secs: u64,
+}
+
+impl LockFile {
+    #[must_use]
+    pub fn new(remote_path: &RemotePath, timeout_secs: u64) -> Option<Self> {
+        Some(Self {
+            path: remote_path.to_href()?,
+            owner: env!("CARGO_PKG_NAME"),
+            timeout_secs,
+        })
+    }
+}
+
+impl Request for LockFile {
+    fn method(&self) -> reqwest::Method {
+        str_to_method("LOCK")
+    }
+
+    fn endpoint(&self) -> Cow<'_, str> {
+        (&self.path).into()
+    }
+
+    fn url(
+        &self,
+        host: &reqwest::Url,
+        _dav_root: &reqwest::Url,
+        _user: &str,
+    ) -> Result<reqwest::Url, url::ParseError> {
+        instance_url(host, &self.endpoint())
+    }
+
+    fn body(&self) -> Body {
+        document(
+            "d:lockinfo",
+            &[
+                Node::Parent("d:lockscope", vec![Node::Empty("d:exclusive")]),
+                Node::Parent("d:locktype", vec![Node::Empty("d:write")]),
+                Node::text("d:owner", self.owner),
+            ],
+        )
+    }
+
+    fn headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("Depth", HeaderValue::from_static("0"));
+        let timeout = format!("Second-{}", self.timeout_secs);
+        headers.insert(
+            "Timeout",
+            HeaderValue::from_str(&timeout).expect("valid header value"),
+        );
+        headers
+    }
+}
+
+/// Token identifying a lock, including the enclosing angle brackets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockToken(String);
+
+#[derive(Debug, Snafu)]
+#[snafu(display("server did not return a lock token"))]
+pub struct MissingLockTokenError;
+
+impl Parse for LockFile {
+    type Output = LockToken;
+    type Error = MissingLockTokenError;
+
+    fn parse(headers: &HeaderMap, _: &str) -> Result<Self::Output, Self::Error> {
+        let token = headers
+            .get("Lock-Token")
+            .and_then(|token| token.to_str().ok())
+            .context(MissingLockTokenSnafu)?;
+        Ok(LockToken(token.to_owned()))
+    }
+}
+
+/// Releases a lock taken with [`LockFile`].
+pub struct UnlockFile {
+    path: String,
+    token: LockToken,
+}
+
+impl UnlockFile {
+    #[must_use]
+    pub fn new(remote_path: &RemotePath, token: LockToken) -> Option<Self> {
+        Some(Self {
+            path: remote_path.to_href()?,
+            token,
+        })
+    }
+}
+
+impl Request for UnlockFile {
+    fn method(&self) -> reqwest::Method {
+        str_to_method("UNLOCK")
+    }
+
+    fn endpoint(&self) -> Cow<'_, str> {
+        (&self.path).into()
+    }
+
+    fn url(
+        &self,
+        host: &reqwest::Url,
+        _dav_root: &reqwest::Url,
+        _user: &str,
+    ) -> Result<reqwest::Url, url::ParseError> {
+        instance_url(host, &self.endpoint())
+    }
+
+    fn headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Ok(token) = HeaderValue::from_str(&self.token.0) {
+            headers.insert("Lock-Token", token);
+        }
+        headers
+    }
+}
+
+impl Parse for UnlockFile {
+    type Output = ();
+    type Error = Infallible;
+
+    fn parse(_: &HeaderMap, _: &str) -> Result<Self::Output, Self::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_lock_token() {
+        let mut headers = HeaderMap::new();
+        assert!(LockFile::parse(&headers, "").is_err());
+
+        headers.insert(
+            "Lock-Token",
+            HeaderValue::from_static("<opaquelocktoken:e71d4fae-5dec-22d6-fea5-00a0c91e6be4>"),
+        );
+        let token = LockFile::parse(&headers, "").unwrap();
+        assert_eq!(
+            token,
+            LockToken("<opaquelocktoken:e71d4fae-5dec-22d6-fea5-00a0c91e6be4>".to_owned())
+        );
+    }
+}
@@ -0,0 +1,39 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use reqwest::header::HeaderMap;
+use reqwest::{Method, StatusCode};
+use url::Url;
+
+/// Response of the server as seen by a [`Middleware`].
+#[derive(Debug, Clone, Copy)]
+pub struct Exchange<'a> {
+    pub method: &'a Method,
+    pub url: &'a Url,
+    pub status: StatusCode,
+    pub headers: &'a HeaderMap,
+    pub payload: &'a str,
+}
+
+/// Hooks into every request sent by a [`Connection`](super::Connection).
+///
+/// Middlewares run in the order they were added. Requests that are retried, e.g. because
+/// of a locked database, pass through the hooks again.
+pub trait Middleware: Debug + Send + Sync {
+    /// Called right before the request is sent. May modify headers, the URL or the body.
+    fn on_request(&self, _request: &mut reqwest::Request) {}
+
+    /// Called when the server responded, including error responses.
+    fn on_response(&self, _exchange: &Exchange<'_>) {}
+}
+
+/// Allows keeping a handle to a middleware, e.g. to read what it recorded.
+impl<M: Middleware + ?Sized> Middleware for Arc<M> {
+    fn on_request(&self, request: &mut reqwest::Request) {
+        (**self).on_request(request);
+    }
+
+    fn on_response(&self, exchange: &Exchange<'_>) {
+        (**self).on_response(exchange);
+    }
+}
@@ -0,0 +1,168 @@
+//! Requests to the OCS API. Nextcloud has no OCS endpoints for system tags, so tags always
+//! need the DAV methods. The quota of the user is only available via the OCS provisioning
+//! API.
+
+use std::borrow::Cow;
+
+use reqwest::header::{HeaderMap, HeaderValue, ACCEPT};
+use serde::Deserialize;
+use snafu::{ensure, ResultExt, Snafu};
+use url::Url;
+
+use super::{instance_url, Parse, Request};
+
+fn ocs_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert("OCS-APIRequest", HeaderValue::from_static("true"));
+    headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+    headers
+}
+
+#[derive(Deserialize)]
+struct Envelope<T> {
+    ocs: Ocs<T>,
+}
+
+#[derive(Deserialize)]
+struct Ocs<T> {
+    meta: Meta,
+    data: T,
+}
+
+#[derive(Deserialize)]
+struct Meta {
+    statuscode: u16,
+    #[serde(default)]
+    message: Option<String>,
+}
+
+fn parse_envelope<'de, T: Deserialize<'de>>(input: &'de str) -> Result<T, OcsError> {
+    let deserializer = &mut serde_json::Deserializer::from_str(input);
+    let envelope: Envelope<T> =
+        serde_path_to_error::deserialize(deserializer).context(JsonSnafu)?;
+    let Meta {
+        statuscode,
+        message,
+    } = envelope.ocs.meta;
+    ensure!(
+        (100..300).contains(&statuscode),
+        StatusSnafu {
+            status: statuscode,
+            message: message.unwrap_or_default(),
+        }
+    );
+    Ok(envelope.ocs.data)
+}
+
+#[derive(Debug, Snafu)]
+pub enum OcsError {
+    #[snafu(display("failed to deserialize OCS response: {source}"))]
+    Json {
+        source: serde_path_to_error::Error<serde_json::Error>,
+    },
+    #[snafu(display("OCS request failed with status {status}: {message}"))]
+    Status { status: u16, message: String },
+}
+
+/// Fetches the storage quota of the user.
+pub struct OcsUserInfo;
+
+impl Request for OcsUserInfo {
+    fn method(&self) -> reqwest::Method {
+        reqwest::Method::GET
+    }
+
+    fn endpoint(&self) -> Cow<'_, str> {
+        "ocs/v2.php/cloud/users/".into()
+    }
+
+    fn url(&self, host: &Url, _dav_root: &Url, user: &str) -> Result<Url, url::ParseError> {
+        instance_url(host, &self.endpoint())?.join(user)
+    }
+
+    fn headers(&self) -> HeaderMap {
+        ocs_headers()
+    }
+}
+
+/// Storage quota of a user in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    pub used: u64,
+    /// `None` if the quota is unlimited.
+    pub total: Option<u64>,
+}
+
+impl Quota {
+    /// Below this many free bytes, some servers reject tag writes with 507 Insufficient
+    /// Storage.
+    pub const LOW_FREE_BYTES: u64 = 1024 * 1024;
+
+    #[must_use]
+    pub fn free(&self) -> Option<u64> {
+        self.total.map(|total| total.saturating_sub(self.used))
+    }
+
+    /// Whether so little space is left that tag writes may fail.
+    #[must_use]
+    pub fn is_exhausted(&self) -> bool {
+        self.free().is_some_and(|free| free < Self::LOW_FREE_BYTES)
+    }
+}
+
+#[derive(Deserialize)]
+struct UserInfo {
+    #[serde(default)]
+    quota: RawQuota,
+}
+
+#[derive(Default, Deserialize)]
+struct RawQuota {
+    #[serde(default)]
+    used: u64,
+    /// Negative if unlimited, missing or `"none"` if the user never logged in.
+    #[serde(default)]
+    quota: serde_json::Value,
+}
+
+impl Parse for OcsUserInfo {
+    type Output = Quota;
+    type Error = OcsError;
+
+    fn parse(_: &HeaderMap, input: &str) -> Result<Self::Output, Self::Error> {
+        let info: UserInfo = parse_envelope(input)?;
+        Ok(Quota {
+            used: info.quota.used,
+            total: info.quota.quota.as_u64(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deserialize_quota() {
+        let input = r#"{ "ocs": { "meta": { "statuscode": 200 }, "data": {
+            "id": "erik", "quota": { "free": 1000, "used": 5000, "total": 6000,
+            "relative": 83.33, "quota": 6000 } } } }"#;
+        let quota = OcsUserInfo::parse(&HeaderMap::new(), input).unwrap();
+        assert_eq!(quota.free(), Some(1000));
+        assert!(quota.is_exhausted());
+
+        let input = r#"{ "ocs": { "meta": { "statuscode": 200 }, "data": {
+            "quota": { "free": -3, "used": 5000, "quota": -3 } } } }"#;
+        let quota = OcsUserInfo::parse(&HeaderMap::new(), input).unwrap();
+        assert_eq!(quota.total, None);
+        assert!(!quota.is_exhausted());
+    }
+
+    #[test]
+    fn failed_status() {
+        let input =
+            r#"{ "ocs": { "meta": { "statuscode": 403, "message": "Forbidden" }, "data": {} } }"#;
+        let err = OcsUserInfo::parse(&HeaderMap::new(), input).unwrap_err();
+        assert!(matches!(err, OcsError::Status { status: 403, .. }));
+    }
+}
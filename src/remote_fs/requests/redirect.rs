@@ -0,0 +1,103 @@
+use std::path::{Path, PathBuf};
+
+use atomic_write_file::AtomicWriteFile;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+use url::Url;
+
+use crate::Config;
+
+/// Instance URL found by following a redirect, stored next to the tag database so later
+/// runs connect to it directly. It is ignored once the configured URL changes.
+#[derive(Debug, Serialize, Deserialize)]
+struct CanonicalInstance {
+    configured: Url,
+    canonical: Url,
+}
+
+pub fn instance_path(config: &Config) -> PathBuf {
+    config.tag_database.with_extension("instance.json")
+}
+
+/// The canonical instance URL discovered by an earlier run, if any.
+pub fn load_canonical(config: &Config) -> Option<Url> {
+    let path = instance_path(config);
+    let data = std::fs::read_to_string(&path).ok()?;
+    let instance: CanonicalInstance = serde_json::from_str(&data)
+        .inspect_err(|e| warn!("Ignoring invalid instance URL {}: {e}", path.display()))
+        .ok()?;
+    if instance.configured != config.nextcloud_instance {
+        return None;
+    }
+    debug!(
+        "Connecting to canonical instance URL {}",
+        instance.canonical
+    );
+    Some(instance.canonical)
+}
+
+pub fn persist_canonical(path: &Path, configured: &Url, canonical: &Url) {
+    let instance = CanonicalInstance {
+        configured: configured.clone(),
+        canonical: canonical.clone(),
+    };
+    let write = || -> std::io::Result<()> {
+        let data = serde_json::to_vec(&instance)?;
+        let mut file = AtomicWriteFile::open(path)?;
+        std::io::Write::write_all(&mut file, &data)?;
+        file.commit()
+    };
+    if let Err(e) = write() {
+        warn!("Failed to store instance URL {}: {e}", path.display());
+    }
+}
+
+/// Instance URL after `request` was redirected to `location`. Only redirects to the same
+/// resource on the same host are followed, e.g. from http to https or to another port,
+/// so credentials are never sent elsewhere and https is never downgraded.
+pub fn moved_instance(instance: &Url, request: &Url, location: &Url) -> Option<Url> {
+    let same_resource = location.host() == request.host()
+        && location.path() == request.path()
+        && location.query() == request.query();
+    let downgrade = request.scheme() == "https" && location.scheme() != "https";
+    if !same_resource || downgrade || location.origin() == request.origin() {
+        return None;
+    }
+    let mut moved = instance.clone();
+    moved.set_scheme(location.scheme()).ok()?;
+    moved.set_port(location.port()).ok()?;
+    Some(moved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_follow_redirects_to_same_resource() {
+        let instance: Url = "http://cloud.example.com:8080".parse().unwrap();
+        let request = instance.join("/remote.php/dav/files/erik/a.txt").unwrap();
+        let moved =
+            |location: &str| moved_instance(&instance, &request, &location.parse().unwrap());
+
+        assert_eq!(
+            moved("https://cloud.example.com/remote.php/dav/files/erik/a.txt"),
+            Some("https://cloud.example.com".parse().unwrap())
+        );
+        assert_eq!(
+            moved("https://cloud.example.com/nextcloud/remote.php/dav/files/erik/a.txt"),
+            None
+        );
+        assert_eq!(
+            moved("https://evil.example.com/remote.php/dav/files/erik/a.txt"),
+            None
+        );
+        assert_eq!(moved(request.as_str()), None);
+
+        let secure: Url = "https://cloud.example.com/remote.php/dav/files/erik/a.txt"
+            .parse()
+            .unwrap();
+        let insecure = "http://cloud.example.com/remote.php/dav/files/erik/a.txt".parse();
+        assert_eq!(moved_instance(&instance, &secure, &insecure.unwrap()), None);
+    }
+}
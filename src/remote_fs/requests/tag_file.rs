@@ -5,6 +5,7 @@ use reqwest::header::HeaderMap;
 use super::{Parse, Request};
 use crate::remote_fs::{FileId, TagId};
 
+/// Assigns a tag to a file.
 pub struct TagFile {
     tag: TagId,
     file: FileId,
@@ -22,7 +23,7 @@ impl Request for TagFile {
         reqwest::Method::PUT
     }
 
-    fn endpoint(&self) -> Cow<str> {
+    fn endpoint(&self) -> Cow<'_, str> {
         format!("systemtags-relations/files/{}/{}", self.file, self.tag).into()
     }
 }
@@ -5,6 +5,7 @@ use reqwest::header::HeaderMap;
 use super::{Parse, Request};
 use crate::remote_fs::{FileId, TagId};
 
+/// Removes a tag from a file.
 pub struct UntagFile {
     tag: TagId,
     file: FileId,
@@ -22,7 +23,7 @@ impl Request for UntagFile {
         reqwest::Method::DELETE
     }
 
-    fn endpoint(&self) -> Cow<str> {
+    fn endpoint(&self) -> Cow<'_, str> {
         format!("systemtags-relations/files/{}/{}", self.file, self.tag).into()
     }
 }
@@ -0,0 +1,163 @@
+use std::borrow::Cow;
+
+use reqwest::header::HeaderMap;
+use serde::Deserialize;
+use snafu::{ensure, ResultExt, Snafu};
+
+use crate::{Tag, TagId};
+
+use super::{
+    parse, str_to_method,
+    xml::{document, Node},
+    Body, DeserializeError, Parse, Request,
+};
+
+/// Renames a tag or changes its color or visibility. Properties which are not set keep
+/// their value.
+pub struct UpdateTag {
+    tag: TagId,
+    name: Option<Tag>,
+    /// Hex color without `#`, e.g. `ff0000`. Requires Nextcloud 31.
+    color: Option<String>,
+    /// Whether users can see and assign the tag.
+    visibility: Option<(bool, bool)>,
+}
+
+impl UpdateTag {
+    #[must_use]
+    pub const fn new(tag: TagId) -> Self {
+        Self {
+            tag,
+            name: None,
+            color: None,
+            visibility: None,
+        }
+    }
+
+    #[must_use]
+    pub fn with_name(mut self, name: Tag) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    #[must_use]
+    pub fn with_color(mut self, color: impl Into<String>) -> Self {
+        self.color = Some(color.into());
+        self
+    }
+
+    #[must_use]
+    pub const fn with_visibility(mut self, user_visible: bool, user_assignable: bool) -> Self {
+        self.visibility = Some((user_visible, user_assignable));
+        self
+    }
+}
+
+impl Request for UpdateTag {
+    fn method(&self) -> reqwest::Method {
+        str_to_method("PROPPATCH")
+    }
+
+    fn endpoint(&self) -> Cow<'_, str> {
+        format!("systemtags/{}", self.tag).into()
+    }
+
+    fn body(&self) -> Body {
+        let mut props = Vec::new();
+        if let Some(name) = &self.name {
+            props.push(Node::text("oc:display-name", &**name));
+        }
+        if let Some(color) = &self.color {
+            props.push(Node::text("nc:color", color));
+        }
+        if let Some((visible, assignable)) = self.visibility {
+            props.push(Node::text("oc:user-visible", visible.to_string()));
+            props.push(Node::text("oc:user-assignable", assignable.to_string()));
+        }
+        let prop = Node::Parent("d:prop", props);
+        document("d:propertyupdate", &[Node::Parent("d:set", vec![prop])])
+    }
+}
+
+impl Parse for UpdateTag {
+    type Output = ();
+    type Error = UpdateTagError;
+
+    /// The server responds with the status of each property.
+    fn parse(_: &HeaderMap, input: &str) -> Result<Self::Output, Self::Error> {
+        let element: MultiStatus = parse(input).context(DeserializeSnafu)?;
+        for propstat in element.response.propstat {
+            ensure!(
+                propstat.status.contains(" 200 "),
+                RejectedSnafu {
+                    status: propstat.status
+                }
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct MultiStatus {
+    response: Response,
+}
+
+#[derive(Debug, Deserialize)]
+struct Response {
+    #[serde(default)]
+    propstat: Vec<PropStat>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PropStat {
+    status: String,
+}
+
+#[derive(Debug, Snafu)]
+pub enum UpdateTagError {
+    #[snafu(display("failed to parse response: {source}"))]
+    Deserialize { source: DeserializeError },
+    #[snafu(display("server rejected the update: {status}"))]
+    Rejected { status: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_send_changed_properties() {
+        let request = UpdateTag::new(TagId::from(7)).with_name("Holiday 2024".parse().unwrap());
+        let Body::Xml(content) = request.body() else {
+            panic!("expected XML body");
+        };
+        let content = String::from_utf8(content).unwrap();
+        assert!(content.contains("<oc:display-name>Holiday 2024</oc:display-name>"));
+        assert!(!content.contains("color"));
+        assert_eq!(request.endpoint(), "systemtags/7");
+    }
+
+    #[test]
+    fn detect_rejected_properties() {
+        let response = |status: &str| {
+            format!(
+                r#"<?xml version="1.0"?>
+<d:multistatus xmlns:d="DAV:" xmlns:oc="http://owncloud.org/ns">
+  <d:response>
+    <d:href>/remote.php/dav/systemtags/7</d:href>
+    <d:propstat>
+      <d:prop><oc:display-name/></d:prop>
+      <d:status>HTTP/1.1 {status}</d:status>
+    </d:propstat>
+  </d:response>
+</d:multistatus>"#
+            )
+        };
+        assert!(UpdateTag::parse(&HeaderMap::new(), &response("200 OK")).is_ok());
+        assert!(matches!(
+            UpdateTag::parse(&HeaderMap::new(), &response("409 Conflict")),
+            Err(UpdateTagError::Rejected { .. })
+        ));
+    }
+}
@@ -0,0 +1,284 @@
+//! Typed builder for the XML bodies of DAV requests. Text content is escaped by the
+//! writer, so tags and paths may contain XML-special characters.
+
+use std::{borrow::Cow, time::SystemTime};
+
+use quick_xml::{
+    events::{BytesDecl, BytesText, Event},
+    Writer,
+};
+
+use super::{empty_as_none, Body, DeserializeError};
+use crate::{FileId, Permissions};
+
+/// Namespaces declared on the root element, usable as prefixes in all element names.
+const NAMESPACES: [(&str, &str); 3] = [
+    ("xmlns:d", "DAV:"),
+    ("xmlns:oc", "http://owncloud.org/ns"),
+    ("xmlns:nc", "http://nextcloud.org/ns"),
+];
+
+/// Element of a request body with a prefixed name like `d:prop`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node<'a> {
+    Empty(&'a str),
+    Text(&'a str, Cow<'a, str>),
+    Parent(&'a str, Vec<Self>),
+}
+
+impl<'a> Node<'a> {
+    pub fn text(name: &'a str, text: impl Into<Cow<'a, str>>) -> Self {
+        Self::Text(name, text.into())
+    }
+
+    fn write(&self, writer: &mut Writer<Vec<u8>>) -> quick_xml::Result<()> {
+        match self {
+            Self::Empty(name) => writer.create_element(name).write_empty()?,
+            Self::Text(name, text) => writer
+                .create_element(name)
+                .write_text_content(BytesText::new(text))?,
+            Self::Parent(name, children) => writer
+                .create_element(name)
+                .write_inner_content(|writer| write_all(children, writer))?,
+        };
+        Ok(())
+    }
+}
+
+fn write_all(nodes: &[Node], writer: &mut Writer<Vec<u8>>) -> quick_xml::Result<()> {
+    nodes.iter().try_for_each(|node| node.write(writer))
+}
+
+/// Serializes a document with the root element `root`.
+///
+/// # Panics
+///
+/// Panics if writing to memory fails, which it does not.
+#[must_use]
+pub fn document(root: &str, children: &[Node]) -> Body {
+    let mut writer = Writer::new_with_indent(Vec::new(), b' ', 4);
+    writer
+        .write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))
+        .and_then(|()| {
+            writer
+                .create_element(root)
+                .with_attributes(NAMESPACES)
+                .write_inner_content(|writer| write_all(children, writer))?;
+            Ok(())
+        })
+        .expect("writing XML to memory cannot fail");
+    Body::Xml(writer.into_inner())
+}
+
+/// DAV property of a file or tag which can be requested with a PROPFIND.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Property {
+    FileId,
+    Permissions,
+    /// Legacy tags of a file, unrelated to system tags.
+    Tags,
+    ResourceType,
+    ETag,
+    LastModified,
+    Size,
+    IsEncrypted,
+    /// Id of a tag.
+    TagId,
+    DisplayName,
+    UserVisible,
+    UserAssignable,
+    CanAssign,
+}
+
+impl Property {
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::FileId => "oc:fileid",
+            Self::Permissions => "oc:permissions",
+            Self::Tags => "oc:tags",
+            Self::ResourceType => "d:resourcetype",
+            Self::ETag => "d:getetag",
+            Self::LastModified => "d:getlastmodified",
+            Self::Size => "oc:size",
+            Self::IsEncrypted => "nc:is-encrypted",
+            Self::TagId => "oc:id",
+            Self::DisplayName => "oc:display-name",
+            Self::UserVisible => "oc:user-visible",
+            Self::UserAssignable => "oc:user-assignable",
+            Self::CanAssign => "oc:can-assign",
+        }
+    }
+}
+
+/// Properties of a file which callers may request in addition to the ones a request
+/// always needs. Each field is `None` unless its [`Property`] was requested.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileProperties {
+    pub etag: Option<String>,
+    pub last_modified: Option<SystemTime>,
+    /// Size in bytes, for folders including their content.
+    pub size: Option<u64>,
+}
+
+/// `d:multistatus` element of a PROPFIND or REPORT response.
+#[derive(Debug, serde::Deserialize)]
+pub struct MultiStatus<P> {
+    #[serde(default = "Vec::new")]
+    pub response: Vec<Response<P>>,
+}
+
+impl<P> MultiStatus<P> {
+    /// Href and found properties of each resource.
+    pub fn found(self) -> impl Iterator<Item = (String, P)> {
+        self.response
+            .into_iter()
+            .filter_map(|response| Some((response.href, found(response.propstat)?)))
+    }
+
+    /// Found properties of the first resource, which is the requested one for PROPFIND.
+    pub fn first(self) -> Option<P> {
+        self.found().next().map(|(_, prop)| prop)
+    }
+}
+
+/// Properties of one resource grouped by status. Properties which the server does not
+/// know or may not reveal are listed with empty values and a status like 404.
+#[derive(Debug, serde::Deserialize)]
+pub struct Response<P> {
+    pub href: String,
+    #[serde(default = "Vec::new")]
+    propstat: Vec<PropStat<P>>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct PropStat<P> {
+    prop: P,
+    /// Status line like `HTTP/1.1 200 OK`.
+    status: String,
+}
+
+/// Properties returned with status 200, `None` if the server found none.
+fn found<P>(propstat: Vec<PropStat<P>>) -> Option<P> {
+    propstat
+        .into_iter()
+        .find(|propstat| propstat.status.split_whitespace().nth(1) == Some("200"))
+        .map(|propstat| propstat.prop)
+}
+
+/// Error for a property the response must contain but the server did not find.
+#[must_use]
+pub fn missing(property: Property) -> DeserializeError {
+    serde_path_to_error::Error::new(
+        serde_path_to_error::Track::new().path(),
+        serde::de::Error::missing_field(property.name()),
+    )
+}
+
+/// `d:prop` element of a file in a multistatus response. All properties are optional
+/// because the same element lists the properties which were not found.
+#[derive(Debug, serde::Deserialize)]
+pub struct FileProp {
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub fileid: Option<FileId>,
+    #[serde(default)]
+    pub permissions: Permissions,
+    #[serde(default)]
+    pub resourcetype: ResourceType,
+    #[serde(default, deserialize_with = "etag")]
+    getetag: Option<String>,
+    #[serde(default, deserialize_with = "http_date")]
+    getlastmodified: Option<SystemTime>,
+    #[serde(default, deserialize_with = "empty_as_none")]
+    size: Option<u64>,
+}
+
+impl FileProp {
+    pub fn properties(&self) -> FileProperties {
+        FileProperties {
+            etag: self.getetag.clone(),
+            last_modified: self.getlastmodified,
+            size: self.size,
+        }
+    }
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct ResourceType {
+    collection: Option<String>,
+}
+
+impl ResourceType {
+    pub const fn is_collection(&self) -> bool {
+        self.collection.is_some()
+    }
+}
+
+/// Owned string because etags are quoted, which servers may escape.
+fn non_empty<'de, D: serde::Deserializer<'de>>(de: D) -> Result<Option<String>, D::Error> {
+    let value: Option<String> = serde::Deserialize::deserialize(de)?;
+    Ok(value.filter(|value| !value.is_empty()))
+}
+
+fn etag<'de, D: serde::Deserializer<'de>>(de: D) -> Result<Option<String>, D::Error> {
+    let etag = non_empty(de)?;
+    Ok(etag.map(|etag| etag.trim_matches('"').to_owned()))
+}
+
+fn http_date<'de, D: serde::Deserializer<'de>>(de: D) -> Result<Option<SystemTime>, D::Error> {
+    non_empty(de)?
+        .map(|date| httpdate::parse_http_date(&date))
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
+/// `d:prop` element listing `properties` without values.
+#[must_use]
+pub fn prop(properties: &[Property]) -> Node<'static> {
+    let properties = properties
+        .iter()
+        .map(|property| Node::Empty(property.name()))
+        .collect();
+    Node::Parent("d:prop", properties)
+}
+
+/// Body of a PROPFIND requesting `properties`.
+#[must_use]
+pub fn propfind(properties: &[Property]) -> Body {
+    document("d:propfind", &[prop(properties)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(body: Body) -> String {
+        let Body::Xml(content) = body else {
+            panic!("expected XML body");
+        };
+        String::from_utf8(content).unwrap()
+    }
+
+    #[test]
+    fn escape_text_content() {
+        let body = document(
+            "d:lockinfo",
+            &[Node::text("d:owner", "<a href=\"x\">Tom & Jerry</a>")],
+        );
+        assert_eq!(
+            render(body),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<d:lockinfo xmlns:d="DAV:" xmlns:oc="http://owncloud.org/ns" xmlns:nc="http://nextcloud.org/ns">
+    <d:owner>&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&lt;/a&gt;</d:owner>
+</d:lockinfo>"#
+        );
+    }
+
+    #[test]
+    fn request_chosen_properties() {
+        let body = render(propfind(&[Property::FileId, Property::ETag]));
+        assert!(
+            body.contains("<d:prop>\n        <oc:fileid/>\n        <d:getetag/>\n    </d:prop>")
+        );
+    }
+}
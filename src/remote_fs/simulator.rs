@@ -0,0 +1,473 @@
+//! In-process stand-in for the system tag and `WebDAV` API of Nextcloud. It answers the
+//! requests [`RemoteFs`](crate::RemoteFs) sends, so its logic can be tested without a
+//! server. Attach it with [`Connection::with_backend`](crate::Connection::with_backend).
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+    sync::{Mutex, PoisonError},
+};
+
+use http::{header::CONTENT_LOCATION, StatusCode};
+
+use crate::{fixtures::MultiStatusBuilder, PrefixMapping};
+
+use super::{RemoteBackend, RemotePath};
+
+/// Permissions of simulated files the user owns.
+const PERMISSIONS: &str = "RGDNVW";
+/// Permissions of files in an incoming share the user may only read.
+const READ_ONLY_SHARE: &str = "SRG";
+
+#[derive(Debug)]
+struct SimulatedTag {
+    name: String,
+    visible: bool,
+    assignable: bool,
+}
+
+#[derive(Debug)]
+struct SimulatedFile {
+    id: u64,
+    folder: bool,
+    tags: BTreeSet<u64>,
+    permissions: &'static str,
+    /// End-to-end encrypted folder.
+    encrypted: bool,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    last_id: u64,
+    tags: BTreeMap<u64, SimulatedTag>,
+    /// Files and folders by their path on the server, e.g. `/remote.php/dav/files/erik/a`.
+    files: BTreeMap<RemotePath, SimulatedFile>,
+}
+
+impl State {
+    /// Ids are shared by files and tags like in Nextcloud, so mixing them up fails.
+    const fn next_id(&mut self) -> u64 {
+        self.last_id += 1;
+        self.last_id
+    }
+
+    fn tag_id(&self, name: &str) -> Option<u64> {
+        self.tags
+            .iter()
+            .find(|(_, tag)| tag.name == name)
+            .map(|(&id, _)| id)
+    }
+
+    fn add(&mut self, path: &Path, folder: bool) -> u64 {
+        for parent in path.ancestors().skip(1) {
+            let parent = RemotePath::from(parent);
+            if !self.files.contains_key(&parent) {
+                let id = self.next_id();
+                self.files.insert(parent, SimulatedFile::new(id, true));
+            }
+        }
+        let path = RemotePath::from(path);
+        if let Some(file) = self.files.get(&path) {
+            return file.id;
+        }
+        let id = self.next_id();
+        self.files.insert(path, SimulatedFile::new(id, folder));
+        id
+    }
+}
+
+impl SimulatedFile {
+    const fn new(id: u64, folder: bool) -> Self {
+        Self {
+            id,
+            folder,
+            tags: BTreeSet::new(),
+            permissions: PERMISSIONS,
+            encrypted: false,
+        }
+    }
+}
+
+/// Simulated Nextcloud server with system tags, files and folders of any user. Requests
+/// it does not implement are answered with 501 Not Implemented.
+#[derive(Debug, Default)]
+pub struct Simulator {
+    state: Mutex<State>,
+}
+
+impl Simulator {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Adds a file and its missing parent folders. `path` is the path on the server, e.g.
+    /// `/remote.php/dav/files/erik/a.jpg`. Returns the id of the file.
+    pub fn add_file(&self, path: impl AsRef<Path>) -> u64 {
+        self.state().add(path.as_ref(), false)
+    }
+
+    /// Adds a folder and its missing parent folders. Returns the id of the folder.
+    pub fn add_folder(&self, path: impl AsRef<Path>) -> u64 {
+        self.state().add(path.as_ref(), true)
+    }
+
+    /// Marks an existing file as part of an incoming share the user may only read.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the file does not exist.
+    pub fn share_read_only(&self, path: impl AsRef<Path>) {
+        self.state()
+            .files
+            .get_mut(&RemotePath::from(path.as_ref()))
+            .expect("file exists")
+            .permissions = READ_ONLY_SHARE;
+    }
+
+    /// Marks an existing folder as end-to-end encrypted.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the folder does not exist.
+    pub fn encrypt(&self, path: impl AsRef<Path>) {
+        self.state()
+            .files
+            .get_mut(&RemotePath::from(path.as_ref()))
+            .expect("folder exists")
+            .encrypted = true;
+    }
+
+    /// Adds a tag. Tags which are not `assignable` can only be assigned with
+    /// [`Self::assign`], like restricted tags by an admin. Returns the id of the tag.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the tag already exists.
+    pub fn add_tag(&self, name: &str, visible: bool, assignable: bool) -> u64 {
+        let mut state = self.state();
+        assert!(state.tag_id(name).is_none(), "tag {name} already exists");
+        let id = state.next_id();
+        let tag = SimulatedTag {
+            name: name.to_owned(),
+            visible,
+            assignable,
+        };
+        state.tags.insert(id, tag);
+        id
+    }
+
+    /// Assigns an existing tag to an existing file or folder.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the tag or the file does not exist.
+    pub fn assign(&self, path: impl AsRef<Path>, tag: &str) {
+        let mut state = self.state();
+        let id = state.tag_id(tag).expect("tag exists");
+        state
+            .files
+            .get_mut(&RemotePath::from(path.as_ref()))
+            .expect("file exists")
+            .tags
+            .insert(id);
+    }
+
+    /// Names of the tags assigned to a file, `None` if it does not exist.
+    #[must_use]
+    pub fn tags_of(&self, path: impl AsRef<Path>) -> Option<BTreeSet<String>> {
+        let state = self.state();
+        let file = state.files.get(&RemotePath::from(path.as_ref()))?;
+        let tags = file
+            .tags
+            .iter()
+            .map(|id| state.tags[id].name.clone())
+            .collect();
+        drop(state);
+        Some(tags)
+    }
+
+    fn list_tags(state: &State) -> Response {
+        let builder = state
+            .tags
+            .iter()
+            .fold(MultiStatusBuilder::new(), |builder, (&id, tag)| {
+                builder.tag(id, &tag.name, tag.visible, tag.assignable)
+            });
+        multi_status(&builder)
+    }
+
+    fn create_tag(state: &mut State, body: &[u8]) -> Response {
+        #[derive(serde::Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct NewTag {
+            name: String,
+            user_visible: bool,
+            user_assignable: bool,
+        }
+
+        let Ok(tag) = serde_json::from_slice::<NewTag>(body) else {
+            return status(StatusCode::BAD_REQUEST);
+        };
+        if state.tag_id(&tag.name).is_some() {
+            return status(StatusCode::CONFLICT);
+        }
+        let id = state.next_id();
+        state.tags.insert(
+            id,
+            SimulatedTag {
+                name: tag.name,
+                visible: tag.user_visible,
+                assignable: tag.user_assignable,
+            },
+        );
+        http::Response::builder()
+            .status(StatusCode::CREATED)
+            .header(CONTENT_LOCATION, format!("/remote.php/dav/systemtags/{id}"))
+            .body(String::new())
+            .expect("valid response")
+    }
+
+    fn delete_tag(state: &mut State, id: u64) -> Response {
+        if state.tags.remove(&id).is_none() {
+            return status(StatusCode::NOT_FOUND);
+        }
+        for file in state.files.values_mut() {
+            file.tags.remove(&id);
+        }
+        status(StatusCode::NO_CONTENT)
+    }
+
+    /// Assigns or removes a tag. Like Nextcloud, only assignable tags may be changed.
+    fn relation(state: &mut State, file: u64, tag: u64, assign: bool) -> Response {
+        let Some(assignable) = state.tags.get(&tag).map(|tag| tag.assignable) else {
+            return status(StatusCode::NOT_FOUND);
+        };
+        let Some(file) = state.files.values_mut().find(|f| f.id == file) else {
+            return status(StatusCode::NOT_FOUND);
+        };
+        if !assignable {
+            return status(StatusCode::FORBIDDEN);
+        }
+        match (assign, file.tags.contains(&tag)) {
+            (true, true) => status(StatusCode::CONFLICT),
+            (false, false) => status(StatusCode::NOT_FOUND),
+            (true, false) => {
+                file.tags.insert(tag);
+                status(StatusCode::CREATED)
+            }
+            (false, true) => {
+                file.tags.remove(&tag);
+                status(StatusCode::NO_CONTENT)
+            }
+        }
+    }
+
+    /// Files and folders below `folder` with the tag in the filter rules of `body`.
+    fn filter_files(state: &State, folder: &RemotePath, body: &str) -> Response {
+        let tag = body
+            .split_once("<oc:systemtag>")
+            .and_then(|(_, rest)| rest.split_once("</oc:systemtag>"))
+            .and_then(|(id, _)| id.trim().parse::<u64>().ok());
+        let Some(tag) = tag else {
+            return status(StatusCode::BAD_REQUEST);
+        };
+        let builder = state
+            .files
+            .iter()
+            .filter(|(path, file)| path.starts_with(folder.as_path()) && file.tags.contains(&tag))
+            .fold(MultiStatusBuilder::new(), |builder, (path, file)| {
+                let href = path.to_href().unwrap_or_default();
+                if file.folder {
+                    builder.folder(&href, file.id)
+                } else {
+                    let id = file.id.to_string();
+                    builder.response(
+                        &href,
+                        [
+                            ("oc:fileid", id.as_str()),
+                            ("d:resourcetype", ""),
+                            ("oc:permissions", file.permissions),
+                        ],
+                    )
+                }
+            });
+        multi_status(&builder)
+    }
+
+    fn file_id(state: &State, path: &RemotePath) -> Response {
+        let Some(file) = state.files.get(path) else {
+            return status(StatusCode::NOT_FOUND);
+        };
+        let id = file.id.to_string();
+        let builder = MultiStatusBuilder::new().response(
+            &path.to_href().unwrap_or_default(),
+            [
+                ("oc:fileid", id.as_str()),
+                ("oc:permissions", file.permissions),
+                ("nc:is-encrypted", if file.encrypted { "1" } else { "0" }),
+            ],
+        );
+        multi_status(&builder)
+    }
+}
+
+type Response = http::Response<String>;
+
+fn status(status: StatusCode) -> Response {
+    http::Response::builder()
+        .status(status)
+        .body(String::new())
+        .expect("valid response")
+}
+
+fn multi_status(builder: &MultiStatusBuilder) -> Response {
+    http::Response::builder()
+        .status(StatusCode::MULTI_STATUS)
+        .header(http::header::CONTENT_TYPE, "application/xml; charset=utf-8")
+        .body(builder.build())
+        .expect("valid response")
+}
+
+impl RemoteBackend for Simulator {
+    fn respond(&self, request: &reqwest::Request) -> Response {
+        let path = RemotePath::from_href(request.url().path());
+        let Ok(endpoint) = path.as_path().strip_prefix(PrefixMapping::DEFAULT_DAV_ROOT) else {
+            return status(StatusCode::NOT_IMPLEMENTED);
+        };
+        let segments: Vec<_> = endpoint.iter().filter_map(|s| s.to_str()).collect();
+        let body = request
+            .body()
+            .and_then(reqwest::Body::as_bytes)
+            .unwrap_or_default();
+        let id = |segment: &str| segment.parse::<u64>().ok();
+        let mut state = self.state();
+        match (request.method().as_str(), segments.as_slice()) {
+            ("PROPFIND", ["systemtags"]) => Self::list_tags(&state),
+            ("POST", ["systemtags"]) => Self::create_tag(&mut state, body),
+            ("DELETE", ["systemtags", tag]) => id(tag).map_or_else(
+                || status(StatusCode::NOT_FOUND),
+                |tag| Self::delete_tag(&mut state, tag),
+            ),
+            (method @ ("PUT" | "DELETE"), ["systemtags-relations", "files", file, tag]) => {
+                match (id(file), id(tag)) {
+                    (Some(file), Some(tag)) => {
+                        Self::relation(&mut state, file, tag, method == "PUT")
+                    }
+                    _ => status(StatusCode::NOT_FOUND),
+                }
+            }
+            ("REPORT", ["files", ..]) => {
+                Self::filter_files(&state, &path, &String::from_utf8_lossy(body))
+            }
+            ("PROPFIND", ["files", ..]) => Self::file_id(&state, &path),
+            _ => status(StatusCode::NOT_IMPLEMENTED),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{Command, CommandOutcome, Config, Connection, FileSystem, RemoteFs, SyncedPath};
+
+    const FILE: &str = "/remote.php/dav/files/erik/data/a.jpg";
+
+    #[tokio::test]
+    async fn synchronize_with_simulated_server() {
+        let simulator = Arc::new(Simulator::new());
+        simulator.add_file(FILE);
+        simulator.add_file("/remote.php/dav/files/erik/other/b.jpg");
+        simulator.add_tag("holiday", true, true);
+        simulator.add_tag("restricted", true, false);
+        simulator.assign(FILE, "holiday");
+        simulator.assign(FILE, "restricted");
+        simulator.assign("/remote.php/dav/files/erik/other/b.jpg", "holiday");
+
+        let config = Arc::new(Config {
+            prefixes: vec![PrefixMapping::new(
+                "/data".into(),
+                "/remote.php/dav/files/erik/data".into(),
+            )
+            .unwrap()],
+            ..Config::default()
+        });
+        let connection = Connection::from_config(&config).with_backend(simulator.clone());
+        let mut remote = RemoteFs::with_connection(connection, config);
+        let (repo, issues) = remote.create_repo().await.unwrap();
+        assert!(issues.is_empty());
+        let files: Vec<_> = repo.files().map(|(_, tags)| tags.to_string()).collect();
+        assert_eq!(files, ["holiday"]);
+
+        let command = Command::for_path(SyncedPath::new(0, "a.jpg"))
+            .add("new")
+            .remove("holiday")
+            .build()
+            .unwrap();
+        let outcomes = remote.update_tags([command]).await;
+        assert!(outcomes.iter().all(CommandOutcome::is_success));
+        assert_eq!(
+            simulator.tags_of(FILE).unwrap(),
+            BTreeSet::from(["new".to_owned(), "restricted".to_owned()])
+        );
+    }
+
+    #[tokio::test]
+    async fn create_tags_without_assigning() {
+        let simulator = Arc::new(Simulator::new());
+        simulator.add_file(FILE);
+        simulator.add_tag("holiday", true, true);
+
+        let config = Arc::new(Config::default());
+        let connection = Connection::from_config(&config).with_backend(simulator.clone());
+        let mut remote = RemoteFs::with_connection(connection, config);
+        let tags = ["holiday", "new"].map(|tag| tag.parse().unwrap());
+        let (created, failed) = remote.create_tags(tags).await.unwrap();
+        assert_eq!(created, BTreeSet::from(["new".parse().unwrap()]));
+        assert!(failed.is_empty());
+        assert!(simulator.tags_of(FILE).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn skip_files_in_encrypted_folders() {
+        let encrypted = "/remote.php/dav/files/erik/data/vault/a.jpg";
+        let simulator = Arc::new(Simulator::new());
+        simulator.add_file(FILE);
+        simulator.add_file(encrypted);
+        simulator.encrypt("/remote.php/dav/files/erik/data/vault");
+        simulator.add_tag("holiday", true, true);
+        simulator.assign(FILE, "holiday");
+        simulator.assign(encrypted, "holiday");
+
+        let config = Arc::new(Config {
+            prefixes: vec![PrefixMapping::new(
+                "/data".into(),
+                "/remote.php/dav/files/erik/data".into(),
+            )
+            .unwrap()],
+            ..Config::default()
+        });
+        let connection = Connection::from_config(&config).with_backend(simulator.clone());
+        let mut remote = RemoteFs::with_connection(connection, config);
+        let (repo, _) = remote.create_repo().await.unwrap();
+        let files: Vec<_> = repo.files().map(|(path, _)| path.clone()).collect();
+        assert_eq!(files, [SyncedPath::new(0, "a.jpg")]);
+        assert_eq!(
+            remote.encrypted_folders,
+            BTreeSet::from([SyncedPath::new(0, "vault")])
+        );
+
+        let command = Command::for_path(SyncedPath::new(0, "vault/a.jpg"))
+            .add("new")
+            .build()
+            .unwrap();
+        let outcomes = remote.update_tags([command]).await;
+        assert!(matches!(outcomes[..], [CommandOutcome::Skipped { .. }]));
+    }
+}
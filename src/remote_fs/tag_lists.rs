@@ -0,0 +1,75 @@
+use std::{collections::BTreeSet, path::PathBuf};
+
+use atomic_write_file::AtomicWriteFile;
+use tracing::warn;
+
+use crate::{Config, Tag};
+
+/// Tags the server refused to work with. They are stored next to the tag database.
+#[derive(Debug, Clone, Copy)]
+pub enum TagList {
+    /// Tags the user may not assign, e.g. because an admin restricted them to a group.
+    Forbidden,
+    /// Tags whose names the server rejected when creating them, e.g. because they are too long.
+    Rejected,
+}
+
+impl TagList {
+    fn path(self, config: &Config) -> PathBuf {
+        let extension = match self {
+            Self::Forbidden => "forbidden-tags.json",
+            Self::Rejected => "rejected-tags.json",
+        };
+        config.tag_database.with_extension(extension)
+    }
+
+    /// Loads the tags stored by earlier synchronizations.
+    pub fn load(self, config: &Config) -> BTreeSet<Tag> {
+        let path = self.path(config);
+        let data = match std::fs::read_to_string(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return BTreeSet::new(),
+            Err(e) => {
+                warn!("Failed to read tag list {}: {e}", path.display());
+                return BTreeSet::new();
+            }
+        };
+        serde_json::from_str(&data).unwrap_or_else(|e| {
+            warn!("Ignoring invalid tag list {}: {e}", path.display());
+            BTreeSet::new()
+        })
+    }
+
+    pub fn persist(self, config: &Config, tags: &BTreeSet<Tag>) {
+        let path = self.path(config);
+        let write = || -> std::io::Result<()> {
+            let data = serde_json::to_vec(tags)?;
+            let mut file = AtomicWriteFile::open(&path)?;
+            std::io::Write::write_all(&mut file, &data)?;
+            file.commit()
+        };
+        if let Err(e) = write() {
+            warn!("Failed to store tag list {}: {e}", path.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tag_lists_survive_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            tag_database: dir.path().join("db.json"),
+            ..Config::default()
+        };
+        assert!(TagList::Forbidden.load(&config).is_empty());
+
+        let tags: BTreeSet<Tag> = ["finance".parse().unwrap()].into();
+        TagList::Forbidden.persist(&config, &tags);
+        assert_eq!(TagList::Forbidden.load(&config), tags);
+        assert!(TagList::Rejected.load(&config).is_empty());
+    }
+}
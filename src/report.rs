@@ -0,0 +1,222 @@
+mod dry_run;
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+};
+
+use crate::{
+    helper::DisplayUnit, i18n::tr, tag_repository::PrefixMappingId, ActionsFormatter,
+    CommandOutcome, FileLocation, FormatOptions, RepositoryStats, ScanIssues, SyncedPath,
+    SyncedPathPrinter, Tag, Tags,
+};
+
+pub use dry_run::DryRunReport;
+
+/// Summary of everything noteworthy that happened during synchronization.
+#[derive(Debug, Default, Clone)]
+pub struct SyncReport {
+    /// Remote end-to-end encrypted folders which were skipped.
+    pub encrypted_folders: BTreeSet<SyncedPath>,
+    /// Files in incoming shares which were skipped because the user may not modify them.
+    pub read_only_shares: BTreeSet<SyncedPath>,
+    /// Tags the user may not assign, e.g. because an admin restricted them to a group.
+    pub forbidden_tags: BTreeSet<Tag>,
+    /// Tags whose names Nextcloud rejected, e.g. because they are too long.
+    pub rejected_tags: BTreeSet<Tag>,
+    /// Local files whose tags were not changed for a long time.
+    pub stale_files: BTreeSet<SyncedPath>,
+    /// Local files which were skipped because they do not belong to any prefix.
+    pub quarantined: BTreeSet<PathBuf>,
+    /// Local files whose remote counterpart was deleted.
+    pub orphaned_files: BTreeSet<SyncedPath>,
+    /// Files which were not synchronized because they carry mutually exclusive tags.
+    pub constraint_violations: BTreeMap<SyncedPath, Tags>,
+    /// Files which are not synchronized anymore because their tags keep flipping.
+    pub looping_files: BTreeSet<SyncedPath>,
+    /// Items skipped while scanning the local file system or Nextcloud.
+    pub scan_issues: ScanIssues,
+    /// Outcome of every command applied to the local file system.
+    pub local_outcomes: Vec<CommandOutcome>,
+    /// Outcome of every command applied to Nextcloud.
+    pub remote_outcomes: Vec<CommandOutcome>,
+    /// Statistics of the synchronized repository.
+    pub stats: RepositoryStats,
+    /// How file trees and tag changes are printed.
+    pub format: FormatOptions,
+}
+
+impl SyncReport {
+    /// Whether nothing noteworthy happened. The statistics are not considered.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.encrypted_folders.is_empty()
+            && self.read_only_shares.is_empty()
+            && self.forbidden_tags.is_empty()
+            && self.rejected_tags.is_empty()
+            && self.quarantined.is_empty()
+            && self.stale_files.is_empty()
+            && self.scan_issues.is_empty()
+            && self.orphaned_files.is_empty()
+            && self.constraint_violations.is_empty()
+            && self.looping_files.is_empty()
+            && !self.local_outcomes.iter().any(CommandOutcome::is_failure)
+            && !self.remote_outcomes.iter().any(CommandOutcome::is_failure)
+    }
+
+    /// Number of local and remote commands which failed at least partially.
+    #[must_use]
+    pub fn failed_commands(&self) -> usize {
+        self.local_outcomes
+            .iter()
+            .chain(&self.remote_outcomes)
+            .filter(|outcome| outcome.is_failure())
+            .count()
+    }
+
+    fn tree<'a>(&self, paths: &'a BTreeSet<SyncedPath>) -> SyncedPathPrinter<'a, DisplayUnit> {
+        SyncedPathPrinter::new(
+            paths.iter().map(|path| (path, DisplayUnit)),
+            self.format.sort_order,
+        )
+    }
+}
+
+impl std::fmt::Display for SyncReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if !self.encrypted_folders.is_empty() {
+            let mut per_prefix: BTreeMap<PrefixMappingId, usize> = BTreeMap::new();
+            for folder in &self.encrypted_folders {
+                *per_prefix.entry(folder.root()).or_default() += 1;
+            }
+
+            writeln!(f, "{}", tr!("report-encrypted-folders"))?;
+            for (prefix, count) in per_prefix {
+                let prefix = prefix.to_string();
+                writeln!(
+                    f,
+                    "{}",
+                    tr!("report-encrypted-prefix", prefix = prefix, count = count)
+                )?;
+            }
+            if !self.format.quiet {
+                write!(f, "{}", self.tree(&self.encrypted_folders))?;
+            }
+        }
+
+        if !self.read_only_shares.is_empty() {
+            let count = self.read_only_shares.len();
+            writeln!(f, "{}", tr!("report-read-only-shares", count = count))?;
+            if !self.format.quiet {
+                write!(f, "{}", self.tree(&self.read_only_shares))?;
+            }
+        }
+
+        for tag in &self.forbidden_tags {
+            let tag = tag.to_string();
+            writeln!(f, "{}", tr!("report-forbidden-tag", tag = tag))?;
+        }
+
+        if !self.rejected_tags.is_empty() {
+            for tag in &self.rejected_tags {
+                let tag = tag.to_string();
+                writeln!(f, "{}", tr!("report-rejected-tag", tag = tag))?;
+            }
+            writeln!(f, "{}", tr!("report-rejected-tags-hint"))?;
+        }
+
+        if !self.quarantined.is_empty() {
+            writeln!(f, "{}", tr!("report-quarantined"))?;
+            for path in &self.quarantined {
+                writeln!(f, "{}", path.display())?;
+            }
+        }
+
+        if !self.stale_files.is_empty() {
+            let count = self.stale_files.len();
+            writeln!(f, "{}", tr!("report-stale-files", count = count))?;
+            if !self.format.quiet {
+                write!(f, "{}", self.tree(&self.stale_files))?;
+            }
+        }
+
+        if !self.orphaned_files.is_empty() {
+            let count = self.orphaned_files.len();
+            writeln!(f, "{}", tr!("report-orphaned-files", count = count))?;
+            if !self.format.quiet {
+                write!(f, "{}", self.tree(&self.orphaned_files))?;
+            }
+        }
+
+        if !self.constraint_violations.is_empty() {
+            let count = self.constraint_violations.len();
+            writeln!(f, "{}", tr!("report-constraint-violations", count = count))?;
+            for (path, tags) in &self.constraint_violations {
+                let path = path.to_string();
+                let tags = tags.to_string();
+                writeln!(
+                    f,
+                    "{}",
+                    tr!("report-constraint-violation", path = path, tags = tags)
+                )?;
+            }
+        }
+
+        if !self.looping_files.is_empty() {
+            let count = self.looping_files.len();
+            writeln!(f, "{}", tr!("report-looping-files", count = count))?;
+            if !self.format.quiet {
+                write!(f, "{}", self.tree(&self.looping_files))?;
+            }
+        }
+
+        if !self.scan_issues.is_empty() {
+            let count = self.scan_issues.len();
+            writeln!(f, "{}", tr!("report-scan-issues", count = count))?;
+            for issue in self.scan_issues.iter() {
+                writeln!(f, "{issue}")?;
+            }
+        }
+
+        write_failures(f, FileLocation::Local, &self.local_outcomes, self.format)?;
+        write_failures(f, FileLocation::Remote, &self.remote_outcomes, self.format)?;
+
+        if !self.format.quiet && !self.stats.per_prefix.is_empty() {
+            writeln!(f, "{}", tr!("report-statistics"))?;
+            write!(f, "{}", self.stats)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_failures(
+    f: &mut std::fmt::Formatter,
+    side: FileLocation,
+    outcomes: &[CommandOutcome],
+    format: FormatOptions,
+) -> std::fmt::Result {
+    let failures: Vec<_> = outcomes
+        .iter()
+        .filter(|outcome| outcome.is_failure())
+        .filter_map(|outcome| Some((outcome.failed()?, outcome.error()?)))
+        .collect();
+    if failures.is_empty() {
+        return Ok(());
+    }
+
+    let (failed, total) = (failures.len(), outcomes.len());
+    let heading = match side {
+        FileLocation::Local => tr!("report-failures-local", failed = failed, total = total),
+        FileLocation::Remote => tr!("report-failures-remote", failed = failed, total = total),
+    };
+    writeln!(f, "{heading}")?;
+    for (cmd, error) in failures {
+        writeln!(
+            f,
+            "{}{}: {error}",
+            cmd.path,
+            ActionsFormatter(&cmd.actions, format)
+        )?;
+    }
+    Ok(())
+}
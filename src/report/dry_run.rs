@@ -0,0 +1,147 @@
+use std::path::{Path, PathBuf};
+
+use atomic_write_file::AtomicWriteFile;
+use serde::Serialize;
+
+use crate::{
+    i18n::tr, translate, Command, CommandsFormatter, FormatOptions, Modification, PendingChanges,
+    PrefixMapping, Tag,
+};
+
+/// Changes computed by a dry run instead of applying them.
+#[derive(Debug, Default)]
+pub struct DryRunReport {
+    pub changes: PendingChanges,
+    /// How file trees and tag changes are printed.
+    pub format: FormatOptions,
+}
+
+/// Change of a single file in the JSON report.
+#[derive(Serialize)]
+struct FileChange<'a> {
+    local: PathBuf,
+    remote: String,
+    add: Vec<&'a Tag>,
+    remove: Vec<&'a Tag>,
+}
+
+impl<'a> FileChange<'a> {
+    fn new(cmd: &'a Command, prefixes: &[PrefixMapping]) -> Self {
+        let tags = |modification| {
+            cmd.actions
+                .iter()
+                .filter(|action| action.modification == modification)
+                .map(|action| &action.tag)
+                .collect()
+        };
+        Self {
+            local: cmd.path.local_file(prefixes),
+            remote: cmd.path.remote_file(prefixes).to_string(),
+            add: tags(Modification::Add),
+            remove: tags(Modification::Remove),
+        }
+    }
+
+    fn all(commands: &'a [Command], prefixes: &[PrefixMapping]) -> Vec<Self> {
+        commands
+            .iter()
+            .map(|cmd| Self::new(cmd, prefixes))
+            .collect()
+    }
+}
+
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    local: Vec<FileChange<'a>>,
+    remote: Vec<FileChange<'a>>,
+}
+
+impl DryRunReport {
+    fn json(&self, prefixes: &[PrefixMapping]) -> serde_json::Result<Vec<u8>> {
+        serde_json::to_vec_pretty(&JsonReport {
+            local: FileChange::all(&self.changes.local, prefixes),
+            remote: FileChange::all(&self.changes.remote, prefixes),
+        })
+    }
+
+    /// Writes the changes with the local and remote path of every file as JSON to `path`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file cannot be written.
+    pub fn write_json(&self, prefixes: &[PrefixMapping], path: &Path) -> std::io::Result<()> {
+        let data = self.json(prefixes)?;
+        let mut file = AtomicWriteFile::open(path)?;
+        std::io::Write::write_all(&mut file, &data)?;
+        file.commit()
+    }
+}
+
+impl std::fmt::Display for DryRunReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "{}", tr!("dry-run-header"))?;
+        for (commands, pending, none) in [
+            (
+                &self.changes.remote,
+                "status-pending-remote",
+                "status-none-remote",
+            ),
+            (
+                &self.changes.local,
+                "status-pending-local",
+                "status-none-local",
+            ),
+        ] {
+            if commands.is_empty() {
+                writeln!(f, "{}", translate(none, None))?;
+            } else {
+                writeln!(f, "{}", translate(pending, None))?;
+                write!(f, "{}", CommandsFormatter(commands, self.format))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SyncedPath, TagAction};
+
+    #[test]
+    fn json_lists_both_paths_of_files() {
+        let prefixes =
+            vec![
+                PrefixMapping::new("/data".into(), "/remote.php/dav/files/erik/data".into())
+                    .unwrap(),
+            ];
+        let report = DryRunReport {
+            changes: PendingChanges {
+                local: Vec::new(),
+                remote: vec![Command {
+                    path: SyncedPath::new(0, "a.txt"),
+                    actions: vec![
+                        TagAction::add("red".parse().unwrap()),
+                        TagAction::remove("blue".parse().unwrap()),
+                    ],
+                }],
+            },
+            format: FormatOptions::default(),
+        };
+
+        let json: serde_json::Value =
+            serde_json::from_slice(&report.json(&prefixes).unwrap()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "local": [],
+                "remote": [{
+                    "local": "/data/a.txt",
+                    "remote": "/remote.php/dav/files/erik/data/a.txt",
+                    "add": ["red"],
+                    "remove": ["blue"],
+                }],
+            })
+        );
+    }
+}
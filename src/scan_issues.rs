@@ -0,0 +1,97 @@
+use std::{collections::BTreeSet, path::PathBuf};
+
+use crate::{i18n::tr, PrefixMappingId, SyncedPath};
+
+/// Item that was skipped while building a repository.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanIssue {
+    /// A prefix could not be scanned completely. Its files keep their previous tags.
+    PrefixFailed {
+        prefix_id: PrefixMappingId,
+        path: PathBuf,
+        error: String,
+    },
+    /// The remote file has tags but its id is unknown, so they cannot be changed.
+    MissingFileId { path: SyncedPath },
+    /// The tags of a local file could not be read, e.g. because one is invalid.
+    UnreadableFile { path: PathBuf, error: String },
+}
+
+impl std::fmt::Display for ScanIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let line = match self {
+            Self::PrefixFailed { path, error, .. } => {
+                let path = path.display().to_string();
+                tr!(
+                    "scan-issue-prefix-failed",
+                    path = path,
+                    error = error.as_str()
+                )
+            }
+            Self::MissingFileId { path } => {
+                let path = path.to_string();
+                tr!("scan-issue-missing-file-id", path = path)
+            }
+            Self::UnreadableFile { path, error } => {
+                let path = path.display().to_string();
+                tr!(
+                    "scan-issue-unreadable-file",
+                    path = path,
+                    error = error.as_str()
+                )
+            }
+        };
+        f.write_str(&line)
+    }
+}
+
+/// Everything that was skipped while building the local and remote repositories.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ScanIssues(Vec<ScanIssue>);
+
+impl ScanIssues {
+    pub fn push(&mut self, issue: ScanIssue) {
+        self.0.push(issue);
+    }
+
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ScanIssue> {
+        self.0.iter()
+    }
+
+    /// Prefixes whose scan failed. Their files must not be synchronized.
+    #[must_use]
+    pub fn failed_prefixes(&self) -> BTreeSet<PrefixMappingId> {
+        self.0
+            .iter()
+            .filter_map(|issue| match issue {
+                ScanIssue::PrefixFailed { prefix_id, .. } => Some(*prefix_id),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl Extend<ScanIssue> for ScanIssues {
+    fn extend<I: IntoIterator<Item = ScanIssue>>(&mut self, iter: I) {
+        self.0.extend(iter);
+    }
+}
+
+impl IntoIterator for ScanIssues {
+    type Item = ScanIssue;
+    type IntoIter = std::vec::IntoIter<ScanIssue>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
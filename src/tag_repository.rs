@@ -1,19 +1,32 @@
 use std::cmp::Ordering;
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::convert::Infallible;
 use std::fmt::Debug;
-use std::io::Write;
 use std::iter::Peekable;
 use std::ops::Deref;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use std::str::FromStr;
 
+#[cfg(feature = "sync")]
 use atomic_write_file::AtomicWriteFile;
 use serde::{Deserialize, Serialize};
-use snafu::{ensure, IntoError, ResultExt, Snafu};
+use snafu::{ensure, IntoError, OptionExt, ResultExt, Snafu};
 use tracing::error;
 
-use crate::newtype;
+use crate::{newtype, Command, Modification, RemotePath, TagAction};
+
+mod anonymize;
+mod builder;
+mod integrity;
+#[cfg(feature = "sync")]
+mod snapshot;
+mod stats;
+
+pub use builder::RepositoryBuilder;
+pub use integrity::{Anomaly, IntegrityReport};
+#[cfg(feature = "sync")]
+pub use snapshot::{SnapshotError, SnapshotReader};
+pub use stats::{RepositoryStats, TagStats};
 
 newtype!(PrefixMappingId, usize);
 
@@ -25,6 +38,7 @@ pub struct SyncedPath {
 
 impl SyncedPath {
     #[cfg(test)]
+    #[must_use]
     pub fn new(prefix_id: usize, path: &str) -> Self {
         Self {
             prefix_id: PrefixMappingId(prefix_id),
@@ -32,36 +46,59 @@ impl SyncedPath {
         }
     }
 
+    /// Returns `None` if the prefix does not exist anymore.
+    #[must_use]
+    pub fn prefix<'a>(&self, prefixes: &'a [PrefixMapping]) -> Option<&'a PrefixMapping> {
+        prefixes.get(self.prefix_id.0)
+    }
+
+    #[must_use]
     pub fn local_file(&self, prefixes: &[PrefixMapping]) -> PathBuf {
         prefixes[self.prefix_id.0].local.join(&self.path)
     }
 
-    pub fn remote_file(&self, prefixes: &[PrefixMapping]) -> PathBuf {
-        prefixes[self.prefix_id.0].remote.join(&self.path)
+    #[must_use]
+    pub fn remote_file(&self, prefixes: &[PrefixMapping]) -> RemotePath {
+        RemotePath::from(prefixes[self.prefix_id.0].remote()).join(&self.path)
     }
 
+    #[must_use]
     pub fn relative(&self) -> &Path {
         &self.path
     }
 
+    #[must_use]
     pub const fn root(&self) -> PrefixMappingId {
         self.prefix_id
     }
 
-    fn from_local(local: &Path, repo: &Repository) -> Self {
-        let (prefix_id, path) = repo.split_prefix(local, FileLocation::Local);
-        Self {
-            prefix_id,
-            path: path.to_owned(),
-        }
+    /// Whether `ancestor` is this path or one of its parent directories within the same prefix.
+    #[must_use]
+    pub fn starts_with(&self, ancestor: &Self) -> bool {
+        self.prefix_id == ancestor.prefix_id && self.path.starts_with(&ancestor.path)
     }
 
-    fn from_remote(remote: &Path, repo: &Repository) -> Self {
-        let (prefix_id, path) = repo.split_prefix(remote, FileLocation::Remote);
-        Self {
-            prefix_id,
+    /// All parent directories within the prefix, starting with the top-most one.
+    pub fn parents(&self) -> impl Iterator<Item = Self> + '_ {
+        let mut parents: Vec<_> = self
+            .path
+            .ancestors()
+            .skip(1)
+            .filter(|p| !p.as_os_str().is_empty())
+            .collect();
+        parents.reverse();
+        parents.into_iter().map(|path| Self {
+            prefix_id: self.prefix_id,
             path: path.to_owned(),
-        }
+        })
+    }
+
+    fn from_local(local: &Path, repo: &Repository) -> Result<Self, MissingPrefixError> {
+        repo.synced_path(local, FileLocation::Local)
+    }
+
+    fn from_remote(remote: &RemotePath, repo: &Repository) -> Result<Self, MissingPrefixError> {
+        repo.synced_path(remote.as_path(), FileLocation::Remote)
     }
 }
 
@@ -87,7 +124,7 @@ impl<'de> Deserialize<'de> for SyncedPath {
     {
         struct Visitor;
 
-        impl<'de> serde::de::Visitor<'de> for Visitor {
+        impl serde::de::Visitor<'_> for Visitor {
             type Value = SyncedPath;
 
             fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
@@ -98,16 +135,7 @@ impl<'de> Deserialize<'de> for SyncedPath {
             where
                 E: serde::de::Error,
             {
-                let Some((prefix_id, path)) = v.split_once(':') else {
-                    return Err(serde::de::Error::custom("Missing ':' in SyncedPath"));
-                };
-
-                Ok(SyncedPath {
-                    prefix_id: prefix_id.parse().map_err(|_| {
-                        serde::de::Error::custom("Prefix mapping id was not a number")
-                    })?,
-                    path: path.into(),
-                })
+                v.parse().map_err(serde::de::Error::custom)
             }
         }
 
@@ -115,6 +143,27 @@ impl<'de> Deserialize<'de> for SyncedPath {
     }
 }
 
+#[derive(Debug, Snafu)]
+pub enum SyncedPathParseError {
+    #[snafu(display("Missing ':' in SyncedPath"))]
+    MissingSeparator,
+    #[snafu(display("Prefix mapping id was not a number"))]
+    InvalidPrefixId { source: std::num::ParseIntError },
+}
+
+impl FromStr for SyncedPath {
+    type Err = SyncedPathParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (prefix_id, path) = s.split_once(':').context(MissingSeparatorSnafu)?;
+
+        Ok(Self {
+            prefix_id: prefix_id.parse().context(InvalidPrefixIdSnafu)?,
+            path: path.into(),
+        })
+    }
+}
+
 impl std::fmt::Display for SyncedPath {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "/[ID-{}]/{}", self.prefix_id, self.path.display())
@@ -140,7 +189,7 @@ pub enum TagParseError {
 
 struct CharacterPrintHelper<'a>(&'a [(usize, char)]);
 
-impl<'a> std::fmt::Display for CharacterPrintHelper<'a> {
+impl std::fmt::Display for CharacterPrintHelper<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         let mut next = self.0.iter().peekable();
         while let Some((position, character)) = next.next() {
@@ -316,28 +365,68 @@ impl Tags {
     }
 }
 
+/// Lexically normalizes a user supplied path: `.` components as well as duplicate and
+/// trailing separators are dropped and `..` removes the preceding component.
+fn normalize_path(path: impl AsRef<Path>) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.as_ref().components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+fn deserialize_local_path<'de, D>(deserializer: D) -> Result<PathBuf, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(normalize_path(PathBuf::deserialize(deserializer)?))
+}
+
 fn deserialize_remote_path<'de, D>(deserializer: D) -> Result<PathBuf, D::Error>
 where
     D: serde::Deserializer<'de>,
     D::Error: serde::de::Error,
 {
-    let path = PathBuf::deserialize(deserializer)?;
-    if path.starts_with(PrefixMapping::EXPECTED_PREFIX) {
+    let path = normalize_path(PathBuf::deserialize(deserializer)?);
+    // The DAV root is configurable, so only the files collection is checked here.
+    // `validate_dav_root` ensures the prefix is below the configured root.
+    if path.has_root() && path.components().any(|c| c.as_os_str() == "files") {
         Ok(path)
     } else {
         Err(serde::de::Error::invalid_value(
             serde::de::Unexpected::Bytes(path.as_os_str().as_encoded_bytes()),
-            // Sadly, I would need an extra dependency to concat string constants at compile time
-            &"a string starting with /remote.php/dav/files/",
+            &"an absolute path into the DAV files collection, e.g. /remote.php/dav/files/",
         ))
     }
 }
 
 #[derive(Debug, Clone, Hash, PartialEq, Eq, Ord, PartialOrd, Serialize, Deserialize)]
 pub struct PrefixMapping {
+    #[serde(deserialize_with = "deserialize_local_path")]
     local: PathBuf,
     #[serde(deserialize_with = "deserialize_remote_path")]
     remote: PathBuf,
+    /// Owner of the files collection containing `remote`, e.g. of a folder shared by
+    /// another user. Taken from `remote` if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    user: Option<String>,
+    /// What to do with the tags of files whose counterpart on the other side is missing.
+    #[serde(default, skip_serializing_if = "CounterpartPolicy::is_wait")]
+    missing_counterpart: CounterpartPolicy,
+    /// Overrides [`crate::Config::keep_side_on_conflict`] for the files of this prefix.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    keep_side_on_conflict: Option<Side>,
 }
 
 impl PrefixMapping {
@@ -347,13 +436,104 @@ impl PrefixMapping {
     ///
     /// This function will return an error if `remote` does not start with /remote.php/dav/files/.
     pub fn new(local: PathBuf, remote: PathBuf) -> Result<Self, &'static str> {
-        if remote.starts_with(Self::EXPECTED_PREFIX) {
-            Ok(Self { local, remote })
+        Self::with_dav_root(local, remote, Self::DEFAULT_DAV_ROOT)
+    }
+
+    /// Constructs a new prefix mapping for a server with a non-default DAV root.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `remote` is not in the files collection of `dav_root`.
+    pub fn with_dav_root(
+        local: PathBuf,
+        remote: PathBuf,
+        dav_root: &str,
+    ) -> Result<Self, &'static str> {
+        let mapping = Self {
+            local: normalize_path(local),
+            remote: normalize_path(remote),
+            user: None,
+            missing_counterpart: CounterpartPolicy::Wait,
+            keep_side_on_conflict: None,
+        };
+        if mapping.is_below(dav_root) {
+            Ok(mapping)
         } else {
-            Err("Remote path must start with /remote.php/dav/files/")
+            Err("Remote path must start with the files collection of the DAV root")
+        }
+    }
+
+    /// Sets the owner of the remote folder.
+    #[must_use]
+    pub fn with_user(mut self, user: impl Into<String>) -> Self {
+        self.user = Some(user.into());
+        self
+    }
+
+    /// Whether the remote path is in the files collection of `dav_root`.
+    #[must_use]
+    pub fn is_below(&self, dav_root: &str) -> bool {
+        self.remote.starts_with(Path::new(dav_root).join("files"))
+    }
+
+    #[must_use]
+    pub fn user(&self) -> Option<&str> {
+        self.user.as_deref()
+    }
+
+    /// Sets what to do with the tags of files whose counterpart is missing.
+    #[must_use]
+    pub const fn with_missing_counterpart(mut self, policy: CounterpartPolicy) -> Self {
+        self.missing_counterpart = policy;
+        self
+    }
+
+    #[must_use]
+    pub const fn missing_counterpart(&self) -> CounterpartPolicy {
+        self.missing_counterpart
+    }
+
+    /// Sets the side whose tags are kept on conflict in this prefix.
+    #[must_use]
+    pub const fn with_keep_side_on_conflict(mut self, side: Side) -> Self {
+        self.keep_side_on_conflict = Some(side);
+        self
+    }
+
+    #[must_use]
+    pub const fn keep_side_on_conflict(&self) -> Option<Side> {
+        self.keep_side_on_conflict
+    }
+
+    /// Owner of the remote folder and the folder relative to the owner's files. Returns
+    /// `None` if the remote path is not in the files of the configured user.
+    #[must_use]
+    pub fn user_folder(&self, dav_root: &str) -> Option<(&str, &Path)> {
+        let files = Path::new(dav_root).join("files");
+        let mut rest = self.remote.strip_prefix(files).ok()?.components();
+        let owner = rest.next()?.as_os_str().to_str()?;
+        if self.user.as_deref().is_some_and(|user| user != owner) {
+            return None;
+        }
+        Some((owner, rest.as_path()))
+    }
+
+    /// Moves the remote path below `directory` unless it already is.
+    pub(crate) fn place_below(&mut self, directory: &Path) {
+        if !self.remote.starts_with(directory) {
+            let relative = self.remote.strip_prefix("/").unwrap_or(&self.remote);
+            self.remote = directory.join(relative);
         }
     }
 
+    /// Expands `~` and environment variables in the local path.
+    pub(crate) fn expand_local(&mut self) -> Result<(), String> {
+        self.local = normalize_path(crate::helper::expand_path(&self.local, |name| {
+            std::env::var(name).ok()
+        })?);
+        Ok(())
+    }
+
     #[must_use]
     pub fn local(&self) -> &Path {
         &self.local
@@ -364,13 +544,103 @@ impl PrefixMapping {
         &self.remote
     }
 
-    pub const EXPECTED_PREFIX: &str = "/remote.php/dav/files/";
+    const fn side(&self, location: FileLocation) -> &PathBuf {
+        match location {
+            FileLocation::Local => &self.local,
+            FileLocation::Remote => &self.remote,
+        }
+    }
+
+    /// Whether `self` is nested in `outer` on the given side and the other side is nested
+    /// at the same relative path. Files in such a prefix map to the same counterpart no
+    /// matter which of the two prefixes is used.
+    fn nests_consistently_in(&self, outer: &Self, location: FileLocation) -> Option<bool> {
+        let other = match location {
+            FileLocation::Local => FileLocation::Remote,
+            FileLocation::Remote => FileLocation::Local,
+        };
+        let relative = self
+            .side(location)
+            .strip_prefix(outer.side(location))
+            .ok()?;
+        Some(
+            !relative.as_os_str().is_empty()
+                && *self.side(other) == outer.side(other).join(relative),
+        )
+    }
+
+    pub const DEFAULT_DAV_ROOT: &str = "/remote.php/dav/";
 }
 
+/// Ensures that every local and remote file maps to at most one counterpart. Identical
+/// prefixes or prefixes nested on only one side are ambiguous.
+///
+/// # Errors
+///
+/// This function will return an error for the first pair of ambiguous prefixes.
+pub fn validate_prefixes(prefixes: &[PrefixMapping]) -> Result<(), AmbiguousPrefixError> {
+    for (i, first) in prefixes.iter().enumerate() {
+        for second in &prefixes[i + 1..] {
+            for location in [FileLocation::Local, FileLocation::Remote] {
+                let consistent = first
+                    .nests_consistently_in(second, location)
+                    .or_else(|| second.nests_consistently_in(first, location))
+                    .unwrap_or(true);
+                ensure!(
+                    consistent,
+                    AmbiguousPrefixSnafu {
+                        first: Box::new(first.clone()),
+                        second: Box::new(second.clone()),
+                    }
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Tags of the files in the synchronized prefixes, e.g. of the local files, of the
+/// Nextcloud instance or of the previous synchronization.
+///
+/// [`Repository::diff`] merges two repositories and yields the differences from which
+/// [`resolve_diffs`](crate::resolve_diffs) computes the commands for both sides:
+///
+/// ```
+/// use std::path::Path;
+///
+/// use nextcloud_tag_sync::{resolve_diffs, PrefixMapping, RemotePath, Repository, Side};
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let prefixes = vec![PrefixMapping::new(
+///     "/home/erik/Pictures".into(),
+///     "/remote.php/dav/files/erik/Pictures".into(),
+/// )?];
+/// let mut local = Repository::new(prefixes.clone());
+/// local.insert_local(Path::new("/home/erik/Pictures/cat.jpg"), "animal,cute".parse()?)?;
+/// let mut remote = Repository::new(prefixes);
+/// let path = RemotePath::from(Path::new("/remote.php/dav/files/erik/Pictures/cat.jpg"));
+/// let cat = remote.insert_remote(&path, "animal,black".parse()?)?;
+///
+/// let mut diff = local.clone().diff(remote.clone(), Side::Both);
+/// let (local_commands, remote_commands) = resolve_diffs(&mut diff);
+/// let merged = diff.finish();
+/// local_commands.into_iter().for_each(|command| local.apply(command));
+/// remote_commands.into_iter().for_each(|command| remote.apply(command));
+///
+/// assert_eq!(merged.tags(&cat).unwrap().to_string(), "animal,black,cute");
+/// assert_eq!(local.tags(&cat), merged.tags(&cat));
+/// assert_eq!(remote.tags(&cat), merged.tags(&cat));
+/// # Ok(())
+/// # }
+/// ```
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct Repository {
     prefixes: Vec<PrefixMapping>,
     files: BTreeMap<SyncedPath, Tags>,
+    /// Files whose counterpart is missing on the given side. Their entry holds the tags
+    /// of the existing side until the counterpart appears.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pending: BTreeMap<SyncedPath, FileLocation>,
 }
 
 impl Repository {
@@ -379,6 +649,7 @@ impl Repository {
         Self {
             prefixes,
             files: BTreeMap::new(),
+            pending: BTreeMap::new(),
         }
     }
 
@@ -393,42 +664,315 @@ impl Repository {
         self.prefixes == expected[..prefix_count]
     }
 
-    fn split_prefix<'a>(
+    /// Maps the repository onto a changed prefix configuration so it does not have to be
+    /// rebuilt from scratch. Prefixes are matched by identical mapping first, then by an
+    /// unchanged local or remote path, i.e. a renamed prefix. Entries of removed prefixes
+    /// are dropped.
+    #[must_use]
+    pub fn migrate_prefixes(self, prefixes: &[PrefixMapping]) -> Self {
+        if self.prefixes == prefixes {
+            return self;
+        }
+
+        let new_ids = self.match_prefixes(prefixes);
+        for (old, new_id) in self.prefixes.iter().zip(&new_ids) {
+            match new_id {
+                Some(i) if prefixes[*i] != *old => tracing::info!(
+                    "Migrating prefix {} -> {} to {} -> {}",
+                    old.local.display(),
+                    old.remote.display(),
+                    prefixes[*i].local.display(),
+                    prefixes[*i].remote.display()
+                ),
+                Some(_) => {}
+                None => tracing::info!(
+                    "Prefix {} -> {} was removed. Dropping its entries.",
+                    old.local.display(),
+                    old.remote.display()
+                ),
+            }
+        }
+
+        let migrate = |path: SyncedPath| {
+            Some(SyncedPath {
+                prefix_id: PrefixMappingId((*new_ids.get(path.prefix_id.0)?)?),
+                path: path.path,
+            })
+        };
+        let files = self
+            .files
+            .into_iter()
+            .filter_map(|(path, tags)| Some((migrate(path)?, tags)))
+            .collect();
+        let pending = self
+            .pending
+            .into_iter()
+            .filter_map(|(path, side)| Some((migrate(path)?, side)))
+            .collect();
+
+        Self {
+            prefixes: prefixes.to_vec(),
+            files,
+            pending,
+        }
+    }
+
+    /// Prefixes whose entries [`Self::migrate_prefixes`] would drop because they match
+    /// none of `prefixes`.
+    #[must_use]
+    pub fn unmatched_prefixes(&self, prefixes: &[PrefixMapping]) -> Vec<&PrefixMapping> {
+        self.prefixes
+            .iter()
+            .zip(self.match_prefixes(prefixes))
+            .filter_map(|(old, new_id)| new_id.is_none().then_some(old))
+            .collect()
+    }
+
+    /// Index into `prefixes` for each prefix of the repository.
+    fn match_prefixes(&self, prefixes: &[PrefixMapping]) -> Vec<Option<usize>> {
+        let mut new_ids: Vec<Option<usize>> = vec![None; self.prefixes.len()];
+        let mut used = vec![false; prefixes.len()];
+        let matchers: [fn(&PrefixMapping, &PrefixMapping) -> bool; 2] = [
+            |old, new| old == new,
+            |old, new| old.local == new.local || old.remote == new.remote,
+        ];
+        for matches in matchers {
+            for (old, new_id) in self.prefixes.iter().zip(&mut new_ids) {
+                if new_id.is_some() {
+                    continue;
+                }
+                *new_id = (0..prefixes.len()).find(|&i| !used[i] && matches(old, &prefixes[i]));
+                if let Some(i) = *new_id {
+                    used[i] = true;
+                }
+            }
+        }
+        new_ids
+    }
+
+    fn synced_path(
         &self,
-        file: &'a Path,
+        file: &Path,
         location: FileLocation,
-    ) -> (PrefixMappingId, &'a Path) {
+    ) -> Result<SyncedPath, MissingPrefixError> {
+        // Nested prefixes are allowed, so the most specific one wins.
         self.prefixes
             .iter()
             .enumerate()
-            .find_map(|(i, prefix_map)| {
-                let prefix = match location {
-                    FileLocation::Local => &prefix_map.local,
-                    FileLocation::Remote => &prefix_map.remote,
-                };
-                file.strip_prefix(prefix)
-                    .map(|suffix| (PrefixMappingId(i), suffix))
-                    .ok()
+            .filter_map(|(i, prefix_map)| {
+                let prefix = prefix_map.side(location);
+                let suffix = file.strip_prefix(prefix).ok()?;
+                Some((prefix.components().count(), i, suffix))
+            })
+            .max_by_key(|(depth, ..)| *depth)
+            .map(|(_, i, suffix)| SyncedPath {
+                prefix_id: PrefixMappingId(i),
+                path: suffix.to_owned(),
             })
-            .unwrap_or_else(|| panic!("missing prefix for {}", file.display()))
+            .context(MissingPrefixSnafu { path: file })
     }
 
-    pub fn insert_local(&mut self, path: &Path, tags: Tags) {
-        let path = SyncedPath::from_local(path, self);
+    /// Inserts a local file.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file is not inside any local prefix.
+    pub fn insert_local(&mut self, path: &Path, tags: Tags) -> Result<(), MissingPrefixError> {
+        let path = SyncedPath::from_local(path, self)?;
         self.insert(path, tags);
+        Ok(())
     }
 
-    pub fn insert_remote(&mut self, path: &Path, tags: Tags) -> SyncedPath {
-        let path = SyncedPath::from_remote(path, self);
+    /// Maps a file to its synced path.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file is not inside any prefix.
+    pub fn synced_path_of(
+        &self,
+        path: &Path,
+        location: FileLocation,
+    ) -> Result<SyncedPath, MissingPrefixError> {
+        self.synced_path(path, location)
+    }
+
+    /// Maps a remote file to its synced path.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file is not inside any remote prefix.
+    pub fn remote_path(&self, path: &RemotePath) -> Result<SyncedPath, MissingPrefixError> {
+        SyncedPath::from_remote(path, self)
+    }
+
+    /// Inserts a remote file.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file is not inside any remote prefix.
+    pub fn insert_remote(
+        &mut self,
+        path: &RemotePath,
+        tags: Tags,
+    ) -> Result<SyncedPath, MissingPrefixError> {
+        let path = SyncedPath::from_remote(path, self)?;
         self.insert(path.clone(), tags);
-        path
+        Ok(path)
     }
 
     pub fn insert(&mut self, path: SyncedPath, tags: Tags) {
         self.files.insert(path, tags);
     }
 
+    /// Renames entries whose path only differs in case from an entry of `reference` to
+    /// the spelling used in `reference`.
+    pub fn adopt_case_from(&mut self, reference: &Self) {
+        let key = |path: &SyncedPath| (path.prefix_id, path.path.to_string_lossy().to_lowercase());
+        let spellings: HashMap<_, _> = reference
+            .files
+            .keys()
+            .map(|path| (key(path), path))
+            .collect();
+
+        let mismatched: Vec<_> = self
+            .files
+            .keys()
+            .filter(|path| !reference.files.contains_key(*path))
+            .filter_map(|path| Some((path.clone(), spellings.get(&key(path))?)))
+            .collect();
+        for (path, spelling) in mismatched {
+            tracing::debug!("Matching {path} case-insensitively to {spelling}");
+            if let Some(tags) = self.files.remove(&path) {
+                self.files
+                    .entry((*spelling).clone())
+                    .or_default()
+                    .insert_all(&tags);
+            }
+        }
+    }
+
+    /// Replaces the entry of `path` with the one in `source` or removes it if `source`
+    /// has none.
+    pub fn copy_entry(&mut self, path: &SyncedPath, source: &Self) {
+        match source.files.get(path) {
+            Some(tags) => {
+                self.files.insert(path.clone(), tags.clone());
+            }
+            None => {
+                self.files.remove(path);
+            }
+        }
+    }
+
+    /// Marks `path` as pending pair whose counterpart on the `missing` side does not exist.
+    pub fn mark_pending(&mut self, path: SyncedPath, missing: FileLocation) {
+        self.pending.insert(path, missing);
+    }
+
+    /// Pending pairs whose counterpart is missing on the given side.
+    pub fn pending(&self, missing: FileLocation) -> impl Iterator<Item = &SyncedPath> {
+        self.pending
+            .iter()
+            .filter(move |(_, side)| **side == missing)
+            .map(|(path, _)| path)
+    }
+
+    /// Forgets the pending pair and its tags once the counterpart exists, so its tags are
+    /// copied like those of a new file.
+    pub fn release_pending(&mut self, path: &SyncedPath) {
+        if self.pending.remove(path).is_some() {
+            self.files.remove(path);
+        }
+    }
+
+    /// Undoes the actions of a command that could not be executed so they are computed
+    /// again in the next synchronization.
+    pub fn revert(&mut self, command: Command) {
+        let tags = self.files.entry(command.path).or_default();
+        for TagAction { tag, modification } in command.actions {
+            match modification {
+                Modification::Add => tags.remove_one(&tag),
+                Modification::Remove => tags.insert_one(tag),
+            }
+        }
+    }
+
+    /// Applies the actions of a command that was executed outside of a diff.
+    pub fn apply(&mut self, command: Command) {
+        let tags = self.files.entry(command.path).or_default();
+        for TagAction { tag, modification } in command.actions {
+            match modification {
+                Modification::Add => tags.insert_one(tag),
+                Modification::Remove => tags.remove_one(&tag),
+            }
+        }
+    }
+
+    /// Moves all files of `other` into this repository. Both must use the same prefixes.
+    pub(crate) fn append(&mut self, mut other: Self) {
+        self.files.append(&mut other.files);
+    }
+
+    /// Replaces the files of the prefix with those of `source`, e.g. to keep the previous
+    /// state of a prefix that could not be scanned.
+    pub fn restore_prefix(&mut self, prefix: PrefixMappingId, source: &Self) {
+        self.clear_prefix(prefix);
+        self.files.extend(
+            source
+                .files
+                .iter()
+                .filter(|(path, _)| path.prefix_id == prefix)
+                .map(|(path, tags)| (path.clone(), tags.clone())),
+        );
+    }
+
+    /// Drops all files of the prefix.
+    pub fn clear_prefix(&mut self, prefix: PrefixMappingId) {
+        self.files.retain(|path, _| path.prefix_id != prefix);
+    }
+
+    /// Tags of a file or `None` if the file is unknown.
+    #[must_use]
+    pub fn tags(&self, path: &SyncedPath) -> Option<&Tags> {
+        self.files.get(path)
+    }
+
+    #[must_use]
+    pub fn prefixes(&self) -> &[PrefixMapping] {
+        &self.prefixes
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = (&SyncedPath, &Tags)> {
+        self.files.iter()
+    }
+
+    pub fn into_files(self) -> impl Iterator<Item = (SyncedPath, Tags)> {
+        self.files.into_iter()
+    }
+
+    /// Only keeps the files for which `keep` returns `true`.
+    /// Returns the number of removed files.
+    pub fn retain<F>(&mut self, mut keep: F) -> usize
+    where
+        F: FnMut(&SyncedPath, &Tags) -> bool,
+    {
+        let before = self.files.len();
+        self.files.retain(|path, tags| keep(path, tags));
+        before - self.files.len()
+    }
+
     /// Computes the differences between self and other file tag repository.
+    /// The pending pairs of `self` are kept in the merged repository.
     ///
     /// # Panics
     ///
@@ -437,12 +981,14 @@ impl Repository {
     #[must_use]
     pub fn diff(self, other: Self, keep_side_on_conflict: Side) -> DiffIterator {
         assert_eq!(self.prefixes, other.prefixes);
-        DiffIterator::new(
+        let mut diff = DiffIterator::new(
             self.files.into_iter(),
             other.files.into_iter(),
             self.prefixes,
             keep_side_on_conflict,
-        )
+        );
+        diff.pending = self.pending;
+        diff
     }
 
     /// Store the repository on disk in json format.
@@ -450,11 +996,12 @@ impl Repository {
     /// # Errors
     ///
     /// This function will return an error if serialization or write process fails.
+    #[cfg(feature = "sync")]
     pub fn persist_on_disk(&self, path: &Path) -> Result<(), PersistingError> {
         tracing::info!("Persisting repository to disk at {}", path.display());
         let result = serde_json::to_string_pretty(self).context(SerializationSnafu)?;
         let mut file = AtomicWriteFile::open(path).with_context(|_| OpenSnafu { path })?;
-        file.write_all(result.as_ref())
+        std::io::Write::write_all(&mut file, result.as_ref())
             .with_context(|_| WriteSnafu { path })?;
         file.commit().with_context(|_| OpenSnafu { path })?;
         Ok(())
@@ -467,13 +1014,50 @@ impl Repository {
     /// This function will return an error if the read process or deserialization fails.
     pub fn read_from_disk(path: &Path) -> Result<Self, LoadError> {
         tracing::info!("Reading repository from disk at {}", path.display());
-        let data = std::fs::read_to_string(path).map_err(|e| match e.kind() {
-            std::io::ErrorKind::NotFound => NotFoundSnafu { path }.into_error(snafu::NoneError),
-            _ => IoSnafu { path }.into_error(e),
-        })?;
+        let data = read_file(path)?;
         let repo = serde_json::from_str(&data).with_context(|_| DeserializationSnafu { path })?;
         Ok(repo)
     }
+
+    /// Read the repository from disk without deduplication and check it for anomalies.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the read process fails or the file is not
+    /// structurally a repository.
+    pub fn check_integrity(path: &Path) -> Result<IntegrityReport, LoadError> {
+        tracing::info!("Checking repository at {}", path.display());
+        let data = read_file(path)?;
+        let raw: integrity::RawRepository =
+            serde_json::from_str(&data).with_context(|_| DeserializationSnafu { path })?;
+        Ok(raw.check())
+    }
+}
+
+fn read_file(path: &Path) -> Result<String, LoadError> {
+    std::fs::read_to_string(path).map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => NotFoundSnafu { path }.into_error(snafu::NoneError),
+        _ => IoSnafu { path }.into_error(e),
+    })
+}
+
+#[derive(Snafu, Debug)]
+#[snafu(display(
+    "prefix mappings {} -> {} and {} -> {} overlap ambiguously",
+    first.local.display(),
+    first.remote.display(),
+    second.local.display(),
+    second.remote.display()
+))]
+pub struct AmbiguousPrefixError {
+    pub first: Box<PrefixMapping>,
+    pub second: Box<PrefixMapping>,
+}
+
+#[derive(Snafu, Debug)]
+#[snafu(display("no prefix mapping matches {}", path.display()))]
+pub struct MissingPrefixError {
+    pub path: PathBuf,
 }
 
 #[derive(Snafu, Debug)]
@@ -492,6 +1076,7 @@ pub enum LoadError {
     NotFound { path: PathBuf },
 }
 
+#[cfg(feature = "sync")]
 #[derive(Snafu, Debug)]
 pub enum PersistingError {
     #[snafu(display("failed to serialize repository as json"))]
@@ -508,7 +1093,7 @@ pub enum PersistingError {
     },
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum FileLocation {
     Local,
     Remote,
@@ -521,6 +1106,11 @@ pub struct DiffIterator {
     prefixes: Vec<PrefixMapping>,
     files: BTreeMap<SyncedPath, Tags>,
     pub source_of_truth: Side,
+    pending: BTreeMap<SyncedPath, FileLocation>,
+    /// Source of truth of each prefix. Empty if all use [`Self::source_of_truth`].
+    prefix_sides: Vec<Side>,
+    /// Files whose left tags are preferred. Used by [`Side::Newest`] and [`Side::Origin`].
+    newer_left: BTreeSet<SyncedPath>,
 }
 
 impl Iterator for &mut DiffIterator {
@@ -571,30 +1161,64 @@ impl DiffIterator {
             prefixes,
             files: BTreeMap::new(),
             source_of_truth,
+            pending: BTreeMap::new(),
+            prefix_sides: Vec::new(),
+            newer_left: BTreeSet::new(),
         }
     }
 
+    /// Keeps the left tags of these files on conflict if the source of truth is
+    /// [`Side::Newest`] or [`Side::Origin`]. The right tags are kept for all other files.
+    #[must_use]
+    pub fn with_newer_left(mut self, newer_left: BTreeSet<SyncedPath>) -> Self {
+        self.newer_left = newer_left;
+        self
+    }
+
+    /// Resolves conflicts with [`PrefixMapping::keep_side_on_conflict`] where a prefix sets
+    /// it. Only meant for diffs between the two sides, not against the tag database.
+    #[must_use]
+    pub fn with_prefix_overrides(mut self) -> Self {
+        self.prefix_sides = self
+            .prefixes
+            .iter()
+            .map(|prefix| prefix.keep_side_on_conflict.unwrap_or(self.source_of_truth))
+            .collect();
+        self
+    }
+
     pub fn finish(mut self) -> Repository {
         // exhaust iterator if not already exhausted
         (&mut self).for_each(drop);
         Repository {
             prefixes: self.prefixes,
             files: self.files,
+            pending: self.pending,
         }
     }
 
-    fn diff_tags(&mut self, left: Tags, right: Tags, path: SyncedPath) -> (Tags, Tags) {
+    fn diff_tags(&mut self, left: Tags, right: Tags, path: SyncedPath) -> (Tags, Tags, Side) {
         let diff = left.diff(right);
         let mut result_tags = diff.identical;
 
-        match self.source_of_truth {
+        let source_of_truth = self
+            .prefix_sides
+            .get(path.prefix_id.0)
+            .copied()
+            .unwrap_or(self.source_of_truth);
+        let keep = match source_of_truth {
+            Side::Newest | Side::Origin if self.newer_left.contains(&path) => Side::Left,
+            Side::Newest | Side::Origin => Side::Right,
+            side => side,
+        };
+        match keep {
             Side::Left => {
                 result_tags.insert_all(&diff.left_only);
             }
             Side::Right => {
                 result_tags.insert_all(&diff.right_only);
             }
-            Side::Both => {
+            Side::Both | Side::Newest | Side::Origin => {
                 result_tags.insert_all(&diff.left_only);
                 result_tags.insert_all(&diff.right_only);
             }
@@ -602,7 +1226,7 @@ impl DiffIterator {
 
         self.files.insert(path, result_tags);
 
-        (diff.left_only, diff.right_only)
+        (diff.left_only, diff.right_only, keep)
     }
 
     fn advance(&mut self, side: Side) -> Option<DiffResult> {
@@ -615,16 +1239,17 @@ impl DiffIterator {
                 let (path, right) = self.right.next()?;
                 ((path.clone(), Tags::new()), (path, right))
             }
-            Side::Both => (self.left.next()?, self.right.next()?),
+            Side::Both | Side::Newest | Side::Origin => (self.left.next()?, self.right.next()?),
         };
 
-        let (left_only, right_only) = self.diff_tags(left_tags, right_tags, same_path);
+        let (left_only, right_only, keep) = self.diff_tags(left_tags, right_tags, same_path);
         let is_different = !left_only.is_empty() || !right_only.is_empty();
 
         is_different.then_some(DiffResult {
             path,
             left_only,
             right_only,
+            keep,
         })
     }
 }
@@ -634,13 +1259,46 @@ pub struct DiffResult {
     pub path: SyncedPath,
     pub left_only: Tags,
     pub right_only: Tags,
+    /// Side whose tags are kept for this file.
+    pub keep: Side,
 }
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum Side {
     Left,
     Right,
     Both,
+    /// The side which changed its tags last. Requires recorded local tag times.
+    Newest,
+    /// The side which created the tags of a file according to the recorded tag origins.
+    Origin,
+}
+
+/// What to do with the tags of a file whose counterpart on the other side does not exist,
+/// e.g. because the desktop client did not upload or download it yet.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum CounterpartPolicy {
+    /// Apply the tags anyway. This fails and is retried by every synchronization.
+    #[default]
+    Wait,
+    /// Skip the file without reporting a failure until its counterpart exists.
+    Drop,
+    /// Keep the tags as pending pair in the tag database and apply them as soon as the
+    /// counterpart exists.
+    MirrorWhenAppears,
+}
+
+impl CounterpartPolicy {
+    #[expect(
+        clippy::trivially_copy_pass_by_ref,
+        reason = "Signature required by serde"
+    )]
+    const fn is_wait(&self) -> bool {
+        matches!(self, Self::Wait)
+    }
 }
 
 #[cfg(test)]
@@ -659,10 +1317,16 @@ mod tests {
             PrefixMapping {
                 local: "/local/one".into(),
                 remote: "/remote/one".into(),
+                user: None,
+                missing_counterpart: CounterpartPolicy::Wait,
+                keep_side_on_conflict: None,
             },
             PrefixMapping {
                 local: "/local/two".into(),
                 remote: "/remote/two".into(),
+                user: None,
+                missing_counterpart: CounterpartPolicy::Wait,
+                keep_side_on_conflict: None,
             },
         ]
     }
@@ -730,6 +1394,220 @@ mod tests {
         }
     }
 
+    #[test]
+    fn restore_failed_prefix() {
+        let tags = |tags: &str| -> Tags { tags.parse().unwrap() };
+        let mut previous = Repository::new(mock_prefixes());
+        previous.insert(SyncedPath::new(0, "a.txt"), tags("red"));
+        previous.insert(SyncedPath::new(1, "b.txt"), tags("blue"));
+        let mut scanned = Repository::new(mock_prefixes());
+        scanned.insert(SyncedPath::new(0, "a.txt"), tags("green"));
+        scanned.insert(SyncedPath::new(1, "partial.txt"), tags("blue"));
+
+        scanned.restore_prefix(PrefixMappingId(1), &previous);
+        assert_eq!(
+            scanned.files().collect::<Vec<_>>(),
+            [
+                (&SyncedPath::new(0, "a.txt"), &tags("green")),
+                (&SyncedPath::new(1, "b.txt"), &tags("blue")),
+            ]
+        );
+        scanned.clear_prefix(PrefixMappingId(0));
+        assert_eq!(scanned.len(), 1);
+    }
+
+    #[test]
+    fn parents_of_synced_path() {
+        let path = SyncedPath::new(1, "a/b/c.txt");
+        let parents: Vec<_> = path.parents().collect();
+        assert_eq!(
+            parents,
+            [SyncedPath::new(1, "a"), SyncedPath::new(1, "a/b")]
+        );
+        assert!(parents.iter().all(|parent| path.starts_with(parent)));
+        assert!(!path.starts_with(&SyncedPath::new(0, "a")));
+    }
+
+    #[test]
+    fn migrate_prefixes() {
+        let [one, two] = <[_; 2]>::try_from(mock_prefixes()).unwrap();
+        let mut repo = Repository::new(vec![one.clone(), two.clone()]);
+        let tags: Tags = std::iter::once("red").collect();
+        repo.insert(SyncedPath::new(0, "a.txt"), tags.clone());
+        repo.insert(SyncedPath::new(1, "b.txt"), tags.clone());
+
+        let renamed = PrefixMapping {
+            local: "/local/renamed".into(),
+            ..one
+        };
+        let migrated = repo
+            .clone()
+            .migrate_prefixes(&[two.clone(), renamed.clone()]);
+        assert_eq!(migrated.prefixes(), [two.clone(), renamed]);
+        assert_eq!(
+            migrated.files().collect::<Vec<_>>(),
+            [
+                (&SyncedPath::new(0, "b.txt"), &tags),
+                (&SyncedPath::new(1, "a.txt"), &tags)
+            ]
+        );
+
+        let removed = repo.migrate_prefixes(&[two]);
+        assert_eq!(
+            removed.files().collect::<Vec<_>>(),
+            [(&SyncedPath::new(0, "b.txt"), &tags)]
+        );
+    }
+
+    #[test]
+    fn nested_prefixes() {
+        let outer = PrefixMapping {
+            local: "/local".into(),
+            remote: "/remote".into(),
+            user: None,
+            missing_counterpart: CounterpartPolicy::Wait,
+            keep_side_on_conflict: None,
+        };
+        let inner = PrefixMapping {
+            local: "/local/inner".into(),
+            remote: "/remote/inner".into(),
+            user: None,
+            missing_counterpart: CounterpartPolicy::Wait,
+            keep_side_on_conflict: None,
+        };
+        let elsewhere = PrefixMapping {
+            local: "/local/inner".into(),
+            remote: "/remote/elsewhere".into(),
+            user: None,
+            missing_counterpart: CounterpartPolicy::Wait,
+            keep_side_on_conflict: None,
+        };
+
+        let prefixes = vec![outer.clone(), inner];
+        assert!(validate_prefixes(&prefixes).is_ok());
+        let repo = Repository::new(prefixes);
+        assert_eq!(
+            repo.remote_path(&Path::new("/remote/inner/a.txt").into())
+                .unwrap(),
+            SyncedPath::new(1, "a.txt")
+        );
+
+        assert!(validate_prefixes(&[outer.clone(), elsewhere]).is_err());
+        assert!(validate_prefixes(&[outer.clone(), outer]).is_err());
+    }
+
+    #[test]
+    fn prefix_below_custom_dav_root() {
+        let remote = PathBuf::from("/index.php/dav/files/user/photos");
+        assert!(PrefixMapping::new("/photos".into(), remote.clone()).is_err());
+        let mapping =
+            PrefixMapping::with_dav_root("/photos".into(), remote, "/index.php/dav/").unwrap();
+        assert!(mapping.is_below("/index.php/dav"));
+        assert!(!mapping.is_below(PrefixMapping::DEFAULT_DAV_ROOT));
+    }
+
+    #[test]
+    fn normalize_prefixes() {
+        let mapping: PrefixMapping = serde_json::from_str(
+            r#"{ "local": "/home/user/./photos//", "remote": "/remote.php/dav//files/user/x/../photos/" }"#,
+        )
+        .unwrap();
+        assert_eq!(mapping.local().to_str(), Some("/home/user/photos"));
+        assert_eq!(
+            mapping.remote().to_str(),
+            Some("/remote.php/dav/files/user/photos")
+        );
+        assert!(serde_json::from_str::<PrefixMapping>(
+            r#"{ "local": "/photos", "remote": "/remote.php/dav/files/../other" }"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn shared_folder_owner() {
+        let shared =
+            PrefixMapping::new("/shared".into(), "/remote.php/dav/files/bob/Team".into()).unwrap();
+        let root = PrefixMapping::DEFAULT_DAV_ROOT;
+        assert_eq!(shared.user_folder(root), Some(("bob", Path::new("Team"))));
+        let shared = shared.with_user("bob");
+        assert_eq!(shared.user_folder(root), Some(("bob", Path::new("Team"))));
+        assert_eq!(shared.with_user("alice").user_folder(root), None);
+    }
+
+    #[test]
+    fn adopt_case() {
+        let tags: Tags = std::iter::once("red").collect();
+        let mut reference = Repository::new(mock_prefixes());
+        reference.insert(SyncedPath::new(0, "Photos/A.jpg"), tags.clone());
+        let mut remote = Repository::new(mock_prefixes());
+        remote.insert(SyncedPath::new(0, "photos/a.jpg"), tags.clone());
+        remote.insert(SyncedPath::new(1, "photos/a.jpg"), tags.clone());
+
+        remote.adopt_case_from(&reference);
+        assert_eq!(
+            remote.files().collect::<Vec<_>>(),
+            [
+                (&SyncedPath::new(0, "Photos/A.jpg"), &tags),
+                (&SyncedPath::new(1, "photos/a.jpg"), &tags)
+            ]
+        );
+    }
+
+    #[test]
+    fn file_outside_prefixes() {
+        let mut repo = Repository::new(mock_prefixes());
+        let tags: Tags = std::iter::once("red").collect();
+        assert!(repo
+            .insert_local(Path::new("/local/one/a.txt"), tags.clone())
+            .is_ok());
+        assert!(repo
+            .insert_local(Path::new("/local/three/a.txt"), tags)
+            .is_err());
+        assert_eq!(repo.len(), 1);
+    }
+
+    #[test]
+    fn revert_failed_command() {
+        let path = SyncedPath::new(0, "a.txt");
+        let mut repo = Repository::new(mock_prefixes());
+        repo.insert(path.clone(), ["red", "green"].into_iter().collect());
+
+        repo.revert(Command {
+            path: path.clone(),
+            actions: vec![
+                TagAction {
+                    tag: "red".parse().unwrap(),
+                    modification: Modification::Add,
+                },
+                TagAction {
+                    tag: "blue".parse().unwrap(),
+                    modification: Modification::Remove,
+                },
+            ],
+        });
+
+        let tags: Tags = ["blue", "green"].into_iter().collect();
+        assert_eq!(repo.files().collect::<Vec<_>>(), [(&path, &tags)]);
+    }
+
+    #[test]
+    fn keep_side_per_prefix() {
+        let mut prefixes = mock_prefixes();
+        prefixes[1] = prefixes[1].clone().with_keep_side_on_conflict(Side::Left);
+        let mut local = Repository::new(prefixes.clone());
+        let mut remote = Repository::new(prefixes);
+        for prefix_id in [0, 1] {
+            local.insert(SyncedPath::new(prefix_id, "a.txt"), "red".parse().unwrap());
+            remote.insert(SyncedPath::new(prefix_id, "a.txt"), "blue".parse().unwrap());
+        }
+
+        let diff = local.diff(remote, Side::Right).with_prefix_overrides();
+        let merged = diff.finish();
+        let tags = |prefix_id| merged.tags(&SyncedPath::new(prefix_id, "a.txt")).unwrap();
+        assert_eq!(tags(0).to_string(), "blue");
+        assert_eq!(tags(1).to_string(), "red");
+    }
+
     #[test]
     fn compute_new_repo_with_both() {
         compute_new_repo(Side::Both);
@@ -745,23 +1623,39 @@ mod tests {
         compute_new_repo(Side::Right);
     }
 
+    #[test]
+    fn compute_new_repo_with_newest() {
+        compute_new_repo(Side::Newest);
+    }
+
     fn compute_new_repo(keep_action: Side) {
         let prefixes = mock_prefixes();
         let files = mock_files();
+        // Only the first file was retagged locally after the last synchronization.
+        let newer_left: BTreeSet<_> = files.iter().take(1).map(|f| f.0.clone()).collect();
 
         let local_repo = make_repo(prefixes.clone(), &files, false);
         let remote_repo = make_repo(prefixes.clone(), &files, true);
 
-        let diffs = local_repo.diff(remote_repo, keep_action);
+        let diffs = local_repo
+            .diff(remote_repo, keep_action)
+            .with_newer_left(newer_left.clone());
         let new_repo = diffs.finish();
         println!("{new_repo:?}");
         assert_eq!(new_repo.prefixes, prefixes);
         for (actual, expected) in std::iter::zip(new_repo.files, files) {
             let (path, combined, local, remote) = expected;
-            let tags = match keep_action {
+            let keep = match keep_action {
+                Side::Newest | Side::Origin if newer_left.contains(&path) => Side::Left,
+                Side::Newest | Side::Origin => Side::Right,
+                side => side,
+            };
+            let tags = match keep {
                 Side::Left => combined.into_iter().chain(local).collect(),
                 Side::Right => combined.into_iter().chain(remote).collect(),
-                Side::Both => combined.into_iter().chain(local).chain(remote).collect(),
+                Side::Both | Side::Newest | Side::Origin => {
+                    combined.into_iter().chain(local).chain(remote).collect()
+                }
             };
 
             assert_eq!(actual.1, tags, "Failed for file {}", path.path.display());
@@ -0,0 +1,134 @@
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Component, Path, PathBuf};
+
+use super::{PrefixMapping, Repository, SyncedPath, Tag, Tags};
+
+/// Components of the DAV root that carry no private information.
+const PUBLIC_COMPONENTS: [&str; 3] = ["remote.php", "dav", "files"];
+
+struct Anonymizer {
+    salt: u64,
+}
+
+impl Anonymizer {
+    fn pseudonym(&self, name: &OsStr) -> String {
+        let mut hasher = DefaultHasher::new();
+        self.salt.hash(&mut hasher);
+        name.hash(&mut hasher);
+        format!("{:016x}", hasher.finish())
+    }
+
+    /// Keeps the extension so issues with certain file types remain visible.
+    fn component(&self, name: &OsStr) -> String {
+        if PUBLIC_COMPONENTS.iter().any(|public| name == *public) {
+            return name.to_string_lossy().into_owned();
+        }
+        let path = Path::new(name);
+        match (path.file_stem(), path.extension()) {
+            (Some(stem), Some(extension)) => {
+                format!("{}.{}", self.pseudonym(stem), extension.to_string_lossy())
+            }
+            _ => self.pseudonym(name),
+        }
+    }
+
+    fn path(&self, path: &Path) -> PathBuf {
+        path.components()
+            .map(|component| match component {
+                Component::Normal(name) => PathBuf::from(self.component(name)),
+                other => PathBuf::from(other.as_os_str()),
+            })
+            .collect()
+    }
+
+    fn tags(&self, tags: &Tags) -> Tags {
+        Tags(
+            tags.iter()
+                .map(|tag| Tag(self.pseudonym(OsStr::new(&**tag))))
+                .collect(),
+        )
+    }
+}
+
+impl Repository {
+    /// Replaces all file names, folder names and tags by salted hashes so the repository
+    /// can be shared without revealing them. Equal names get equal hashes, so the structure
+    /// of the repository is preserved.
+    #[must_use]
+    pub fn anonymized(&self, salt: u64) -> Self {
+        let anonymizer = Anonymizer { salt };
+        let prefixes = self
+            .prefixes
+            .iter()
+            .map(|prefix| PrefixMapping {
+                local: anonymizer.path(&prefix.local),
+                remote: anonymizer.path(&prefix.remote),
+                user: prefix
+                    .user
+                    .as_deref()
+                    .map(|user| anonymizer.component(OsStr::new(user))),
+                missing_counterpart: prefix.missing_counterpart,
+                keep_side_on_conflict: prefix.keep_side_on_conflict,
+            })
+            .collect();
+        let anonymize = |path: &SyncedPath| SyncedPath {
+            prefix_id: path.prefix_id,
+            path: anonymizer.path(&path.path),
+        };
+        let files: BTreeMap<_, _> = self
+            .files
+            .iter()
+            .map(|(path, tags)| (anonymize(path), anonymizer.tags(tags)))
+            .collect();
+        let pending = self
+            .pending
+            .iter()
+            .map(|(path, side)| (anonymize(path), *side))
+            .collect();
+        Self {
+            prefixes,
+            files,
+            pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hide_names_keep_structure() {
+        let prefixes =
+            vec![
+                PrefixMapping::new("/home/alice".into(), "/remote.php/dav/files/alice".into())
+                    .unwrap(),
+            ];
+        let mut repo = Repository::new(prefixes);
+        for file in [
+            "/home/alice/secret/plan.pdf",
+            "/home/alice/secret/notes.txt",
+        ] {
+            repo.insert_local(Path::new(file), "private".parse().unwrap())
+                .unwrap();
+        }
+
+        let anonymized = repo.anonymized(42);
+        let json = serde_json::to_string(&anonymized).unwrap();
+        for name in ["alice", "secret", "plan", "notes", "private"] {
+            assert!(!json.contains(name), "{name} leaked: {json}");
+        }
+        assert!(json.contains("/remote.php/dav/files/"));
+        assert!(json.contains(".pdf"));
+
+        let files: Vec<_> = anonymized.files().collect();
+        assert_eq!(files.len(), 2);
+        assert_eq!(
+            files[0].0.relative().parent(),
+            files[1].0.relative().parent()
+        );
+        assert_eq!(files[0].1, files[1].1);
+    }
+}
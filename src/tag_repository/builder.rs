@@ -0,0 +1,143 @@
+use std::collections::btree_map::Entry;
+use std::path::{Path, PathBuf};
+
+use super::{
+    validate_prefixes, AmbiguousPrefixError, FileLocation, MissingPrefixError, PrefixMapping,
+    Repository, SyncedPath, Tags,
+};
+
+/// Builds a [`Repository`] from `(path, tags)` pairs of an arbitrary source, e.g. a
+/// database export, so it can be diffed against a repository scanned by this crate.
+///
+/// Paths are interpreted as local or remote paths depending on the location the builder
+/// was created for. Tags of duplicate paths are merged.
+#[derive(Debug)]
+pub struct RepositoryBuilder {
+    repo: Repository,
+    location: FileLocation,
+    skipped: Vec<PathBuf>,
+}
+
+impl RepositoryBuilder {
+    /// Creates a builder for paths of the given location.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the prefixes are ambiguous.
+    pub fn new(
+        prefixes: Vec<PrefixMapping>,
+        location: FileLocation,
+    ) -> Result<Self, AmbiguousPrefixError> {
+        validate_prefixes(&prefixes)?;
+        Ok(Self {
+            repo: Repository::new(prefixes),
+            location,
+            skipped: Vec::new(),
+        })
+    }
+
+    /// Adds a file given by its absolute path.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file is not inside any prefix.
+    pub fn add(&mut self, path: impl AsRef<Path>, tags: Tags) -> Result<(), MissingPrefixError> {
+        let path = self.repo.synced_path(path.as_ref(), self.location)?;
+        self.merge(path, tags);
+        Ok(())
+    }
+
+    /// Adds a file given by its path relative to the prefix with index `prefix`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if there is no prefix with this index.
+    pub fn add_relative(
+        &mut self,
+        prefix: usize,
+        path: impl AsRef<Path>,
+        tags: Tags,
+    ) -> Result<(), MissingPrefixError> {
+        let path = path.as_ref();
+        let Some(prefix_map) = self.repo.prefixes.get(prefix) else {
+            return Err(MissingPrefixError {
+                path: path.to_owned(),
+            });
+        };
+        let path = self
+            .repo
+            .synced_path(&prefix_map.side(self.location).join(path), self.location)?;
+        self.merge(path, tags);
+        Ok(())
+    }
+
+    fn merge(&mut self, path: SyncedPath, tags: Tags) {
+        match self.repo.files.entry(path) {
+            Entry::Vacant(entry) => {
+                entry.insert(tags);
+            }
+            Entry::Occupied(mut entry) => entry.get_mut().insert_all(&tags),
+        }
+    }
+
+    /// Paths passed via [`Extend`] which were skipped because they are outside all prefixes.
+    #[must_use]
+    pub fn skipped(&self) -> &[PathBuf] {
+        &self.skipped
+    }
+
+    #[must_use]
+    pub fn build(self) -> Repository {
+        self.repo
+    }
+}
+
+impl<P: AsRef<Path>> Extend<(P, Tags)> for RepositoryBuilder {
+    /// Adds all files, skipping those outside all prefixes.
+    fn extend<T: IntoIterator<Item = (P, Tags)>>(&mut self, iter: T) {
+        for (path, tags) in iter {
+            if let Err(MissingPrefixError { path }) = self.add(path, tags) {
+                tracing::debug!("Skipping {} outside of all prefixes", path.display());
+                self.skipped.push(path);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_from_pairs() {
+        let prefixes = vec![
+            PrefixMapping::new("/local".into(), "/remote.php/dav/files/user".into()).unwrap(),
+            PrefixMapping::new(
+                "/local/nested".into(),
+                "/remote.php/dav/files/user/nested".into(),
+            )
+            .unwrap(),
+        ];
+        let tags = |tags: &str| -> Tags { tags.parse().unwrap() };
+        let mut builder = RepositoryBuilder::new(prefixes, FileLocation::Local).unwrap();
+        builder.extend([
+            ("/local/a.txt", tags("red")),
+            ("/local/nested/b.txt", tags("blue")),
+            ("/elsewhere/c.txt", tags("green")),
+            ("/local/a.txt", tags("blue")),
+        ]);
+        builder.add_relative(1, "c.txt", tags("green")).unwrap();
+        assert!(builder.add_relative(2, "d.txt", tags("red")).is_err());
+
+        assert_eq!(builder.skipped(), [PathBuf::from("/elsewhere/c.txt")]);
+        let repo = builder.build();
+        assert_eq!(
+            repo.files().collect::<Vec<_>>(),
+            [
+                (&SyncedPath::new(0, "a.txt"), &tags("blue,red")),
+                (&SyncedPath::new(1, "b.txt"), &tags("blue")),
+                (&SyncedPath::new(1, "c.txt"), &tags("green")),
+            ]
+        );
+    }
+}
@@ -0,0 +1,312 @@
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::i18n::tr;
+
+use super::{PrefixMapping, Repository, SyncedPath, SyncedPathParseError, Tag, Tags};
+
+/// An inconsistency found in a persisted repository.
+#[derive(Debug)]
+pub enum Anomaly {
+    /// The key could not be parsed as a [`SyncedPath`]. Repaired by dropping the entry.
+    UnparsableKey {
+        key: String,
+        source: SyncedPathParseError,
+    },
+    /// The prefix id does not refer to a prefix mapping of the repository.
+    /// Repaired by dropping the entry.
+    UnknownPrefix { path: SyncedPath },
+    /// The path is absolute, empty or leaves its prefix via `..`.
+    /// Repaired by dropping the entry.
+    InvalidPath { path: SyncedPath },
+    /// The path contains `.` components or is percent-encoded while only the decoded
+    /// file exists locally. Repaired by moving the tags to the normalized path.
+    NonNormalizedPath {
+        path: SyncedPath,
+        normalized: PathBuf,
+    },
+    /// Multiple keys refer to the same file. Repaired by merging their tags.
+    DuplicateKey {
+        path: SyncedPath,
+        occurrences: usize,
+    },
+    /// The tag name is not allowed. Repaired by dropping the tag.
+    InvalidTag { path: SyncedPath, tag: String },
+}
+
+impl std::fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::UnparsableKey { key, source } => write!(f, "unparsable key '{key}': {source}"),
+            Self::UnknownPrefix { path } => write!(f, "{path} refers to an unknown prefix"),
+            Self::InvalidPath { path } => write!(f, "{path} is not a valid relative path"),
+            Self::NonNormalizedPath { path, normalized } => write!(
+                f,
+                "{path} is not normalized, expected {}",
+                normalized.display()
+            ),
+            Self::DuplicateKey { path, occurrences } => {
+                write!(f, "{path} occurs {occurrences} times")
+            }
+            Self::InvalidTag { path, tag } => write!(f, "{path} has invalid tag '{tag}'"),
+        }
+    }
+}
+
+/// Result of checking a persisted repository for anomalies.
+#[derive(Debug)]
+pub struct IntegrityReport {
+    pub anomalies: Vec<Anomaly>,
+    /// The repository with all anomalies repaired.
+    pub repaired: Repository,
+}
+
+impl IntegrityReport {
+    #[must_use]
+    pub const fn is_clean(&self) -> bool {
+        self.anomalies.is_empty()
+    }
+}
+
+impl std::fmt::Display for IntegrityReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.is_clean() {
+            return writeln!(f, "{}", tr!("integrity-clean"));
+        }
+
+        let count = self.anomalies.len();
+        writeln!(f, "{}", tr!("integrity-anomalies", count = count))?;
+        for anomaly in &self.anomalies {
+            writeln!(f, "- {anomaly}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Repository as it is stored on disk without any validation or deduplication.
+#[derive(Deserialize)]
+pub(super) struct RawRepository {
+    prefixes: Vec<PrefixMapping>,
+    files: RawEntries,
+}
+
+struct RawEntries(Vec<(String, Vec<String>)>);
+
+impl<'de> Deserialize<'de> for RawEntries {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct Visitor;
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = RawEntries;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a map from synced paths to lists of tags")
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::MapAccess<'de>,
+            {
+                let mut entries = Vec::with_capacity(map.size_hint().unwrap_or_default());
+                while let Some(entry) = map.next_entry()? {
+                    entries.push(entry);
+                }
+                Ok(RawEntries(entries))
+            }
+        }
+
+        deserializer.deserialize_map(Visitor)
+    }
+}
+
+impl RawRepository {
+    pub(super) fn check(self) -> IntegrityReport {
+        let mut anomalies = Vec::new();
+        let mut files: BTreeMap<SyncedPath, (usize, Tags)> = BTreeMap::new();
+
+        for (key, raw_tags) in self.files.0 {
+            let path: SyncedPath = match key.parse() {
+                Ok(path) => path,
+                Err(source) => {
+                    anomalies.push(Anomaly::UnparsableKey { key, source });
+                    continue;
+                }
+            };
+
+            if path.prefix_id.0 >= self.prefixes.len() {
+                anomalies.push(Anomaly::UnknownPrefix { path });
+                continue;
+            }
+
+            let local = self.prefixes[path.prefix_id.0].local();
+            let Some(normalized) = normalize(&path.path, local) else {
+                anomalies.push(Anomaly::InvalidPath { path });
+                continue;
+            };
+
+            // `Path` comparison ignores redundant separators, so compare the raw strings.
+            let path = if normalized.as_os_str() == path.path.as_os_str() {
+                path
+            } else {
+                let normalized_path = SyncedPath {
+                    prefix_id: path.prefix_id,
+                    path: normalized.clone(),
+                };
+                anomalies.push(Anomaly::NonNormalizedPath { path, normalized });
+                normalized_path
+            };
+
+            let mut tags = Tags::new();
+            for tag in raw_tags {
+                match tag.parse::<Tag>() {
+                    Ok(tag) => tags.insert_one(tag),
+                    Err(_) => anomalies.push(Anomaly::InvalidTag {
+                        path: path.clone(),
+                        tag,
+                    }),
+                }
+            }
+
+            let (occurrences, merged) = files.entry(path).or_default();
+            *occurrences += 1;
+            merged.insert_all(&tags);
+        }
+
+        let mut repaired = Repository::new(self.prefixes);
+        for (path, (occurrences, tags)) in files {
+            if occurrences > 1 {
+                anomalies.push(Anomaly::DuplicateKey {
+                    path: path.clone(),
+                    occurrences,
+                });
+            }
+            repaired.insert(path, tags);
+        }
+
+        IntegrityReport {
+            anomalies,
+            repaired,
+        }
+    }
+}
+
+/// Normalizes a path relative to its prefix. Returns `None` if the path cannot be
+/// represented relative to the prefix.
+///
+/// `%` is valid in file names, so the path is only percent-decoded if it does not exist
+/// below `local` but the decoded one does.
+fn normalize(path: &Path, local: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(c) => normalized.push(c),
+            Component::CurDir => {}
+            Component::Prefix(_) | Component::RootDir | Component::ParentDir => return None,
+        }
+    }
+    if normalized.as_os_str().is_empty() {
+        return None;
+    }
+
+    if !local.join(&normalized).exists() {
+        if let Some(decoded) = percent_decode(&normalized) {
+            if local.join(&decoded).exists() {
+                return Some(decoded);
+            }
+        }
+    }
+    Some(normalized)
+}
+
+fn percent_decode(path: &Path) -> Option<PathBuf> {
+    let decoded = percent_encoding::percent_decode_str(path.to_str()?)
+        .decode_utf8()
+        .ok()?;
+    Some(PathBuf::from(decoded.as_ref()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(local: &Path, files: &str) -> IntegrityReport {
+        let json = format!(
+            r#"{{
+                "prefixes": [{{ "local": "{}", "remote": "/remote.php/dav/files/user/remote" }}],
+                "files": {files}
+            }}"#,
+            local.display()
+        );
+        serde_json::from_str::<RawRepository>(&json)
+            .unwrap()
+            .check()
+    }
+
+    #[test]
+    fn clean_repository() {
+        let report = check(
+            Path::new("/local"),
+            r#"{ "0:a/b.txt": ["red"], "0:c.txt": ["blue", "green"] }"#,
+        );
+        assert!(report.is_clean(), "{report}");
+        assert_eq!(report.repaired.len(), 2);
+    }
+
+    #[test]
+    fn repair_anomalies() {
+        let local = tempfile::tempdir().unwrap();
+        std::fs::write(local.path().join("c d.txt"), b"").unwrap();
+        let report = check(
+            local.path(),
+            r#"{
+                "0:a/b.txt": ["red"],
+                "0:a//b.txt": ["blue"],
+                "0:./c%20d.txt": ["green", "in/valid"],
+                "1:unknown.txt": ["red"],
+                "0:../escape.txt": ["red"],
+                "no-separator": ["red"]
+            }"#,
+        );
+
+        assert_eq!(report.anomalies.len(), 7, "{report}");
+        let files: Vec<_> = report.repaired.files().collect();
+        assert_eq!(
+            files,
+            [
+                (
+                    &SyncedPath::new(0, "a/b.txt"),
+                    &["blue", "red"].into_iter().collect()
+                ),
+                (
+                    &SyncedPath::new(0, "c d.txt"),
+                    &std::iter::once("green").collect()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn keep_percent_in_existing_file_names() {
+        let local = tempfile::tempdir().unwrap();
+        std::fs::write(local.path().join("c%20d.txt"), b"").unwrap();
+        let report = check(
+            local.path(),
+            r#"{ "0:c%20d.txt": ["green"], "0:missing%20file.txt": ["red"] }"#,
+        );
+
+        assert!(report.is_clean(), "{report}");
+        let files: Vec<_> = report.repaired.files().map(|(path, _)| path).collect();
+        assert_eq!(
+            files,
+            [
+                &SyncedPath::new(0, "c%20d.txt"),
+                &SyncedPath::new(0, "missing%20file.txt")
+            ]
+        );
+    }
+}
@@ -0,0 +1,454 @@
+//! Compact binary encoding of a [`Repository`] for fast startup. The JSON database stays
+//! the canonical format; the snapshot is only used while it is at least as new.
+//!
+//! Layout (integers are little-endian `u32`, byte strings are length-prefixed):
+//! magic, version, prefix count, prefixes (local, remote, user or empty, counterpart
+//! policy, side kept on conflict or 0), file count, files (prefix id, path, tag count, tags), pending count, pending
+//! pairs (prefix id, path, missing side), index (`u64` offset of each file), `u64` offset
+//! of the index. Files are sorted by path, so the index allows binary search.
+
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use atomic_write_file::AtomicWriteFile;
+use snafu::{ensure, ResultExt, Snafu};
+
+use super::{
+    CounterpartPolicy, FileLocation, OpenSnafu, PersistingError, PrefixMapping, PrefixMappingId,
+    Repository, Side, SyncedPath, Tag, TagParseError, Tags, WriteSnafu,
+};
+
+const MAGIC: &[u8; 8] = b"NCTSSNAP";
+const VERSION: u32 = 5;
+
+/// The snapshot is stored next to the tag database.
+fn snapshot_path(database: &Path) -> PathBuf {
+    database.with_extension("snapshot")
+}
+
+#[derive(Debug, Snafu)]
+pub enum SnapshotError {
+    #[snafu(display("failed to read snapshot {}", path.display()))]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("failed to read snapshot"))]
+    Io { source: std::io::Error },
+    #[snafu(display("not a repository snapshot"))]
+    Magic,
+    #[snafu(display("unsupported snapshot version {version}"))]
+    Version { version: u32 },
+    #[snafu(display("snapshot is truncated"))]
+    Truncated,
+    #[snafu(display("snapshot contains an invalid tag"))]
+    InvalidTag { source: TagParseError },
+    #[snafu(display("snapshot contains a non UTF-8 tag"))]
+    NonUtf8Tag { source: std::str::Utf8Error },
+    #[snafu(display("snapshot contains a non UTF-8 user"))]
+    NonUtf8User { source: std::str::Utf8Error },
+    #[snafu(display("snapshot refers to unknown prefix {prefix_id}"))]
+    UnknownPrefix { prefix_id: usize },
+    #[snafu(display("snapshot contains an invalid value {value}"))]
+    InvalidValue { value: u32 },
+}
+
+impl Repository {
+    /// Stores the repository as binary snapshot next to the JSON database at `database`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if writing the file fails.
+    pub fn persist_snapshot(&self, database: &Path) -> Result<(), PersistingError> {
+        let path = &snapshot_path(database);
+        tracing::info!("Persisting repository snapshot at {}", path.display());
+        let mut file = AtomicWriteFile::open(path).context(OpenSnafu { path })?;
+        file.write_all(&self.encode())
+            .context(WriteSnafu { path })?;
+        file.commit().context(OpenSnafu { path })
+    }
+
+    /// Reads the binary snapshot belonging to the JSON database at `database`. Returns
+    /// `None` if there is no snapshot or it is older than the database.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the snapshot cannot be read or decoded.
+    pub fn read_snapshot(database: &Path) -> Result<Option<Self>, SnapshotError> {
+        let Some(reader) = SnapshotReader::open(database)? else {
+            return Ok(None);
+        };
+        reader.into_repository().map(Some)
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        put_u32(&mut out, VERSION);
+        put_len(&mut out, self.prefixes.len());
+        for prefix in &self.prefixes {
+            put_bytes(&mut out, prefix.local.as_os_str().as_bytes());
+            put_bytes(&mut out, prefix.remote.as_os_str().as_bytes());
+            put_bytes(
+                &mut out,
+                prefix.user.as_deref().unwrap_or_default().as_bytes(),
+            );
+            put_u32(
+                &mut out,
+                match prefix.missing_counterpart {
+                    CounterpartPolicy::Wait => 0,
+                    CounterpartPolicy::Drop => 1,
+                    CounterpartPolicy::MirrorWhenAppears => 2,
+                },
+            );
+            put_u32(
+                &mut out,
+                match prefix.keep_side_on_conflict {
+                    None => 0,
+                    Some(Side::Left) => 1,
+                    Some(Side::Right) => 2,
+                    Some(Side::Both) => 3,
+                    Some(Side::Newest) => 4,
+                    Some(Side::Origin) => 5,
+                },
+            );
+        }
+        put_len(&mut out, self.files.len());
+        let mut index = Vec::with_capacity(self.files.len());
+        for (path, tags) in &self.files {
+            index.push(out.len());
+            put_len(&mut out, path.prefix_id.0);
+            put_bytes(&mut out, path.path.as_os_str().as_bytes());
+            put_len(&mut out, tags.len());
+            for tag in tags.iter() {
+                put_bytes(&mut out, tag.as_bytes());
+            }
+        }
+        put_len(&mut out, self.pending.len());
+        for (path, side) in &self.pending {
+            put_len(&mut out, path.prefix_id.0);
+            put_bytes(&mut out, path.path.as_os_str().as_bytes());
+            put_u32(
+                &mut out,
+                match side {
+                    FileLocation::Local => 0,
+                    FileLocation::Remote => 1,
+                },
+            );
+        }
+        let index_offset = out.len();
+        for offset in index {
+            put_u64(&mut out, offset);
+        }
+        put_u64(&mut out, index_offset);
+        out
+    }
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(out: &mut Vec<u8>, value: usize) {
+    out.extend_from_slice(&(value as u64).to_le_bytes());
+}
+
+fn put_len(out: &mut Vec<u8>, len: usize) {
+    put_u32(
+        out,
+        u32::try_from(len).expect("snapshot entries exceed u32"),
+    );
+}
+
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    put_len(out, bytes.len());
+    out.extend_from_slice(bytes);
+}
+
+/// Streams the entries of a snapshot without loading the whole repository into memory.
+pub struct SnapshotReader<R = BufReader<File>> {
+    reader: Reader<R>,
+    prefixes: Vec<PrefixMapping>,
+    files: usize,
+    remaining: usize,
+}
+
+impl SnapshotReader {
+    /// Opens the binary snapshot belonging to the JSON database at `database`. Returns
+    /// `None` if there is no snapshot or it is older than the database.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the snapshot cannot be opened or its header
+    /// is invalid.
+    pub fn open(database: &Path) -> Result<Option<Self>, SnapshotError> {
+        let path = &snapshot_path(database);
+        let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        let (Some(snapshot), Some(json)) = (modified(path), modified(database)) else {
+            return Ok(None);
+        };
+        if snapshot < json {
+            tracing::info!("Ignoring snapshot {} older than database", path.display());
+            return Ok(None);
+        }
+
+        tracing::info!("Reading repository snapshot at {}", path.display());
+        Self::open_file(path).map(Some)
+    }
+
+    /// Opens the snapshot file at `path` regardless of its name or age.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file cannot be opened or is not a
+    /// snapshot.
+    pub fn open_file(path: &Path) -> Result<Self, SnapshotError> {
+        let file = File::open(path).context(ReadSnafu { path })?;
+        Self::new(BufReader::new(file))
+    }
+}
+
+impl<R: Read> SnapshotReader<R> {
+    fn new(inner: R) -> Result<Self, SnapshotError> {
+        let mut reader = Reader {
+            inner,
+            buf: Vec::new(),
+        };
+        ensure!(reader.bytes_exact(MAGIC.len())? == MAGIC, MagicSnafu);
+        let version = reader.u32()?;
+        ensure!(version == VERSION, VersionSnafu { version });
+
+        let prefixes = (0..reader.len()?)
+            .map(|_| {
+                Ok(PrefixMapping {
+                    local: reader.path()?,
+                    remote: reader.path()?,
+                    user: reader.user()?,
+                    missing_counterpart: match reader.u32()? {
+                        0 => CounterpartPolicy::Wait,
+                        1 => CounterpartPolicy::Drop,
+                        2 => CounterpartPolicy::MirrorWhenAppears,
+                        value => return InvalidValueSnafu { value }.fail(),
+                    },
+                    keep_side_on_conflict: match reader.u32()? {
+                        0 => None,
+                        1 => Some(Side::Left),
+                        2 => Some(Side::Right),
+                        3 => Some(Side::Both),
+                        4 => Some(Side::Newest),
+                        5 => Some(Side::Origin),
+                        value => return InvalidValueSnafu { value }.fail(),
+                    },
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let files = reader.len()?;
+        Ok(Self {
+            reader,
+            prefixes,
+            files,
+            remaining: files,
+        })
+    }
+
+    #[must_use]
+    pub fn prefixes(&self) -> &[PrefixMapping] {
+        &self.prefixes
+    }
+
+    fn path(&mut self) -> Result<SyncedPath, SnapshotError> {
+        let prefix_id = self.reader.len()?;
+        ensure!(
+            prefix_id < self.prefixes.len(),
+            UnknownPrefixSnafu { prefix_id }
+        );
+        Ok(SyncedPath {
+            prefix_id: PrefixMappingId(prefix_id),
+            path: self.reader.path()?,
+        })
+    }
+
+    fn entry(&mut self) -> Result<(SyncedPath, Tags), SnapshotError> {
+        let path = self.path()?;
+        let mut tags = Tags::new();
+        for _ in 0..self.reader.len()? {
+            let tag = std::str::from_utf8(self.reader.bytes()?).context(NonUtf8TagSnafu)?;
+            tags.insert_one(tag.parse::<Tag>().context(InvalidTagSnafu)?);
+        }
+        Ok((path, tags))
+    }
+
+    /// Decodes all remaining entries.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if an entry cannot be decoded.
+    pub fn into_repository(mut self) -> Result<Repository, SnapshotError> {
+        let mut repo = Repository::new(self.prefixes.clone());
+        for entry in &mut self {
+            let (path, tags) = entry?;
+            repo.files.insert(path, tags);
+        }
+        for _ in 0..self.reader.len()? {
+            let path = self.path()?;
+            let side = match self.reader.u32()? {
+                0 => FileLocation::Local,
+                1 => FileLocation::Remote,
+                value => return InvalidValueSnafu { value }.fail(),
+            };
+            repo.pending.insert(path, side);
+        }
+        Ok(repo)
+    }
+}
+
+impl<R: Read + Seek> SnapshotReader<R> {
+    /// Looks up the tags of a single file by binary search over the index instead of
+    /// decoding all entries. Returns `None` if the file is not in the snapshot.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the index or an entry cannot be decoded.
+    pub fn tags_of(mut self, target: &SyncedPath) -> Result<Option<Tags>, SnapshotError> {
+        self.reader.seek(SeekFrom::End(-8))?;
+        let index = self.reader.u64()?;
+        let (mut low, mut high) = (0, self.files);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            self.reader.seek(SeekFrom::Start(index + 8 * mid as u64))?;
+            let offset = self.reader.u64()?;
+            self.reader.seek(SeekFrom::Start(offset))?;
+            let (path, tags) = self.entry()?;
+            match path.cmp(target) {
+                Ordering::Less => low = mid + 1,
+                Ordering::Greater => high = mid,
+                Ordering::Equal => return Ok(Some(tags)),
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<R: Read> Iterator for SnapshotReader<R> {
+    type Item = Result<(SyncedPath, Tags), SnapshotError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let entry = self.entry();
+        if entry.is_err() {
+            self.remaining = 0;
+        }
+        Some(entry)
+    }
+}
+
+struct Reader<R> {
+    inner: R,
+    buf: Vec<u8>,
+}
+
+impl<R: Read> Reader<R> {
+    fn bytes_exact(&mut self, n: usize) -> Result<&[u8], SnapshotError> {
+        self.buf.resize(n, 0);
+        self.inner
+            .read_exact(&mut self.buf)
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::UnexpectedEof => SnapshotError::Truncated,
+                _ => SnapshotError::Io { source: e },
+            })?;
+        Ok(&self.buf)
+    }
+
+    fn u32(&mut self) -> Result<u32, SnapshotError> {
+        let bytes = self.bytes_exact(4)?.try_into().expect("read 4 bytes");
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn u64(&mut self) -> Result<u64, SnapshotError> {
+        let bytes = self.bytes_exact(8)?.try_into().expect("read 8 bytes");
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn len(&mut self) -> Result<usize, SnapshotError> {
+        self.u32().map(|len| len as usize)
+    }
+
+    fn bytes(&mut self) -> Result<&[u8], SnapshotError> {
+        let len = self.len()?;
+        self.bytes_exact(len)
+    }
+
+    fn path(&mut self) -> Result<PathBuf, SnapshotError> {
+        Ok(PathBuf::from(std::ffi::OsStr::from_bytes(self.bytes()?)))
+    }
+
+    fn user(&mut self) -> Result<Option<String>, SnapshotError> {
+        let user = std::str::from_utf8(self.bytes()?).context(NonUtf8UserSnafu)?;
+        Ok(Some(user.to_owned()).filter(|user| !user.is_empty()))
+    }
+}
+
+impl<R: Seek> Reader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, SnapshotError> {
+        self.inner.seek(pos).context(IoSnafu)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let prefixes =
+            vec![PrefixMapping::new("/local".into(), "/remote.php/dav/files/user".into()).unwrap()];
+        let mut repo = Repository::new(prefixes);
+        repo.insert(SyncedPath::new(0, "a/b.txt"), "red,blue".parse().unwrap());
+        repo.insert(SyncedPath::new(0, "c.txt"), Tags::new());
+        repo.mark_pending(SyncedPath::new(0, "c.txt"), FileLocation::Remote);
+
+        let encoded = repo.encode();
+        let decoded = SnapshotReader::new(&encoded[..])
+            .unwrap()
+            .into_repository()
+            .unwrap();
+        assert_eq!(decoded.prefixes, repo.prefixes);
+        assert_eq!(decoded.files, repo.files);
+        assert_eq!(decoded.pending, repo.pending);
+
+        let index_len = 8 * (repo.files.len() + 1);
+        let truncated = SnapshotReader::new(&encoded[..encoded.len() - index_len - 1])
+            .unwrap()
+            .into_repository();
+        assert!(matches!(truncated, Err(SnapshotError::Truncated)));
+    }
+
+    #[test]
+    fn look_up_single_file() {
+        let prefixes =
+            vec![PrefixMapping::new("/local".into(), "/remote.php/dav/files/user".into()).unwrap()];
+        let mut repo = Repository::new(prefixes);
+        for (file, tags) in [("a.txt", "red"), ("b/c.txt", "blue"), ("d.txt", "")] {
+            repo.insert(SyncedPath::new(0, file), tags.parse().unwrap());
+        }
+        let encoded = repo.encode();
+        let tags_of = |file: &str| {
+            SnapshotReader::new(std::io::Cursor::new(&encoded))
+                .unwrap()
+                .tags_of(&SyncedPath::new(0, file))
+                .unwrap()
+        };
+
+        for (path, tags) in &repo.files {
+            assert_eq!(tags_of(path.path.to_str().unwrap()).as_ref(), Some(tags));
+        }
+        assert_eq!(tags_of("b.txt"), None);
+        assert_eq!(tags_of("e.txt"), None);
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::BTreeSet;
+
+use crate::i18n::tr;
+
+use super::{PrefixMapping, Repository, Tag};
+
+/// Counts of files and tags in a [`Repository`] or one of its prefixes.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TagStats {
+    pub files: usize,
+    /// Files with at least one tag.
+    pub tagged_files: usize,
+    /// Number of tag assignments over all files.
+    pub tags: usize,
+    pub distinct_tags: usize,
+}
+
+impl std::fmt::Display for TagStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let message = tr!(
+            "stats-counts",
+            files = self.files,
+            tagged = self.tagged_files,
+            tags = self.tags,
+            distinct = self.distinct_tags,
+        );
+        f.write_str(&message)
+    }
+}
+
+/// [`TagStats`] of the whole repository and broken down per prefix mapping.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RepositoryStats {
+    pub total: TagStats,
+    pub per_prefix: Vec<(PrefixMapping, TagStats)>,
+}
+
+impl std::fmt::Display for RepositoryStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "{}", tr!("stats-total", stats = self.total.to_string()))?;
+        for (prefix, stats) in &self.per_prefix {
+            writeln!(
+                f,
+                "{} -> {}: {stats}",
+                prefix.local().display(),
+                prefix.remote().display()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Counter<'a> {
+    stats: TagStats,
+    distinct: BTreeSet<&'a Tag>,
+}
+
+impl<'a> Counter<'a> {
+    fn add(&mut self, tags: &'a super::Tags) {
+        self.stats.files += 1;
+        self.stats.tagged_files += usize::from(!tags.is_empty());
+        self.stats.tags += tags.len();
+        self.distinct.extend(tags.iter());
+    }
+
+    fn finish(mut self) -> TagStats {
+        self.stats.distinct_tags = self.distinct.len();
+        self.stats
+    }
+}
+
+impl Repository {
+    #[must_use]
+    pub fn stats(&self) -> RepositoryStats {
+        let mut total = Counter::default();
+        let mut per_prefix: Vec<Counter> =
+            self.prefixes.iter().map(|_| Counter::default()).collect();
+        for (path, tags) in &self.files {
+            total.add(tags);
+            if let Some(counter) = per_prefix.get_mut(path.prefix_id.0) {
+                counter.add(tags);
+            }
+        }
+
+        RepositoryStats {
+            total: total.finish(),
+            per_prefix: self
+                .prefixes
+                .iter()
+                .cloned()
+                .zip(per_prefix.into_iter().map(Counter::finish))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SyncedPath;
+
+    #[test]
+    fn stats_per_prefix() {
+        let prefixes = vec![
+            PrefixMapping::new("/photos".into(), "/remote.php/dav/files/user/photos".into())
+                .unwrap(),
+            PrefixMapping::new("/docs".into(), "/remote.php/dav/files/user/docs".into()).unwrap(),
+        ];
+        let mut repo = Repository::new(prefixes);
+        repo.insert(SyncedPath::new(0, "a.jpg"), "red,blue".parse().unwrap());
+        repo.insert(SyncedPath::new(0, "b.jpg"), "red".parse().unwrap());
+        repo.insert(SyncedPath::new(1, "c.txt"), "".parse().unwrap());
+
+        let stats = repo.stats();
+        let expected = |files, tagged_files, tags, distinct_tags| TagStats {
+            files,
+            tagged_files,
+            tags,
+            distinct_tags,
+        };
+        assert_eq!(stats.total, expected(3, 2, 3, 2));
+        assert_eq!(stats.per_prefix[0].1, expected(2, 2, 3, 2));
+        assert_eq!(stats.per_prefix[1].1, expected(1, 0, 0, 0));
+    }
+}
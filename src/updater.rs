@@ -1,73 +1,301 @@
-use std::sync::Arc;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
 
-use snafu::Snafu;
+use checkpoint::{apply_in_chunks, start_checkpoint};
+use hooks::Hooks;
+use lock::SyncLock;
+use loops::LoopDetector;
+use origins::TagOrigins;
+use session::{session_path, Session};
+use snafu::{ensure, ResultExt, Snafu};
+use watchdog::Watchdog;
 
 use crate::{
-    resolve_diffs,
+    backup::{backup_removed_tags, BackupError},
+    preview::{pending_changes, PendingChanges},
+    resolve_diffs, split_pinned,
     tag_repository::{LoadError, PersistingError, Side},
-    CommandsFormatter, Config, FileSystem, ListTagsError, LocalError, LocalFs, RemoteFs,
-    Repository,
+    Command, CommandOutcome, CommandsFormatter, Config, Connection, DeserializeError, FileLocation,
+    FileSystem, FormatOptions, ListTagsError, LocalError, LocalFs, PrefixMapping, RemoteFs,
+    Repository, RequestError, ScanIssue, ScanIssues, SyncReport, SyncedPath, Tags,
 };
 
+pub use hooks::HookError;
+pub use loops::release_looping_files;
+pub use origins::{tag_origins, TagOrigin};
+pub use orphans::{OrphanPolicy, ORPHAN_TAG};
+pub use session::SessionError;
+pub use watchdog::Phase;
+
+mod checkpoint;
+mod conflicts;
+mod constraints;
+mod counterparts;
+#[cfg(test)]
+mod end_to_end;
+mod fault;
+mod hooks;
+mod lock;
+mod loops;
+mod origins;
+mod orphans;
+mod session;
+mod skip;
+mod watchdog;
+
+/// Repository and issues of scanning one side.
+type Scan = (Repository, ScanIssues);
+
 pub struct Uninitialized {
     pub config: Arc<Config>,
     pub remote_fs: RemoteFs,
     pub local_fs: LocalFs,
+    /// No further commands are applied after this point in time.
+    deadline: Option<Instant>,
+    watchdog: Arc<Watchdog>,
+    loops: LoopDetector,
+    origins: TagOrigins,
+    hooks: Hooks,
+    /// Taken before the session journal or the tag database are touched.
+    lock: Option<SyncLock>,
 }
 
 impl Uninitialized {
     #[must_use]
     pub fn new(config: Arc<Config>) -> Self {
+        let deadline = config
+            .max_duration_secs
+            .map(|secs| Instant::now() + Duration::from_secs(secs));
+        let watchdog = Arc::new(Watchdog::new(&config));
+        let connection = Connection::from_config(&config).with_middleware(watchdog.clone());
         Self {
-            remote_fs: RemoteFs::new(config.clone()),
+            remote_fs: RemoteFs::with_connection(connection, config.clone()),
             local_fs: LocalFs::new(config.clone()),
+            loops: LoopDetector::load(&config),
+            origins: TagOrigins::load(&config),
             config,
+            deadline,
+            watchdog,
+            hooks: Hooks::default(),
+            lock: None,
         }
     }
 
-    async fn create_from_local_remote_diff(mut self) -> Result<Initialized, InitError> {
-        let remote_repo_task = self.remote_fs.create_repo();
-        let local_repo_task = self.local_fs.create_repo();
+    /// Sends all requests to `backend` instead of the configured Nextcloud instance.
+    #[cfg(test)]
+    fn with_backend(mut self, backend: Arc<crate::Simulator>) -> Self {
+        let connection = Connection::from_config(&self.config)
+            .with_middleware(self.watchdog.clone())
+            .with_backend(backend);
+        self.remote_fs = RemoteFs::with_connection(connection, self.config.clone());
+        self
+    }
+
+    /// Makes sure no other synchronization uses the same tag database.
+    fn lock(&mut self) -> Result<(), InitError> {
+        if self.lock.is_none() {
+            self.lock = Some(SyncLock::acquire(&self.config)?);
+        }
+        Ok(())
+    }
 
-        let (local, remote) = merge_results(futures::join!(local_repo_task, remote_repo_task))?;
+    /// Scans both sides concurrently.
+    async fn scan(&mut self) -> Result<(Scan, Scan), InitError> {
+        let local = self.watchdog.watch(
+            Phase::Scan(FileLocation::Local),
+            self.local_fs.create_repo(),
+        );
+        let remote = self.watchdog.watch(
+            Phase::Scan(FileLocation::Remote),
+            self.remote_fs.create_repo(),
+        );
+        let (local, remote) = futures::join!(local, remote);
+        merge_results((local.and_then(|scan| scan), remote.and_then(|scan| scan)))
+    }
+
+    async fn create_from_local_remote_diff(mut self) -> Result<Initialized, InitError> {
+        let ((mut local, mut issues), (mut remote, remote_issues)) = self.scan().await?;
+        issues.extend(remote_issues);
+        // Without a previous state, failed prefixes are left out until a later run.
+        let empty = Repository::new(self.config.prefixes.clone());
+        isolate_failed_prefixes(&mut local, &issues, &empty);
+        isolate_failed_prefixes(&mut remote, &issues, &empty);
+        if self.config.case_insensitive_remote {
+            remote.adopt_case_from(&local);
+        }
 
-        let mut diff_events = local.diff(remote, self.config.keep_side_on_conflict);
-        let (local_actions, remote_actions) =
-            resolve_diffs(&mut diff_events, self.config.keep_side_on_conflict);
+        let mut diff_events = local
+            .diff(remote, self.config.keep_side_on_conflict)
+            .with_prefix_overrides()
+            .with_newer_left(preferred_local(&self.config, &self.local_fs, &self.origins));
+        let (local_actions, remote_actions) = resolve_diffs(&mut diff_events);
 
-        let cmd_fmt = CommandsFormatter(&local_actions);
+        let cmd_fmt = CommandsFormatter(&local_actions, FormatOptions::from_config(&self.config));
         tracing::debug!("Local actions: {cmd_fmt}");
-        let cmd_fmt = CommandsFormatter(&remote_actions);
+        let cmd_fmt = CommandsFormatter(&remote_actions, FormatOptions::from_config(&self.config));
         tracing::debug!("Remote actions: {cmd_fmt}");
 
-        self.remote_fs.update_tags(remote_actions).await;
-        self.local_fs.update_tags(local_actions).await;
+        let files: BTreeSet<_> = local_actions
+            .iter()
+            .chain(&remote_actions)
+            .map(|cmd| &cmd.path)
+            .collect();
+        let files = files.len();
+        if !self.config.confirm_initial_sync
+            && self
+                .config
+                .initial_sync_confirmation_threshold
+                .is_some_and(|threshold| files > threshold)
+        {
+            return ConfirmationRequiredSnafu {
+                files,
+                changes: PendingChanges {
+                    local: local_actions,
+                    remote: remote_actions,
+                },
+            }
+            .fail();
+        }
+
+        let mut repo = diff_events.finish();
+        let local_actions = keep_pinned(&self.config, &mut repo, local_actions);
+        let skipped = self.remote_fs.find_skipped().await?;
+        let local_actions = skip::hold(&mut repo, local_actions, &skipped);
+        let remote_actions = skip::hold(&mut repo, remote_actions, &skipped);
+        let [local_actions, remote_actions] = self
+            .hold_missing(&mut repo, [local_actions, remote_actions])
+            .await;
+        let violations = constraints::violations(
+            &self.config.exclusive_tags,
+            &repo,
+            local_actions.iter().chain(&remote_actions),
+        );
+        let local_actions = constraints::hold(&mut repo, local_actions, &violations);
+        let remote_actions = constraints::hold(&mut repo, remote_actions, &violations);
+        let [local_actions, remote_actions] = loops::hold_loops(
+            &mut self.loops,
+            &self.config,
+            &mut repo,
+            [local_actions, remote_actions],
+        );
+        let local_actions = self
+            .hooks
+            .apply(&mut repo, FileLocation::Local, local_actions);
+        let remote_actions = self
+            .hooks
+            .apply(&mut repo, FileLocation::Remote, remote_actions);
+        let mut initialized = self
+            .apply(repo, &local_actions, &remote_actions, Vec::new())
+            .await?;
+        initialized.scan_issues = issues;
+        initialized.constraint_violations = violations;
+        if initialized.config.comment_conflicts {
+            let comments =
+                conflicts::comments(&initialized.local_outcomes, &initialized.remote_outcomes);
+            initialized.remote_fs.comment(comments).await;
+        }
+        Ok(initialized)
+    }
+
+    /// Applies the commands to both sides. `repo` is the repository after all commands
+    /// succeeded and `failed` are commands which failed in an interrupted earlier run.
+    async fn apply(
+        mut self,
+        mut repo: Repository,
+        local_actions: &[Command],
+        remote_actions: &[Command],
+        failed: Vec<Command>,
+    ) -> Result<Initialized, InitError> {
+        backup_removed_tags(&self.config, &repo, local_actions, remote_actions)
+            .context(BackupSnafu)?;
+        let mut checkpoint = start_checkpoint(
+            &self.config,
+            &repo,
+            local_actions,
+            remote_actions,
+            failed.clone(),
+        );
+        let remote_outcomes = apply_in_chunks(
+            &mut self.remote_fs,
+            FileLocation::Remote,
+            remote_actions,
+            &mut checkpoint,
+            &self.config,
+            self.deadline,
+            &self.watchdog,
+        )
+        .await?;
+        ensure!(!self.remote_fs.in_maintenance(), MaintenanceSnafu);
+        let local_outcomes = apply_in_chunks(
+            &mut self.local_fs,
+            FileLocation::Local,
+            local_actions,
+            &mut checkpoint,
+            &self.config,
+            self.deadline,
+            &self.watchdog,
+        )
+        .await?;
+        drop(checkpoint);
+        Session::discard(&self.config);
+        self.origins
+            .record_now(FileLocation::Remote, &remote_outcomes);
+        self.origins
+            .record_now(FileLocation::Local, &local_outcomes);
+
+        for cmd in failed {
+            repo.revert(cmd);
+        }
+        revert_failed(&mut repo, &remote_outcomes);
+        revert_failed(&mut repo, &local_outcomes);
 
         Ok(Initialized {
-            repo: diff_events.finish(),
+            repo,
+            local_outcomes,
+            remote_outcomes,
+            scan_issues: ScanIssues::default(),
+            orphaned_files: BTreeSet::new(),
+            constraint_violations: BTreeMap::new(),
             remote_fs: self.remote_fs,
             local_fs: self.local_fs,
             config: self.config,
+            deadline: self.deadline,
+            watchdog: self.watchdog,
+            loops: self.loops,
+            origins: self.origins,
+            hooks: self.hooks,
+            _lock: self.lock,
         })
     }
 
     #[expect(clippy::result_large_err, reason = "Only called once at startup")]
     fn load_from_file(self) -> Result<Initialized, Self> {
-        match Repository::read_from_disk(&self.config.tag_database) {
-            Ok(repo) if repo.validate_prefix_mapping(&self.config.prefixes) => Ok(Initialized {
-                repo,
+        match read_repository(&self.config) {
+            Ok(repo) => Ok(Initialized {
+                repo: repo.migrate_prefixes(&self.config.prefixes),
+                local_outcomes: Vec::new(),
+                remote_outcomes: Vec::new(),
+                scan_issues: ScanIssues::default(),
+                orphaned_files: BTreeSet::new(),
+                constraint_violations: BTreeMap::new(),
                 local_fs: self.local_fs,
                 remote_fs: self.remote_fs,
                 config: self.config,
+                deadline: self.deadline,
+                watchdog: self.watchdog,
+                loops: self.loops,
+                origins: self.origins,
+                hooks: self.hooks,
+                _lock: self.lock,
             }),
             Err(LoadError::NotFound { .. }) => {
                 tracing::info!("No previous repository exists yet. Starting from scratch.");
                 Err(self)
             }
-            Ok(_) => {
-                tracing::error!("Repository created for incompatible prefix mapping configuration. Ignoring it.");
-                Err(self)
-            }
             Err(e) => {
                 tracing::error!("Failed to load repository file: {e:?}");
                 Err(self)
@@ -75,14 +303,141 @@ impl Uninitialized {
         }
     }
 
+    /// Holds back the commands of files whose counterpart is missing according to the
+    /// policy of their prefix.
+    async fn hold_missing(
+        &self,
+        repo: &mut Repository,
+        [local_actions, remote_actions]: [Vec<Command>; 2],
+    ) -> [Vec<Command>; 2] {
+        let prefixes = &self.config.prefixes;
+        let missing_locally = counterparts::missing(
+            &self.remote_fs,
+            prefixes,
+            FileLocation::Local,
+            counterparts::candidates(prefixes, &local_actions),
+        );
+        let missing_remotely = counterparts::missing(
+            &self.remote_fs,
+            prefixes,
+            FileLocation::Remote,
+            counterparts::candidates(prefixes, &remote_actions),
+        );
+        let (missing_locally, missing_remotely) = futures::join!(missing_locally, missing_remotely);
+        [
+            counterparts::hold(repo, FileLocation::Local, local_actions, &missing_locally),
+            counterparts::hold(
+                repo,
+                FileLocation::Remote,
+                remote_actions,
+                &missing_remotely,
+            ),
+        ]
+    }
+
+    /// Computes the changes a synchronization would apply without applying them.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if computing the local or remote file tag
+    /// repository fails.
+    pub async fn status(mut self) -> Result<PendingChanges, InitError> {
+        let cached = read_repository(&self.config)
+            .ok()
+            .map(|repo| repo.migrate_prefixes(&self.config.prefixes));
+        let ((mut local, local_issues), (mut remote, remote_issues)) = self.scan().await?;
+        ensure!(!self.remote_fs.in_maintenance(), MaintenanceSnafu);
+
+        if let Some(cached) = &cached {
+            isolate_failed_prefixes(&mut local, &local_issues, cached);
+            isolate_failed_prefixes(&mut remote, &remote_issues, cached);
+            if self.config.case_insensitive_remote {
+                remote.adopt_case_from(cached);
+            }
+            counterparts::restore_pending(&mut local, cached, FileLocation::Local);
+            counterparts::restore_pending(&mut remote, cached, FileLocation::Remote);
+        } else {
+            let empty = Repository::new(self.config.prefixes.clone());
+            let mut issues = local_issues;
+            issues.extend(remote_issues);
+            isolate_failed_prefixes(&mut local, &issues, &empty);
+            isolate_failed_prefixes(&mut remote, &issues, &empty);
+            if self.config.case_insensitive_remote {
+                remote.adopt_case_from(&local);
+            }
+        }
+        let mut changes = pending_changes(
+            local,
+            remote,
+            cached,
+            self.config.keep_side_on_conflict,
+            preferred_local(&self.config, &self.local_fs, &self.origins),
+        );
+        (changes.local, _) = split_pinned(changes.local, &self.config.pinned_local_tags);
+        let skipped = self.remote_fs.find_skipped().await?;
+        changes
+            .local
+            .retain(|cmd| !skip::is_skipped(&skipped, &cmd.path));
+        changes
+            .remote
+            .retain(|cmd| !skip::is_skipped(&skipped, &cmd.path));
+        let hooks = Hooks::load(&self.config).context(HookSnafu)?;
+        let mut scratch = Repository::new(self.config.prefixes.clone());
+        changes.local = hooks.apply(&mut scratch, FileLocation::Local, changes.local);
+        changes.remote = hooks.apply(&mut scratch, FileLocation::Remote, changes.remote);
+        Ok(changes)
+    }
+
+    /// Continues an interrupted synchronization by applying its remaining commands without
+    /// scanning either side. Returns `None` if there is no unfinished session.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if another synchronization is running, the
+    /// session journal cannot be read or the server is in maintenance mode.
+    pub async fn resume(mut self) -> Result<Option<Initialized>, InitError> {
+        self.lock()?;
+        let Some(session) = Session::load(&self.config).context(SessionSnafu)? else {
+            return Ok(None);
+        };
+        let Session {
+            target,
+            local,
+            remote,
+            failed,
+        } = session;
+        tracing::info!(
+            "Resuming session with {} local and {} remote commands",
+            local.len(),
+            remote.len()
+        );
+        let target = target.migrate_prefixes(&self.config.prefixes);
+        self.apply(target, &local, &remote, failed).await.map(Some)
+    }
+
+    /// Removes the journal of an unfinished synchronization. Returns whether there was one.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if another synchronization is running.
+    pub fn discard_session(&mut self) -> Result<bool, InitError> {
+        self.lock()?;
+        Ok(Session::discard(&self.config))
+    }
+
     /// Initialize a file tag repository by loading it from a cache file.
     /// If loading from file fails, e.g. because no cache exists yet, a new
     /// one is built from scratch.
     ///
     /// # Errors
     ///
-    /// This function will return an error if initialization fails.
-    pub async fn initialize(self) -> Result<Initialized, InitError> {
+    /// This function will return an error if initialization fails, another
+    /// synchronization is running or an interrupted one was not resumed yet.
+    pub async fn initialize(mut self) -> Result<Initialized, InitError> {
+        self.lock()?;
+        let journal = session_path(&self.config);
+        ensure!(!journal.exists(), UnfinishedSessionSnafu { journal });
+        self.hooks = Hooks::load(&self.config).context(HookSnafu)?;
         match self.load_from_file() {
             Ok(o) => Ok(o),
             Err(this) => this.create_from_local_remote_diff().await,
@@ -94,8 +449,22 @@ impl Uninitialized {
 pub struct Initialized {
     config: Arc<Config>,
     repo: Repository,
+    local_outcomes: Vec<CommandOutcome>,
+    remote_outcomes: Vec<CommandOutcome>,
+    scan_issues: ScanIssues,
+    /// Local files whose remote counterpart was deleted.
+    orphaned_files: BTreeSet<SyncedPath>,
+    /// Files not synchronized because they carry mutually exclusive tags.
+    constraint_violations: BTreeMap<SyncedPath, Tags>,
     remote_fs: RemoteFs,
     local_fs: LocalFs,
+    deadline: Option<Instant>,
+    watchdog: Arc<Watchdog>,
+    loops: LoopDetector,
+    origins: TagOrigins,
+    hooks: Hooks,
+    /// Released when the synchronization is dropped.
+    _lock: Option<SyncLock>,
 }
 
 impl Initialized {
@@ -104,23 +473,142 @@ impl Initialized {
         &self.repo
     }
 
+    /// Summary of everything noteworthy that happened during synchronization so far.
+    #[must_use]
+    pub fn report(&self) -> SyncReport {
+        SyncReport {
+            encrypted_folders: self.remote_fs.encrypted_folders.clone(),
+            read_only_shares: self.remote_fs.read_only_shares.clone(),
+            forbidden_tags: self.remote_fs.forbidden_tags(),
+            rejected_tags: self.remote_fs.rejected_tags.clone(),
+            quarantined: self.local_fs.quarantined.clone(),
+            stale_files: stale_files(&self.config, &self.local_fs),
+            local_outcomes: self.local_outcomes.clone(),
+            remote_outcomes: self.remote_outcomes.clone(),
+            scan_issues: self.scan_issues.clone(),
+            orphaned_files: self.orphaned_files.clone(),
+            constraint_violations: self.constraint_violations.clone(),
+            looping_files: self.loops.looping().clone(),
+            stats: self.repo.stats(),
+            format: FormatOptions::from_config(&self.config),
+        }
+    }
+
+    /// Synchronizes tags in both directions and summarizes the run.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if computing the local or remote file tag
+    /// repository fails.
+    pub async fn sync(&mut self) -> Result<SyncReport, InitError> {
+        self.sync_local_to_remote().await?;
+        self.sync_remote_to_local().await?;
+        Ok(self.report())
+    }
+
     /// Computes changes of the local tags compared to the cache and uploads all changes to the remote.
     ///
     /// # Errors
     ///
     /// This function will return an error if computing the local file tag repository fails.
     pub async fn sync_local_to_remote(&mut self) -> Result<(), InitError> {
-        let local = self.local_fs.create_repo().await?;
+        let (mut local, issues) = self
+            .watchdog
+            .watch(
+                Phase::Scan(FileLocation::Local),
+                self.local_fs.create_repo(),
+            )
+            .await??;
+        isolate_failed_prefixes(&mut local, &issues, &self.repo);
+        self.scan_issues.extend(issues);
+        self.upload_local(local).await
+    }
+
+    /// Uploads the changes of the local files at or below `paths`, e.g. reported by a
+    /// [`crate::Watcher`], without walking the prefixes. Files which could not be read
+    /// keep their previous tags.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if scanning the paths fails.
+    pub async fn sync_local_changes(&mut self, paths: BTreeSet<PathBuf>) -> Result<(), InitError> {
+        let (scanned, issues) = self
+            .watchdog
+            .watch(
+                Phase::Scan(FileLocation::Local),
+                self.local_fs.scan_paths(paths.clone()),
+            )
+            .await??;
+        let unreadable: Vec<_> = issues
+            .iter()
+            .filter_map(|issue| match issue {
+                ScanIssue::UnreadableFile { path, .. } => {
+                    self.repo.synced_path_of(path, FileLocation::Local).ok()
+                }
+                _ => None,
+            })
+            .collect();
+        let changed: Vec<_> = paths
+            .iter()
+            .filter_map(|path| self.repo.synced_path_of(path, FileLocation::Local).ok())
+            .filter(|path| !unreadable.contains(path))
+            .collect();
+        let mut local = self.repo.clone();
+        local.retain(|path, _| !changed.iter().any(|changed| path.starts_with(changed)));
+        local.append(scanned);
+        for path in &unreadable {
+            local.copy_entry(path, &self.repo);
+        }
+        self.scan_issues.extend(issues);
+        self.upload_local(local).await
+    }
 
+    /// Uploads the differences between the tag database and the `local` tags.
+    async fn upload_local(&mut self, mut local: Repository) -> Result<(), InitError> {
+        counterparts::restore_pending(&mut local, &self.repo, FileLocation::Local);
+        let appeared =
+            counterparts::appeared(&self.remote_fs, &self.repo, FileLocation::Remote).await;
+        counterparts::release(&mut self.repo, appeared);
         let repo = std::mem::take(&mut self.repo);
         let mut diff_events = repo.diff(local, Side::Right);
-        let (_, actions) = resolve_diffs(&mut diff_events, Side::Right);
+        let (actions, _) = resolve_diffs(&mut diff_events);
 
-        let cmd_fmt = CommandsFormatter(&actions);
+        let cmd_fmt = CommandsFormatter(&actions, FormatOptions::from_config(&self.config));
         tracing::debug!("Remote actions: {cmd_fmt}");
 
-        self.remote_fs.update_tags(actions).await;
-        self.repo = diff_events.finish();
+        let mut repo = diff_events.finish();
+        let skipped = self.remote_fs.find_skipped().await?;
+        let actions = skip::hold(&mut repo, actions, &skipped);
+        let actions = self
+            .hold_missing(&mut repo, FileLocation::Remote, actions)
+            .await;
+        let actions = self.hold_violations(&mut repo, actions);
+        let [actions, _] = loops::hold_loops(
+            &mut self.loops,
+            &self.config,
+            &mut repo,
+            [actions, Vec::new()],
+        );
+        let actions = self.hooks.apply(&mut repo, FileLocation::Remote, actions);
+        backup_removed_tags(&self.config, &repo, &[], &actions).context(BackupSnafu)?;
+        let mut checkpoint = start_checkpoint(&self.config, &repo, &[], &actions, Vec::new());
+        let outcomes = apply_in_chunks(
+            &mut self.remote_fs,
+            FileLocation::Remote,
+            &actions,
+            &mut checkpoint,
+            &self.config,
+            self.deadline,
+            &self.watchdog,
+        )
+        .await?;
+        drop(checkpoint);
+        Session::discard(&self.config);
+        revert_failed(&mut repo, &outcomes);
+        self.origins.record_now(FileLocation::Remote, &outcomes);
+        self.repo = repo;
+        self.remote_outcomes.extend(outcomes);
+        ensure!(!self.remote_fs.in_maintenance(), MaintenanceSnafu);
         Ok(())
     }
 
@@ -130,28 +618,207 @@ impl Initialized {
     ///
     /// This function will return an error if computing the remote file tag repository fails.
     pub async fn sync_remote_to_local(&mut self) -> Result<(), InitError> {
-        let remote = self.remote_fs.create_repo().await?;
+        let (mut remote, issues) = self
+            .watchdog
+            .watch(
+                Phase::Scan(FileLocation::Remote),
+                self.remote_fs.create_repo(),
+            )
+            .await??;
+        isolate_failed_prefixes(&mut remote, &issues, &self.repo);
+        self.scan_issues.extend(issues);
+        if self.config.case_insensitive_remote {
+            remote.adopt_case_from(&self.repo);
+        }
+        counterparts::restore_pending(&mut remote, &self.repo, FileLocation::Remote);
+        let appeared =
+            counterparts::appeared(&self.remote_fs, &self.repo, FileLocation::Local).await;
+        counterparts::release(&mut self.repo, appeared);
 
         let repo = std::mem::take(&mut self.repo);
         let mut diff_events = repo.diff(remote, Side::Right);
-        let (_, actions) = resolve_diffs(&mut diff_events, Side::Right);
+        let (actions, _) = resolve_diffs(&mut diff_events);
 
-        let cmd_fmt = CommandsFormatter(&actions);
+        let cmd_fmt = CommandsFormatter(&actions, FormatOptions::from_config(&self.config));
         tracing::debug!("Local actions: {cmd_fmt}");
 
-        self.local_fs.update_tags(actions).await;
-
-        self.repo = diff_events.finish();
+        let mut repo = diff_events.finish();
+        let actions = keep_pinned(&self.config, &mut repo, actions);
+        let skipped = self.remote_fs.find_skipped().await?;
+        let actions = skip::hold(&mut repo, actions, &skipped);
+        let actions = self
+            .hold_missing(&mut repo, FileLocation::Local, actions)
+            .await;
+        let actions = self.hold_violations(&mut repo, actions);
+        let [actions, _] = loops::hold_loops(
+            &mut self.loops,
+            &self.config,
+            &mut repo,
+            [actions, Vec::new()],
+        );
+        let orphaned: BTreeSet<_> = self
+            .remote_fs
+            .find_missing_files(orphans::candidates(&repo, &actions))
+            .await
+            .into_iter()
+            .collect();
+        let actions =
+            orphans::apply_policy(self.config.orphaned_files, &mut repo, actions, &orphaned);
+        let actions = self.hooks.apply(&mut repo, FileLocation::Local, actions);
+        self.orphaned_files.extend(orphaned);
+        backup_removed_tags(&self.config, &repo, &actions, &[]).context(BackupSnafu)?;
+        let mut checkpoint = start_checkpoint(&self.config, &repo, &actions, &[], Vec::new());
+        let outcomes = apply_in_chunks(
+            &mut self.local_fs,
+            FileLocation::Local,
+            &actions,
+            &mut checkpoint,
+            &self.config,
+            self.deadline,
+            &self.watchdog,
+        )
+        .await?;
+        drop(checkpoint);
+        Session::discard(&self.config);
+        revert_failed(&mut repo, &outcomes);
+        self.origins.record_now(FileLocation::Local, &outcomes);
+        self.repo = repo;
+        self.local_outcomes.extend(outcomes);
         Ok(())
     }
 
+    /// Holds back the commands of files whose counterpart on `target` is missing according
+    /// to the policy of their prefix.
+    async fn hold_missing(
+        &self,
+        repo: &mut Repository,
+        target: FileLocation,
+        actions: Vec<Command>,
+    ) -> Vec<Command> {
+        let candidates = counterparts::candidates(&self.config.prefixes, &actions);
+        let missing =
+            counterparts::missing(&self.remote_fs, &self.config.prefixes, target, candidates).await;
+        counterparts::hold(repo, target, actions, &missing)
+    }
+
+    /// Drops and records the commands of files violating [`Config::exclusive_tags`].
+    fn hold_violations(&mut self, repo: &mut Repository, actions: Vec<Command>) -> Vec<Command> {
+        let violations = constraints::violations(&self.config.exclusive_tags, repo, &actions);
+        let actions = constraints::hold(repo, actions, &violations);
+        self.constraint_violations.extend(violations);
+        actions
+    }
+
     /// Persist the repository to disk.
     ///
     /// # Errors
     ///
     /// This function will return an error if persisting failed.
     pub fn persist_repository(&self) -> Result<(), PersistingError> {
-        self.repo.persist_on_disk(&self.config.tag_database)
+        self.repo.persist_on_disk(&self.config.tag_database)?;
+        if self.config.binary_snapshot {
+            self.repo.persist_snapshot(&self.config.tag_database)?;
+        }
+        self.loops.persist(&self.config);
+        self.origins.persist(&self.config);
+        Ok(())
+    }
+}
+
+/// Reads the repository from the binary snapshot if enabled and up to date, otherwise
+/// from the JSON database.
+fn read_repository(config: &Config) -> Result<Repository, LoadError> {
+    if config.binary_snapshot {
+        match Repository::read_snapshot(&config.tag_database) {
+            Ok(Some(repo)) => return Ok(repo),
+            Ok(None) => {}
+            Err(e) => tracing::warn!("Failed to read snapshot, falling back to JSON: {e}"),
+        }
+    }
+    Repository::read_from_disk(&config.tag_database)
+}
+
+/// Files whose local tags are kept on conflict by [`Side::Newest`] or [`Side::Origin`],
+/// depending on which of them applies to their prefix.
+fn preferred_local(
+    config: &Config,
+    local_fs: &LocalFs,
+    origins: &TagOrigins,
+) -> BTreeSet<SyncedPath> {
+    let uses = |side: Side| {
+        move |path: &SyncedPath| {
+            path.prefix(&config.prefixes)
+                .and_then(PrefixMapping::keep_side_on_conflict)
+                .unwrap_or(config.keep_side_on_conflict)
+                == side
+        }
+    };
+    let newer = newer_local_tags(config, local_fs)
+        .into_iter()
+        .filter(uses(Side::Newest));
+    let created = origins
+        .created_locally()
+        .into_iter()
+        .filter(uses(Side::Origin));
+    newer.chain(created).collect()
+}
+
+/// Local files whose tags changed after the last synchronization, i.e. after the tag
+/// database was written. Without a tag database, every recorded tag time counts.
+fn newer_local_tags(config: &Config, local_fs: &LocalFs) -> BTreeSet<SyncedPath> {
+    let last_sync = std::fs::metadata(&config.tag_database)
+        .and_then(|metadata| metadata.modified())
+        .ok();
+    local_fs
+        .tag_times
+        .iter()
+        .filter(|(_, time)| last_sync.is_none_or(|last_sync| **time > last_sync))
+        .map(|(path, _)| path.clone())
+        .collect()
+}
+
+/// Local files whose tags were not changed for `stale_after_days`.
+fn stale_files(config: &Config, local_fs: &LocalFs) -> BTreeSet<SyncedPath> {
+    let Some(days) = config.stale_after_days else {
+        return BTreeSet::new();
+    };
+    let Some(threshold) = SystemTime::now().checked_sub(Duration::from_hours(24 * days)) else {
+        return BTreeSet::new();
+    };
+    local_fs
+        .tag_times
+        .iter()
+        .filter(|(_, time)| **time < threshold)
+        .map(|(path, _)| path.clone())
+        .collect()
+}
+
+/// Drops removals of pinned tags from local `actions` and keeps the tags in `repo`, so
+/// they are not pushed to Nextcloud again either.
+fn keep_pinned(config: &Config, repo: &mut Repository, actions: Vec<Command>) -> Vec<Command> {
+    let (actions, removals) = split_pinned(actions, &config.pinned_local_tags);
+    for cmd in removals {
+        tracing::debug!("Keeping pinned tags of {} in repository", cmd.path);
+        repo.revert(cmd);
+    }
+    actions
+}
+
+/// Keeps the previous state of prefixes that could not be scanned, so none of their files
+/// are changed or treated as untagged.
+fn isolate_failed_prefixes(scanned: &mut Repository, issues: &ScanIssues, previous: &Repository) {
+    for prefix in issues.failed_prefixes() {
+        tracing::warn!("Skipping prefix {prefix} because it could not be scanned");
+        scanned.restore_prefix(prefix, previous);
+    }
+}
+
+/// The merged repository assumes all commands succeeded. Failed ones are undone so the
+/// cache matches the actual state and they are retried in the next synchronization.
+fn revert_failed(repo: &mut Repository, outcomes: &[CommandOutcome]) {
+    for cmd in outcomes.iter().filter_map(CommandOutcome::failed) {
+        tracing::debug!("Reverting failed command for {} in repository", cmd.path);
+        repo.revert(cmd.clone());
     }
 }
 
@@ -161,7 +828,22 @@ fn merge_results<T, U>(
 ) -> Result<(T, U), InitError> {
     match results {
         (Ok(l), Ok(r)) => Ok((l, r)),
-        (Ok(_), Err(e)) | (Err(e), Ok(_)) => Err(e),
+        (Ok(_), Err(e))
+        | (Err(e), Ok(_))
+        | (
+            Err(
+                e
+                @ (InitError::Maintenance | InitError::Aborted { .. } | InitError::Stalled { .. }),
+            ),
+            Err(_),
+        )
+        | (
+            Err(_),
+            Err(
+                e
+                @ (InitError::Maintenance | InitError::Aborted { .. } | InitError::Stalled { .. }),
+            ),
+        ) => Err(e),
         (
             Err(InitError::Local {
                 source: source_local,
@@ -182,7 +864,11 @@ fn merge_results<T, U>(
             source_remote,
         }
         .fail(),
-        _ => panic!("Wrongly called, todo"),
+        // Both sides failed for unrelated reasons, the local error is reported.
+        (Err(local), Err(remote)) => {
+            tracing::warn!("Scanning the remote side failed as well: {remote}");
+            Err(local)
+        }
     }
 }
 
@@ -198,4 +884,59 @@ pub enum InitError {
         source_local: LocalError,
         source_remote: ListTagsError,
     },
+    #[snafu(display("Nextcloud is in maintenance mode, try again later"))]
+    Maintenance,
+    #[snafu(display("aborted scan of remote files: {source}"))]
+    Aborted {
+        source: RequestError<DeserializeError>,
+    },
+    #[snafu(display("failed to resume session"))]
+    Session { source: SessionError },
+    #[snafu(display("initial synchronization would change {files} files and must be confirmed"))]
+    ConfirmationRequired {
+        files: usize,
+        changes: PendingChanges,
+    },
+    #[snafu(display("refused to remove tags without a backup"))]
+    Backup { source: BackupError },
+    #[snafu(display("stopped at the maximum duration with {files} files left for the next run"))]
+    DeadlineExceeded { files: usize },
+    #[snafu(display(
+        "an interrupted synchronization is unfinished, continue it with sync --resume or \
+         delete {} to start over",
+        journal.display()
+    ))]
+    UnfinishedSession { journal: PathBuf },
+    #[snafu(display("failed to list files excluded with the skip tag"))]
+    Skipped {
+        source: RequestError<DeserializeError>,
+    },
+    #[snafu(display("aborted {phase} after {secs}s without progress"))]
+    Stalled { phase: Phase, secs: u64 },
+    #[snafu(display("another synchronization holds the lock {}", path.display()))]
+    Locked { path: PathBuf },
+    #[snafu(display("failed to open lock {}", path.display()))]
+    LockFile {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("failed to load the hook script"))]
+    Hook { source: HookError },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_unrelated_errors() {
+        let result = merge_results::<(), ()>((
+            Err(InitError::DeadlineExceeded { files: 1 }),
+            Err(InitError::DeadlineExceeded { files: 2 }),
+        ));
+        assert!(matches!(
+            result,
+            Err(InitError::DeadlineExceeded { files: 1 })
+        ));
+    }
 }
@@ -0,0 +1,361 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::Instant;
+
+use crate::{
+    Command, CommandOutcome, Config, FileLocation, FileSystem, InitError, Repository, SyncedPath,
+};
+
+use super::{
+    fault::inject_failures,
+    session::{Session, SessionRef},
+    watchdog::{Phase, Watchdog},
+    DeadlineExceededSnafu,
+};
+
+/// Repository state persisted between chunks of commands. Files keep their previous tags
+/// until all of their commands finished, so an interruption loses at most one chunk of
+/// progress. The remaining commands are journaled so the session can be resumed.
+pub struct Checkpoint<'a> {
+    repo: Repository,
+    target: &'a Repository,
+    pending: HashMap<SyncedPath, usize>,
+    failed_per_file: HashMap<SyncedPath, Vec<Command>>,
+    local: BTreeMap<SyncedPath, &'a Command>,
+    remote: BTreeMap<SyncedPath, &'a Command>,
+    failed: Vec<Command>,
+}
+
+impl<'a> Checkpoint<'a> {
+    /// `target` is the repository after all commands succeeded. Reverting the commands
+    /// yields the state before the synchronization. `failed` are commands which already
+    /// failed in an earlier, interrupted run of this session.
+    pub fn new(
+        target: &'a Repository,
+        local: &'a [Command],
+        remote: &'a [Command],
+        failed: Vec<Command>,
+    ) -> Self {
+        let mut repo = target.clone();
+        let mut pending: HashMap<_, usize> = HashMap::new();
+        for cmd in local.iter().chain(remote).chain(&failed) {
+            repo.revert(cmd.clone());
+        }
+        for cmd in local.iter().chain(remote) {
+            *pending.entry(cmd.path.clone()).or_default() += 1;
+        }
+        let by_path =
+            |commands: &'a [Command]| commands.iter().map(|cmd| (cmd.path.clone(), cmd)).collect();
+
+        Self {
+            repo,
+            target,
+            pending,
+            failed_per_file: HashMap::new(),
+            local: by_path(local),
+            remote: by_path(remote),
+            failed,
+        }
+    }
+
+    fn record(&mut self, location: FileLocation, outcomes: &[CommandOutcome]) {
+        let remaining = match location {
+            FileLocation::Local => &mut self.local,
+            FileLocation::Remote => &mut self.remote,
+        };
+        let mut finished = HashSet::new();
+        for outcome in outcomes {
+            let path = outcome.path();
+            remaining.remove(path);
+            if let Some(failed) = outcome.failed() {
+                self.failed_per_file
+                    .entry(path.clone())
+                    .or_default()
+                    .push(failed.clone());
+                self.failed.push(failed.clone());
+            }
+            finished.insert(path);
+        }
+
+        for path in finished {
+            let Some(count) = self.pending.get_mut(path) else {
+                continue;
+            };
+            *count -= 1;
+            if *count > 0 {
+                continue;
+            }
+
+            self.pending.remove(path);
+            self.repo.copy_entry(path, self.target);
+            for cmd in self.failed_per_file.remove(path).into_iter().flatten() {
+                self.repo.revert(cmd);
+            }
+        }
+    }
+
+    /// Persists the checkpoint and the journal of remaining commands. Once nothing is
+    /// pending anymore, the journal is removed instead. In strict mode only the journal
+    /// is written since the tag database must stay untouched if any command fails.
+    pub fn persist(&self, config: &Config) {
+        if self.pending.is_empty() {
+            Session::discard(config);
+            return;
+        }
+
+        tracing::info!(
+            "Persisting checkpoint, {} files pending",
+            self.pending.len()
+        );
+        let session = SessionRef {
+            target: self.target,
+            local: self.local.values().copied().collect(),
+            remote: self.remote.values().copied().collect(),
+            failed: &self.failed,
+        };
+        if let Err(e) = session.persist(config) {
+            tracing::warn!("Failed to persist session journal: {e}");
+        }
+        if config.strict {
+            return;
+        }
+        if let Err(e) = self.repo.persist_on_disk(&config.tag_database) {
+            tracing::warn!("Failed to persist checkpoint: {e}");
+        }
+    }
+}
+
+/// Creates and persists a checkpoint if the commands are applied in more than one chunk.
+/// Only large command queues are split up since every checkpoint rewrites the database.
+/// With a deadline, a checkpoint is always created. Otherwise the commands are journaled
+/// once, so a killed run can still be resumed. The caller removes that journal with
+/// [`Session::discard`] once the commands ran.
+pub fn start_checkpoint<'a>(
+    config: &Config,
+    target: &'a Repository,
+    local: &'a [Command],
+    remote: &'a [Command],
+    failed: Vec<Command>,
+) -> Option<Checkpoint<'a>> {
+    let chunk_size = config.command_chunk_size;
+    let resumed = !failed.is_empty();
+    let fits_one_chunk = local.len() + remote.len() <= chunk_size;
+    let limited = config.max_duration_secs.is_some() || config.stall_timeout_secs.is_some();
+    if chunk_size == 0 || (fits_one_chunk && !resumed && !limited) {
+        if !local.is_empty() || !remote.is_empty() {
+            let session = SessionRef::new(target, local, remote, &failed);
+            if let Err(e) = session.persist(config) {
+                tracing::warn!("Failed to persist session journal: {e}");
+            }
+        }
+        return None;
+    }
+
+    let checkpoint = Checkpoint::new(target, local, remote, failed);
+    checkpoint.persist(config);
+    Some(checkpoint)
+}
+
+/// Applies the commands in chunks of [`Config::command_chunk_size`] and persists the
+/// checkpoint after each chunk. No further chunk is started once `deadline` passed and a
+/// chunk which stalls aborts the remaining ones.
+pub async fn apply_in_chunks<F: FileSystem>(
+    fs: &mut F,
+    location: FileLocation,
+    commands: &[Command],
+    checkpoint: &mut Option<Checkpoint<'_>>,
+    config: &Config,
+    deadline: Option<Instant>,
+    watchdog: &Watchdog,
+) -> Result<Vec<CommandOutcome>, InitError> {
+    let phase = Phase::Update(location);
+    let expired = || deadline.is_some_and(|deadline| Instant::now() >= deadline);
+    let Some(checkpoint) = checkpoint else {
+        if expired() && !commands.is_empty() {
+            return DeadlineExceededSnafu {
+                files: commands.len(),
+            }
+            .fail();
+        }
+        return watchdog
+            .watch(phase, execute(fs, commands.to_vec(), config))
+            .await;
+    };
+
+    let mut outcomes = Vec::with_capacity(commands.len());
+    for chunk in commands.chunks(config.command_chunk_size.max(1)) {
+        if expired() {
+            tracing::warn!("Maximum duration exceeded, journaled the remaining commands");
+            return DeadlineExceededSnafu {
+                files: checkpoint.pending.len(),
+            }
+            .fail();
+        }
+        let chunk_outcomes = match watchdog
+            .watch(phase, execute(fs, chunk.to_vec(), config))
+            .await
+        {
+            Ok(outcomes) => outcomes,
+            Err(e) => {
+                tracing::warn!("Journaled the commands of the stalled and remaining chunks");
+                return Err(e);
+            }
+        };
+        checkpoint.record(location, &chunk_outcomes);
+        checkpoint.persist(config);
+        outcomes.extend(chunk_outcomes);
+    }
+    Ok(outcomes)
+}
+
+async fn execute<F: FileSystem>(
+    fs: &mut F,
+    commands: Vec<Command>,
+    config: &Config,
+) -> Vec<CommandOutcome> {
+    let (commands, mut simulated) = inject_failures(commands, config);
+    let mut outcomes = fs.update_tags(commands).await;
+    outcomes.append(&mut simulated);
+    outcomes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Modification, PrefixMapping, TagAction, Tags};
+
+    #[test]
+    fn checkpoint_keeps_previous_tags_until_finished() {
+        let prefixes =
+            vec![PrefixMapping::new("/local".into(), "/remote.php/dav/files/user".into()).unwrap()];
+        let tags = |tags: &[&str]| -> Tags { tags.iter().copied().collect() };
+        let add = |path: &str, tag: &str| Command {
+            path: SyncedPath::new(0, path),
+            actions: vec![TagAction {
+                tag: tag.parse().unwrap(),
+                modification: Modification::Add,
+            }],
+        };
+
+        let mut target = Repository::new(prefixes);
+        target.insert(SyncedPath::new(0, "a.txt"), tags(&["red", "blue"]));
+        target.insert(SyncedPath::new(0, "b.txt"), tags(&["red"]));
+        target.insert(SyncedPath::new(0, "c.txt"), tags(&["red"]));
+        let commands = [
+            add("a.txt", "blue"),
+            add("b.txt", "red"),
+            add("c.txt", "red"),
+        ];
+
+        let mut checkpoint = Checkpoint::new(&target, &[], &commands, Vec::new());
+        checkpoint.record(
+            FileLocation::Remote,
+            &[
+                CommandOutcome::Success(commands[0].clone()),
+                CommandOutcome::Failed {
+                    command: commands[1].clone(),
+                    error: "failed".to_owned(),
+                },
+            ],
+        );
+
+        assert_eq!(
+            checkpoint.repo.files().collect::<Vec<_>>(),
+            [
+                (&SyncedPath::new(0, "a.txt"), &tags(&["red", "blue"])),
+                (&SyncedPath::new(0, "b.txt"), &tags(&[])),
+                (&SyncedPath::new(0, "c.txt"), &tags(&[])),
+            ]
+        );
+        assert_eq!(checkpoint.pending.len(), 1);
+        assert_eq!(
+            checkpoint.remote.keys().collect::<Vec<_>>(),
+            [&SyncedPath::new(0, "c.txt")]
+        );
+        assert_eq!(checkpoint.failed, [commands[1].clone()]);
+    }
+
+    struct Untouched;
+
+    impl FileSystem for Untouched {
+        async fn create_repo(&mut self) -> Result<(Repository, crate::ScanIssues), InitError> {
+            unreachable!("not scanned")
+        }
+
+        async fn update_tags<I>(&mut self, _: I) -> Vec<CommandOutcome>
+        where
+            I: IntoIterator<Item = Command> + Send,
+        {
+            panic!("no commands must be applied after the deadline");
+        }
+    }
+
+    #[tokio::test]
+    async fn journal_commands_after_deadline() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            tag_database: dir.path().join("db.json"),
+            max_duration_secs: Some(0),
+            ..Config::default()
+        };
+        let prefixes =
+            vec![PrefixMapping::new("/local".into(), "/remote.php/dav/files/user".into()).unwrap()];
+        let mut target = Repository::new(prefixes);
+        target.insert(
+            SyncedPath::new(0, "a.txt"),
+            std::iter::once("red").collect(),
+        );
+        let commands = [Command {
+            path: SyncedPath::new(0, "a.txt"),
+            actions: vec![TagAction {
+                tag: "red".parse().unwrap(),
+                modification: Modification::Add,
+            }],
+        }];
+
+        let mut checkpoint = start_checkpoint(&config, &target, &[], &commands, Vec::new());
+        let result = apply_in_chunks(
+            &mut Untouched,
+            FileLocation::Remote,
+            &commands,
+            &mut checkpoint,
+            &config,
+            Some(Instant::now()),
+            &Watchdog::new(&config),
+        )
+        .await;
+
+        assert!(matches!(
+            result,
+            Err(InitError::DeadlineExceeded { files: 1 })
+        ));
+        let session = Session::load(&config).unwrap().unwrap();
+        assert_eq!(session.remote, commands);
+    }
+
+    #[test]
+    fn journal_commands_of_single_chunk() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            tag_database: dir.path().join("db.json"),
+            ..Config::default()
+        };
+        let prefixes =
+            vec![PrefixMapping::new("/local".into(), "/remote.php/dav/files/user".into()).unwrap()];
+        let target = Repository::new(prefixes);
+        let commands = [Command {
+            path: SyncedPath::new(0, "a.txt"),
+            actions: vec![TagAction {
+                tag: "red".parse().unwrap(),
+                modification: Modification::Add,
+            }],
+        }];
+
+        assert!(start_checkpoint(&config, &target, &[], &[], Vec::new()).is_none());
+        assert!(Session::load(&config).unwrap().is_none());
+
+        assert!(start_checkpoint(&config, &target, &commands, &[], Vec::new()).is_none());
+        let session = Session::load(&config).unwrap().unwrap();
+        assert_eq!(session.local, commands);
+        assert!(session.remote.is_empty());
+    }
+}
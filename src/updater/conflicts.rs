@@ -0,0 +1,67 @@
+use std::collections::BTreeMap;
+
+use crate::{i18n::tr, CommandOutcome, Modification, SyncedPath, Tags};
+
+/// Comments listing the tags the initial synchronization removed from either side of a
+/// file. Without a previous state, tags are only removed to resolve a conflict.
+pub fn comments(
+    local: &[CommandOutcome],
+    remote: &[CommandOutcome],
+) -> BTreeMap<SyncedPath, String> {
+    let mut dropped: BTreeMap<SyncedPath, [Tags; 2]> = BTreeMap::new();
+    for (side, outcomes) in [local, remote].into_iter().enumerate() {
+        for cmd in outcomes.iter().filter_map(CommandOutcome::applied) {
+            let removed = cmd
+                .actions
+                .iter()
+                .filter(|action| action.modification == Modification::Remove);
+            for action in removed {
+                dropped.entry(cmd.path.clone()).or_default()[side].insert_one(action.tag.clone());
+            }
+        }
+    }
+    dropped
+        .into_iter()
+        .map(|(path, [local, remote])| (path, message(&local, &remote)))
+        .collect()
+}
+
+fn message(local: &Tags, remote: &Tags) -> String {
+    let list = |tags: &Tags| tags.iter().map(|tag| &**tag).collect::<Vec<_>>().join(", ");
+    let mut lines = vec![tr!("conflict-comment")];
+    if !local.is_empty() {
+        lines.push(tr!("conflict-comment-local", tags = list(local)));
+    }
+    if !remote.is_empty() {
+        lines.push(tr!("conflict-comment-remote", tags = list(remote)));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Command;
+
+    #[test]
+    fn comment_dropped_tags() {
+        let command = |path: &str| Command::for_path(SyncedPath::new(0, path));
+        let local = [
+            CommandOutcome::Success(command("a.txt").remove("red").add("blue").build().unwrap()),
+            CommandOutcome::Failed {
+                command: command("b.txt").remove("red").build().unwrap(),
+                error: "failed".to_owned(),
+            },
+        ];
+        let remote = [CommandOutcome::Success(
+            command("a.txt").remove("green").build().unwrap(),
+        )];
+
+        let comments = comments(&local, &remote);
+        assert_eq!(comments.len(), 1);
+        let comment = &comments[&SyncedPath::new(0, "a.txt")];
+        assert!(comment.contains("red"));
+        assert!(comment.contains("green"));
+        assert!(!comment.contains("blue"));
+    }
+}
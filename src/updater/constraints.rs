@@ -0,0 +1,96 @@
+use std::collections::BTreeMap;
+
+use crate::{Command, Repository, SyncedPath, Tag, Tags};
+
+/// Files among those changed by `commands` which would carry more than one tag of a
+/// group in [`crate::Config::exclusive_tags`], with the conflicting tags.
+pub fn violations<'a>(
+    groups: &[Vec<Tag>],
+    repo: &Repository,
+    commands: impl IntoIterator<Item = &'a Command>,
+) -> BTreeMap<SyncedPath, Tags> {
+    let mut violations = BTreeMap::new();
+    for cmd in commands {
+        let Some(tags) = repo.tags(&cmd.path) else {
+            continue;
+        };
+        let conflicting: Tags = groups
+            .iter()
+            .map(|group| group.iter().filter(|tag| tags.contains(*tag)))
+            .filter(|present| present.clone().count() > 1)
+            .flatten()
+            .map(ToString::to_string)
+            .collect();
+        if !conflicting.is_empty() {
+            violations.insert(cmd.path.clone(), conflicting);
+        }
+    }
+    violations
+}
+
+/// Drops the commands of files with violations and reverts them in `repo`, so they are
+/// computed and reported again until a user resolves the conflict.
+pub fn hold(
+    repo: &mut Repository,
+    actions: Vec<Command>,
+    violations: &BTreeMap<SyncedPath, Tags>,
+) -> Vec<Command> {
+    let (held, actions): (Vec<_>, Vec<_>) = actions
+        .into_iter()
+        .partition(|cmd| violations.contains_key(&cmd.path));
+    for cmd in held {
+        tracing::warn!("Not synchronizing {} with conflicting tags", cmd.path);
+        repo.revert(cmd);
+    }
+    actions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Modification, PrefixMapping, TagAction};
+
+    #[test]
+    fn hold_conflicting_workflow_tags() {
+        let prefixes =
+            vec![PrefixMapping::new("/local".into(), "/remote.php/dav/files/user".into()).unwrap()];
+        let tags = |tags: &[&str]| -> Tags { tags.iter().copied().collect() };
+        let add = |path: &str, tag: &str| Command {
+            path: SyncedPath::new(0, path),
+            actions: vec![TagAction {
+                tag: tag.parse().unwrap(),
+                modification: Modification::Add,
+            }],
+        };
+        let groups = vec![["status-todo", "status-doing", "status-done"]
+            .map(|tag| tag.parse().unwrap())
+            .to_vec()];
+
+        let mut repo = Repository::new(prefixes);
+        repo.insert(
+            SyncedPath::new(0, "a.txt"),
+            tags(&["status-todo", "status-doing", "red"]),
+        );
+        repo.insert(SyncedPath::new(0, "b.txt"), tags(&["status-done", "red"]));
+        let local = vec![add("a.txt", "status-doing"), add("b.txt", "red")];
+        let remote = vec![add("a.txt", "red")];
+
+        let violations = violations(&groups, &repo, local.iter().chain(&remote));
+        assert_eq!(
+            violations.iter().collect::<Vec<_>>(),
+            [(
+                &SyncedPath::new(0, "a.txt"),
+                &tags(&["status-todo", "status-doing"])
+            )]
+        );
+
+        let local = hold(&mut repo, local, &violations);
+        let remote = hold(&mut repo, remote, &violations);
+        assert_eq!(local, [add("b.txt", "red")]);
+        assert!(remote.is_empty());
+        assert_eq!(
+            repo.tags(&SyncedPath::new(0, "a.txt")),
+            Some(&tags(&["status-todo"]))
+        );
+    }
+}
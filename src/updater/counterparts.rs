@@ -0,0 +1,147 @@
+use std::collections::BTreeSet;
+
+use crate::{
+    Command, CounterpartPolicy, FileLocation, PrefixMapping, RemoteFs, Repository, SyncedPath,
+};
+
+/// Files of `actions` which have to exist on the target side before their tags are applied
+/// because their prefix does not just [`CounterpartPolicy::Wait`].
+pub fn candidates(prefixes: &[PrefixMapping], actions: &[Command]) -> Vec<SyncedPath> {
+    actions
+        .iter()
+        .filter(|cmd| {
+            cmd.path
+                .prefix(prefixes)
+                .is_some_and(|prefix| prefix.missing_counterpart() != CounterpartPolicy::Wait)
+        })
+        .map(|cmd| cmd.path.clone())
+        .collect()
+}
+
+/// The given files which do not exist on `side`. Remote files which could not be checked
+/// are assumed to exist.
+pub async fn missing(
+    remote_fs: &RemoteFs,
+    prefixes: &[PrefixMapping],
+    side: FileLocation,
+    paths: Vec<SyncedPath>,
+) -> BTreeSet<SyncedPath> {
+    match side {
+        FileLocation::Local => paths
+            .into_iter()
+            .filter(|path| !path.local_file(prefixes).exists())
+            .collect(),
+        FileLocation::Remote => remote_fs
+            .find_missing_files(paths)
+            .await
+            .into_iter()
+            .collect(),
+    }
+}
+
+/// Pending pairs of `repo` whose counterpart now exists on `side`.
+pub async fn appeared(
+    remote_fs: &RemoteFs,
+    repo: &Repository,
+    side: FileLocation,
+) -> Vec<SyncedPath> {
+    let pending: Vec<_> = repo.pending(side).cloned().collect();
+    if pending.is_empty() {
+        return pending;
+    }
+    let missing = missing(remote_fs, repo.prefixes(), side, pending.clone()).await;
+    pending
+        .into_iter()
+        .filter(|path| !missing.contains(path))
+        .collect()
+}
+
+/// Drops the commands of files which are `missing` on the `target` side. Dropped files are
+/// reverted in `repo`, files of [`CounterpartPolicy::MirrorWhenAppears`] keep their tags as
+/// pending pair instead.
+pub fn hold(
+    repo: &mut Repository,
+    target: FileLocation,
+    actions: Vec<Command>,
+    missing: &BTreeSet<SyncedPath>,
+) -> Vec<Command> {
+    let (held, actions): (Vec<_>, Vec<_>) = actions
+        .into_iter()
+        .partition(|cmd| missing.contains(&cmd.path));
+    for cmd in held {
+        let policy = cmd
+            .path
+            .prefix(repo.prefixes())
+            .map(PrefixMapping::missing_counterpart);
+        match policy.unwrap_or_default() {
+            CounterpartPolicy::MirrorWhenAppears => {
+                tracing::debug!("Keeping tags of {} until it exists", cmd.path);
+                repo.mark_pending(cmd.path, target);
+            }
+            CounterpartPolicy::Wait | CounterpartPolicy::Drop => {
+                tracing::debug!("Skipping {} because it does not exist", cmd.path);
+                repo.revert(cmd);
+            }
+        }
+    }
+    actions
+}
+
+/// Keeps the tags of pending pairs missing on the `scanned` side, so their absence is not
+/// mistaken for removed tags.
+pub fn restore_pending(scanned: &mut Repository, repo: &Repository, side: FileLocation) {
+    for path in repo.pending(side) {
+        scanned.copy_entry(path, repo);
+    }
+}
+
+/// Forgets the pending pairs whose counterpart `appeared`, so their tags are copied to it.
+pub fn release(repo: &mut Repository, appeared: impl IntoIterator<Item = SyncedPath>) {
+    for path in appeared {
+        tracing::debug!("Counterpart of {path} appeared, copying its tags");
+        repo.release_pending(&path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Side, Tags};
+
+    #[test]
+    fn mirror_tags_once_counterpart_appears() {
+        let prefixes =
+            vec![
+                PrefixMapping::new("/local".into(), "/remote.php/dav/files/user".into())
+                    .unwrap()
+                    .with_missing_counterpart(CounterpartPolicy::MirrorWhenAppears),
+            ];
+        let path = SyncedPath::new(0, "a.jpg");
+        let tags: Tags = "red".parse().unwrap();
+        let mut local = Repository::new(prefixes.clone());
+        local.insert(path.clone(), tags.clone());
+
+        // The file only exists locally, so the upload is held back.
+        let mut diff = Repository::new(prefixes.clone()).diff(local.clone(), Side::Right);
+        let (actions, _) = crate::resolve_diffs(&mut diff);
+        let mut repo = diff.finish();
+        let missing = BTreeSet::from([path.clone()]);
+        let actions = hold(&mut repo, FileLocation::Remote, actions, &missing);
+        assert!(actions.is_empty());
+        assert_eq!(repo.tags(&path), Some(&tags));
+
+        // The missing remote file does not remove the local tags.
+        let mut remote = Repository::new(prefixes);
+        restore_pending(&mut remote, &repo, FileLocation::Remote);
+        let mut diff = repo.diff(remote, Side::Right);
+        assert!(crate::resolve_diffs(&mut diff).0.is_empty());
+        let mut repo = diff.finish();
+
+        // Once it is uploaded, the local tags are copied.
+        release(&mut repo, [path]);
+        let mut diff = repo.diff(local, Side::Right);
+        let (actions, _) = crate::resolve_diffs(&mut diff);
+        assert_eq!(actions.len(), 1);
+        assert!(diff.finish().pending(FileLocation::Remote).next().is_none());
+    }
+}
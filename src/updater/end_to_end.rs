@@ -0,0 +1,180 @@
+//! Synchronizations of a temporary local folder with a [`Simulator`], covering the
+//! whole run from scanning both sides to persisting the tag database.
+
+use std::{path::PathBuf, sync::Arc};
+
+use tempfile::TempDir;
+
+use super::{
+    session::{session_path, SessionRef},
+    InitError, Uninitialized,
+};
+use crate::{
+    Command, Config, PrefixMapping, Repository, Simulator, SyncReport, SyncedPath, TagAction,
+};
+
+const REMOTE: &str = "/remote.php/dav/files/erik/data";
+
+fn remote_path(name: &str) -> String {
+    format!("{REMOTE}/{name}")
+}
+
+struct Setup {
+    dir: TempDir,
+    simulator: Arc<Simulator>,
+    config: Arc<Config>,
+}
+
+impl Setup {
+    fn new(configure: impl FnOnce(&mut Config)) -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let local = dir.path().join("data");
+        std::fs::create_dir(&local).unwrap();
+        let mut config = Config {
+            prefixes: vec![PrefixMapping::new(local, REMOTE.into()).unwrap()],
+            tag_database: dir.path().join("db.json"),
+            tag_backup_directory: dir.path().join("backups"),
+            ..Config::default()
+        };
+        configure(&mut config);
+        Self {
+            dir,
+            simulator: Arc::new(Simulator::new()),
+            config: Arc::new(config),
+        }
+    }
+
+    fn local_path(&self, name: &str) -> PathBuf {
+        self.dir.path().join("data").join(name)
+    }
+
+    /// Creates the file locally and on the simulated server with `tags` on the local one.
+    fn add_file(&self, name: &str, tags: &str) {
+        let path = self.local_path(name);
+        std::fs::write(&path, b"").unwrap();
+        xattr::set(&path, &self.config.local_tag_property_name, tags.as_bytes()).unwrap();
+        self.simulator.add_file(remote_path(name));
+    }
+
+    fn set_local_tags(&self, name: &str, tags: &str) {
+        xattr::set(
+            self.local_path(name),
+            &self.config.local_tag_property_name,
+            tags.as_bytes(),
+        )
+        .unwrap();
+    }
+
+    fn local_tags(&self, name: &str) -> String {
+        let tags = xattr::get(self.local_path(name), &self.config.local_tag_property_name)
+            .unwrap()
+            .unwrap_or_default();
+        String::from_utf8(tags).unwrap()
+    }
+
+    fn uninitialized(&self) -> Uninitialized {
+        Uninitialized::new(self.config.clone()).with_backend(self.simulator.clone())
+    }
+
+    fn reconfigure(&mut self, configure: impl FnOnce(&mut Config)) {
+        configure(Arc::make_mut(&mut self.config));
+    }
+
+    /// Synchronizes like the `sync` command and persists the tag database unless a
+    /// command failed in strict mode.
+    async fn sync(&self) -> Result<SyncReport, InitError> {
+        let mut initialized = self.uninitialized().initialize().await?;
+        let report = initialized.sync().await?;
+        if !self.config.strict || report.failed_commands() == 0 {
+            initialized.persist_repository().unwrap();
+        }
+        Ok(report)
+    }
+}
+
+#[tokio::test]
+async fn keep_local_tags_of_read_only_shares() {
+    let setup = Setup::new(|_| {});
+    setup.add_file("a.jpg", "holiday");
+    setup.simulator.share_read_only(remote_path("a.jpg"));
+
+    for _ in 0..2 {
+        let report = setup.sync().await.unwrap();
+        assert_eq!(report.read_only_shares.len(), 1);
+        assert_eq!(report.failed_commands(), 0);
+        assert_eq!(setup.local_tags("a.jpg"), "holiday");
+    }
+    assert!(setup
+        .simulator
+        .tags_of(remote_path("a.jpg"))
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn keep_tag_database_of_failed_strict_runs() {
+    let mut setup = Setup::new(|_| {});
+    setup.add_file("a.jpg", "red");
+    setup.add_file("b.jpg", "");
+    setup.sync().await.unwrap();
+    let database = std::fs::read_to_string(&setup.config.tag_database).unwrap();
+
+    setup.simulator.add_tag("locked", true, false);
+    setup.set_local_tags("a.jpg", "red,green");
+    setup.set_local_tags("b.jpg", "locked");
+    setup.reconfigure(|config| {
+        config.strict = true;
+        config.command_chunk_size = 1;
+    });
+    let report = setup.sync().await.unwrap();
+
+    assert_eq!(report.failed_commands(), 1);
+    assert_eq!(
+        std::fs::read_to_string(&setup.config.tag_database).unwrap(),
+        database
+    );
+}
+
+#[tokio::test]
+async fn resume_journaled_session() {
+    let setup = Setup::new(|_| {});
+    setup.add_file("a.jpg", "red,green");
+    setup.add_file("b.jpg", "blue");
+    setup.simulator.add_tag("blue", true, true);
+    setup.simulator.assign(remote_path("b.jpg"), "blue");
+
+    // The interrupted run already tagged b.jpg. Tagging a.jpg with green locally happened
+    // afterwards and is only picked up by a rescan.
+    let mut target = Repository::new(setup.config.prefixes.clone());
+    target.insert(SyncedPath::new(0, "a.jpg"), "red".parse().unwrap());
+    target.insert(SyncedPath::new(0, "b.jpg"), "blue".parse().unwrap());
+    let remaining = Command {
+        path: SyncedPath::new(0, "a.jpg"),
+        actions: vec![TagAction::add("red".parse().unwrap())],
+    };
+    SessionRef {
+        target: &target,
+        local: Vec::new(),
+        remote: vec![&remaining],
+        failed: &[],
+    }
+    .persist(&setup.config)
+    .unwrap();
+
+    assert!(matches!(
+        setup.sync().await,
+        Err(InitError::UnfinishedSession { .. })
+    ));
+    assert!(session_path(&setup.config).exists());
+
+    let initialized = setup.uninitialized().resume().await.unwrap().unwrap();
+    assert_eq!(initialized.report().failed_commands(), 0);
+    initialized.persist_repository().unwrap();
+
+    let tags = |name| setup.simulator.tags_of(remote_path(name)).unwrap();
+    assert_eq!(tags("a.jpg"), ["red".to_owned()].into());
+    assert_eq!(tags("b.jpg"), ["blue".to_owned()].into());
+    let repo = Repository::read_from_disk(&setup.config.tag_database).unwrap();
+    assert!(repo.files().eq(target.files()));
+    assert!(!session_path(&setup.config).exists());
+}
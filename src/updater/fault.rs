@@ -0,0 +1,58 @@
+use std::hash::BuildHasher;
+
+use crate::{Command, CommandOutcome, Config};
+
+/// Splits off commands which are artificially failed according to
+/// [`Config::simulate_failures`]. Returns the commands to execute and the simulated failures.
+pub fn inject_failures(
+    commands: Vec<Command>,
+    config: &Config,
+) -> (Vec<Command>, Vec<CommandOutcome>) {
+    let percentage = config.simulate_failures;
+    if percentage <= 0.0 {
+        return (commands, Vec::new());
+    }
+
+    let random = std::collections::hash_map::RandomState::new();
+    let (failed, executed): (Vec<_>, Vec<_>) =
+        commands.into_iter().enumerate().partition(|(i, _)| {
+            let roll = u32::try_from(random.hash_one(i) % 10_000).expect("always fits");
+            f64::from(roll) < percentage * 100.0
+        });
+    let failed = failed
+        .into_iter()
+        .map(|(_, command)| {
+            tracing::debug!("Simulating failure for {}", command.path);
+            CommandOutcome::Failed {
+                command,
+                error: "simulated failure".to_owned(),
+            }
+        })
+        .collect();
+    (executed.into_iter().map(|(_, cmd)| cmd).collect(), failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SyncedPath;
+
+    #[test]
+    fn fail_configured_fraction() {
+        let commands: Vec<_> = (0..100)
+            .map(|i| Command {
+                path: SyncedPath::new(0, &format!("{i}.txt")),
+                actions: Vec::new(),
+            })
+            .collect();
+        let mut config = Config::default();
+
+        let (executed, failed) = inject_failures(commands.clone(), &config);
+        assert_eq!((executed.len(), failed.len()), (100, 0));
+
+        config.simulate_failures = 100.0;
+        let (executed, failed) = inject_failures(commands, &config);
+        assert_eq!((executed.len(), failed.len()), (0, 100));
+        assert!(failed.iter().all(|outcome| !outcome.is_success()));
+    }
+}
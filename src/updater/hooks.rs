@@ -0,0 +1,277 @@
+//! User scripts which inspect the commands of a synchronization before they are applied.
+//!
+//! The script configured in [`Config::hook_script`] defines `on_command(cmd)`. `cmd` is a
+//! map with the `side` the command is applied to (`"local"` or `"remote"`), the local
+//! `path` of the file and the tags to `add` and `remove`. The function returns `true` to
+//! keep the command, `false` or `()` to veto it and a map with changed `add` and `remove`
+//! lists to rewrite it. Vetoed commands are computed again in the next synchronization.
+
+use std::path::PathBuf;
+
+use snafu::Snafu;
+
+use crate::{Command, Config, FileLocation, Repository};
+
+#[derive(Debug, Snafu)]
+pub enum HookError {
+    #[snafu(display("hook scripts are not supported by this build"))]
+    Unsupported,
+    #[cfg(feature = "scripting")]
+    #[snafu(display("failed to compile hook script {}", path.display()))]
+    Compile {
+        path: PathBuf,
+        source: Box<rhai::EvalAltResult>,
+    },
+    #[snafu(display("hook script {} does not define on_command(cmd)", path.display()))]
+    MissingFunction { path: PathBuf },
+}
+
+/// Hook script of [`Config::hook_script`], if any.
+#[derive(Default)]
+pub struct Hooks {
+    #[cfg(feature = "scripting")]
+    script: Option<Script>,
+}
+
+impl std::fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Hooks").finish_non_exhaustive()
+    }
+}
+
+impl Hooks {
+    /// Compiles the hook script of `config`.
+    pub fn load(config: &Config) -> Result<Self, HookError> {
+        let Some(path) = &config.hook_script else {
+            return Ok(Self::default());
+        };
+        #[cfg(feature = "scripting")]
+        {
+            Ok(Self {
+                script: Some(Script::compile(path)?),
+            })
+        }
+        #[cfg(not(feature = "scripting"))]
+        {
+            let _ = path;
+            UnsupportedSnafu.fail()
+        }
+    }
+
+    /// Passes `actions` for `location` through the script. Vetoed and rewritten commands
+    /// are reverted in `repo` and rewritten ones applied instead.
+    #[cfg_attr(
+        not(feature = "scripting"),
+        expect(
+            clippy::unused_self,
+            clippy::missing_const_for_fn,
+            clippy::needless_pass_by_ref_mut,
+            reason = "Commands pass unchanged without scripting"
+        )
+    )]
+    pub fn apply(
+        &self,
+        repo: &mut Repository,
+        location: FileLocation,
+        actions: Vec<Command>,
+    ) -> Vec<Command> {
+        #[cfg(feature = "scripting")]
+        if let Some(script) = &self.script {
+            return script.apply(repo, location, actions);
+        }
+        let _ = (repo, location);
+        actions
+    }
+}
+
+#[cfg(feature = "scripting")]
+struct Script {
+    engine: rhai::Engine,
+    ast: rhai::AST,
+}
+
+#[cfg(feature = "scripting")]
+impl Script {
+    const FUNCTION: &'static str = "on_command";
+    /// Bounds the work per command, so a looping script fails instead of hanging.
+    const MAX_OPERATIONS: u64 = 1_000_000;
+
+    fn compile(path: &std::path::Path) -> Result<Self, HookError> {
+        use snafu::ResultExt;
+
+        let mut engine = rhai::Engine::new();
+        engine.set_max_operations(Self::MAX_OPERATIONS);
+        engine.on_print(|text| tracing::info!("Hook script: {text}"));
+        engine.on_debug(|text, _, _| tracing::debug!("Hook script: {text}"));
+        let ast = engine
+            .compile_file(path.to_owned())
+            .context(CompileSnafu { path })?;
+        snafu::ensure!(
+            ast.iter_functions()
+                .any(|function| function.name == Self::FUNCTION && function.params.len() == 1),
+            MissingFunctionSnafu { path }
+        );
+        Ok(Self { engine, ast })
+    }
+
+    fn apply(
+        &self,
+        repo: &mut Repository,
+        location: FileLocation,
+        actions: Vec<Command>,
+    ) -> Vec<Command> {
+        let mut kept = Vec::with_capacity(actions.len());
+        for cmd in actions {
+            match self.verdict(repo, location, &cmd) {
+                Ok(Some(rewritten)) if rewritten == cmd => kept.push(cmd),
+                Ok(Some(rewritten)) => {
+                    tracing::info!("Hook script rewrote the command for {}", cmd.path);
+                    repo.revert(cmd);
+                    repo.apply(rewritten.clone());
+                    kept.push(rewritten);
+                }
+                Ok(None) => {
+                    tracing::info!("Hook script vetoed the command for {}", cmd.path);
+                    repo.revert(cmd);
+                }
+                Err(e) => {
+                    tracing::error!("Hook script failed for {}, skipping it: {e}", cmd.path);
+                    repo.revert(cmd);
+                }
+            }
+        }
+        kept
+    }
+
+    /// Calls the script for `cmd`. `None` if it vetoes the command.
+    fn verdict(
+        &self,
+        repo: &Repository,
+        location: FileLocation,
+        cmd: &Command,
+    ) -> Result<Option<Command>, String> {
+        let tags = |modification| -> rhai::Array {
+            cmd.actions
+                .iter()
+                .filter(|action| action.modification == modification)
+                .map(|action| action.tag.to_string().into())
+                .collect()
+        };
+        let side = match location {
+            FileLocation::Local => "local",
+            FileLocation::Remote => "remote",
+        };
+        let mut map = rhai::Map::new();
+        map.insert("side".into(), side.into());
+        let path = cmd.path.local_file(repo.prefixes());
+        map.insert("path".into(), path.display().to_string().into());
+        map.insert("add".into(), tags(crate::Modification::Add).into());
+        map.insert("remove".into(), tags(crate::Modification::Remove).into());
+
+        let result: rhai::Dynamic = self
+            .engine
+            .call_fn(&mut rhai::Scope::new(), &self.ast, Self::FUNCTION, (map,))
+            .map_err(|e| e.to_string())?;
+        if result.is_unit() {
+            return Ok(None);
+        }
+        if let Ok(keep) = result.as_bool() {
+            return Ok(keep.then(|| cmd.clone()));
+        }
+        let map = result
+            .try_cast::<rhai::Map>()
+            .ok_or("on_command must return a bool, () or a map")?;
+        let tags = |key: &str| -> Result<Vec<String>, String> {
+            map.get(key).map_or_else(
+                || Ok(Vec::new()),
+                |tags| {
+                    tags.clone()
+                        .into_typed_array::<String>()
+                        .map_err(|_| format!("`{key}` must be an array of strings"))
+                },
+            )
+        };
+        let mut builder = Command::for_path(cmd.path.clone());
+        for tag in tags("add")? {
+            builder = builder.add(tag);
+        }
+        for tag in tags("remove")? {
+            builder = builder.remove(tag);
+        }
+        match builder.build() {
+            Ok(rewritten) => Ok(Some(rewritten)),
+            Err(crate::CommandError::NoActions { .. }) => Ok(None),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "scripting"))]
+mod tests {
+    use super::*;
+    use crate::{PrefixMapping, SyncedPath, Tags};
+
+    #[test]
+    fn veto_and_rewrite_commands() {
+        let script = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(
+            script.path(),
+            r#"
+            fn on_command(cmd) {
+                if cmd.side == "local" && cmd.remove.contains("archived") {
+                    return false;
+                }
+                cmd.add = cmd.add.map(|tag| if tag == "Red" { "red" } else { tag });
+                cmd
+            }
+            "#,
+        )
+        .unwrap();
+        let config = Config {
+            hook_script: Some(script.path().to_owned()),
+            ..Config::default()
+        };
+        let hooks = Hooks::load(&config).unwrap();
+
+        let prefixes =
+            vec![PrefixMapping::new("/local".into(), "/remote.php/dav/files/user".into()).unwrap()];
+        let tags = |tags: &str| -> Tags { tags.parse().unwrap() };
+        let mut repo = Repository::new(prefixes);
+        repo.insert(SyncedPath::new(0, "a.txt"), tags(""));
+        repo.insert(SyncedPath::new(0, "b.txt"), tags("Red"));
+        let unarchive = Command::for_path(SyncedPath::new(0, "a.txt"))
+            .remove("archived")
+            .build()
+            .unwrap();
+        let add = |tag: &str| {
+            Command::for_path(SyncedPath::new(0, "b.txt"))
+                .add(tag)
+                .build()
+                .unwrap()
+        };
+
+        let local = hooks.apply(&mut repo, FileLocation::Local, vec![unarchive]);
+        assert!(local.is_empty());
+        assert_eq!(
+            repo.tags(&SyncedPath::new(0, "a.txt")),
+            Some(&tags("archived"))
+        );
+        let remote = hooks.apply(&mut repo, FileLocation::Remote, vec![add("Red")]);
+        assert_eq!(remote, [add("red")]);
+        assert_eq!(repo.tags(&SyncedPath::new(0, "b.txt")), Some(&tags("red")));
+    }
+
+    #[test]
+    fn reject_script_without_hook() {
+        let script = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(script.path(), "fn other(cmd) { true }").unwrap();
+        let config = Config {
+            hook_script: Some(script.path().to_owned()),
+            ..Config::default()
+        };
+        assert!(matches!(
+            Hooks::load(&config),
+            Err(HookError::MissingFunction { .. })
+        ));
+    }
+}
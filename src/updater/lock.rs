@@ -0,0 +1,66 @@
+use std::{
+    fs::{File, TryLockError},
+    path::PathBuf,
+};
+
+use snafu::ResultExt;
+
+use crate::Config;
+
+use super::{InitError, LockFileSnafu, LockedSnafu};
+
+/// Exclusive lock next to the tag database, held for a whole synchronization so concurrent
+/// runs neither apply the same commands twice nor overwrite each other's database. The
+/// operating system releases it when the process exits, even after a crash.
+#[derive(Debug)]
+pub struct SyncLock {
+    _file: File,
+}
+
+fn path(config: &Config) -> PathBuf {
+    config.tag_database.with_extension("lock")
+}
+
+impl SyncLock {
+    /// Takes the lock without waiting for another synchronization to finish.
+    pub fn acquire(config: &Config) -> Result<Self, InitError> {
+        let path = path(config);
+        // Synchronized users keep their state in a directory of their own.
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir).context(LockFileSnafu { path: &path })?;
+        }
+        let file = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .context(LockFileSnafu { path: &path })?;
+        match file.try_lock() {
+            Ok(()) => Ok(Self { _file: file }),
+            Err(TryLockError::WouldBlock) => LockedSnafu { path }.fail(),
+            Err(TryLockError::Error(e)) => Err(e).context(LockFileSnafu { path }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuse_second_synchronization() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            tag_database: dir.path().join("tags.json"),
+            ..Config::default()
+        };
+
+        let lock = SyncLock::acquire(&config).unwrap();
+        assert!(matches!(
+            SyncLock::acquire(&config),
+            Err(InitError::Locked { .. })
+        ));
+        drop(lock);
+        assert!(SyncLock::acquire(&config).is_ok());
+    }
+}
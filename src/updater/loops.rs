@@ -0,0 +1,223 @@
+use std::{
+    collections::{btree_map::Entry, BTreeMap, BTreeSet},
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use atomic_write_file::AtomicWriteFile;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{Command, Config, Modification, Repository, SyncedPath, Tag};
+
+/// Flips older than this are forgotten.
+const WINDOW_SECS: u64 = 24 * 60 * 60;
+
+/// Detects tags which flip between added and removed in every synchronization, e.g.
+/// because another tool rewrites the extended attributes. Stored next to the tag database.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LoopDetector {
+    history: BTreeMap<SyncedPath, BTreeMap<Tag, TagHistory>>,
+    /// Files which are not synchronized anymore because their tags flip.
+    looping: BTreeSet<SyncedPath>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TagHistory {
+    last: Modification,
+    /// Seconds since the Unix epoch of the last modification.
+    at: u64,
+    /// Seconds since the Unix epoch of recent changes reverting the previous one.
+    flips: Vec<u64>,
+}
+
+fn path(config: &Config) -> PathBuf {
+    config.tag_database.with_extension("loops.json")
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+impl LoopDetector {
+    /// Loads the history of earlier synchronizations.
+    pub fn load(config: &Config) -> Self {
+        let path = path(config);
+        let data = match std::fs::read_to_string(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Self::default(),
+            Err(e) => {
+                warn!("Failed to read loop history {}: {e}", path.display());
+                return Self::default();
+            }
+        };
+        serde_json::from_str(&data).unwrap_or_else(|e| {
+            warn!("Ignoring invalid loop history {}: {e}", path.display());
+            Self::default()
+        })
+    }
+
+    pub fn persist(&self, config: &Config) {
+        let path = path(config);
+        let write = || -> std::io::Result<()> {
+            let data = serde_json::to_vec(self)?;
+            let mut file = AtomicWriteFile::open(&path)?;
+            std::io::Write::write_all(&mut file, &data)?;
+            file.commit()
+        };
+        if let Err(e) = write() {
+            warn!("Failed to store loop history {}: {e}", path.display());
+        }
+    }
+
+    #[must_use]
+    pub const fn looping(&self) -> &BTreeSet<SyncedPath> {
+        &self.looping
+    }
+
+    /// Releases all looping files so they are synchronized again. Returns their number.
+    pub fn release(&mut self) -> usize {
+        std::mem::take(&mut self.looping).len()
+    }
+
+    /// Records the modifications of `commands` and marks files as looping once a tag
+    /// flipped `threshold` times within a day.
+    pub fn record<'a>(
+        &mut self,
+        threshold: usize,
+        commands: impl IntoIterator<Item = &'a Command>,
+        now: u64,
+    ) {
+        self.history.retain(|_, tags| {
+            tags.retain(|_, history| now.saturating_sub(history.at) < WINDOW_SECS);
+            !tags.is_empty()
+        });
+        for cmd in commands {
+            if self.looping.contains(&cmd.path) {
+                continue;
+            }
+            let tags = self.history.entry(cmd.path.clone()).or_default();
+            for action in &cmd.actions {
+                let history = match tags.entry(action.tag.clone()) {
+                    Entry::Vacant(entry) => entry.insert(TagHistory {
+                        last: action.modification,
+                        at: now,
+                        flips: Vec::new(),
+                    }),
+                    Entry::Occupied(entry) => entry.into_mut(),
+                };
+                if history.last != action.modification {
+                    history.flips.push(now);
+                }
+                history.last = action.modification;
+                history.at = now;
+                history
+                    .flips
+                    .retain(|flip| now.saturating_sub(*flip) < WINDOW_SECS);
+            }
+            if tags
+                .values()
+                .any(|history| history.flips.len() >= threshold)
+            {
+                warn!(
+                    "Tags of {} keep flipping, maybe another tool rewrites them",
+                    cmd.path
+                );
+                self.history.remove(&cmd.path);
+                self.looping.insert(cmd.path.clone());
+            }
+        }
+    }
+
+    /// Drops the commands of looping files and reverts them in `repo`.
+    pub fn hold(&self, repo: &mut Repository, actions: Vec<Command>) -> Vec<Command> {
+        let (held, actions): (Vec<_>, Vec<_>) = actions
+            .into_iter()
+            .partition(|cmd| self.looping.contains(&cmd.path));
+        for cmd in held {
+            repo.revert(cmd);
+        }
+        actions
+    }
+}
+
+/// Synchronizes the files again which were held back because their tags kept flipping.
+/// Returns their number.
+#[must_use]
+pub fn release_looping_files(config: &Config) -> usize {
+    let mut detector = LoopDetector::load(config);
+    let released = detector.release();
+    detector.persist(config);
+    released
+}
+
+/// Records `commands` with the current time if loop detection is enabled and drops the
+/// commands of looping files from both lists.
+pub fn hold_loops(
+    detector: &mut LoopDetector,
+    config: &Config,
+    repo: &mut Repository,
+    commands: [Vec<Command>; 2],
+) -> [Vec<Command>; 2] {
+    if let Some(threshold) = config.loop_flips_per_day {
+        detector.record(threshold, commands.iter().flatten(), now());
+    }
+    commands.map(|actions| detector.hold(repo, actions))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PrefixMapping, TagAction, Tags};
+
+    #[test]
+    fn hold_files_with_flipping_tags() {
+        let prefixes =
+            vec![PrefixMapping::new("/local".into(), "/remote.php/dav/files/user".into()).unwrap()];
+        let command = |path: &str, modification| Command {
+            path: SyncedPath::new(0, path),
+            actions: vec![TagAction {
+                tag: "red".parse().unwrap(),
+                modification,
+            }],
+        };
+        let flip = [
+            command("a.txt", Modification::Remove),
+            command("a.txt", Modification::Add),
+        ];
+        let mut detector = LoopDetector::default();
+
+        for run in 0..2 {
+            detector.record(4, &flip, run * 60);
+            assert!(detector.looping().is_empty());
+        }
+        // Flips from the day before are forgotten.
+        detector.record(4, &flip, WINDOW_SECS + 120);
+        assert!(detector.looping().is_empty());
+        detector.record(4, &flip, WINDOW_SECS + 180);
+        assert!(detector.looping().is_empty());
+        detector.record(4, &flip, WINDOW_SECS + 240);
+        detector.record(4, &[command("b.txt", Modification::Add)], WINDOW_SECS + 240);
+        assert_eq!(
+            detector.looping().iter().collect::<Vec<_>>(),
+            [&SyncedPath::new(0, "a.txt")]
+        );
+
+        let mut repo = Repository::new(prefixes);
+        repo.insert(SyncedPath::new(0, "a.txt"), Tags::default());
+        let actions = detector.hold(
+            &mut repo,
+            vec![
+                command("a.txt", Modification::Remove),
+                command("b.txt", Modification::Add),
+            ],
+        );
+        assert_eq!(actions, [command("b.txt", Modification::Add)]);
+        let red: Tags = std::iter::once("red").collect();
+        assert_eq!(repo.tags(&SyncedPath::new(0, "a.txt")), Some(&red));
+        assert_eq!(detector.release(), 1);
+    }
+}
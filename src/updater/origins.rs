@@ -0,0 +1,183 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use atomic_write_file::AtomicWriteFile;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{CommandOutcome, Config, FileLocation, Modification, SyncedPath, Tag};
+
+/// Side which introduced a tag assignment and when.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TagOrigin {
+    pub side: FileLocation,
+    /// Seconds since the Unix epoch of the synchronization which copied the tag.
+    pub added: u64,
+}
+
+impl fmt::Display for TagOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let side = match self.side {
+            FileLocation::Local => "locally",
+            FileLocation::Remote => "on Nextcloud",
+        };
+        let added = i64::try_from(self.added)
+            .ok()
+            .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+            .map(|time| time.with_timezone(&chrono::Local));
+        match added {
+            Some(time) => write!(f, "added {side} on {}", time.format("%Y-%m-%d %H:%M")),
+            None => write!(f, "added {side}"),
+        }
+    }
+}
+
+/// Origins of the tag assignments copied by earlier synchronizations. Stored next to the
+/// tag database. Tags which were already on both sides at the first synchronization have
+/// no origin.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TagOrigins {
+    files: BTreeMap<SyncedPath, BTreeMap<Tag, TagOrigin>>,
+}
+
+fn path(config: &Config) -> PathBuf {
+    config.tag_database.with_extension("origins.json")
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+impl TagOrigins {
+    /// Loads the origins recorded by earlier synchronizations.
+    pub fn load(config: &Config) -> Self {
+        let path = path(config);
+        let data = match std::fs::read_to_string(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Self::default(),
+            Err(e) => {
+                warn!("Failed to read tag origins {}: {e}", path.display());
+                return Self::default();
+            }
+        };
+        serde_json::from_str(&data).unwrap_or_else(|e| {
+            warn!("Ignoring invalid tag origins {}: {e}", path.display());
+            Self::default()
+        })
+    }
+
+    pub fn persist(&self, config: &Config) {
+        let path = path(config);
+        let write = || -> std::io::Result<()> {
+            let data = serde_json::to_vec(self)?;
+            let mut file = AtomicWriteFile::open(&path)?;
+            std::io::Write::write_all(&mut file, &data)?;
+            file.commit()
+        };
+        if let Err(e) = write() {
+            warn!("Failed to store tag origins {}: {e}", path.display());
+        }
+    }
+
+    /// Records the actions applied to `target`. Added tags originate from the other side
+    /// and keep their first origin, removed tags are forgotten.
+    pub fn record(&mut self, target: FileLocation, outcomes: &[CommandOutcome], now: u64) {
+        let side = match target {
+            FileLocation::Local => FileLocation::Remote,
+            FileLocation::Remote => FileLocation::Local,
+        };
+        for cmd in outcomes.iter().filter_map(CommandOutcome::applied) {
+            let tags = self.files.entry(cmd.path.clone()).or_default();
+            for action in &cmd.actions {
+                match action.modification {
+                    Modification::Add => {
+                        tags.entry(action.tag.clone())
+                            .or_insert(TagOrigin { side, added: now });
+                    }
+                    Modification::Remove => {
+                        tags.remove(&action.tag);
+                    }
+                }
+            }
+            if tags.is_empty() {
+                self.files.remove(&cmd.path);
+            }
+        }
+    }
+
+    /// Records the actions applied to `target` with the current time.
+    pub fn record_now(&mut self, target: FileLocation, outcomes: &[CommandOutcome]) {
+        self.record(target, outcomes, now());
+    }
+
+    #[must_use]
+    pub fn of(&self, path: &SyncedPath) -> Option<&BTreeMap<Tag, TagOrigin>> {
+        self.files.get(path)
+    }
+
+    /// Files whose earliest recorded tag was created locally. Used by [`crate::Side::Origin`].
+    #[must_use]
+    pub fn created_locally(&self) -> BTreeSet<SyncedPath> {
+        self.files
+            .iter()
+            .filter(|(_, tags)| {
+                tags.values()
+                    .min_by_key(|origin| origin.added)
+                    .is_some_and(|origin| origin.side == FileLocation::Local)
+            })
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+}
+
+/// Origins of the tags of a file recorded by earlier synchronizations.
+#[must_use]
+pub fn tag_origins(config: &Config, path: &SyncedPath) -> BTreeMap<Tag, TagOrigin> {
+    TagOrigins::load(config)
+        .of(path)
+        .cloned()
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Command, TagAction};
+
+    #[test]
+    fn keep_first_origin_of_tags() {
+        let path = SyncedPath::new(0, "a.txt");
+        let outcome = |modification| {
+            vec![CommandOutcome::Success(Command {
+                path: path.clone(),
+                actions: vec![TagAction {
+                    tag: "red".parse().unwrap(),
+                    modification,
+                }],
+            })]
+        };
+        let red: Tag = "red".parse().unwrap();
+        let mut origins = TagOrigins::default();
+
+        origins.record(FileLocation::Remote, &outcome(Modification::Add), 10);
+        origins.record(FileLocation::Local, &outcome(Modification::Add), 20);
+        assert_eq!(
+            origins.of(&path).unwrap()[&red],
+            TagOrigin {
+                side: FileLocation::Local,
+                added: 10
+            }
+        );
+        assert_eq!(origins.created_locally(), BTreeSet::from([path.clone()]));
+
+        origins.record(FileLocation::Local, &outcome(Modification::Remove), 30);
+        assert!(origins.of(&path).is_none());
+    }
+}
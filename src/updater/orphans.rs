@@ -0,0 +1,105 @@
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Command, Modification, Repository, SyncedPath, TagAction};
+
+/// Tag added to local files by [`OrphanPolicy::Mark`].
+pub const ORPHAN_TAG: &str = "orphaned-remote";
+
+/// What to do with the tags of local files whose remote counterpart was deleted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OrphanPolicy {
+    /// Remove the tags of the local file, like for any file whose tags were removed remotely.
+    #[default]
+    Clear,
+    /// Keep the tags of the local file.
+    Keep,
+    /// Keep the tags of the local file and add the `orphaned-remote` tag.
+    Mark,
+}
+
+/// Files whose remaining tags are all removed while the file still exists locally.
+/// Either all their tags were removed remotely or they were deleted remotely.
+pub fn candidates(repo: &Repository, actions: &[Command]) -> Vec<SyncedPath> {
+    actions
+        .iter()
+        .filter(|cmd| {
+            cmd.actions
+                .iter()
+                .all(|action| action.modification == Modification::Remove)
+        })
+        .filter(|cmd| repo.tags(&cmd.path).is_some_and(|tags| tags.is_empty()))
+        .filter(|cmd| cmd.path.local_file(repo.prefixes()).exists())
+        .map(|cmd| cmd.path.clone())
+        .collect()
+}
+
+/// Replaces the local commands of `orphans` according to `policy`. The repository keeps
+/// the tags of orphans which are not cleared so they are not pushed to Nextcloud again.
+pub fn apply_policy(
+    policy: OrphanPolicy,
+    repo: &mut Repository,
+    actions: Vec<Command>,
+    orphans: &BTreeSet<SyncedPath>,
+) -> Vec<Command> {
+    if policy == OrphanPolicy::Clear {
+        return actions;
+    }
+    let (orphaned, mut actions): (Vec<_>, Vec<_>) = actions
+        .into_iter()
+        .partition(|cmd| orphans.contains(&cmd.path));
+    for cmd in orphaned {
+        let path = cmd.path.clone();
+        repo.revert(cmd);
+        if policy == OrphanPolicy::Mark {
+            let mark = Command {
+                path,
+                actions: vec![TagAction {
+                    tag: ORPHAN_TAG.parse().expect("valid tag"),
+                    modification: Modification::Add,
+                }],
+            };
+            repo.apply(mark.clone());
+            actions.push(mark);
+        }
+    }
+    actions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PrefixMapping, Tags};
+
+    #[test]
+    fn mark_orphans() {
+        let path = SyncedPath::new(0, "a.jpg");
+        let mut repo = Repository::new(vec![PrefixMapping::new(
+            "/local".into(),
+            "/remote.php/dav/files/user".into(),
+        )
+        .unwrap()]);
+        repo.insert(path.clone(), Tags::default());
+        let remove: Tags = "red,blue".parse().unwrap();
+        let actions = vec![Command {
+            path: path.clone(),
+            actions: remove
+                .into_iter()
+                .map(|tag| TagAction {
+                    tag,
+                    modification: Modification::Remove,
+                })
+                .collect(),
+        }];
+        let orphans = BTreeSet::from([path.clone()]);
+
+        let actions = apply_policy(OrphanPolicy::Mark, &mut repo, actions, &orphans);
+        assert_eq!(actions.len(), 1);
+        assert_eq!(actions[0].actions[0].tag.to_string(), ORPHAN_TAG);
+        assert_eq!(
+            repo.tags(&path).unwrap().to_string(),
+            "blue,orphaned-remote,red"
+        );
+    }
+}
@@ -0,0 +1,97 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use atomic_write_file::AtomicWriteFile;
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+
+use crate::{Command, Config, Repository};
+
+/// Commands of an interrupted synchronization which have not been applied yet.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Session {
+    /// Repository after all commands succeeded.
+    pub target: Repository,
+    pub local: Vec<Command>,
+    pub remote: Vec<Command>,
+    /// Commands which failed before the interruption.
+    pub failed: Vec<Command>,
+}
+
+/// Borrowed counterpart of [`Session`] to avoid cloning the repository for every write.
+#[derive(Serialize)]
+pub struct SessionRef<'a> {
+    pub target: &'a Repository,
+    pub local: Vec<&'a Command>,
+    pub remote: Vec<&'a Command>,
+    pub failed: &'a [Command],
+}
+
+/// The session journal is stored next to the tag database.
+pub fn session_path(config: &Config) -> PathBuf {
+    config.tag_database.with_extension("session.json")
+}
+
+impl Session {
+    /// Loads the unfinished session, if there is one.
+    pub fn load(config: &Config) -> Result<Option<Self>, SessionError> {
+        let path = &session_path(config);
+        let data = match std::fs::read_to_string(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).context(IoSnafu { path }),
+        };
+        serde_json::from_str(&data)
+            .map(Some)
+            .context(SerdeSnafu { path })
+    }
+
+    /// Removes the session journal. Returns whether one existed.
+    pub fn discard(config: &Config) -> bool {
+        std::fs::remove_file(session_path(config)).is_ok()
+    }
+}
+
+impl<'a> SessionRef<'a> {
+    pub fn new(
+        target: &'a Repository,
+        local: &'a [Command],
+        remote: &'a [Command],
+        failed: &'a [Command],
+    ) -> Self {
+        Self {
+            target,
+            local: local.iter().collect(),
+            remote: remote.iter().collect(),
+            failed,
+        }
+    }
+
+    pub fn persist(&self, config: &Config) -> Result<(), SessionError> {
+        let path = &session_path(config);
+        let data = serde_json::to_string(self).context(SerdeSnafu { path })?;
+        write_atomically(path, data.as_bytes()).context(IoSnafu { path })
+    }
+}
+
+fn write_atomically(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let mut file = AtomicWriteFile::open(path)?;
+    file.write_all(data)?;
+    file.commit()
+}
+
+#[derive(Debug, Snafu)]
+pub enum SessionError {
+    #[snafu(display("failed to access session journal {}", path.display()))]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("session journal {} is corrupt", path.display()))]
+    Serde {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+}
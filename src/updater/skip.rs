@@ -0,0 +1,69 @@
+use std::collections::BTreeSet;
+
+use crate::{Command, Repository, SyncedPath};
+
+/// Whether `path` or one of its parent folders carries [`crate::Config::skip_tag`].
+pub fn is_skipped(skipped: &BTreeSet<SyncedPath>, path: &SyncedPath) -> bool {
+    skipped.iter().any(|skipped| path.starts_with(skipped))
+}
+
+/// Drops the commands of skipped files and reverts them in `repo`, so their changes are
+/// synchronized once the skip tag is removed again.
+pub fn hold(
+    repo: &mut Repository,
+    actions: Vec<Command>,
+    skipped: &BTreeSet<SyncedPath>,
+) -> Vec<Command> {
+    if skipped.is_empty() {
+        return actions;
+    }
+    let (held, actions): (Vec<_>, Vec<_>) = actions
+        .into_iter()
+        .partition(|cmd| is_skipped(skipped, &cmd.path));
+    for cmd in held {
+        tracing::debug!("Skipping {} tagged on Nextcloud", cmd.path);
+        repo.revert(cmd);
+    }
+    actions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PrefixMapping, Tags};
+
+    #[test]
+    fn hold_files_in_skipped_folders() {
+        let prefixes =
+            vec![PrefixMapping::new("/local".into(), "/remote.php/dav/files/user".into()).unwrap()];
+        let tags = |tags: &str| -> Tags { tags.parse().unwrap() };
+        let add = |path: &str, tag: &str| {
+            Command::for_path(SyncedPath::new(0, path))
+                .add(tag)
+                .build()
+                .unwrap()
+        };
+
+        let mut repo = Repository::new(prefixes);
+        repo.insert(SyncedPath::new(0, "private/a.txt"), tags("red"));
+        repo.insert(SyncedPath::new(0, "private.txt"), tags("red"));
+        repo.insert(SyncedPath::new(0, "b.txt"), tags("red,ncts-skip"));
+        let actions = vec![
+            add("private/a.txt", "red"),
+            add("private.txt", "red"),
+            add("b.txt", "red"),
+        ];
+        let skipped = BTreeSet::from([SyncedPath::new(0, "private"), SyncedPath::new(0, "b.txt")]);
+
+        let actions = hold(&mut repo, actions, &skipped);
+        assert_eq!(actions, [add("private.txt", "red")]);
+        assert_eq!(
+            repo.tags(&SyncedPath::new(0, "private/a.txt")),
+            Some(&tags(""))
+        );
+        assert_eq!(
+            repo.tags(&SyncedPath::new(0, "b.txt")),
+            Some(&tags("ncts-skip"))
+        );
+    }
+}
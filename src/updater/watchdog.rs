@@ -0,0 +1,122 @@
+use std::{future::Future, sync::Mutex, time::Duration};
+
+use tokio::time::Instant;
+
+use crate::{Config, Exchange, FileLocation, InitError, Middleware};
+
+use super::StalledSnafu;
+
+/// Step of a synchronization which is aborted if it stalls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Scan(FileLocation),
+    Update(FileLocation),
+}
+
+impl std::fmt::Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let side = |location| match location {
+            FileLocation::Local => "local",
+            FileLocation::Remote => "remote",
+        };
+        match self {
+            Self::Scan(location) => write!(f, "scanning {} files", side(*location)),
+            Self::Update(location) => write!(f, "updating {} tags", side(*location)),
+        }
+    }
+}
+
+/// Detects phases which make no progress for [`Config::stall_timeout_secs`]. As a
+/// [`Middleware`] of the connection, every response of the server counts as progress,
+/// as does every finished phase.
+#[derive(Debug)]
+pub struct Watchdog {
+    timeout: Option<Duration>,
+    last_progress: Mutex<Instant>,
+}
+
+impl Watchdog {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            timeout: config.stall_timeout_secs.map(Duration::from_secs),
+            last_progress: Mutex::new(Instant::now()),
+        }
+    }
+
+    fn pet(&self) {
+        *self
+            .last_progress
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner) = Instant::now();
+    }
+
+    fn idle(&self) -> Duration {
+        self.last_progress
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .elapsed()
+    }
+
+    /// Runs `future` until it completes or stalls. A stalled future is dropped, so its
+    /// partial results are lost unless it persisted them.
+    pub async fn watch<F: Future>(&self, phase: Phase, future: F) -> Result<F::Output, InitError> {
+        let Some(timeout) = self.timeout else {
+            return Ok(future.await);
+        };
+        self.pet();
+        let mut future = std::pin::pin!(future);
+        loop {
+            let idle = self.idle();
+            let Some(remaining) = timeout.checked_sub(idle).filter(|left| !left.is_zero()) else {
+                tracing::error!("Aborted {phase} after {}s without progress", idle.as_secs());
+                return StalledSnafu {
+                    phase,
+                    secs: timeout.as_secs(),
+                }
+                .fail();
+            };
+            tokio::select! {
+                output = &mut future => {
+                    self.pet();
+                    return Ok(output);
+                }
+                () = tokio::time::sleep(remaining) => {}
+            }
+        }
+    }
+}
+
+impl Middleware for Watchdog {
+    fn on_response(&self, _exchange: &Exchange<'_>) {
+        self.pet();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn abort_only_without_progress() {
+        let config = Config {
+            stall_timeout_secs: Some(10),
+            ..Config::default()
+        };
+        let watchdog = Watchdog::new(&config);
+        let phase = Phase::Scan(FileLocation::Remote);
+
+        let progressing = async {
+            for _ in 0..5 {
+                tokio::time::sleep(Duration::from_secs(8)).await;
+                watchdog.pet();
+            }
+        };
+        assert!(watchdog.watch(phase, progressing).await.is_ok());
+
+        let stalled = tokio::time::sleep(Duration::from_secs(30));
+        assert!(matches!(
+            watchdog.watch(phase, stalled).await,
+            Err(InitError::Stalled { secs: 10, .. })
+        ));
+    }
+}
@@ -0,0 +1,154 @@
+//! Notifications about changed local files for continuous synchronization.
+
+use std::{collections::BTreeSet, path::PathBuf, time::Duration};
+
+use notify::{
+    event::ModifyKind, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher as _,
+};
+use snafu::{ResultExt, Snafu};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+use tracing::warn;
+
+use crate::Config;
+
+pub use window::{time_until_scan_window, ScanWindow, ScanWindowParseError};
+
+mod window;
+
+#[derive(Debug, Snafu)]
+pub enum WatchError {
+    #[snafu(display("failed to set up file notifications: {source}"))]
+    Setup { source: notify::Error },
+    #[snafu(display("failed to watch {}: {source}", path.display()))]
+    Watch {
+        path: PathBuf,
+        source: notify::Error,
+    },
+}
+
+/// Local changes reported by a [`Watcher`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LocalChanges {
+    /// Files or folders whose tags may have changed.
+    Paths(BTreeSet<PathBuf>),
+    /// Notifications were lost, e.g. because the queue of the kernel overflowed, so all
+    /// files have to be scanned again.
+    Rescan,
+}
+
+impl LocalChanges {
+    /// Adds a notification. `None` stands for lost ones.
+    fn add(&mut self, paths: Option<Vec<PathBuf>>) {
+        match (self, paths) {
+            (Self::Paths(changed), Some(paths)) => changed.extend(paths),
+            (changes, None) => *changes = Self::Rescan,
+            (Self::Rescan, Some(_)) => {}
+        }
+    }
+}
+
+/// Watches the local prefixes for changes of files and their tags.
+///
+/// Changes are debounced by [`Config::watch_debounce_secs`] because writing tags
+/// usually changes several files or attributes in a row.
+pub struct Watcher {
+    /// Stops the notifications when dropped.
+    _notifier: RecommendedWatcher,
+    events: UnboundedReceiver<Option<Vec<PathBuf>>>,
+    debounce: Duration,
+}
+
+impl std::fmt::Debug for Watcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Watcher")
+            .field("debounce", &self.debounce)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Watcher {
+    /// Starts watching all prefixes recursively.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the notifications cannot be set up, e.g.
+    /// because a prefix does not exist or the limit of watches is reached.
+    pub fn new(config: &Config) -> Result<Self, WatchError> {
+        let (sender, events) = unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            let paths = match event {
+                Ok(event) if event.need_rescan() => None,
+                Ok(event) if is_tag_change(event.kind) => Some(event.paths),
+                Ok(_) => return,
+                Err(e) => {
+                    warn!("Lost notifications about local changes: {e}");
+                    None
+                }
+            };
+            // The receiver is only gone once the watcher is dropped.
+            let _ = sender.send(paths);
+        })
+        .context(SetupSnafu)?;
+        for prefix in &config.prefixes {
+            watcher
+                .watch(prefix.local(), RecursiveMode::Recursive)
+                .context(WatchSnafu {
+                    path: prefix.local(),
+                })?;
+        }
+        Ok(Self {
+            _notifier: watcher,
+            events,
+            debounce: Duration::from_secs(config.watch_debounce_secs),
+        })
+    }
+
+    /// Waits for the next changes. Returns once no further change was reported for the
+    /// debounce period.
+    pub async fn changes(&mut self) -> LocalChanges {
+        let mut changes = LocalChanges::Paths(BTreeSet::new());
+        changes.add(self.events.recv().await.flatten());
+        while let Ok(Some(paths)) = tokio::time::timeout(self.debounce, self.events.recv()).await {
+            changes.add(paths);
+        }
+        changes
+    }
+}
+
+/// Whether the event may change the tags of a file. Reading and writing the contents of
+/// a file does not.
+const fn is_tag_change(kind: EventKind) -> bool {
+    !matches!(
+        kind,
+        EventKind::Access(_) | EventKind::Modify(ModifyKind::Data(_))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PrefixMapping;
+
+    #[tokio::test]
+    async fn report_changed_tags() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            prefixes: vec![PrefixMapping::new(
+                dir.path().to_owned(),
+                "/remote.php/dav/files/user".into(),
+            )
+            .unwrap()],
+            watch_debounce_secs: 1,
+            ..Config::default()
+        };
+        let file = dir.path().join("a.txt");
+        std::fs::write(&file, "").unwrap();
+
+        let mut watcher = Watcher::new(&config).unwrap();
+        xattr::set(&file, &config.local_tag_property_name, b"red").unwrap();
+        let changes = tokio::time::timeout(Duration::from_secs(10), watcher.changes())
+            .await
+            .unwrap();
+        assert_eq!(changes, LocalChanges::Paths(BTreeSet::from([file])));
+    }
+}
@@ -0,0 +1,121 @@
+use std::{fmt, str::FromStr, time::Duration};
+
+use chrono::{NaiveTime, Timelike};
+use serde::{Deserialize, Serialize};
+use snafu::{OptionExt, Snafu};
+
+#[derive(Debug, Snafu)]
+#[snafu(display("invalid scan window {input:?}, expected a time range like 01:00-06:00"))]
+pub struct ScanWindowParseError {
+    input: String,
+}
+
+/// Time of day in which full synchronizations may run, e.g. `01:00-06:00`. Windows
+/// ending before they start span midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ScanWindow {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl ScanWindow {
+    fn contains(self, time: NaiveTime) -> bool {
+        if self.start < self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+
+    /// Time from `time` until the window opens the next time.
+    fn until_start(self, time: NaiveTime) -> Duration {
+        const DAY_SECS: i64 = 24 * 60 * 60;
+        let secs = (self.start - time).num_seconds().rem_euclid(DAY_SECS);
+        Duration::from_secs(secs.unsigned_abs())
+    }
+}
+
+/// Time until one of the `windows` is open, zero if one is open now or there are none.
+#[must_use]
+pub fn time_until_scan_window(windows: &[ScanWindow]) -> Duration {
+    until_open(
+        windows,
+        chrono::Local::now()
+            .time()
+            .with_nanosecond(0)
+            .unwrap_or_default(),
+    )
+}
+
+fn until_open(windows: &[ScanWindow], time: NaiveTime) -> Duration {
+    if windows.iter().any(|window| window.contains(time)) {
+        return Duration::ZERO;
+    }
+    windows
+        .iter()
+        .map(|window| window.until_start(time))
+        .min()
+        .unwrap_or_default()
+}
+
+impl FromStr for ScanWindow {
+    type Err = ScanWindowParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let time = |time: &str| NaiveTime::parse_from_str(time.trim(), "%H:%M").ok();
+        let (start, end) = s
+            .split_once('-')
+            .and_then(|(start, end)| Some((time(start)?, time(end)?)))
+            .context(ScanWindowParseSnafu { input: s })?;
+        Ok(Self { start, end })
+    }
+}
+
+impl TryFrom<String> for ScanWindow {
+    type Error = ScanWindowParseError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<ScanWindow> for String {
+    fn from(window: ScanWindow) -> Self {
+        window.to_string()
+    }
+}
+
+impl fmt::Display for ScanWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wait_for_next_window() {
+        let windows: Vec<ScanWindow> = ["01:00-06:00", "22:30-00:30"]
+            .iter()
+            .map(|window| window.parse().unwrap())
+            .collect();
+        assert_eq!(windows[1].to_string(), "22:30-00:30");
+        assert!("01:00".parse::<ScanWindow>().is_err());
+        let at =
+            |time: &str| until_open(&windows, NaiveTime::parse_from_str(time, "%H:%M").unwrap());
+
+        assert_eq!(at("02:00"), Duration::ZERO);
+        assert_eq!(at("00:15"), Duration::ZERO);
+        assert_eq!(at("00:30"), Duration::from_mins(30));
+        assert_eq!(at("12:00"), Duration::from_mins(10 * 60 + 30));
+        assert_eq!(until_open(&[], NaiveTime::MIN), Duration::ZERO);
+    }
+}
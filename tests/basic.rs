@@ -1,3 +1,7 @@
+#[allow(
+    dead_code,
+    reason = "Helpers are shared with the other integration tests"
+)]
 mod common;
 
 use std::{
@@ -10,7 +14,7 @@ use test_log::test;
 use common::{Nextcloud, Result};
 use data_basic::*;
 use nextcloud_tag_sync::{
-    Config, FileLocation, PrefixMapping, Repository, Side, Tags, Uninitialized,
+    Config, FileLocation, InitError, PrefixMapping, Repository, Side, Tags, Uninitialized,
 };
 use url::Url;
 use walkdir::WalkDir;
@@ -221,7 +225,7 @@ impl TestEnv {
             nextcloud_instance: self.nextcloud_instance.clone(),
             user: self.user.clone(),
             token: self.token.clone(),
-            max_concurrent_requests: 100,
+            scan_concurrency: 100,
             tag_database: self.temp_dir.path().join("db.json"),
             ..Default::default()
         }
@@ -265,8 +269,14 @@ impl TestEnv {
                 FileLocation::Remote => "remote",
             };
             match expected_tags {
-                Some(expected_tags) => assert_eq!(&actual_tags, expected_tags, "Wrong tags on {location} file {file}"),
-                None => assert!(actual_tags.is_empty(), "Unexpectedly found tags on {location} file {file}"),
+                Some(expected_tags) => assert_eq!(
+                    &actual_tags, expected_tags,
+                    "Wrong tags on {location} file {file}"
+                ),
+                None => assert!(
+                    actual_tags.is_empty(),
+                    "Unexpectedly found tags on {location} file {file}"
+                ),
             }
         }
         Ok(())
@@ -312,6 +322,46 @@ async fn run_initial_sync_to_remote() -> Result {
     Ok(())
 }
 
+#[test(tokio::test)]
+async fn refuse_concurrent_sync() -> Result {
+    let mut env = TestEnv::new()
+        .await
+        .with_prefix(&LOCAL_DIR, REMOTE_DIR)
+        .await;
+    env.tag_local(foo::IGNORE_TXT, tag::YELLOW)?;
+    env.tag_remote(bar::baz::DRAT_PDF, tag::RED).await?;
+
+    let run = |config: Arc<Config>| async move {
+        let mut initialized = Uninitialized::new(config).initialize().await?;
+        initialized.sync().await?;
+        initialized.persist_repository()?;
+        Result::Ok(())
+    };
+    let locked = |result: &Result| {
+        result
+            .as_ref()
+            .is_err_and(|e| matches!(e.downcast_ref(), Some(InitError::Locked { .. })))
+    };
+    let (first, second) = tokio::join!(run(env.arc_config()), run(env.arc_config()));
+    assert!(
+        first.is_ok() && locked(&second) || locked(&first) && second.is_ok(),
+        "exactly one run must win the lock: {first:?} {second:?}"
+    );
+    run(env.arc_config()).await?;
+
+    let expected = [
+        (foo::IGNORE_TXT, Some(tag::YELLOW_TAG.clone())),
+        (bar::baz::DRAT_PDF, Some(tag::RED_TAG.clone())),
+        (dummy::ERR_PDF, None),
+    ];
+    env.assert_tags(FileLocation::Remote, &expected).await?;
+    env.assert_tags(FileLocation::Local, &expected).await?;
+    let repo = Repository::read_from_disk(&env.config().tag_database)?;
+    assert_eq!(repo.len(), 2);
+
+    Ok(())
+}
+
 #[test(tokio::test)]
 async fn run_initial_sync_to_local() -> Result {
     let mut env = TestEnv::new()
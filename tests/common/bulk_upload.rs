@@ -0,0 +1,82 @@
+use std::{borrow::Cow, collections::BTreeMap};
+
+use nextcloud_tag_sync::{instance_url, Body, Parse, Request};
+use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+
+const BOUNDARY: &str = "ncts-bulk-upload";
+/// The bulk endpoint requires a checksum, all uploaded files are empty.
+const EMPTY_MD5: &str = "d41d8cd98f00b204e9800998ecf8427e";
+
+/// Uploads many empty files in one multipart request. Paths are relative to the files of
+/// the user and their folders must exist.
+pub struct BulkUpload {
+    paths: Vec<String>,
+}
+
+impl BulkUpload {
+    #[must_use]
+    pub const fn new(paths: Vec<String>) -> Self {
+        Self { paths }
+    }
+}
+
+impl Request for BulkUpload {
+    fn method(&self) -> reqwest::Method {
+        reqwest::Method::POST
+    }
+
+    fn endpoint(&self) -> Cow<'_, str> {
+        "/remote.php/dav/bulk".into()
+    }
+
+    fn url(
+        &self,
+        host: &reqwest::Url,
+        _dav_root: &reqwest::Url,
+        _user: &str,
+    ) -> Result<reqwest::Url, url::ParseError> {
+        instance_url(host, &self.endpoint())
+    }
+
+    fn body(&self) -> Body {
+        let mut body = String::new();
+        for path in &self.paths {
+            body.push_str(&format!(
+                "--{BOUNDARY}\r\nX-File-Path: {path}\r\nX-File-MD5: {EMPTY_MD5}\r\n\
+                 X-File-Mtime: 0\r\nContent-Length: 0\r\n\r\n\r\n"
+            ));
+        }
+        body.push_str(&format!("--{BOUNDARY}--\r\n"));
+        Body::Raw(body.into_bytes())
+    }
+
+    fn headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let content_type = format!("multipart/related; boundary={BOUNDARY}");
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_str(&content_type).expect("valid header"),
+        );
+        headers
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Outcome {
+    error: bool,
+}
+
+impl Parse for BulkUpload {
+    /// Paths which failed to upload.
+    type Output = Vec<String>;
+    type Error = serde_json::Error;
+
+    fn parse(_: &HeaderMap, body: &str) -> Result<Self::Output, Self::Error> {
+        let outcomes: BTreeMap<String, Outcome> = serde_json::from_str(body)?;
+        Ok(outcomes
+            .into_iter()
+            .filter(|(_, outcome)| outcome.error)
+            .map(|(path, _)| path)
+            .collect())
+    }
+}
@@ -21,12 +21,17 @@ impl Request for CreateDirectory {
         reqwest::Method::from_bytes(b"MKCOL").expect("HTTP method should be valid")
     }
 
-    fn endpoint(&self) -> Cow<str> {
+    fn endpoint(&self) -> Cow<'_, str> {
         (&self.path).into()
     }
 
-    fn url(&self, host: &reqwest::Url, _user: &str) -> reqwest::Url {
-        host.join(&self.endpoint()).expect("failed to create URL")
+    fn url(
+        &self,
+        host: &reqwest::Url,
+        _dav_root: &reqwest::Url,
+        _user: &str,
+    ) -> Result<reqwest::Url, url::ParseError> {
+        host.join(&self.endpoint())
     }
 }
 
@@ -1,8 +1,12 @@
+use std::collections::BTreeSet;
+
 use bimap::BiHashMap;
+use bulk_upload::BulkUpload;
 use create_dir::CreateDirectory;
-use get_file_tags::GetFileTags;
+use futures::{StreamExt, TryStreamExt};
 use nextcloud_tag_sync::{
-    get_tags_of_file, Config, Connection, CreateTag, FileId, Tag, TagFile, TagMap, Tags, UntagFile,
+    get_tags_of_file, Config, Connection, CreateTag, FileId, GetFileTags, ListDirectory,
+    RemotePath, Tag, TagFile, TagMap, Tags, UntagFile,
 };
 use testcontainers::{core::WaitFor, runners::AsyncRunner as _, ContainerAsync, Image};
 use upload_file::UploadFile;
@@ -11,10 +15,13 @@ use walkdir::WalkDir;
 
 pub type Result<T = (), E = Box<dyn std::error::Error + 'static>> = std::result::Result<T, E>;
 
+mod bulk_upload;
 mod create_dir;
-mod get_file_tags;
 mod upload_file;
 
+/// Files per bulk upload request.
+const BULK_SIZE: usize = 1000;
+
 pub struct NextcloudImage;
 
 impl Image for NextcloudImage {
@@ -126,6 +133,80 @@ impl Nextcloud {
         Ok(())
     }
 
+    /// Creates empty files at the remote paths `files` and their folders with a few bulk
+    /// requests instead of one request per file.
+    pub async fn upload_empty(&mut self, files: &[String]) -> Result {
+        let root = format!("/remote.php/dav/files/{}/", Self::ADMIN_USER);
+        let folders: BTreeSet<_> = files
+            .iter()
+            .filter_map(|file| file.rsplit_once('/').map(|(folder, _)| folder))
+            .flat_map(|folder| GrowingSegments::new(folder, '/').skip(4))
+            .collect();
+        // Parents sort before their children.
+        for folder in &folders {
+            let file_id = self
+                .connection
+                .request(CreateDirectory::new(*folder))
+                .await?;
+            self.files.insert(file_id, (*folder).to_owned());
+        }
+
+        for chunk in files.chunks(BULK_SIZE) {
+            let paths = chunk
+                .iter()
+                .map(|file| file.strip_prefix(&root).map(str::to_owned))
+                .collect::<Option<_>>()
+                .ok_or_else(|| format!("Files must be below {root}"))?;
+            let failed = self.connection.request(BulkUpload::new(paths)).await?;
+            if !failed.is_empty() {
+                return Err(format!("Bulk upload failed for {failed:?}").into());
+            }
+        }
+
+        for folder in folders {
+            let entries = self
+                .connection
+                .request(ListDirectory::new(&RemotePath::from_href(folder)).ok_or("non-UTF8")?)
+                .await?;
+            for entry in entries.into_iter().filter(|entry| !entry.is_directory) {
+                self.files
+                    .insert(entry.id, entry.path.as_path().display().to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Assigns many tags concurrently. Missing tags are created first.
+    pub async fn tag_many(&mut self, assignments: &[(String, Tag)]) -> Result {
+        for (_, tag) in assignments {
+            if !self.tags.contains_right(tag) {
+                let tag_id = self.connection.request(CreateTag::new(tag.clone())).await?;
+                self.tags.insert(tag_id, tag.clone());
+            }
+        }
+        let requests = assignments
+            .iter()
+            .map(|(file_path, tag)| {
+                let file_id = self
+                    .files
+                    .get_by_right(file_path)
+                    .ok_or_else(|| format!("File {file_path} not uploaded"))?;
+                let tag_id = self
+                    .tags
+                    .get_by_right(tag)
+                    .ok_or_else(|| format!("Tag {tag} not created"))?;
+                Result::Ok(TagFile::new(*tag_id, *file_id))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let connection = &self.connection;
+        futures::stream::iter(requests)
+            .map(|request| connection.request(request))
+            .buffer_unordered(16)
+            .try_collect::<Vec<_>>()
+            .await?;
+        Ok(())
+    }
+
     pub async fn sync_tags(&mut self, nc_base_folder: &str, source: &std::path::Path) -> Result {
         let files = WalkDir::new(source).min_depth(1);
         for entry in files {
@@ -191,7 +272,7 @@ impl Nextcloud {
             .files
             .get_by_right(file_path)
             .ok_or_else(|| format!("File {file_path} not uploaded"))?;
-        Ok(self.connection.request(GetFileTags(file_id)).await?)
+        Ok(self.connection.request(GetFileTags::new(file_id)).await?)
     }
 }
 
@@ -1,6 +1,6 @@
 use std::{borrow::Cow, num::ParseIntError, str::Utf8Error};
 
-use nextcloud_tag_sync::{Body, FileId, Parse, Request};
+use nextcloud_tag_sync::{instance_url, Body, FileId, Parse, Request};
 use reqwest::header::{HeaderMap, HeaderValue};
 use snafu::{OptionExt, ResultExt, Snafu};
 
@@ -24,12 +24,17 @@ impl Request for UploadFile {
         reqwest::Method::PUT
     }
 
-    fn endpoint(&self) -> Cow<str> {
+    fn endpoint(&self) -> Cow<'_, str> {
         (&self.path).into()
     }
 
-    fn url(&self, host: &reqwest::Url, _user: &str) -> reqwest::Url {
-        host.join(&self.endpoint()).expect("failed to create URL")
+    fn url(
+        &self,
+        host: &reqwest::Url,
+        _dav_root: &reqwest::Url,
+        _user: &str,
+    ) -> Result<reqwest::Url, url::ParseError> {
+        instance_url(host, &self.endpoint())
     }
 
     fn body(&self) -> Body {
@@ -0,0 +1,157 @@
+//! Scalability harness syncing thousands of generated files. It is ignored by default, run
+//! it with `cargo test --test scale -- --ignored --nocapture`. `NCTS_SCALE_FILES` sets the
+//! number of files and `NCTS_SCALE_SEED` the seed of the generated tags.
+
+#[allow(
+    dead_code,
+    reason = "Helpers are shared with the other integration tests"
+)]
+mod common;
+
+use std::{
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use test_log::test;
+
+use common::{Nextcloud, Result};
+use nextcloud_tag_sync::{Config, PrefixMapping, Tags, Uninitialized};
+
+const REMOTE_DIR: &str = "/remote.php/dav/files/tester/scale";
+const TAGS: [&str; 8] = [
+    "red", "green", "blue", "holiday", "work", "family", "archive", "todo",
+];
+const FILES_PER_FOLDER: usize = 250;
+
+/// Deterministic generator, so a slow run can be reproduced with the same seed.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Random subset of [`TAGS`] with each tag picked with a probability of 1/4.
+    fn tags(&mut self) -> Vec<&'static str> {
+        let bits = self.next();
+        TAGS.iter()
+            .enumerate()
+            .filter(|(i, _)| (bits >> (2 * i)) & 3 == 0)
+            .map(|(_, tag)| *tag)
+            .collect()
+    }
+}
+
+struct GeneratedFile {
+    /// Path relative to the synchronized folders.
+    path: String,
+    local: Vec<&'static str>,
+    remote: Vec<&'static str>,
+}
+
+fn generate(count: usize, seed: u64) -> Vec<GeneratedFile> {
+    let mut rng = XorShift(seed.max(1));
+    (0..count)
+        .map(|i| GeneratedFile {
+            path: format!("folder-{}/file-{i}.txt", i / FILES_PER_FOLDER),
+            local: rng.tags(),
+            remote: rng.tags(),
+        })
+        .collect()
+}
+
+fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Peak resident memory of the test process in KiB, only available on Linux.
+fn peak_memory_kib() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+fn print_measurement(step: &str, duration: Duration) {
+    match peak_memory_kib() {
+        Some(kib) => println!("{step} took {duration:?}, peak memory {kib} KiB"),
+        None => println!("{step} took {duration:?}"),
+    }
+}
+
+#[test(tokio::test)]
+#[ignore = "starts a Nextcloud container and syncs thousands of files"]
+async fn sync_thousands_of_files() -> Result {
+    let count = env_or("NCTS_SCALE_FILES", 2000);
+    let seed = env_or("NCTS_SCALE_SEED", 42);
+    let files = generate(count, seed);
+    let mut container = Nextcloud::start().await?;
+    let temp_dir = tempfile::tempdir()?;
+    let local_root = temp_dir.path().join("scale");
+    let config = Config {
+        prefixes: vec![PrefixMapping::new(local_root.clone(), REMOTE_DIR.into())?],
+        nextcloud_instance: container.url().await?,
+        user: Nextcloud::ADMIN_USER.to_owned(),
+        token: Nextcloud::ADMIN_PASSWORD.to_owned(),
+        scan_concurrency: 100,
+        tag_database: temp_dir.path().join("db.json"),
+        confirm_initial_sync: true,
+        ..Default::default()
+    };
+
+    let started = Instant::now();
+    for file in &files {
+        let path = local_root.join(&file.path);
+        std::fs::create_dir_all(path.parent().ok_or("file without folder")?)?;
+        std::fs::write(&path, b"")?;
+        if !file.local.is_empty() {
+            let tags: Tags = file.local.iter().copied().collect();
+            xattr::set(
+                &path,
+                &config.local_tag_property_name,
+                tags.to_string().as_bytes(),
+            )?;
+        }
+    }
+    let remote_path = |file: &GeneratedFile| format!("{REMOTE_DIR}/{}", file.path);
+    container
+        .upload_empty(&files.iter().map(remote_path).collect::<Vec<_>>())
+        .await?;
+    let assignments: Vec<_> = files
+        .iter()
+        .flat_map(|file| file.remote.iter().map(move |tag| (remote_path(file), tag)))
+        .map(|(path, tag)| Ok((path, tag.parse()?)))
+        .collect::<Result<_>>()?;
+    container.tag_many(&assignments).await?;
+    print_measurement(
+        &format!("Generating {count} files with seed {seed}"),
+        started.elapsed(),
+    );
+
+    let config = Arc::new(config);
+    let started = Instant::now();
+    let initialized = Uninitialized::new(config.clone()).initialize().await?;
+    print_measurement("Initial synchronization", started.elapsed());
+    let tagged = files
+        .iter()
+        .filter(|file| !file.local.is_empty() || !file.remote.is_empty())
+        .count();
+    assert_eq!(initialized.repository().len(), tagged);
+    initialized.persist_repository()?;
+    drop(initialized);
+
+    let started = Instant::now();
+    let mut initialized = Uninitialized::new(config).initialize().await?;
+    let report = initialized.sync().await?;
+    print_measurement("Follow-up synchronization", started.elapsed());
+    assert_eq!(report.failed_commands(), 0);
+
+    Ok(())
+}